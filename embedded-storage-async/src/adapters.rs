@@ -0,0 +1,7 @@
+mod sanitizer;
+mod striped;
+mod timeout;
+
+pub use sanitizer::{Access, AccessKind, Sanitized, SanitizerError, Tracker};
+pub use striped::{Striped, StripedError};
+pub use timeout::{Deadlines, DelayUs, Timeout, TimeoutError};
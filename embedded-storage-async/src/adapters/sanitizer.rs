@@ -0,0 +1,231 @@
+use core::cell::Cell;
+
+use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind, ProgramPolarity};
+
+use crate::nor_flash::{MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+/// Kind of an operation tracked by a [`Tracker`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AccessKind {
+	/// A read, which only conflicts with writes and erases.
+	Read,
+	/// A write.
+	Write,
+	/// An erase.
+	Erase,
+}
+
+/// An operation in flight on the range `from..to` of a flash.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Access {
+	/// Kind of the operation.
+	pub kind: AccessKind,
+	/// Start of the accessed range.
+	pub from: u32,
+	/// End of the accessed range.
+	pub to: u32,
+}
+
+impl Access {
+	/// Whether the operations race, i.e. their ranges overlap and one of them modifies the flash.
+	pub fn conflicts(&self, other: &Access) -> bool {
+		let modifies = self.kind != AccessKind::Read || other.kind != AccessKind::Read;
+		modifies && self.from < other.to && other.from < self.to
+	}
+}
+
+/// Errors returned by [`Sanitized`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SanitizerError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The operation races with another one in flight and was not forwarded.
+	Overlap {
+		/// The rejected operation.
+		access: Access,
+		/// The operation in flight it races with.
+		in_flight: Access,
+	},
+}
+
+impl<E: NorFlashError> NorFlashError for SanitizerError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Overlap { .. } => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Record of the operations in flight on a flash, shared by the [`Sanitized`] handles to it.
+///
+/// Up to `N` operations can be in flight at once.
+pub struct Tracker<const N: usize> {
+	slots: [Cell<Option<Access>>; N],
+	conflicts: Cell<usize>,
+}
+
+impl<const N: usize> Tracker<N> {
+	// Only used to initialize the slots, each use is a new cell.
+	#[allow(clippy::declare_interior_mutable_const)]
+	const EMPTY: Cell<Option<Access>> = Cell::new(None);
+
+	/// Create a tracker with no operation in flight.
+	pub const fn new() -> Self {
+		Self {
+			slots: [Self::EMPTY; N],
+			conflicts: Cell::new(0),
+		}
+	}
+
+	/// Number of races detected so far, including those whose error was ignored by the caller.
+	pub fn conflicts(&self) -> usize {
+		self.conflicts.get()
+	}
+
+	/// Register `access`, which stays in flight until the returned guard is dropped.
+	///
+	/// **NOTE** This will panic if `N` operations are already in flight.
+	fn start(&self, access: Access) -> Result<Guard<'_, N>, Access> {
+		let racing = self
+			.slots
+			.iter()
+			.filter_map(Cell::get)
+			.find(|in_flight| in_flight.conflicts(&access));
+		if let Some(in_flight) = racing {
+			self.conflicts.set(self.conflicts.get() + 1);
+			return Err(in_flight);
+		}
+		let index = self
+			.slots
+			.iter()
+			.position(|slot| slot.get().is_none())
+			.expect("Too many operations in flight");
+		self.slots[index].set(Some(access));
+		Ok(Guard {
+			tracker: self,
+			index,
+		})
+	}
+}
+
+impl<const N: usize> Default for Tracker<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Operation in flight, removed from the tracker when completed or cancelled.
+struct Guard<'a, const N: usize> {
+	tracker: &'a Tracker<N>,
+	index: usize,
+}
+
+impl<const N: usize> Drop for Guard<'_, N> {
+	fn drop(&mut self) {
+		self.tracker.slots[self.index].set(None);
+	}
+}
+
+/// Debug wrapper detecting overlapping operations in flight on the same flash.
+///
+/// Every handle sharing a flash, e.g. through a mutex or a bus manager, is wrapped with the same
+/// [`Tracker`]. An operation starting while another one modifying an overlapping range is in
+/// flight, such as a read racing an erase, is rejected with [`SanitizerError::Overlap`] instead of
+/// silently returning or storing corrupted data. Cancelled operations are removed from the
+/// tracker when their future is dropped.
+pub struct Sanitized<'a, S, const N: usize> {
+	flash: S,
+	tracker: &'a Tracker<N>,
+}
+
+impl<'a, S, const N: usize> Sanitized<'a, S, N> {
+	/// Wrap the handle `flash`, recording its operations in `tracker`.
+	pub fn new(flash: S, tracker: &'a Tracker<N>) -> Self {
+		Self { flash, tracker }
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+}
+
+impl<S: ErrorType, const N: usize> ErrorType for Sanitized<'_, S, N> {
+	type Error = SanitizerError<S::Error>;
+}
+
+impl<S: ReadNorFlash, const N: usize> ReadNorFlash for Sanitized<'_, S, N> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	async fn read(
+		&mut self,
+		offset: u32,
+		bytes: &mut [u8],
+	) -> Result<(), SanitizerError<S::Error>> {
+		let access = Access {
+			kind: AccessKind::Read,
+			from: offset,
+			to: offset.saturating_add(bytes.len() as u32),
+		};
+		let _guard = self
+			.tracker
+			.start(access)
+			.map_err(|in_flight| SanitizerError::Overlap { access, in_flight })?;
+		self.flash
+			.read(offset, bytes)
+			.await
+			.map_err(SanitizerError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, const N: usize> NorFlash for Sanitized<'_, S, N> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), SanitizerError<S::Error>> {
+		let access = Access {
+			kind: AccessKind::Erase,
+			from,
+			to,
+		};
+		let _guard = self
+			.tracker
+			.start(access)
+			.map_err(|in_flight| SanitizerError::Overlap { access, in_flight })?;
+		self.flash
+			.erase(from, to)
+			.await
+			.map_err(SanitizerError::Flash)
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), SanitizerError<S::Error>> {
+		let access = Access {
+			kind: AccessKind::Write,
+			from: offset,
+			to: offset.saturating_add(bytes.len() as u32),
+		};
+		let _guard = self
+			.tracker
+			.start(access)
+			.map_err(|in_flight| SanitizerError::Overlap { access, in_flight })?;
+		self.flash
+			.write(offset, bytes)
+			.await
+			.map_err(SanitizerError::Flash)
+	}
+
+	async fn flush(&mut self) -> Result<(), SanitizerError<S::Error>> {
+		self.flash.flush().await.map_err(SanitizerError::Flash)
+	}
+}
+
+impl<S: MultiwriteNorFlash, const N: usize> MultiwriteNorFlash for Sanitized<'_, S, N> {}
@@ -0,0 +1,280 @@
+use core::ops::Range;
+
+use embedded_storage::nor_flash::{
+	CheckError, ErrorType, NorFlashError, NorFlashErrorKind, ProgramPolarity,
+};
+
+use crate::join::join;
+use crate::nor_flash::{
+	check_erase, check_read, check_write, write_chunked, MultiwriteNorFlash, NorFlash, ReadNorFlash,
+};
+
+/// Errors returned by [`Striped`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StripedError<A, B> {
+	/// The first flash returned an error.
+	First(A),
+	/// The second flash returned an error.
+	Second(B),
+	/// The arguments are not aligned or out of bounds of the striped flash.
+	Invalid(CheckError),
+}
+
+impl<A: NorFlashError, B: NorFlashError> NorFlashError for StripedError<A, B> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::First(e) => e.kind(),
+			Self::Second(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+const fn max(a: usize, b: usize) -> usize {
+	if a > b {
+		a
+	} else {
+		b
+	}
+}
+
+const fn min(a: usize, b: usize) -> usize {
+	if a < b {
+		a
+	} else {
+		b
+	}
+}
+
+/// Part of an operation lying in one pair of stripes, mapped onto both devices.
+struct Pair {
+	/// Offset on the first device and range within the operation.
+	first: (u32, Range<usize>),
+	/// Offset on the second device and range within the operation.
+	second: (u32, Range<usize>),
+}
+
+impl Pair {
+	/// Map the part of `offset..offset + length` starting at `offset + done` which lies in a
+	/// single pair of stripes of size `stripe`.
+	fn new(stripe: usize, offset: u32, length: usize, done: usize) -> Self {
+		let (offset, start, end) = (
+			offset as usize,
+			offset as usize + done,
+			offset as usize + length,
+		);
+		let pair = start / (2 * stripe);
+		let base = pair * 2 * stripe;
+		let middle = base + stripe;
+		let first = start..if start < middle {
+			end.min(middle)
+		} else {
+			start
+		};
+		let second_end = end.min(base + 2 * stripe);
+		let second = if end > middle {
+			start.max(middle)
+		} else {
+			second_end
+		}..second_end;
+		Self {
+			first: (
+				(pair * stripe + first.start - base) as u32,
+				first.start - offset..first.end - offset,
+			),
+			second: (
+				(pair * stripe + second.start.saturating_sub(base + stripe)) as u32,
+				second.start - offset..second.end - offset,
+			),
+		}
+	}
+
+	/// Length of the operation done once this pair is processed.
+	fn done(&self) -> usize {
+		self.first.1.end.max(self.second.1.end)
+	}
+}
+
+/// Interleaving of two flash devices, alternating between them every erase page.
+///
+/// Even pages of the striped address space are stored on the first device, odd pages on the
+/// second one. Both halves of an operation are issued concurrently, so sequential accesses spanning
+/// several pages proceed on both devices at once, nearly doubling throughput. Both devices must
+/// have the same erased contents and program polarity.
+pub struct Striped<A, B> {
+	first: A,
+	second: B,
+}
+
+impl<A, B> Striped<A, B>
+where
+	A: NorFlash,
+	B: NorFlash,
+{
+	/// Interleave `first` and `second`.
+	///
+	/// **NOTE** This will panic if the erase sizes, the erased contents or the program polarities
+	/// of the devices differ.
+	pub fn new(first: A, second: B) -> Self {
+		match Self::try_new(first, second) {
+			Ok(striped) => striped,
+			Err(CheckError::Incompatible) => {
+				panic!("Striped flashes have different erased contents or polarities")
+			}
+			Err(_) => panic!("Erase sizes of the striped devices differ"),
+		}
+	}
+
+	/// Interleave `first` and `second`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if the erase sizes of the devices differ, or
+	/// [`CheckError::Incompatible`] if their erased contents or program polarities differ.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(first: A, second: B) -> Result<Self, CheckError> {
+		if A::ERASE_SIZE != B::ERASE_SIZE {
+			return Err(CheckError::NotAligned);
+		}
+		if A::ERASE_BYTE != B::ERASE_BYTE
+			|| A::ERASE_PATTERN != B::ERASE_PATTERN
+			|| A::PROGRAM_POLARITY != B::PROGRAM_POLARITY
+		{
+			return Err(CheckError::Incompatible);
+		}
+		Ok(Self { first, second })
+	}
+
+	/// Release both devices.
+	pub fn into_inner(self) -> (A, B) {
+		(self.first, self.second)
+	}
+}
+
+impl<A: ErrorType, B: ErrorType> ErrorType for Striped<A, B> {
+	type Error = StripedError<A::Error, B::Error>;
+}
+
+impl<A: NorFlash, B: NorFlash> ReadNorFlash for Striped<A, B> {
+	const READ_SIZE: usize = max(A::READ_SIZE, B::READ_SIZE);
+
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len()).map_err(StripedError::Invalid)?;
+		let mut done = 0;
+		while done < bytes.len() {
+			let pair = Pair::new(A::ERASE_SIZE, offset, bytes.len(), done);
+			done = pair.done();
+			let Pair {
+				first: (first_offset, first_range),
+				second: (second_offset, second_range),
+			} = pair;
+			let (head, tail) = bytes.split_at_mut(second_range.start);
+			let first_bytes = &mut head[first_range];
+			let second_bytes = &mut tail[..second_range.len()];
+			let (first_flash, second_flash) = (&mut self.first, &mut self.second);
+			let (first, second) = join(
+				async move {
+					if first_bytes.is_empty() {
+						return Ok(());
+					}
+					first_flash.read(first_offset, first_bytes).await
+				},
+				async move {
+					if second_bytes.is_empty() {
+						return Ok(());
+					}
+					second_flash.read(second_offset, second_bytes).await
+				},
+			)
+			.await;
+			first.map_err(StripedError::First)?;
+			second.map_err(StripedError::Second)?;
+		}
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		2 * self.first.capacity().min(self.second.capacity())
+	}
+}
+
+impl<A: NorFlash, B: NorFlash> NorFlash for Striped<A, B> {
+	const WRITE_SIZE: usize = max(A::WRITE_SIZE, B::WRITE_SIZE);
+	const ERASE_SIZE: usize = A::ERASE_SIZE;
+	const ERASE_BYTE: u8 = A::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = A::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = A::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = min(A::MAX_WRITE_CHUNK, B::MAX_WRITE_CHUNK);
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to).map_err(StripedError::Invalid)?;
+		let (from, to) = (from as usize, to as usize);
+		// Even stripes are on the first device, odd ones on the second.
+		let (from, to) = (from / Self::ERASE_SIZE, to / Self::ERASE_SIZE);
+		let first =
+			((from + 1) / 2 * Self::ERASE_SIZE) as u32..((to + 1) / 2 * Self::ERASE_SIZE) as u32;
+		let second = (from / 2 * Self::ERASE_SIZE) as u32..(to / 2 * Self::ERASE_SIZE) as u32;
+		let (first_flash, second_flash) = (&mut self.first, &mut self.second);
+		let (first, second) = join(
+			async move {
+				if first.is_empty() {
+					return Ok(());
+				}
+				first_flash.erase(first.start, first.end).await
+			},
+			async move {
+				if second.is_empty() {
+					return Ok(());
+				}
+				second_flash.erase(second.start, second.end).await
+			},
+		)
+		.await;
+		first.map_err(StripedError::First)?;
+		second.map_err(StripedError::Second)
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len()).map_err(StripedError::Invalid)?;
+		let mut done = 0;
+		while done < bytes.len() {
+			let pair = Pair::new(A::ERASE_SIZE, offset, bytes.len(), done);
+			done = pair.done();
+			let Pair {
+				first: (first_offset, first_range),
+				second: (second_offset, second_range),
+			} = pair;
+			let (first_bytes, second_bytes) = (&bytes[first_range], &bytes[second_range]);
+			let (first_flash, second_flash) = (&mut self.first, &mut self.second);
+			let (first, second) = join(
+				async move {
+					if first_bytes.is_empty() {
+						return Ok(());
+					}
+					write_chunked(first_flash, first_offset, first_bytes).await
+				},
+				async move {
+					if second_bytes.is_empty() {
+						return Ok(());
+					}
+					write_chunked(second_flash, second_offset, second_bytes).await
+				},
+			)
+			.await;
+			first.map_err(StripedError::First)?;
+			second.map_err(StripedError::Second)?;
+		}
+		Ok(())
+	}
+
+	async fn flush(&mut self) -> Result<(), Self::Error> {
+		let (first, second) = join(self.first.flush(), self.second.flush()).await;
+		first.map_err(StripedError::First)?;
+		second.map_err(StripedError::Second)
+	}
+}
+
+impl<A: MultiwriteNorFlash, B: MultiwriteNorFlash> MultiwriteNorFlash for Striped<A, B> {}
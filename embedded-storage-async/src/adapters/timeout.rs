@@ -0,0 +1,133 @@
+use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind, ProgramPolarity};
+
+use crate::join::{select, Either};
+use crate::nor_flash::{MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+/// Async delay, as provided by the timer of a HAL or executor.
+///
+/// This mirrors the delay traits of `embedded-hal-async`, so that implementations are one line
+/// wrappers.
+pub trait DelayUs {
+	/// Wait for at least `us` microseconds.
+	async fn delay_us(&mut self, us: u32);
+}
+
+impl<T: DelayUs> DelayUs for &mut T {
+	async fn delay_us(&mut self, us: u32) {
+		T::delay_us(self, us).await
+	}
+}
+
+/// Maximum duration of each kind of operation, in microseconds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Deadlines {
+	/// Deadline of every read.
+	pub read: u32,
+	/// Deadline of every write.
+	pub write: u32,
+	/// Deadline of every erase.
+	pub erase: u32,
+}
+
+/// Errors returned by [`Timeout`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimeoutError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The operation did not complete before its deadline and was cancelled.
+	Timeout,
+}
+
+impl<E: NorFlashError> NorFlashError for TimeoutError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Timeout => NorFlashErrorKind::Timeout,
+		}
+	}
+}
+
+/// Wrapper cancelling operations which do not complete before their deadline.
+///
+/// Every operation races with a delay of `D`, so that a stuck transaction, e.g. a QSPI flash
+/// never reporting the end of an erase, returns [`TimeoutError::Timeout`] instead of hanging the
+/// whole firmware. The wrapped operation is dropped on expiry, the state of the flash is then
+/// unknown and the driver may need to be reset.
+pub struct Timeout<S, D> {
+	flash: S,
+	delay: D,
+	deadlines: Deadlines,
+}
+
+impl<S, D> Timeout<S, D> {
+	/// Wrap `flash`, cancelling operations after the `deadlines` measured with `delay`.
+	pub fn new(flash: S, delay: D, deadlines: Deadlines) -> Self {
+		Self {
+			flash,
+			delay,
+			deadlines,
+		}
+	}
+
+	/// Change the deadlines of subsequent operations.
+	pub fn set_deadlines(&mut self, deadlines: Deadlines) {
+		self.deadlines = deadlines;
+	}
+
+	/// Release the wrapped flash and delay.
+	pub fn into_inner(self) -> (S, D) {
+		(self.flash, self.delay)
+	}
+}
+
+impl<S: ErrorType, D> ErrorType for Timeout<S, D> {
+	type Error = TimeoutError<S::Error>;
+}
+
+impl<S: ReadNorFlash, D: DelayUs> ReadNorFlash for Timeout<S, D> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), TimeoutError<S::Error>> {
+		let operation = self.flash.read(offset, bytes);
+		match select(operation, self.delay.delay_us(self.deadlines.read)).await {
+			Either::First(result) => result.map_err(TimeoutError::Flash),
+			Either::Second(()) => Err(TimeoutError::Timeout),
+		}
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, D: DelayUs> NorFlash for Timeout<S, D> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), TimeoutError<S::Error>> {
+		let operation = self.flash.erase(from, to);
+		match select(operation, self.delay.delay_us(self.deadlines.erase)).await {
+			Either::First(result) => result.map_err(TimeoutError::Flash),
+			Either::Second(()) => Err(TimeoutError::Timeout),
+		}
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), TimeoutError<S::Error>> {
+		let operation = self.flash.write(offset, bytes);
+		match select(operation, self.delay.delay_us(self.deadlines.write)).await {
+			Either::First(result) => result.map_err(TimeoutError::Flash),
+			Either::Second(()) => Err(TimeoutError::Timeout),
+		}
+	}
+
+	async fn flush(&mut self) -> Result<(), TimeoutError<S::Error>> {
+		// A flush may write back any amount of data, so it has no deadline.
+		self.flash.flush().await.map_err(TimeoutError::Flash)
+	}
+}
+
+impl<S: MultiwriteNorFlash, D: DelayUs> MultiwriteNorFlash for Timeout<S, D> {}
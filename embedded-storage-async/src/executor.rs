@@ -0,0 +1,238 @@
+use core::cell::RefCell;
+use core::task::{Context, Poll, Waker};
+
+use embedded_storage::nor_flash::ErrorType;
+
+use crate::join::poll_fn;
+use crate::nor_flash::{check_erase, MultiwriteNorFlash, NorFlash, ProgramPolarity, ReadNorFlash};
+
+/// Priority of the operations of a [`Handle`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Priority {
+	/// Maintenance such as erasing pages ahead of time, which any other operation preempts.
+	Background,
+	/// Regular operations.
+	Normal,
+	/// Latency sensitive operations, e.g. reads serving a request.
+	Urgent,
+}
+
+const LEVELS: usize = 3;
+
+struct State<S, const N: usize> {
+	/// The flash, taken by the operation in progress.
+	flash: Option<S>,
+	/// Number of operations waiting for the flash, by priority.
+	waiting: [usize; LEVELS],
+	wakers: [Option<Waker>; N],
+}
+
+impl<S, const N: usize> State<S, N> {
+	fn wake_all(&self) {
+		for waker in self.wakers.iter().flatten() {
+			waker.wake_by_ref();
+		}
+	}
+}
+
+/// Scheduler of the operations of several async tasks sharing one flash.
+///
+/// Every task gets a [`Handle`] with a [`Priority`], implementing the async flash traits. Each
+/// operation waits until the flash is free and no operation of a higher priority is waiting,
+/// then runs to completion. Erases are split into single pages, so that a background erase of a
+/// large range lets operations of a higher priority through after every page instead of delaying
+/// them for the whole range.
+///
+/// The executor tracks up to `N` waiting operations, further ones poll again immediately instead
+/// of waiting to be woken.
+pub struct FlashExecutor<S, const N: usize> {
+	state: RefCell<State<S, N>>,
+	capacity: usize,
+}
+
+impl<S: ReadNorFlash, const N: usize> FlashExecutor<S, N> {
+	/// Schedule the operations on `flash`.
+	pub fn new(flash: S) -> Self {
+		const NONE: Option<Waker> = None;
+		Self {
+			capacity: flash.capacity(),
+			state: RefCell::new(State {
+				flash: Some(flash),
+				waiting: [0; LEVELS],
+				wakers: [NONE; N],
+			}),
+		}
+	}
+
+	/// A handle issuing operations with `priority`.
+	pub fn handle(&self, priority: Priority) -> Handle<'_, S, N> {
+		Handle {
+			executor: self,
+			priority,
+		}
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		// No operation can be in progress without a handle borrowing the executor.
+		self.state
+			.into_inner()
+			.flash
+			.expect("Flash taken by an operation")
+	}
+
+	/// Wait for the flash to be available to an operation of `priority`.
+	async fn acquire(&self, priority: Priority) -> Permit<'_, S, N> {
+		let mut waiter = Waiter {
+			executor: self,
+			priority,
+			slot: None,
+			queued: true,
+		};
+		self.state.borrow_mut().waiting[priority as usize] += 1;
+		poll_fn(|cx| waiter.poll(cx)).await
+	}
+}
+
+/// Operation waiting for the flash, dequeued when dropped.
+struct Waiter<'a, S, const N: usize> {
+	executor: &'a FlashExecutor<S, N>,
+	priority: Priority,
+	/// Slot holding the waker of the operation.
+	slot: Option<usize>,
+	queued: bool,
+}
+
+impl<'a, S, const N: usize> Waiter<'a, S, N> {
+	fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Permit<'a, S, N>> {
+		let mut state = self.executor.state.borrow_mut();
+		let level = self.priority as usize;
+		let preempted = state.waiting[level + 1..].iter().any(|count| *count > 0);
+		if !preempted {
+			if let Some(flash) = state.flash.take() {
+				state.waiting[level] -= 1;
+				if let Some(slot) = self.slot.take() {
+					state.wakers[slot] = None;
+				}
+				self.queued = false;
+				return Poll::Ready(Permit {
+					executor: self.executor,
+					flash: Some(flash),
+				});
+			}
+		}
+		match self
+			.slot
+			.or_else(|| state.wakers.iter().position(Option::is_none))
+		{
+			Some(slot) => {
+				state.wakers[slot] = Some(cx.waker().clone());
+				self.slot = Some(slot);
+			}
+			None => cx.waker().wake_by_ref(),
+		}
+		Poll::Pending
+	}
+}
+
+impl<S, const N: usize> Drop for Waiter<'_, S, N> {
+	fn drop(&mut self) {
+		if !self.queued {
+			return;
+		}
+		// A cancelled operation may have been holding back operations of a lower priority.
+		let mut state = self.executor.state.borrow_mut();
+		state.waiting[self.priority as usize] -= 1;
+		if let Some(slot) = self.slot {
+			state.wakers[slot] = None;
+		}
+		state.wake_all();
+	}
+}
+
+/// Exclusive access to the flash, given back when dropped.
+struct Permit<'a, S, const N: usize> {
+	executor: &'a FlashExecutor<S, N>,
+	flash: Option<S>,
+}
+
+impl<S, const N: usize> Permit<'_, S, N> {
+	fn flash(&mut self) -> &mut S {
+		self.flash.as_mut().unwrap()
+	}
+}
+
+impl<S, const N: usize> Drop for Permit<'_, S, N> {
+	fn drop(&mut self) {
+		let mut state = self.executor.state.borrow_mut();
+		state.flash = self.flash.take();
+		state.wake_all();
+	}
+}
+
+/// Access to a flash shared through a [`FlashExecutor`], with the priority of its operations.
+pub struct Handle<'a, S, const N: usize> {
+	executor: &'a FlashExecutor<S, N>,
+	priority: Priority,
+}
+
+impl<S, const N: usize> Handle<'_, S, N> {
+	/// The priority of the operations of this handle.
+	pub fn priority(&self) -> Priority {
+		self.priority
+	}
+}
+
+impl<S: ErrorType, const N: usize> ErrorType for Handle<'_, S, N> {
+	type Error = S::Error;
+}
+
+impl<S: ReadNorFlash, const N: usize> ReadNorFlash for Handle<'_, S, N> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), S::Error> {
+		let mut permit = self.executor.acquire(self.priority).await;
+		permit.flash().read(offset, bytes).await
+	}
+
+	fn capacity(&self) -> usize {
+		self.executor.capacity
+	}
+}
+
+impl<S: NorFlash, const N: usize> NorFlash for Handle<'_, S, N> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), S::Error> {
+		if check_erase(self, from, to).is_err() {
+			// Let the flash report the invalid range with its own error.
+			let mut permit = self.executor.acquire(self.priority).await;
+			return permit.flash().erase(from, to).await;
+		}
+		let mut page = from;
+		while page < to {
+			let mut permit = self.executor.acquire(self.priority).await;
+			let end = page + S::ERASE_SIZE as u32;
+			permit.flash().erase(page, end).await?;
+			page = end;
+		}
+		Ok(())
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), S::Error> {
+		let mut permit = self.executor.acquire(self.priority).await;
+		permit.flash().write(offset, bytes).await
+	}
+
+	async fn flush(&mut self) -> Result<(), S::Error> {
+		let mut permit = self.executor.acquire(self.priority).await;
+		permit.flash().flush().await
+	}
+}
+
+impl<S: MultiwriteNorFlash, const N: usize> MultiwriteNorFlash for Handle<'_, S, N> {}
@@ -0,0 +1,85 @@
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+
+/// Future calling `f` whenever polled, returned by [`poll_fn`].
+pub(crate) struct PollFn<F> {
+	f: F,
+}
+
+/// Create a future polling the closure `f`.
+///
+/// This stands for `core::future::poll_fn`, which is newer than the supported Rust versions.
+pub(crate) fn poll_fn<T, F: FnMut(&mut Context<'_>) -> Poll<T>>(f: F) -> PollFn<F> {
+	PollFn { f }
+}
+
+impl<T, F: FnMut(&mut Context<'_>) -> Poll<T> + Unpin> Future for PollFn<F> {
+	type Output = T;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+		(self.get_mut().f)(cx)
+	}
+}
+
+/// Poll both futures concurrently until both are complete.
+pub(crate) async fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+	let (mut a, mut b) = (pin!(a), pin!(b));
+	let (mut output_a, mut output_b) = (None, None);
+	poll_fn(|cx| {
+		if output_a.is_none() {
+			if let Poll::Ready(output) = a.as_mut().poll(cx) {
+				output_a = Some(output);
+			}
+		}
+		if output_b.is_none() {
+			if let Poll::Ready(output) = b.as_mut().poll(cx) {
+				output_b = Some(output);
+			}
+		}
+		match (output_a.take(), output_b.take()) {
+			(Some(a), Some(b)) => Poll::Ready((a, b)),
+			(a, b) => {
+				output_a = a;
+				output_b = b;
+				Poll::Pending
+			}
+		}
+	})
+	.await
+}
+
+/// Return `Pending` once, letting the executor run other tasks.
+pub(crate) async fn yield_now() {
+	let mut yielded = false;
+	poll_fn(|cx| {
+		if yielded {
+			return Poll::Ready(());
+		}
+		yielded = true;
+		cx.waker().wake_by_ref();
+		Poll::Pending
+	})
+	.await
+}
+
+/// Output of [`select`], telling which future completed first.
+pub(crate) enum Either<A, B> {
+	First(A),
+	Second(B),
+}
+
+/// Poll both futures concurrently until one of them is complete, dropping the other one.
+pub(crate) async fn select<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+	let (mut a, mut b) = (pin!(a), pin!(b));
+	poll_fn(|cx| {
+		if let Poll::Ready(output) = a.as_mut().poll(cx) {
+			return Poll::Ready(Either::First(output));
+		}
+		if let Poll::Ready(output) = b.as_mut().poll(cx) {
+			return Poll::Ready(Either::Second(output));
+		}
+		Poll::Pending
+	})
+	.await
+}
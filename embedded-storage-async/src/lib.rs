@@ -5,6 +5,28 @@
 
 #![no_std]
 #![feature(async_fn_in_trait)]
+#![feature(pin_macro)]
 #![allow(incomplete_features)]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+mod join;
+
+/// Wrappers changing the behaviour of async storage peripherals
+pub mod adapters;
+/// Scheduling of the operations of several tasks sharing an async flash
+pub mod executor;
+/// Async implementations and wrappers of the simulated flash devices
+pub mod mock;
 pub mod nor_flash;
+/// Long running flash operations reporting their progress
+pub mod progress;
+/// Async implementation of the serial NOR flash reference driver
+#[cfg(feature = "spinor")]
+pub mod spinor;
+/// Conformance checks for async `NorFlash` implementations
+#[cfg(feature = "std")]
+pub mod test_suite;
+/// Completion signalling from interrupt handlers to async flash operations
+pub mod waker;
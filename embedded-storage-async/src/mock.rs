@@ -0,0 +1,192 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+#[cfg(feature = "alloc")]
+pub use embedded_storage::mock::MockFlashBox;
+pub use embedded_storage::mock::{MockError, MockFlash};
+use embedded_storage::nor_flash::{self as blocking, ErrorType, ProgramPolarity};
+
+use crate::nor_flash::{MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> ReadNorFlash for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		blocking::ReadNorFlash::read(self, offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		CAPACITY
+	}
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> NorFlash for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		blocking::NorFlash::erase(self, from, to)
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		blocking::NorFlash::write(self, offset, bytes)
+	}
+
+	async fn flush(&mut self) -> Result<(), Self::Error> {
+		blocking::NorFlash::flush(self)
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		blocking::ReadNorFlash::read(self, offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		blocking::ReadNorFlash::capacity(self)
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		blocking::NorFlash::erase(self, from, to)
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		blocking::NorFlash::write(self, offset, bytes)
+	}
+
+	async fn flush(&mut self) -> Result<(), Self::Error> {
+		blocking::NorFlash::flush(self)
+	}
+}
+
+/// Future returning `Pending` a given number of times before completing.
+struct Yield {
+	remaining: usize,
+}
+
+impl Future for Yield {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		if self.remaining == 0 {
+			return Poll::Ready(());
+		}
+		self.remaining -= 1;
+		cx.waker().wake_by_ref();
+		Poll::Pending
+	}
+}
+
+/// Number of times each kind of operation yields to the executor before completing.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Latency {
+	/// Yields of every read.
+	pub read: usize,
+	/// Yields of every write.
+	pub write: usize,
+	/// Yields of every erase.
+	pub erase: usize,
+}
+
+/// Wrapper simulating the latency of a flash by yielding to the executor before every operation.
+///
+/// This allows testing cancellation, timeouts and interleaving of async storage tasks on top of a
+/// [`MockFlash`], which otherwise completes every operation on the first poll.
+pub struct Slow<S> {
+	flash: S,
+	latency: Latency,
+}
+
+impl<S> Slow<S> {
+	/// Wrap `flash`, yielding as configured by `latency` on every operation.
+	pub fn new(flash: S, latency: Latency) -> Self {
+		Self { flash, latency }
+	}
+
+	/// Change the latency of subsequent operations.
+	pub fn set_latency(&mut self, latency: Latency) {
+		self.latency = latency;
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+}
+
+impl<S: ErrorType> ErrorType for Slow<S> {
+	type Error = S::Error;
+}
+
+impl<S: ReadNorFlash> ReadNorFlash for Slow<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		Yield {
+			remaining: self.latency.read,
+		}
+		.await;
+		self.flash.read(offset, bytes).await
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash> NorFlash for Slow<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		Yield {
+			remaining: self.latency.erase,
+		}
+		.await;
+		self.flash.erase(from, to).await
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		Yield {
+			remaining: self.latency.write,
+		}
+		.await;
+		self.flash.write(offset, bytes).await
+	}
+
+	async fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush().await
+	}
+}
+
+impl<S: MultiwriteNorFlash> MultiwriteNorFlash for Slow<S> {}
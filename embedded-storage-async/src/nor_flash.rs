@@ -1,4 +1,10 @@
-use embedded_storage::nor_flash::ErrorType;
+use embedded_storage::nor_flash::{check_range, check_slice};
+
+use crate::join::yield_now;
+pub use embedded_storage::nor_flash::{
+	program_byte, CheckError, ErrorType, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+	ProgramPolarity,
+};
 
 /// Read only NOR flash trait.
 pub trait ReadNorFlash: ErrorType {
@@ -18,6 +24,15 @@ pub trait ReadNorFlash: ErrorType {
 	fn capacity(&self) -> usize;
 }
 
+/// Return whether a read operation is within bounds.
+pub fn check_read<T: ReadNorFlash>(
+	flash: &T,
+	offset: u32,
+	length: usize,
+) -> Result<(), CheckError> {
+	check_slice(flash.capacity(), T::READ_SIZE, offset, length)
+}
+
 /// NOR flash trait.
 pub trait NorFlash: ReadNorFlash {
 	/// The minumum number of bytes the storage peripheral can write
@@ -26,8 +41,39 @@ pub trait NorFlash: ReadNorFlash {
 	/// The minumum number of bytes the storage peripheral can erase
 	const ERASE_SIZE: usize;
 
+	/// The most common value of the bytes of an erased range
+	///
+	/// Every byte of an erased range holds this value unless
+	/// [`ERASE_PATTERN`](Self::ERASE_PATTERN) is overridden, so code checking or producing erased
+	/// contents should go through [`erased_byte`], [`is_erased`] or [`fill_erased`] instead.
+	const ERASE_BYTE: u8 = 0xff;
+
+	/// The contents of an erased range, repeated from the start of every page
+	///
+	/// This is for memories erasing words to a pattern rather than every byte to the same value,
+	/// e.g. with ECC bits. Byte `address` of an erased range is
+	/// `ERASE_PATTERN[address % ERASE_PATTERN.len()]`, see [`erased_byte`], and the length of the
+	/// pattern divides `ERASE_SIZE`. It defaults to [`ERASE_BYTE`](Self::ERASE_BYTE) alone, which
+	/// should then be the most common byte of the pattern.
+	const ERASE_PATTERN: &'static [u8] = &[Self::ERASE_BYTE];
+
+	/// How writing to a word already written combines the old and the new data
+	///
+	/// This only matters for [`MultiwriteNorFlash`], see
+	/// [`embedded_storage::nor_flash::NorFlash::PROGRAM_POLARITY`] and [`programmed_byte`].
+	const PROGRAM_POLARITY: ProgramPolarity = ProgramPolarity::FromErased;
+
+	/// The maximum number of bytes the storage peripheral can program in one operation
+	///
+	/// This is e.g. the 256 byte page program buffer of a SPI NOR flash: writes crossing a multiple
+	/// of this size have to be split by the driver, or may be rejected. It is a multiple of
+	/// `WRITE_SIZE`, and defaults to no limit. Generic code can use [`write_chunked`] to only issue
+	/// writes handled in one operation.
+	const MAX_WRITE_CHUNK: usize = usize::MAX;
+
 	/// Erase the given storage range, clearing all data within `[from..to]`.
-	/// The given range will contain all 1s afterwards.
+	/// Every byte of the given range will be [`ERASE_BYTE`](Self::ERASE_BYTE) afterwards, or
+	/// follow the [`ERASE_PATTERN`](Self::ERASE_PATTERN).
 	///
 	/// If power is lost during erase, contents of the page are undefined.
 	///
@@ -47,17 +93,119 @@ pub trait NorFlash: ReadNorFlash {
 	/// Returns an error if the arguments are not aligned or out of bounds. The implementation
 	/// can use the [`check_write`] helper function.
 	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+
+	/// Make the previous writes and erases durable.
+	///
+	/// See [`embedded_storage::nor_flash::NorFlash::flush`]. The default implementation does
+	/// nothing.
+	async fn flush(&mut self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+/// Return whether an erase operation is aligned and within bounds.
+pub fn check_erase<T: NorFlash>(flash: &T, from: u32, to: u32) -> Result<(), CheckError> {
+	check_range(flash.capacity(), T::ERASE_SIZE, from, to)
+}
+
+/// Return whether a write operation is aligned and within bounds.
+pub fn check_write<T: NorFlash>(flash: &T, offset: u32, length: usize) -> Result<(), CheckError> {
+	check_slice(flash.capacity(), T::WRITE_SIZE, offset, length)
+}
+
+/// Write `bytes` at `offset`, split so that no write crosses a multiple of `MAX_WRITE_CHUNK`.
+pub async fn write_chunked<T: NorFlash>(
+	flash: &mut T,
+	offset: u32,
+	bytes: &[u8],
+) -> Result<(), T::Error> {
+	let mut done = 0;
+	while done < bytes.len() {
+		let address = offset as usize + done;
+		let part = (T::MAX_WRITE_CHUNK - address % T::MAX_WRITE_CHUNK).min(bytes.len() - done);
+		flash
+			.write(address as u32, &bytes[done..done + part])
+			.await?;
+		done += part;
+	}
+	Ok(())
 }
 
-impl<T: ErrorType> ErrorType for &mut T {
-	type Error = T::Error;
+/// The value of the erased byte at `address` of a flash of type `T`, following its
+/// [`ERASE_PATTERN`](NorFlash::ERASE_PATTERN).
+pub fn erased_byte<T: NorFlash + ?Sized>(address: u32) -> u8 {
+	let pattern = T::ERASE_PATTERN;
+	address
+		.checked_rem(pattern.len() as u32)
+		.and_then(|index| pattern.get(index as usize).copied())
+		.unwrap_or(T::ERASE_BYTE)
+}
+
+/// Return whether `bytes`, read at `offset` of a flash of type `T`, are erased.
+pub fn is_erased<T: NorFlash + ?Sized>(offset: u32, bytes: &[u8]) -> bool {
+	(offset..)
+		.zip(bytes)
+		.all(|(address, byte)| *byte == erased_byte::<T>(address))
+}
+
+/// Fill `bytes`, to be written at `offset` of a flash of type `T`, with its erased contents.
+///
+/// Such bytes are left as erased when written, e.g. to pad writes up to `WRITE_SIZE`.
+pub fn fill_erased<T: NorFlash + ?Sized>(offset: u32, bytes: &mut [u8]) {
+	for (address, byte) in (offset..).zip(bytes) {
+		*byte = erased_byte::<T>(address);
+	}
+}
+
+/// The value of the byte at `address` of a flash of type `T` after writing `new` over `old`,
+/// following its [`PROGRAM_POLARITY`](NorFlash::PROGRAM_POLARITY).
+///
+/// The byte can be written as is if the result is `new`.
+pub fn programmed_byte<T: NorFlash + ?Sized>(address: u32, old: u8, new: u8) -> u8 {
+	T::PROGRAM_POLARITY.program(erased_byte::<T>(address), old, new)
+}
+
+/// Marker trait for NorFlash relaxing the restrictions on `write`.
+///
+/// Writes to the same word twice are now allowed. The result is the logical AND of the
+/// previous data and the written data. That is, it is only possible to change 1 bits to 0 bits.
+/// This holds for flash erasing to `0xff`, in general bits can only be changed from their erased
+/// value, or as stated by [`PROGRAM_POLARITY`](NorFlash::PROGRAM_POLARITY), see
+/// [`programmed_byte`].
+///
+/// If power is lost during write:
+/// - Bits that were 1 on flash and are written to 1 are guaranteed to stay as 1
+/// - Bits that were 1 on flash and are written to 0 are undefined
+/// - Bits that were 0 on flash are guaranteed to stay as 0
+/// - Rest of the bits in the page are guaranteed to be unchanged
+pub trait MultiwriteNorFlash: NorFlash {}
+
+/// Readiness of a flash running program and erase operations in the background.
+///
+/// This is the async counterpart of the blocking
+/// [`FlashStatus`](embedded_storage::nor_flash::FlashStatus), letting adapters and executors poll
+/// the device without blocking other tasks.
+pub trait FlashStatus: ErrorType {
+	/// Whether the flash is still running an operation.
+	async fn is_busy(&mut self) -> Result<bool, Self::Error>;
+
+	/// Wait until the flash is ready to accept commands.
+	///
+	/// The default implementation polls [`is_busy`](Self::is_busy), yielding to the executor
+	/// between polls.
+	async fn wait_ready(&mut self) -> Result<(), Self::Error> {
+		while self.is_busy().await? {
+			yield_now().await;
+		}
+		Ok(())
+	}
 }
 
 impl<T: ReadNorFlash> ReadNorFlash for &mut T {
 	const READ_SIZE: usize = T::READ_SIZE;
 
-	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
-		T::read(self, offset, bytes)
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), T::Error> {
+		T::read(self, offset, bytes).await
 	}
 
 	fn capacity(&self) -> usize {
@@ -68,12 +216,32 @@ impl<T: ReadNorFlash> ReadNorFlash for &mut T {
 impl<T: NorFlash> NorFlash for &mut T {
 	const WRITE_SIZE: usize = T::WRITE_SIZE;
 	const ERASE_SIZE: usize = T::ERASE_SIZE;
+	const ERASE_BYTE: u8 = T::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = T::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = T::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = T::MAX_WRITE_CHUNK;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), T::Error> {
+		T::erase(self, from, to).await
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), T::Error> {
+		T::write(self, offset, bytes).await
+	}
+
+	async fn flush(&mut self) -> Result<(), T::Error> {
+		T::flush(self).await
+	}
+}
+
+impl<T: MultiwriteNorFlash> MultiwriteNorFlash for &mut T {}
 
-	async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
-		T::erase(self, from, to)
+impl<T: FlashStatus> FlashStatus for &mut T {
+	async fn is_busy(&mut self) -> Result<bool, T::Error> {
+		T::is_busy(self).await
 	}
 
-	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
-		T::write(self, offset, bytes)
+	async fn wait_ready(&mut self) -> Result<(), T::Error> {
+		T::wait_ready(self).await
 	}
 }
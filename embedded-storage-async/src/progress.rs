@@ -0,0 +1,308 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use core::task::{Context, Poll};
+
+use crate::nor_flash::{check_erase, fill_erased, write_chunked, NorFlash};
+use crate::waker::OperationWaker;
+
+/// Size of the buffer used to move data.
+const BUFFER: usize = 256;
+
+/// Stage of a long running operation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Phase {
+	/// Erasing pages.
+	Erase,
+	/// Copying data.
+	Copy,
+	/// Swapping pages.
+	Swap,
+}
+
+impl Phase {
+	fn from_u8(value: u8) -> Self {
+		match value {
+			0 => Self::Erase,
+			1 => Self::Copy,
+			_ => Self::Swap,
+		}
+	}
+}
+
+/// Progress of a long running operation, in bytes of its current phase.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Event {
+	/// Stage of the operation.
+	pub phase: Phase,
+	/// Number of bytes processed.
+	pub done: u32,
+	/// Number of bytes to process.
+	pub total: u32,
+}
+
+/// Progress reported by the operations of this module, e.g. to render a progress bar.
+///
+/// The operation updates it, usually after every page, and the task showing the progress either
+/// polls [`event`](Self::event) or awaits [`next`](Self::next). It is `Sync`, so it can be a
+/// `static` shared between tasks.
+pub struct Progress {
+	phase: AtomicU8,
+	done: AtomicU32,
+	total: AtomicU32,
+	changed: AtomicBool,
+	waker: OperationWaker,
+}
+
+impl Progress {
+	/// Create a progress with nothing done.
+	pub const fn new() -> Self {
+		Self {
+			phase: AtomicU8::new(0),
+			done: AtomicU32::new(0),
+			total: AtomicU32::new(0),
+			changed: AtomicBool::new(false),
+			waker: OperationWaker::new(),
+		}
+	}
+
+	/// The latest progress.
+	pub fn current(&self) -> Event {
+		Event {
+			phase: Phase::from_u8(self.phase.load(Ordering::Acquire)),
+			done: self.done.load(Ordering::Acquire),
+			total: self.total.load(Ordering::Acquire),
+		}
+	}
+
+	/// The latest progress, if it changed since the previous call.
+	pub fn event(&self) -> Option<Event> {
+		if self.changed.swap(false, Ordering::AcqRel) {
+			Some(self.current())
+		} else {
+			None
+		}
+	}
+
+	/// Wait for the progress to change.
+	pub async fn next(&self) -> Event {
+		loop {
+			self.waker.start();
+			if let Some(event) = self.event() {
+				return event;
+			}
+			self.waker.wait().await;
+		}
+	}
+
+	fn update(&self, phase: Phase, done: u32, total: u32) {
+		self.phase.store(phase as u8, Ordering::Release);
+		self.total.store(total, Ordering::Release);
+		self.done.store(done, Ordering::Release);
+		self.changed.store(true, Ordering::Release);
+		self.waker.signal();
+	}
+}
+
+impl Default for Progress {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl core::fmt::Debug for Progress {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_tuple("Progress").field(&self.current()).finish()
+	}
+}
+
+/// Long running operation, reporting its progress while it is awaited.
+///
+/// The operation is a future to await or to poll along other futures, e.g. with a `select` on the
+/// user interface, which checks [`event`](Self::event) to update the display.
+pub struct Operation<'a, F> {
+	future: F,
+	progress: &'a Progress,
+}
+
+impl<'a, F> Operation<'a, F> {
+	/// The latest progress, if it changed since the previous call.
+	pub fn event(&self) -> Option<Event> {
+		self.progress.event()
+	}
+
+	/// The latest progress.
+	pub fn current(&self) -> Event {
+		self.progress.current()
+	}
+}
+
+impl<F: Future> Future for Operation<'_, F> {
+	type Output = F::Output;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+		// SAFETY: the future is never moved out of the operation, which is pinned.
+		unsafe { self.map_unchecked_mut(|operation| &mut operation.future) }.poll(cx)
+	}
+}
+
+/// Erase `from..to` of `flash` one page at a time, reporting the progress.
+pub fn erase_region<'a, S: NorFlash>(
+	flash: &'a mut S,
+	from: u32,
+	to: u32,
+	progress: &'a Progress,
+) -> Operation<'a, impl Future<Output = Result<(), S::Error>> + 'a> {
+	let future = async move {
+		if check_erase(flash, from, to).is_err() {
+			// Let the flash report the error in its own terms.
+			return flash.erase(from, to).await;
+		}
+		erase_pages(flash, from, to, progress).await
+	};
+	Operation { future, progress }
+}
+
+/// Copy `range` of `flash` to the page aligned `offset`, reporting the progress.
+///
+/// The pages of the copy are erased first, then the data is programmed with its end padded with
+/// erased bytes up to `WRITE_SIZE`. `range` must start at a multiple of `READ_SIZE`.
+///
+/// **NOTE** This will panic if the copy overlaps `range`, or if `READ_SIZE` or `WRITE_SIZE` is
+/// over 256 bytes.
+pub fn copy<'a, S: NorFlash>(
+	flash: &'a mut S,
+	range: core::ops::Range<u32>,
+	offset: u32,
+	progress: &'a Progress,
+) -> Operation<'a, impl Future<Output = Result<(), S::Error>> + 'a> {
+	check_buffer::<S>();
+	let length = range.end.saturating_sub(range.start);
+	let page = S::ERASE_SIZE as u32;
+	let erased = (length + page - 1) / page * page;
+	assert!(
+		disjoint(range.start, length, offset, erased),
+		"Copy overlaps the source"
+	);
+	let future = async move {
+		erase_pages(flash, offset, offset + erased, progress).await?;
+		let mut buffer = [0; BUFFER];
+		let mut done = 0;
+		progress.update(Phase::Copy, 0, length);
+		while done < length {
+			let part = (BUFFER as u32).min(length - done);
+			move_data(flash, range.start + done, offset + done, part, &mut buffer).await?;
+			done += part;
+			progress.update(Phase::Copy, done, length);
+		}
+		Ok(())
+	};
+	Operation { future, progress }
+}
+
+/// Exchange the `length` bytes at `a` and `b` of `flash`, one page at a time through the page at
+/// `scratch`, reporting the progress.
+///
+/// Every page of both regions is erased and programmed once, and the scratch page as many times as
+/// there are pages to swap. A power loss in the middle leaves the regions partly swapped, with the
+/// page being swapped in the scratch page.
+///
+/// **NOTE** This will panic if the regions or the scratch page are not aligned to `ERASE_SIZE` or
+/// overlap, or if `READ_SIZE` or `WRITE_SIZE` is over 256 bytes.
+pub fn swap<'a, S: NorFlash>(
+	flash: &'a mut S,
+	a: u32,
+	b: u32,
+	length: u32,
+	scratch: u32,
+	progress: &'a Progress,
+) -> Operation<'a, impl Future<Output = Result<(), S::Error>> + 'a> {
+	check_buffer::<S>();
+	let page = S::ERASE_SIZE as u32;
+	assert!(
+		[a, b, length, scratch]
+			.iter()
+			.all(|value| value % page == 0),
+		"Regions not aligned to ERASE_SIZE"
+	);
+	assert!(
+		disjoint(a, length, b, length)
+			&& disjoint(a, length, scratch, page)
+			&& disjoint(b, length, scratch, page),
+		"Regions overlap"
+	);
+	let future = async move {
+		let mut buffer = [0; BUFFER];
+		progress.update(Phase::Swap, 0, length);
+		let mut done = 0;
+		while done < length {
+			let (x, y) = (a + done, b + done);
+			flash.erase(scratch, scratch + page).await?;
+			move_data(flash, x, scratch, page, &mut buffer).await?;
+			flash.erase(x, x + page).await?;
+			move_data(flash, y, x, page, &mut buffer).await?;
+			flash.erase(y, y + page).await?;
+			move_data(flash, scratch, y, page, &mut buffer).await?;
+			done += page;
+			progress.update(Phase::Swap, done, length);
+		}
+		Ok(())
+	};
+	Operation { future, progress }
+}
+
+/// Whether the `x_len` bytes at `x` and the `y_len` bytes at `y` are disjoint.
+fn disjoint(x: u32, x_len: u32, y: u32, y_len: u32) -> bool {
+	x.saturating_add(x_len) <= y || y.saturating_add(y_len) <= x
+}
+
+fn check_buffer<S: NorFlash>() {
+	assert!(
+		S::READ_SIZE <= BUFFER && S::WRITE_SIZE <= BUFFER,
+		"READ_SIZE or WRITE_SIZE too large"
+	);
+}
+
+async fn erase_pages<S: NorFlash>(
+	flash: &mut S,
+	from: u32,
+	to: u32,
+	progress: &Progress,
+) -> Result<(), S::Error> {
+	let total = to - from;
+	progress.update(Phase::Erase, 0, total);
+	let mut address = from;
+	while address < to {
+		let end = address + S::ERASE_SIZE as u32;
+		flash.erase(address, end).await?;
+		address = end;
+		progress.update(Phase::Erase, address - from, total);
+	}
+	Ok(())
+}
+
+/// Copy `length` bytes at `from` to `to`, through `buffer`, in chunks of its size.
+async fn move_data<S: NorFlash>(
+	flash: &mut S,
+	mut from: u32,
+	mut to: u32,
+	length: u32,
+	buffer: &mut [u8; BUFFER],
+) -> Result<(), S::Error> {
+	let end = from + length;
+	while from < end {
+		let part = (BUFFER as u32).min(end - from) as usize;
+		let read = align(part, S::READ_SIZE);
+		flash.read(from, &mut buffer[..read]).await?;
+		let write = align(part, S::WRITE_SIZE);
+		fill_erased::<S>(to + part as u32, &mut buffer[part..write]);
+		write_chunked(flash, to, &buffer[..write]).await?;
+		from += part as u32;
+		to += part as u32;
+	}
+	Ok(())
+}
+
+fn align(length: usize, size: usize) -> usize {
+	(length + size - 1) / size * size
+}
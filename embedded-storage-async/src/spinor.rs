@@ -0,0 +1,55 @@
+use embedded_storage::nor_flash as blocking;
+pub use embedded_storage::spinor::{SpiNor, SpiNorError};
+
+use crate::nor_flash::{FlashStatus, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+// The driver runs on a blocking SPI device, so these operations complete on the first poll. A
+// driver over an async SPI bus needs `embedded-hal-async`, which does not build on the nightly
+// this crate is pinned to.
+
+impl<SPI> ReadNorFlash for SpiNor<SPI>
+where
+	Self: blocking::ReadNorFlash,
+{
+	const READ_SIZE: usize = <Self as blocking::ReadNorFlash>::READ_SIZE;
+
+	async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		blocking::ReadNorFlash::read(self, offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		blocking::ReadNorFlash::capacity(self)
+	}
+}
+
+impl<SPI> NorFlash for SpiNor<SPI>
+where
+	Self: blocking::NorFlash,
+{
+	const WRITE_SIZE: usize = <Self as blocking::NorFlash>::WRITE_SIZE;
+	const ERASE_SIZE: usize = <Self as blocking::NorFlash>::ERASE_SIZE;
+	const ERASE_BYTE: u8 = <Self as blocking::NorFlash>::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = <Self as blocking::NorFlash>::ERASE_PATTERN;
+	const PROGRAM_POLARITY: blocking::ProgramPolarity =
+		<Self as blocking::NorFlash>::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = <Self as blocking::NorFlash>::MAX_WRITE_CHUNK;
+
+	async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		blocking::NorFlash::erase(self, from, to)
+	}
+
+	async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		blocking::NorFlash::write(self, offset, bytes)
+	}
+}
+
+impl<SPI> MultiwriteNorFlash for SpiNor<SPI> where Self: blocking::MultiwriteNorFlash {}
+
+impl<SPI> FlashStatus for SpiNor<SPI>
+where
+	Self: blocking::FlashStatus,
+{
+	async fn is_busy(&mut self) -> Result<bool, Self::Error> {
+		blocking::FlashStatus::is_busy(self)
+	}
+}
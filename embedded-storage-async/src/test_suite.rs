@@ -0,0 +1,298 @@
+use core::future::Future;
+use core::pin::Pin;
+use std::boxed::Box;
+use std::vec;
+
+use embedded_storage::nor_flash::{self as blocking, ErrorType, ProgramPolarity};
+pub use embedded_storage::test_suite::Config;
+
+use crate::nor_flash::{MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+/// A future produced by an operation of an async flash, as handed to the `block_on` function.
+pub type Operation<'a, E> = Pin<&'a mut (dyn Future<Output = Result<(), E>> + 'a)>;
+
+/// Blocking `NorFlash` view of an async flash.
+///
+/// Every operation is boxed and handed to `block_on`, which must poll it to completion and return
+/// its output, e.g. `|op| futures::executor::block_on(op)`.
+pub struct BlockingNorFlash<S, B> {
+	flash: S,
+	block_on: B,
+}
+
+impl<S, B> BlockingNorFlash<S, B>
+where
+	S: ReadNorFlash,
+	B: FnMut(Operation<'_, S::Error>) -> Result<(), S::Error>,
+{
+	/// Wrap `flash`, running its operations to completion with `block_on`.
+	pub fn new(flash: S, block_on: B) -> Self {
+		Self { flash, block_on }
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+}
+
+impl<S: ErrorType, B> ErrorType for BlockingNorFlash<S, B> {
+	type Error = S::Error;
+}
+
+impl<S, B> blocking::ReadNorFlash for BlockingNorFlash<S, B>
+where
+	S: ReadNorFlash,
+	B: FnMut(Operation<'_, S::Error>) -> Result<(), S::Error>,
+{
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		let mut op = Box::pin(self.flash.read(offset, bytes));
+		(self.block_on)(op.as_mut())
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S, B> blocking::NorFlash for BlockingNorFlash<S, B>
+where
+	S: NorFlash,
+	B: FnMut(Operation<'_, S::Error>) -> Result<(), S::Error>,
+{
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		let mut op = Box::pin(self.flash.erase(from, to));
+		(self.block_on)(op.as_mut())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		let mut op = Box::pin(self.flash.write(offset, bytes));
+		(self.block_on)(op.as_mut())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		let mut op = Box::pin(self.flash.flush());
+		(self.block_on)(op.as_mut())
+	}
+}
+
+impl<S, B> blocking::MultiwriteNorFlash for BlockingNorFlash<S, B>
+where
+	S: MultiwriteNorFlash,
+	B: FnMut(Operation<'_, S::Error>) -> Result<(), S::Error>,
+{
+}
+
+/// Run all `NorFlash` conformance checks against the async `flash`.
+///
+/// The checks of [`embedded_storage::test_suite`] are run through [`BlockingNorFlash`], so any
+/// executor can be used to drive the operations.
+///
+/// This is destructive, the whole flash is erased and overwritten with random data.
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_nor_flash<S, B>(flash: &mut S, config: &Config, block_on: B)
+where
+	S: NorFlash,
+	B: FnMut(Operation<'_, S::Error>) -> Result<(), S::Error>,
+{
+	embedded_storage::test_suite::check_nor_flash(
+		&mut BlockingNorFlash::new(flash, block_on),
+		config,
+	);
+}
+
+/// Check that the async `MultiwriteNorFlash` `flash` writes on top of written words AND the
+/// existing contents, see [`embedded_storage::test_suite::check_multiwrite`].
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_multiwrite<S, B>(flash: &mut S, config: &Config, block_on: B)
+where
+	S: MultiwriteNorFlash,
+	B: FnMut(Operation<'_, S::Error>) -> Result<(), S::Error>,
+{
+	embedded_storage::test_suite::check_multiwrite(
+		&mut BlockingNorFlash::new(flash, block_on),
+		config,
+	);
+}
+
+/// A step of the script run by [`check_differential`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Step<'a> {
+	/// Erase `from..to`.
+	Erase(u32, u32),
+	/// Write the bytes at the offset.
+	Write(u32, &'a [u8]),
+	/// Read the given number of bytes at the offset.
+	Read(u32, usize),
+	/// Flush the buffered writes.
+	Flush,
+}
+
+/// Run the same `script` on the blocking flash `expected` and on the async `flash`, checking that
+/// they behave identically, e.g. to keep a blocking driver or adapter and its async counterpart
+/// from drifting apart.
+///
+/// Every step must succeed or fail with the same error kind on both flashes, reads must return
+/// the same bytes, and both flashes must hold bit-identical images at the end. Both flashes
+/// should start with the same contents.
+///
+/// # Panics
+///
+/// Panics if the geometries differ, or with a description of the first difference found.
+pub fn check_differential<T, S, B>(
+	expected: &mut T,
+	flash: &mut S,
+	script: &[Step<'_>],
+	block_on: B,
+) where
+	T: blocking::NorFlash,
+	S: NorFlash,
+	B: FnMut(Operation<'_, S::Error>) -> Result<(), S::Error>,
+{
+	use blocking::{NorFlash as _, NorFlashError, ReadNorFlash as _};
+
+	assert!(
+		(T::READ_SIZE, T::WRITE_SIZE, T::ERASE_SIZE)
+			== (S::READ_SIZE, S::WRITE_SIZE, S::ERASE_SIZE)
+			&& expected.capacity() == flash.capacity(),
+		"Geometries differ"
+	);
+	let mut flash = BlockingNorFlash::new(flash, block_on);
+	for (index, step) in script.iter().enumerate() {
+		let results = match *step {
+			Step::Erase(from, to) => (
+				expected.erase(from, to).map_err(|e| e.kind()),
+				flash.erase(from, to).map_err(|e| e.kind()),
+			),
+			Step::Write(offset, bytes) => (
+				expected.write(offset, bytes).map_err(|e| e.kind()),
+				flash.write(offset, bytes).map_err(|e| e.kind()),
+			),
+			Step::Read(offset, length) => {
+				let mut left = vec![0; length];
+				let mut right = vec![0; length];
+				let results = (
+					expected.read(offset, &mut left).map_err(|e| e.kind()),
+					flash.read(offset, &mut right).map_err(|e| e.kind()),
+				);
+				if results.0.is_ok() && results.1.is_ok() {
+					assert!(
+						left == right,
+						"Step {} {:?}: read {:02x?}, expected {:02x?}",
+						index,
+						step,
+						right,
+						left
+					);
+				}
+				results
+			}
+			Step::Flush => (
+				expected.flush().map_err(|e| e.kind()),
+				flash.flush().map_err(|e| e.kind()),
+			),
+		};
+		assert!(
+			results.0 == results.1,
+			"Step {} {:?}: returned {:?}, expected {:?}",
+			index,
+			step,
+			results.1,
+			results.0
+		);
+	}
+
+	let capacity = expected.capacity();
+	let mut left = vec![0; capacity];
+	let mut right = vec![0; capacity];
+	expected
+		.read(0, &mut left)
+		.unwrap_or_else(|e| panic!("Reading the expected image: {}", e.kind()));
+	flash
+		.read(0, &mut right)
+		.unwrap_or_else(|e| panic!("Reading the image: {}", e.kind()));
+	if let Some(address) = (0..capacity).find(|address| left[*address] != right[*address]) {
+		panic!(
+			"Images differ at {:#x}: {:#04x}, expected {:#04x}",
+			address, right[address], left[address]
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+	use super::*;
+	use crate::mock::{Latency, MockFlash, Slow};
+
+	type Flash = MockFlash<4096, 1, 4, 1024>;
+
+	fn block_on<E>(mut op: Operation<'_, E>) -> Result<(), E> {
+		fn raw() -> RawWaker {
+			fn clone(_: *const ()) -> RawWaker {
+				raw()
+			}
+			fn noop(_: *const ()) {}
+			static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+			RawWaker::new(core::ptr::null(), &VTABLE)
+		}
+		// SAFETY: the vtable functions do nothing.
+		let waker = unsafe { Waker::from_raw(raw()) };
+		let mut cx = Context::from_waker(&waker);
+		loop {
+			if let Poll::Ready(result) = op.as_mut().poll(&mut cx) {
+				return result;
+			}
+		}
+	}
+
+	const DATA: [u8; 64] = [0x5a; 64];
+
+	const SCRIPT: &[Step<'static>] = &[
+		Step::Erase(0, 1024),
+		Step::Write(0, &DATA),
+		Step::Read(0, 64),
+		Step::Read(3, 5),
+		Step::Write(0, &DATA),
+		Step::Write(66, &[0; 4]),
+		Step::Read(4092, 8),
+		Step::Erase(512, 1024),
+		Step::Write(1020, &[0xa5; 4]),
+		Step::Flush,
+		Step::Erase(0, 4096),
+	];
+
+	#[test]
+	fn mock_flash() {
+		let latency = Latency {
+			read: 1,
+			write: 2,
+			erase: 3,
+		};
+		let mut flash = Slow::new(Flash::new(), latency);
+		check_differential(&mut Flash::new(), &mut flash, SCRIPT, block_on);
+	}
+
+	#[test]
+	#[should_panic(expected = "Step 0 ")]
+	fn different_contents() {
+		let mut expected = Flash::from_bytes([0; 4096]);
+		check_differential(&mut expected, &mut Flash::new(), &SCRIPT[2..], block_on);
+	}
+}
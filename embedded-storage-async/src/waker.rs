@@ -0,0 +1,152 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::task::{Poll, Waker};
+
+use crate::join::poll_fn;
+
+/// No registration or wake up in progress.
+const IDLE: u8 = 0;
+/// The waker is being replaced by the task.
+const REGISTERING: u8 = 1;
+/// The waker is being taken by the interrupt handler.
+const WAKING: u8 = 2;
+
+/// Completion signal of a flash operation, set from an interrupt handler.
+///
+/// Drivers of flash controllers raising an interrupt at the end of every operation embed one,
+/// usually in a `static` shared with the interrupt handler. The driver calls
+/// [`start`](Self::start) before issuing an operation to the hardware and awaits
+/// [`wait`](Self::wait), while the interrupt handler calls [`signal`](Self::signal) once the
+/// hardware is done:
+///
+/// ```ignore
+/// static DONE: OperationWaker = OperationWaker::new();
+///
+/// async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+///     DONE.start();
+///     self.regs.start_erase(from, to);
+///     DONE.wait().await;
+///     self.regs.result()
+/// }
+///
+/// fn flash_interrupt() {
+///     DONE.signal();
+/// }
+/// ```
+///
+/// Registering the waker and signalling never block each other, so `signal` can preempt the task
+/// at any point. This relies on atomic compare and swap instructions, which some targets such as
+/// ARMv6-M lack.
+pub struct OperationWaker {
+	state: AtomicU8,
+	waker: UnsafeCell<Option<Waker>>,
+	complete: AtomicBool,
+}
+
+// The waker is only accessed by whoever moved the state out of `IDLE`, see `register` and `take`.
+unsafe impl Sync for OperationWaker {}
+
+impl OperationWaker {
+	/// Create a signal with no operation in progress.
+	pub const fn new() -> Self {
+		Self {
+			state: AtomicU8::new(IDLE),
+			waker: UnsafeCell::new(None),
+			complete: AtomicBool::new(false),
+		}
+	}
+
+	/// Clear the completion of the previous operation, before starting a new one.
+	pub fn start(&self) {
+		self.complete.store(false, Ordering::Release);
+	}
+
+	/// Signal the completion of the operation, waking the task waiting for it.
+	///
+	/// This is meant to be called from the interrupt handler.
+	pub fn signal(&self) {
+		self.complete.store(true, Ordering::Release);
+		if let Some(waker) = self.take() {
+			waker.wake();
+		}
+	}
+
+	/// Whether the operation completed since the last [`start`](Self::start).
+	pub fn is_complete(&self) -> bool {
+		self.complete.load(Ordering::Acquire)
+	}
+
+	/// Wait for the completion of the operation.
+	pub async fn wait(&self) {
+		poll_fn(|cx| {
+			// Register before checking, so that a signal in between wakes the task.
+			self.register(cx.waker());
+			if self.is_complete() {
+				Poll::Ready(())
+			} else {
+				Poll::Pending
+			}
+		})
+		.await
+	}
+
+	/// Set the waker woken by the next [`signal`](Self::signal).
+	pub fn register(&self, waker: &Waker) {
+		match self
+			.state
+			.compare_exchange(IDLE, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+		{
+			Ok(_) => {
+				// SAFETY: the `REGISTERING` state grants exclusive access to the waker.
+				let slot = unsafe { &mut *self.waker.get() };
+				match slot {
+					Some(old) if old.will_wake(waker) => {}
+					_ => *slot = Some(waker.clone()),
+				}
+				if self
+					.state
+					.compare_exchange(REGISTERING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+					.is_err()
+				{
+					// A signal came in while registering and left the waking to us.
+					let waker = slot.take();
+					self.state.swap(IDLE, Ordering::AcqRel);
+					if let Some(waker) = waker {
+						waker.wake();
+					}
+				}
+			}
+			// A signal is taking the previous waker, poll again right away.
+			Err(WAKING) => waker.wake_by_ref(),
+			// Another task is registering concurrently, which a single operation does not do.
+			Err(_) => {}
+		}
+	}
+
+	/// Take the registered waker, unless the task is replacing it.
+	fn take(&self) -> Option<Waker> {
+		match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+			IDLE => {
+				// SAFETY: the `WAKING` state grants exclusive access to the waker.
+				let waker = unsafe { (*self.waker.get()).take() };
+				self.state.fetch_and(!WAKING, Ordering::Release);
+				waker
+			}
+			_ => None,
+		}
+	}
+}
+
+impl Default for OperationWaker {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl core::fmt::Debug for OperationWaker {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("OperationWaker")
+			.field("complete", &self.is_complete())
+			.finish()
+	}
+}
@@ -0,0 +1,27 @@
+mod auto_wake;
+mod byte_read;
+mod chain;
+mod metrics;
+mod mirrored;
+mod offset;
+mod power_gate;
+mod protected;
+mod read_only;
+mod remap;
+mod retry;
+mod sector_erase;
+mod xip_guard;
+
+pub use auto_wake::AutoWake;
+pub use byte_read::{ByteRead, ByteReadError};
+pub use chain::{Chain, ChainError, Concat, ConcatError};
+pub use metrics::{Metric, Metrics, MetricsSink};
+pub use mirrored::{Mirrored, MirroredError};
+pub use offset::{Offset, OffsetError};
+pub use power_gate::{PowerGate, PowerGateError};
+pub use protected::{Protected, ProtectedError};
+pub use read_only::{IntoReadOnly, ReadOnly};
+pub use remap::{AddressMap, Interleave, Remap, RemapError, Xor};
+pub use retry::{is_transient, Retry};
+pub use sector_erase::SectorErase;
+pub use xip_guard::{XipError, XipGuard, XipHooks};
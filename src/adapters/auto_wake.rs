@@ -0,0 +1,106 @@
+use crate::nor_flash::{
+	ChipErase, ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash, PowerControl, ProgramPolarity,
+	ReadNorFlash,
+};
+
+/// NOR flash wrapper waking the flash from its low power mode before every operation.
+///
+/// The wrapper keeps track of the power state, so the flash is only woken up by the first
+/// operation after [`sleep`](PowerControl::sleep). Flashes not reporting their state are assumed
+/// to be asleep when wrapped, which only costs a wake up command if they are not.
+#[derive(Debug)]
+pub struct AutoWake<S> {
+	flash: S,
+	asleep: bool,
+}
+
+impl<S: PowerControl> AutoWake<S> {
+	/// Wrap `flash`.
+	pub fn new(flash: S) -> Self {
+		let asleep = flash.is_asleep().unwrap_or(true);
+		Self { flash, asleep }
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	fn awake(&mut self) -> Result<&mut S, S::Error> {
+		if self.asleep {
+			self.flash.wake()?;
+			self.asleep = false;
+		}
+		Ok(&mut self.flash)
+	}
+}
+
+impl<S: ErrorType> ErrorType for AutoWake<S> {
+	type Error = S::Error;
+}
+
+impl<S: ReadNorFlash + PowerControl> ReadNorFlash for AutoWake<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("AutoWake::read", offset = offset, length = bytes.len());
+		self.awake()?.read(offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash + PowerControl> NorFlash for AutoWake<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("AutoWake::erase", from = from, to = to);
+		self.awake()?.erase(from, to)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("AutoWake::write", offset = offset, length = bytes.len());
+		self.awake()?.write(offset, bytes)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("AutoWake::flush");
+		self.awake()?.flush()
+	}
+
+	fn info(&self) -> FlashInfo {
+		self.flash.info()
+	}
+}
+
+impl<S: MultiwriteNorFlash + PowerControl> MultiwriteNorFlash for AutoWake<S> {}
+
+impl<S: ChipErase + PowerControl> ChipErase for AutoWake<S> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("AutoWake::erase_chip");
+		self.awake()?.erase_chip()
+	}
+}
+
+impl<S: PowerControl> PowerControl for AutoWake<S> {
+	fn sleep(&mut self) -> Result<(), Self::Error> {
+		self.flash.sleep()?;
+		self.asleep = true;
+		Ok(())
+	}
+
+	fn wake(&mut self) -> Result<(), Self::Error> {
+		self.awake().map(|_| ())
+	}
+
+	fn is_asleep(&self) -> Option<bool> {
+		Some(self.asleep)
+	}
+}
@@ -0,0 +1,158 @@
+use crate::nor_flash::{
+	check_erase, check_read, check_write, CheckError, ChipErase, ErrorType, MultiwriteNorFlash,
+	NorFlash, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo, ProgramPolarity, ReadNorFlash,
+};
+
+/// Errors returned by [`ByteRead`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ByteReadError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The arguments are not aligned or out of bounds of the flash.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for ByteReadError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Byte granular reads over a flash with a larger `READ_SIZE`, e.g. words protected by ECC.
+///
+/// Reads are forwarded to the wrapped flash as is when aligned, and the unaligned head and tail
+/// words go through a buffer of `N` bytes. Writes and erases are forwarded unchanged, use the
+/// [`RmwNorFlashStorage`](crate::nor_flash::RmwNorFlashStorage) adapters to also relax the
+/// write granularity.
+pub struct ByteRead<S, const N: usize> {
+	flash: S,
+	buffer: [u8; N],
+}
+
+impl<S: ReadNorFlash, const N: usize> ByteRead<S, N> {
+	/// Provide byte reads over `flash`.
+	///
+	/// **NOTE** This will panic if `N` is smaller than the `READ_SIZE` of the flash.
+	pub fn new(flash: S) -> Self {
+		match Self::try_new(flash) {
+			Ok(flash) => flash,
+			Err(_) => panic!("Buffer smaller than READ_SIZE"),
+		}
+	}
+
+	/// Provide byte reads over `flash`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::OutOfBounds`] if `N` is smaller than the `READ_SIZE` of the flash.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(flash: S) -> Result<Self, CheckError> {
+		if N < S::READ_SIZE {
+			return Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+				offset: 0,
+				length: S::READ_SIZE,
+				capacity: N,
+			}));
+		}
+		Ok(Self {
+			flash,
+			buffer: [0; N],
+		})
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Read through the buffer for the unaligned head and tail words.
+	fn read_words(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ByteReadError<S::Error>> {
+		let size = S::READ_SIZE;
+		let mut done = 0;
+		while done < bytes.len() {
+			let address = offset as usize + done;
+			let skip = address % size;
+			let left = bytes.len() - done;
+			if skip == 0 && left >= size {
+				let part = left - left % size;
+				self.flash
+					.read(address as u32, &mut bytes[done..done + part])
+					.map_err(ByteReadError::Flash)?;
+				done += part;
+			} else {
+				let word = &mut self.buffer[..size];
+				self.flash
+					.read((address - skip) as u32, word)
+					.map_err(ByteReadError::Flash)?;
+				let part = (size - skip).min(left);
+				bytes[done..done + part].copy_from_slice(&word[skip..skip + part]);
+				done += part;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl<S: ErrorType, const N: usize> ErrorType for ByteRead<S, N> {
+	type Error = ByteReadError<S::Error>;
+}
+
+impl<S: ReadNorFlash, const N: usize> ReadNorFlash for ByteRead<S, N> {
+	const READ_SIZE: usize = 1;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("ByteRead::read", offset = offset, length = bytes.len());
+		check_read(self, offset, bytes.len()).map_err(ByteReadError::Invalid)?;
+		let result = self.read_words(offset, bytes);
+		#[cfg(feature = "zeroize")]
+		crate::secret::wipe(&mut self.buffer);
+		result
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, const N: usize> NorFlash for ByteRead<S, N> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("ByteRead::erase", from = from, to = to);
+		check_erase(self, from, to).map_err(ByteReadError::Invalid)?;
+		self.flash.erase(from, to).map_err(ByteReadError::Flash)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("ByteRead::write", offset = offset, length = bytes.len());
+		check_write(self, offset, bytes.len()).map_err(ByteReadError::Invalid)?;
+		self.flash
+			.write(offset, bytes)
+			.map_err(ByteReadError::Flash)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("ByteRead::flush");
+		self.flash.flush().map_err(ByteReadError::Flash)
+	}
+}
+
+impl<S: MultiwriteNorFlash, const N: usize> MultiwriteNorFlash for ByteRead<S, N> {}
+
+impl<S: ChipErase, const N: usize> ChipErase for ByteRead<S, N> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("ByteRead::erase_chip");
+		self.flash.erase_chip().map_err(ByteReadError::Flash)
+	}
+}
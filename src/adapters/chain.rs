@@ -0,0 +1,341 @@
+use crate::nor_flash::{
+	check_compatible, check_erase, check_read, check_write, write_chunked, CheckError, ChipErase,
+	ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ProgramPolarity,
+	ReadNorFlash,
+};
+
+const fn max(a: usize, b: usize) -> usize {
+	if a > b {
+		a
+	} else {
+		b
+	}
+}
+
+const fn min(a: usize, b: usize) -> usize {
+	if a < b {
+		a
+	} else {
+		b
+	}
+}
+
+/// Errors returned by [`Chain`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChainError<A, B> {
+	/// The first flash returned an error.
+	First(A),
+	/// The second flash returned an error.
+	Second(B),
+	/// The arguments are not aligned or out of bounds of the combined flash.
+	Invalid(CheckError),
+}
+
+impl<A: NorFlashError, B: NorFlashError> NorFlashError for ChainError<A, B> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::First(e) => e.kind(),
+			Self::Second(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Split `offset..offset + length` at `boundary`, returning the lengths below and above it.
+fn split(offset: u32, length: usize, boundary: usize) -> (usize, usize) {
+	let below = boundary.saturating_sub(offset as usize).min(length);
+	(below, length - below)
+}
+
+/// Concatenation of two flash devices into one address space.
+///
+/// The second device starts right after the end of the first one. Operations straddling the
+/// boundary are split between both devices. The read, write and erase sizes are the largest ones
+/// of both devices, which are expected to be powers of two, and writes are split by the smallest
+/// `MAX_WRITE_CHUNK`. Both devices must have the same erased contents and program polarity.
+pub struct Chain<A, B> {
+	first: A,
+	second: B,
+}
+
+impl<A, B> Chain<A, B>
+where
+	A: NorFlash,
+	B: NorFlash,
+{
+	/// Concatenate `first` and `second`.
+	///
+	/// **NOTE** This will panic if the capacity of `first` is not a multiple of the erase size of
+	/// the chain, or if the devices have different erased contents or program polarities.
+	pub fn new(first: A, second: B) -> Self {
+		match Self::try_new(first, second) {
+			Ok(chain) => chain,
+			Err(CheckError::Incompatible) => {
+				panic!("Chained flashes have different erased contents or polarities")
+			}
+			Err(_) => panic!("Capacity of the first flash is not a multiple of the erase size"),
+		}
+	}
+
+	/// Concatenate `first` and `second`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if the capacity of `first` is not a multiple of the erase
+	/// size of the chain, or [`CheckError::Incompatible`] if the devices have different erased
+	/// contents or program polarities.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(first: A, second: B) -> Result<Self, CheckError> {
+		check_compatible::<A, B>()?;
+		if first.capacity() % <Self as NorFlash>::ERASE_SIZE != 0 {
+			return Err(CheckError::NotAligned);
+		}
+		Ok(Self { first, second })
+	}
+}
+
+impl<A, B> Chain<A, B> {
+	/// Release both devices.
+	pub fn into_inner(self) -> (A, B) {
+		(self.first, self.second)
+	}
+}
+
+impl<A: ErrorType, B: ErrorType> ErrorType for Chain<A, B> {
+	type Error = ChainError<A::Error, B::Error>;
+}
+
+impl<A: ReadNorFlash, B: ReadNorFlash> ReadNorFlash for Chain<A, B> {
+	const READ_SIZE: usize = max(A::READ_SIZE, B::READ_SIZE);
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Chain::read", offset = offset, length = bytes.len());
+		check_read(self, offset, bytes.len()).map_err(ChainError::Invalid)?;
+		let boundary = self.first.capacity();
+		let (below, _) = split(offset, bytes.len(), boundary);
+		let (first, second) = bytes.split_at_mut(below);
+		if !first.is_empty() {
+			self.first.read(offset, first).map_err(ChainError::First)?;
+		}
+		if !second.is_empty() {
+			let offset = (offset as usize + below - boundary) as u32;
+			self.second
+				.read(offset, second)
+				.map_err(ChainError::Second)?;
+		}
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.first.capacity() + self.second.capacity()
+	}
+}
+
+impl<A: NorFlash, B: NorFlash> NorFlash for Chain<A, B> {
+	const WRITE_SIZE: usize = max(A::WRITE_SIZE, B::WRITE_SIZE);
+	const ERASE_SIZE: usize = max(A::ERASE_SIZE, B::ERASE_SIZE);
+	const ERASE_BYTE: u8 = A::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = A::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = A::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = min(A::MAX_WRITE_CHUNK, B::MAX_WRITE_CHUNK);
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Chain::erase", from = from, to = to);
+		check_erase(self, from, to).map_err(ChainError::Invalid)?;
+		let boundary = self.first.capacity() as u32;
+		if from < boundary {
+			self.first
+				.erase(from, to.min(boundary))
+				.map_err(ChainError::First)?;
+		}
+		if to > boundary {
+			let from = from.max(boundary) - boundary;
+			self.second
+				.erase(from, to - boundary)
+				.map_err(ChainError::Second)?;
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Chain::write", offset = offset, length = bytes.len());
+		check_write(self, offset, bytes.len()).map_err(ChainError::Invalid)?;
+		let boundary = self.first.capacity();
+		let (below, _) = split(offset, bytes.len(), boundary);
+		let (first, second) = bytes.split_at(below);
+		if !first.is_empty() {
+			write_chunked(&mut self.first, offset, first).map_err(ChainError::First)?;
+		}
+		if !second.is_empty() {
+			let offset = (offset as usize + below - boundary) as u32;
+			write_chunked(&mut self.second, offset, second).map_err(ChainError::Second)?;
+		}
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Chain::flush");
+		self.first.flush().map_err(ChainError::First)?;
+		self.second.flush().map_err(ChainError::Second)
+	}
+}
+
+impl<A: MultiwriteNorFlash, B: MultiwriteNorFlash> MultiwriteNorFlash for Chain<A, B> {}
+
+impl<A: ChipErase, B: ChipErase> ChipErase for Chain<A, B> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("Chain::erase_chip");
+		self.first.erase_chip().map_err(ChainError::First)?;
+		self.second.erase_chip().map_err(ChainError::Second)
+	}
+}
+
+/// Errors returned by [`Concat`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConcatError<E> {
+	/// One of the devices returned an error.
+	Flash(E),
+	/// The arguments are not aligned or out of bounds of the combined flash.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for ConcatError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Concatenation of `N` flash devices of the same type into one address space.
+///
+/// This is the N-way version of [`Chain`], devices are laid out one after the other in order.
+pub struct Concat<S, const N: usize> {
+	devices: [S; N],
+}
+
+impl<S: NorFlash, const N: usize> Concat<S, N> {
+	/// Concatenate `devices`.
+	///
+	/// **NOTE** This will panic if the capacity of a device is not a multiple of its erase size.
+	pub fn new(devices: [S; N]) -> Self {
+		match Self::try_new(devices) {
+			Ok(concat) => concat,
+			Err(_) => panic!("Capacity of a flash is not a multiple of the erase size"),
+		}
+	}
+
+	/// Concatenate `devices`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if the capacity of a device is not a multiple of its
+	/// erase size.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(devices: [S; N]) -> Result<Self, CheckError> {
+		if devices.iter().any(|d| d.capacity() % S::ERASE_SIZE != 0) {
+			return Err(CheckError::NotAligned);
+		}
+		Ok(Self { devices })
+	}
+
+	/// Release the devices.
+	pub fn into_inner(self) -> [S; N] {
+		self.devices
+	}
+
+	/// Call `op` for every part of `offset..offset + length` mapped to a single device, with the
+	/// device, the offset within the device and the range within `offset..offset + length`.
+	fn for_each_part<F>(&mut self, offset: u32, length: usize, mut op: F) -> Result<(), S::Error>
+	where
+		F: FnMut(&mut S, u32, core::ops::Range<usize>) -> Result<(), S::Error>,
+	{
+		let (mut base, mut done) = (0, 0);
+		for device in self.devices.iter_mut() {
+			let capacity = device.capacity();
+			let start = offset as usize + done;
+			if done < length && start < base + capacity {
+				let part = (base + capacity - start).min(length - done);
+				op(device, (start - base) as u32, done..done + part)?;
+				done += part;
+			}
+			base += capacity;
+		}
+		Ok(())
+	}
+}
+
+impl<S: ErrorType, const N: usize> ErrorType for Concat<S, N> {
+	type Error = ConcatError<S::Error>;
+}
+
+impl<S: NorFlash, const N: usize> ReadNorFlash for Concat<S, N> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Concat::read", offset = offset, length = bytes.len());
+		check_read(self, offset, bytes.len()).map_err(ConcatError::Invalid)?;
+		self.for_each_part(offset, bytes.len(), |device, offset, range| {
+			device.read(offset, &mut bytes[range])
+		})
+		.map_err(ConcatError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.devices.iter().map(|d| d.capacity()).sum()
+	}
+}
+
+impl<S: NorFlash, const N: usize> NorFlash for Concat<S, N> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Concat::erase", from = from, to = to);
+		check_erase(self, from, to).map_err(ConcatError::Invalid)?;
+		self.for_each_part(from, (to - from) as usize, |device, from, range| {
+			device.erase(from, from + range.len() as u32)
+		})
+		.map_err(ConcatError::Flash)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Concat::write", offset = offset, length = bytes.len());
+		check_write(self, offset, bytes.len()).map_err(ConcatError::Invalid)?;
+		self.for_each_part(offset, bytes.len(), |device, offset, range| {
+			device.write(offset, &bytes[range])
+		})
+		.map_err(ConcatError::Flash)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Concat::flush");
+		self.devices
+			.iter_mut()
+			.try_for_each(NorFlash::flush)
+			.map_err(ConcatError::Flash)
+	}
+}
+
+impl<S: MultiwriteNorFlash, const N: usize> MultiwriteNorFlash for Concat<S, N> {}
+
+impl<S: ChipErase, const N: usize> ChipErase for Concat<S, N> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("Concat::erase_chip");
+		self.devices
+			.iter_mut()
+			.try_for_each(|device| device.erase_chip())
+			.map_err(ConcatError::Flash)
+	}
+}
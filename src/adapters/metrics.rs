@@ -0,0 +1,181 @@
+use crate::nor_flash::{
+	ChipErase, ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash, ProgramPolarity, ReadNorFlash,
+};
+
+/// Counter published by [`Metrics`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Metric {
+	/// Completed reads.
+	Reads,
+	/// Bytes read.
+	BytesRead,
+	/// Completed writes.
+	Writes,
+	/// Bytes written.
+	BytesWritten,
+	/// Completed erases, including chip erases.
+	Erases,
+	/// Bytes erased.
+	BytesErased,
+	/// Completed flushes.
+	Flushes,
+	/// Operations which returned an error.
+	Errors,
+}
+
+impl Metric {
+	/// Conventional name of the counter, e.g. `flash_bytes_written`.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Reads => "flash_reads",
+			Self::BytesRead => "flash_bytes_read",
+			Self::Writes => "flash_writes",
+			Self::BytesWritten => "flash_bytes_written",
+			Self::Erases => "flash_erases",
+			Self::BytesErased => "flash_bytes_erased",
+			Self::Flushes => "flash_flushes",
+			Self::Errors => "flash_errors",
+		}
+	}
+}
+
+/// Telemetry receiving the counter increments of a [`Metrics`] wrapper.
+///
+/// Implementations forward the increments to whatever telemetry system the firmware already has,
+/// e.g. by looking up a counter by the [`name`](Metric::name) of the metric and its labels. The
+/// unit type implements a sink dropping every increment.
+pub trait MetricsSink {
+	/// Add `value` to the counter `metric` identified by `labels`, as `(key, value)` pairs.
+	fn increment(&mut self, metric: Metric, labels: &[(&str, &str)], value: u64);
+}
+
+impl MetricsSink for () {
+	fn increment(&mut self, _metric: Metric, _labels: &[(&str, &str)], _value: u64) {}
+}
+
+impl<T: MetricsSink> MetricsSink for &mut T {
+	fn increment(&mut self, metric: Metric, labels: &[(&str, &str)], value: u64) {
+		T::increment(self, metric, labels, value)
+	}
+}
+
+/// NOR flash wrapper publishing the usage of the flash to a [`MetricsSink`].
+///
+/// Every completed operation increments the counter of its kind by one and the matching byte
+/// counter by its length, failed operations only increment [`Metric::Errors`]. All the counters
+/// carry the labels given to [`new`](Self::new), so several flashes or partitions can share one
+/// sink, e.g. `&[("partition", "config")]`. Errors are passed through unchanged.
+pub struct Metrics<'a, S, M> {
+	flash: S,
+	sink: M,
+	labels: &'a [(&'a str, &'a str)],
+}
+
+impl<'a, S, M: MetricsSink> Metrics<'a, S, M> {
+	/// Wrap `flash`, publishing its counters with `labels` to `sink`.
+	pub fn new(flash: S, sink: M, labels: &'a [(&'a str, &'a str)]) -> Self {
+		Self {
+			flash,
+			sink,
+			labels,
+		}
+	}
+
+	/// The labels of the published counters.
+	pub fn labels(&self) -> &'a [(&'a str, &'a str)] {
+		self.labels
+	}
+
+	/// Release the wrapped flash and the sink.
+	pub fn into_inner(self) -> (S, M) {
+		(self.flash, self.sink)
+	}
+
+	/// Publish the outcome of an operation counted as `metric`, with its byte counter and length.
+	fn publish<E>(
+		&mut self,
+		result: Result<(), E>,
+		metric: Metric,
+		bytes: Option<(Metric, usize)>,
+	) -> Result<(), E> {
+		match result {
+			Ok(()) => {
+				self.sink.increment(metric, self.labels, 1);
+				if let Some((metric, length)) = bytes {
+					self.sink.increment(metric, self.labels, length as u64);
+				}
+			}
+			Err(_) => self.sink.increment(Metric::Errors, self.labels, 1),
+		}
+		result
+	}
+}
+
+impl<S: ErrorType, M> ErrorType for Metrics<'_, S, M> {
+	type Error = S::Error;
+}
+
+impl<S: ReadNorFlash, M: MetricsSink> ReadNorFlash for Metrics<'_, S, M> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Metrics::read", offset = offset, length = bytes.len());
+		let result = self.flash.read(offset, bytes);
+		self.publish(
+			result,
+			Metric::Reads,
+			Some((Metric::BytesRead, bytes.len())),
+		)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, M: MetricsSink> NorFlash for Metrics<'_, S, M> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Metrics::erase", from = from, to = to);
+		let result = self.flash.erase(from, to);
+		let length = to.saturating_sub(from) as usize;
+		self.publish(result, Metric::Erases, Some((Metric::BytesErased, length)))
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Metrics::write", offset = offset, length = bytes.len());
+		let result = self.flash.write(offset, bytes);
+		self.publish(
+			result,
+			Metric::Writes,
+			Some((Metric::BytesWritten, bytes.len())),
+		)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Metrics::flush");
+		let result = self.flash.flush();
+		self.publish(result, Metric::Flushes, None)
+	}
+
+	fn info(&self) -> FlashInfo {
+		self.flash.info()
+	}
+}
+
+impl<S: MultiwriteNorFlash, M: MetricsSink> MultiwriteNorFlash for Metrics<'_, S, M> {}
+
+impl<S: ChipErase, M: MetricsSink> ChipErase for Metrics<'_, S, M> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("Metrics::erase_chip");
+		let result = self.flash.erase_chip();
+		let length = self.flash.capacity();
+		self.publish(result, Metric::Erases, Some((Metric::BytesErased, length)))
+	}
+}
@@ -0,0 +1,204 @@
+use crate::nor_flash::{
+	check_compatible, check_erase, check_read, check_write, write_chunked, CheckError, ChipErase,
+	ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+	ProgramPolarity, ReadNorFlash,
+};
+
+const fn max(a: usize, b: usize) -> usize {
+	if a > b {
+		a
+	} else {
+		b
+	}
+}
+
+const fn min(a: usize, b: usize) -> usize {
+	if a < b {
+		a
+	} else {
+		b
+	}
+}
+
+/// Errors returned by [`Mirrored`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MirroredError<A, B> {
+	/// The primary flash returned an error.
+	Primary(A),
+	/// The secondary flash returned an error.
+	Secondary(B),
+	/// Reading from both devices failed.
+	Both(A, B),
+	/// Neither copy of the data read passed validation.
+	Corrupted,
+	/// The arguments are not aligned or out of bounds of the mirrored flash.
+	Invalid(CheckError),
+}
+
+impl<A: NorFlashError, B: NorFlashError> NorFlashError for MirroredError<A, B> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Primary(e) | Self::Both(e, _) => e.kind(),
+			Self::Secondary(e) => e.kind(),
+			Self::Corrupted => NorFlashErrorKind::Other,
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Redundant storage keeping the same data on two devices, RAID-1 style.
+///
+/// Writes and erases are applied to both devices. Reads are served by the primary device and fall
+/// back to the secondary one when the primary fails, or when its data does not pass the
+/// validation of [`Mirrored::read_validated`], e.g. a CRC check. Both devices must have the same
+/// erased contents and program polarity.
+pub struct Mirrored<A, B> {
+	primary: A,
+	secondary: B,
+}
+
+impl<A: NorFlash, B: NorFlash> Mirrored<A, B> {
+	/// Mirror `primary` onto `secondary`.
+	///
+	/// **NOTE** This will panic if the devices have different erased contents or program
+	/// polarities.
+	pub fn new(primary: A, secondary: B) -> Self {
+		match Self::try_new(primary, secondary) {
+			Ok(mirrored) => mirrored,
+			Err(_) => panic!("Mirrored flashes have different erased contents or polarities"),
+		}
+	}
+
+	/// Mirror `primary` onto `secondary`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::Incompatible`] if the devices have different erased contents or
+	/// program polarities.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(primary: A, secondary: B) -> Result<Self, CheckError> {
+		check_compatible::<A, B>()?;
+		Ok(Self { primary, secondary })
+	}
+
+	/// Release both devices.
+	pub fn into_inner(self) -> (A, B) {
+		(self.primary, self.secondary)
+	}
+
+	/// Read from the primary device, falling back to the secondary one if the read fails or
+	/// `validate` rejects the data.
+	pub fn read_validated<F>(
+		&mut self,
+		offset: u32,
+		bytes: &mut [u8],
+		validate: F,
+	) -> Result<(), MirroredError<A::Error, B::Error>>
+	where
+		F: Fn(&[u8]) -> bool,
+	{
+		check_read(self, offset, bytes.len()).map_err(MirroredError::Invalid)?;
+		let primary = match self.primary.read(offset, bytes) {
+			Ok(()) if validate(bytes) => return Ok(()),
+			Ok(()) => None,
+			Err(e) => Some(e),
+		};
+		match (self.secondary.read(offset, bytes), primary) {
+			(Ok(()), _) if validate(bytes) => Ok(()),
+			(Ok(()), _) => Err(MirroredError::Corrupted),
+			(Err(secondary), Some(primary)) => Err(MirroredError::Both(primary, secondary)),
+			(Err(secondary), None) => Err(MirroredError::Secondary(secondary)),
+		}
+	}
+
+	/// Restore the erase page starting at `page` of the primary device from the secondary one.
+	///
+	/// This is meant to be called after [`Mirrored::read_validated`] had to fall back to the
+	/// secondary device. `buffer` must be at least `ERASE_SIZE` long.
+	pub fn scrub(
+		&mut self,
+		page: u32,
+		buffer: &mut [u8],
+	) -> Result<(), MirroredError<A::Error, B::Error>> {
+		let size = <Self as NorFlash>::ERASE_SIZE;
+		check_erase(self, page, page + size as u32).map_err(MirroredError::Invalid)?;
+		let short = MirroredError::Invalid(CheckError::OutOfBounds(OutOfBoundsInfo {
+			offset: 0,
+			length: size,
+			capacity: buffer.len(),
+		}));
+		let buffer = buffer.get_mut(..size).ok_or(short)?;
+		self.secondary
+			.read(page, buffer)
+			.map_err(MirroredError::Secondary)?;
+		self.primary
+			.erase(page, page + size as u32)
+			.map_err(MirroredError::Primary)?;
+		write_chunked(&mut self.primary, page, buffer).map_err(MirroredError::Primary)
+	}
+}
+
+impl<A: ErrorType, B: ErrorType> ErrorType for Mirrored<A, B> {
+	type Error = MirroredError<A::Error, B::Error>;
+}
+
+impl<A: NorFlash, B: NorFlash> ReadNorFlash for Mirrored<A, B> {
+	const READ_SIZE: usize = max(A::READ_SIZE, B::READ_SIZE);
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Mirrored::read", offset = offset, length = bytes.len());
+		self.read_validated(offset, bytes, |_| true)
+	}
+
+	fn capacity(&self) -> usize {
+		self.primary.capacity().min(self.secondary.capacity())
+	}
+}
+
+impl<A: NorFlash, B: NorFlash> NorFlash for Mirrored<A, B> {
+	const WRITE_SIZE: usize = max(A::WRITE_SIZE, B::WRITE_SIZE);
+	const ERASE_SIZE: usize = max(A::ERASE_SIZE, B::ERASE_SIZE);
+	const ERASE_BYTE: u8 = A::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = A::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = A::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = min(A::MAX_WRITE_CHUNK, B::MAX_WRITE_CHUNK);
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Mirrored::erase", from = from, to = to);
+		check_erase(self, from, to).map_err(MirroredError::Invalid)?;
+		self.primary
+			.erase(from, to)
+			.map_err(MirroredError::Primary)?;
+		self.secondary
+			.erase(from, to)
+			.map_err(MirroredError::Secondary)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Mirrored::write", offset = offset, length = bytes.len());
+		check_write(self, offset, bytes.len()).map_err(MirroredError::Invalid)?;
+		write_chunked(&mut self.primary, offset, bytes).map_err(MirroredError::Primary)?;
+		write_chunked(&mut self.secondary, offset, bytes).map_err(MirroredError::Secondary)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Mirrored::flush");
+		self.primary.flush().map_err(MirroredError::Primary)?;
+		self.secondary.flush().map_err(MirroredError::Secondary)
+	}
+}
+
+impl<A: MultiwriteNorFlash, B: MultiwriteNorFlash> MultiwriteNorFlash for Mirrored<A, B> {}
+
+impl<A: ChipErase, B: ChipErase> ChipErase for Mirrored<A, B> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("Mirrored::erase_chip");
+		self.primary.erase_chip().map_err(MirroredError::Primary)?;
+		self.secondary
+			.erase_chip()
+			.map_err(MirroredError::Secondary)
+	}
+}
@@ -0,0 +1,225 @@
+use crate::nor_flash::{
+	check_erase, check_read, check_slice, check_write, CheckError, ErrorType, MultiwriteNorFlash,
+	NorFlash, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo, ProgramPolarity, ReadNorFlash,
+};
+use crate::{ReadStorage, Storage};
+
+/// Errors returned by [`Offset`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OffsetError<E> {
+	/// The wrapped storage returned an error.
+	Storage(E),
+	/// The arguments are not aligned or out of bounds of the window.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for OffsetError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Storage(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Window of `length` bytes starting at a fixed `base` address of a storage peripheral.
+///
+/// Offsets given to the window are relative to `base`, and accesses past `length` are rejected.
+/// This is a lightweight way to map e.g. a region of a memory-mapped device onto 0-based offsets.
+/// When wrapping a NOR flash, `base` and `length` should be multiples of its erase size, and
+/// `base` must be a multiple of the length of its
+/// [`ERASE_PATTERN`](NorFlash::ERASE_PATTERN) for the pattern to line up with the window.
+///
+/// The window is clamped to the capacity of the wrapped storage, so it never extends past
+/// `capacity - base` even if a larger `length` was given to [`new`](Self::new).
+pub struct Offset<S> {
+	storage: S,
+	base: u32,
+	length: usize,
+}
+
+impl<S> Offset<S> {
+	/// Map `base..base + length` of `storage` onto `0..length`.
+	///
+	/// The window is not checked against the capacity of `storage`, see [`try_new`](Self::try_new)
+	/// for a checked window.
+	pub fn new(storage: S, base: u32, length: usize) -> Self {
+		Self {
+			storage,
+			base,
+			length,
+		}
+	}
+
+	/// The base address of the window on the wrapped storage.
+	pub fn base(&self) -> u32 {
+		self.base
+	}
+
+	/// Release the wrapped storage.
+	pub fn into_inner(self) -> S {
+		self.storage
+	}
+
+	/// The address of `offset` on the wrapped storage, for an access of `length` bytes.
+	fn address(&self, offset: u32, length: usize) -> Result<u32, CheckError> {
+		self.base
+			.checked_add(offset)
+			.ok_or(CheckError::OutOfBounds(OutOfBoundsInfo {
+				offset,
+				length,
+				capacity: self.length,
+			}))
+	}
+}
+
+/// Check that `base..base + length` fits in `capacity` bytes.
+fn check_window(base: u32, length: usize, capacity: usize) -> Result<(), CheckError> {
+	match (base as usize).checked_add(length) {
+		Some(end) if end <= capacity => Ok(()),
+		_ => Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+			offset: base,
+			length,
+			capacity,
+		})),
+	}
+}
+
+impl<S: NorFlash> Offset<S> {
+	/// Map `base..base + length` of the NOR flash `storage` onto `0..length`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::OutOfBounds`] if the window does not fit in the capacity of
+	/// `storage`, or [`CheckError::NotAligned`] if `base` is not a multiple of the length of the
+	/// erase pattern.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(storage: S, base: u32, length: usize) -> Result<Self, CheckError> {
+		check_window(base, length, ReadNorFlash::capacity(&storage))?;
+		if base as usize % S::ERASE_PATTERN.len().max(1) != 0 {
+			return Err(CheckError::NotAligned);
+		}
+		Ok(Self::new(storage, base, length))
+	}
+}
+
+impl<S: ReadStorage> Offset<S> {
+	/// Map `base..base + length` of `storage` onto `0..length`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::OutOfBounds`] if the window does not fit in the capacity of
+	/// `storage`.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new_storage(storage: S, base: u32, length: usize) -> Result<Self, CheckError> {
+		check_window(base, length, ReadStorage::capacity(&storage))?;
+		Ok(Self::new(storage, base, length))
+	}
+
+	fn check(&self, offset: u32, length: usize) -> Result<u32, CheckError> {
+		check_slice(ReadStorage::capacity(self), 1, offset, length)?;
+		self.address(offset, length)
+	}
+}
+
+impl<S: ErrorType> ErrorType for Offset<S> {
+	type Error = OffsetError<S::Error>;
+}
+
+impl<S: ReadNorFlash> ReadNorFlash for Offset<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Offset::read", offset = offset, length = bytes.len());
+		check_read(self, offset, bytes.len()).map_err(OffsetError::Invalid)?;
+		let offset = self
+			.address(offset, bytes.len())
+			.map_err(OffsetError::Invalid)?;
+		self.storage
+			.read(offset, bytes)
+			.map_err(OffsetError::Storage)
+	}
+
+	fn capacity(&self) -> usize {
+		let capacity = self.storage.capacity();
+		self.length.min(capacity.saturating_sub(self.base as usize))
+	}
+}
+
+impl<S: NorFlash> NorFlash for Offset<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Offset::erase", from = from, to = to);
+		check_erase(self, from, to).map_err(OffsetError::Invalid)?;
+		let length = to.saturating_sub(from) as usize;
+		let from = self.address(from, length).map_err(OffsetError::Invalid)?;
+		let to = self.address(to, 0).map_err(OffsetError::Invalid)?;
+		self.storage.erase(from, to).map_err(OffsetError::Storage)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Offset::write", offset = offset, length = bytes.len());
+		check_write(self, offset, bytes.len()).map_err(OffsetError::Invalid)?;
+		let offset = self
+			.address(offset, bytes.len())
+			.map_err(OffsetError::Invalid)?;
+		self.storage
+			.write(offset, bytes)
+			.map_err(OffsetError::Storage)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Offset::flush");
+		NorFlash::flush(&mut self.storage).map_err(OffsetError::Storage)
+	}
+}
+
+impl<S: MultiwriteNorFlash> MultiwriteNorFlash for Offset<S> {}
+
+impl<S: ReadStorage> ReadStorage for Offset<S> {
+	type Error = OffsetError<S::Error>;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Offset::read", offset = offset, length = bytes.len());
+		let offset = self
+			.check(offset, bytes.len())
+			.map_err(OffsetError::Invalid)?;
+		self.storage
+			.read(offset, bytes)
+			.map_err(OffsetError::Storage)
+	}
+
+	fn capacity(&self) -> usize {
+		let capacity = self.storage.capacity();
+		self.length.min(capacity.saturating_sub(self.base as usize))
+	}
+}
+
+impl<S: Storage> Storage for Offset<S> {
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Offset::write", offset = offset, length = bytes.len());
+		let offset = self
+			.check(offset, bytes.len())
+			.map_err(OffsetError::Invalid)?;
+		self.storage
+			.write(offset, bytes)
+			.map_err(OffsetError::Storage)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Offset::flush");
+		Storage::flush(&mut self.storage).map_err(OffsetError::Storage)
+	}
+}
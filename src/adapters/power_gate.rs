@@ -0,0 +1,163 @@
+use crate::nor_flash::{
+	check_erase, check_write, ChipErase, ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash,
+	NorFlashError, NorFlashErrorKind, ProgramPolarity, ReadNorFlash,
+};
+
+/// Errors returned by [`PowerGate`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PowerGateError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The supply was too low to start the operation, which was not forwarded.
+	LowPower,
+}
+
+impl<E: NorFlashError> NorFlashError for PowerGateError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::LowPower => NorFlashErrorKind::LowPower,
+		}
+	}
+}
+
+/// NOR flash wrapper only starting erases and writes when the supply allows completing them.
+///
+/// An erase or program interrupted by a brown-out leaves its page or words undefined. The
+/// `can_start` hook, typically checking the supply voltage, is consulted before erasing every
+/// page and before writing the part of the data within every page, which then fail with
+/// [`PowerGateError::LowPower`]. With [`with_deferral`](Self::with_deferral), the operations
+/// wait for the supply to recover instead. Reads are always allowed.
+///
+/// Steps of a long operation which were started are not undone when a later step is refused, so
+/// such operations should be resumable, see [`resumable`](crate::resumable).
+pub struct PowerGate<'a, S> {
+	flash: S,
+	can_start: &'a mut dyn FnMut() -> bool,
+	deferral: Option<&'a mut dyn FnMut(usize) -> bool>,
+}
+
+impl<'a, S> PowerGate<'a, S> {
+	/// Wrap `flash`, starting erases and writes only while `can_start` returns `true`.
+	pub fn new(flash: S, can_start: &'a mut dyn FnMut() -> bool) -> Self {
+		Self {
+			flash,
+			can_start,
+			deferral: None,
+		}
+	}
+
+	/// Call `hook` with the number of the attempt when the supply is too low, e.g. to sleep until
+	/// it recovers. The supply is checked again while `hook` returns `true`, the operation fails
+	/// once it returns `false`.
+	pub fn with_deferral(mut self, hook: &'a mut dyn FnMut(usize) -> bool) -> Self {
+		self.deferral = Some(hook);
+		self
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Wait until the next step may be started, if allowed to.
+	fn gate<E>(&mut self) -> Result<(), PowerGateError<E>> {
+		let mut attempt = 0;
+		while !(self.can_start)() {
+			attempt += 1;
+			let deferred = match &mut self.deferral {
+				Some(deferral) => deferral(attempt),
+				None => false,
+			};
+			if !deferred {
+				return Err(PowerGateError::LowPower);
+			}
+		}
+		Ok(())
+	}
+}
+
+impl<'a, S: ErrorType> ErrorType for PowerGate<'a, S> {
+	type Error = PowerGateError<S::Error>;
+}
+
+impl<'a, S: ReadNorFlash> ReadNorFlash for PowerGate<'a, S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("PowerGate::read", offset = offset, length = bytes.len());
+		self.flash
+			.read(offset, bytes)
+			.map_err(PowerGateError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<'a, S: NorFlash> NorFlash for PowerGate<'a, S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("PowerGate::erase", from = from, to = to);
+		if check_erase(self, from, to).is_err() {
+			// Let the wrapped flash report the error in its own terms.
+			return self.flash.erase(from, to).map_err(PowerGateError::Flash);
+		}
+		for page in (from..to).step_by(S::ERASE_SIZE) {
+			self.gate()?;
+			self.flash
+				.erase(page, page + S::ERASE_SIZE as u32)
+				.map_err(PowerGateError::Flash)?;
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("PowerGate::write", offset = offset, length = bytes.len());
+		if check_write(self, offset, bytes.len()).is_err() {
+			return self
+				.flash
+				.write(offset, bytes)
+				.map_err(PowerGateError::Flash);
+		}
+		let mut done = 0;
+		while done < bytes.len() {
+			let address = offset as usize + done;
+			let part = (S::ERASE_SIZE - address % S::ERASE_SIZE).min(bytes.len() - done);
+			self.gate()?;
+			self.flash
+				.write(address as u32, &bytes[done..done + part])
+				.map_err(PowerGateError::Flash)?;
+			done += part;
+		}
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("PowerGate::flush");
+		// Flushing buffered writes programs the flash as well.
+		self.gate()?;
+		self.flash.flush().map_err(PowerGateError::Flash)
+	}
+
+	fn info(&self) -> FlashInfo {
+		self.flash.info()
+	}
+}
+
+impl<'a, S: MultiwriteNorFlash> MultiwriteNorFlash for PowerGate<'a, S> {}
+
+impl<'a, S: ChipErase> ChipErase for PowerGate<'a, S> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("PowerGate::erase_chip");
+		self.gate()?;
+		self.flash.erase_chip().map_err(PowerGateError::Flash)
+	}
+}
@@ -0,0 +1,118 @@
+use core::ops::Range;
+
+use crate::nor_flash::{
+	ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind,
+	ProgramPolarity, ReadNorFlash,
+};
+
+/// Errors returned by [`Protected`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ProtectedError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The operation overlaps a protected range and was not forwarded.
+	Locked,
+}
+
+impl<E: NorFlashError> NorFlashError for ProtectedError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Locked => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// NOR flash wrapper rejecting writes and erases overlapping any of a set of protected ranges.
+///
+/// This guards regions such as the bootloader against buggy application code on devices
+/// without hardware write protection. Reads are always allowed.
+pub struct Protected<'a, S> {
+	flash: S,
+	ranges: &'a [Range<u32>],
+}
+
+impl<'a, S> Protected<'a, S> {
+	/// Wrap `flash`, protecting every range of `ranges`.
+	pub fn new(flash: S, ranges: &'a [Range<u32>]) -> Self {
+		Self { flash, ranges }
+	}
+
+	/// The protected ranges.
+	pub fn ranges(&self) -> &'a [Range<u32>] {
+		self.ranges
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Whether `from..to` overlaps one of the protected ranges.
+	pub fn is_locked(&self, from: u32, to: u32) -> bool {
+		self.ranges
+			.iter()
+			.any(|range| from < range.end && range.start < to)
+	}
+}
+
+impl<'a, S: ErrorType> ErrorType for Protected<'a, S> {
+	type Error = ProtectedError<S::Error>;
+}
+
+impl<'a, S: ReadNorFlash> ReadNorFlash for Protected<'a, S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Protected::read", offset = offset, length = bytes.len());
+		self.flash
+			.read(offset, bytes)
+			.map_err(ProtectedError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<'a, S: NorFlash> NorFlash for Protected<'a, S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Protected::erase", from = from, to = to);
+		if self.is_locked(from, to) {
+			return Err(ProtectedError::Locked);
+		}
+		self.flash.erase(from, to).map_err(ProtectedError::Flash)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Protected::write", offset = offset, length = bytes.len());
+		if self.is_locked(offset, offset.saturating_add(bytes.len() as u32)) {
+			return Err(ProtectedError::Locked);
+		}
+		self.flash
+			.write(offset, bytes)
+			.map_err(ProtectedError::Flash)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Protected::flush");
+		self.flash.flush().map_err(ProtectedError::Flash)
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: false,
+			power_down: false,
+			..self.flash.info()
+		}
+	}
+}
+
+impl<'a, S: MultiwriteNorFlash> MultiwriteNorFlash for Protected<'a, S> {}
@@ -0,0 +1,60 @@
+use crate::nor_flash::{ErrorType, ReadNorFlash};
+use crate::ReadStorage;
+
+/// Read only view of a storage peripheral.
+///
+/// Only [`ReadNorFlash`] and [`ReadStorage`] are implemented, so code handed a `ReadOnly` cannot
+/// write to or erase the wrapped peripheral, e.g. for factory data or code executed in place.
+/// The wrapped peripheral cannot be released either, code needing to write again must keep its
+/// own handle, e.g. wrap a `&mut` reference instead of the peripheral.
+#[derive(Debug)]
+pub struct ReadOnly<S> {
+	storage: S,
+}
+
+impl<S> ReadOnly<S> {
+	/// Restrict `storage` to read operations.
+	pub fn new(storage: S) -> Self {
+		Self { storage }
+	}
+}
+
+impl<S: ErrorType> ErrorType for ReadOnly<S> {
+	type Error = S::Error;
+}
+
+impl<S: ReadNorFlash> ReadNorFlash for ReadOnly<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("ReadOnly::read", offset = offset, length = bytes.len());
+		self.storage.read(offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.storage.capacity()
+	}
+}
+
+impl<S: ReadStorage> ReadStorage for ReadOnly<S> {
+	type Error = S::Error;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("ReadOnly::read", offset = offset, length = bytes.len());
+		self.storage.read(offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.storage.capacity()
+	}
+}
+
+/// Conversion of a NOR flash into a [`ReadOnly`] view.
+pub trait IntoReadOnly: Sized {
+	/// Restrict `self` to read operations.
+	fn into_read_only(self) -> ReadOnly<Self> {
+		ReadOnly::new(self)
+	}
+}
+
+impl<T: ReadNorFlash> IntoReadOnly for T {}
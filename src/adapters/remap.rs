@@ -0,0 +1,320 @@
+use core::ops::Range;
+
+use crate::nor_flash::{
+	check_erase, check_read, check_write, write_chunked, CheckError, ChipErase, ErrorType,
+	FlashInfo, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+	ProgramPolarity, ReadNorFlash,
+};
+
+/// Errors returned by [`Remap`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RemapError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The arguments are not aligned or out of bounds.
+	Invalid(CheckError),
+	/// The erased range does not map onto whole erase blocks of the wrapped flash, so erasing it
+	/// would erase data outside of the range.
+	Scattered,
+}
+
+impl<E: NorFlashError> NorFlashError for RemapError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Scattered => NorFlashErrorKind::NotAligned,
+		}
+	}
+}
+
+/// Bijection between the addresses of a [`Remap`] and those of the flash it wraps.
+///
+/// Both functions must be the inverse of each other over the capacity of the flash, and keep
+/// words of `WRITE_SIZE` and `READ_SIZE` bytes contiguous and aligned, as [`Remap`] only maps
+/// the address of every word. With a per-address [`ERASE_PATTERN`](NorFlash::ERASE_PATTERN),
+/// addresses must also be moved by multiples of its length.
+pub trait AddressMap {
+	/// The address on the wrapped flash of the logical `address`.
+	fn map(&self, address: u32) -> u32;
+
+	/// The logical address of `address` on the wrapped flash.
+	fn unmap(&self, address: u32) -> u32;
+}
+
+impl<T: AddressMap> AddressMap for &T {
+	fn map(&self, address: u32) -> u32 {
+		T::map(self, address)
+	}
+
+	fn unmap(&self, address: u32) -> u32 {
+		T::unmap(self, address)
+	}
+}
+
+/// Interleaving of consecutive units of `unit` bytes across `ways` devices of `size` bytes
+/// placed one after the other, e.g. two dies joined with [`Concat`](super::Concat).
+///
+/// Unit `n` is stored in the device `n % ways`, so `Interleave::new(2, 1, size)` puts even
+/// bytes on the first device and odd bytes on the second.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Interleave {
+	ways: u32,
+	unit: u32,
+	size: u32,
+}
+
+impl Interleave {
+	/// Interleave units of `unit` bytes across `ways` devices of `size` bytes each.
+	///
+	/// **NOTE** This will panic if `ways` or `unit` is zero, or if `size` is not a multiple of
+	/// `unit`.
+	pub fn new(ways: u32, unit: u32, size: u32) -> Self {
+		match Self::try_new(ways, unit, size) {
+			Ok(map) => map,
+			Err(CheckError::NotAligned) => panic!("Empty unit or size not a multiple of the unit"),
+			Err(_) => panic!("No ways"),
+		}
+	}
+
+	/// Interleave units of `unit` bytes across `ways` devices of `size` bytes each, without
+	/// panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::OutOfBounds`] if `ways` is zero, and [`CheckError::NotAligned`] if
+	/// `unit` is zero or `size` is not a multiple of `unit`.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(ways: u32, unit: u32, size: u32) -> Result<Self, CheckError> {
+		if ways == 0 {
+			return Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+				offset: 0,
+				length: unit as usize,
+				capacity: 0,
+			}));
+		}
+		if size.checked_rem(unit) != Some(0) {
+			return Err(CheckError::NotAligned);
+		}
+		Ok(Self { ways, unit, size })
+	}
+}
+
+impl AddressMap for Interleave {
+	fn map(&self, address: u32) -> u32 {
+		let (index, within) = (address / self.unit, address % self.unit);
+		(index % self.ways) * self.size + (index / self.ways) * self.unit + within
+	}
+
+	fn unmap(&self, address: u32) -> u32 {
+		let (way, offset) = (address / self.size, address % self.size);
+		let (index, within) = (offset / self.unit, offset % self.unit);
+		(index * self.ways + way) * self.unit + within
+	}
+}
+
+/// Scrambling of addresses by XOR with a mask, a bijection of any power of two capacity larger
+/// than the mask.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Xor(pub u32);
+
+impl AddressMap for Xor {
+	fn map(&self, address: u32) -> u32 {
+		address ^ self.0
+	}
+
+	fn unmap(&self, address: u32) -> u32 {
+		address ^ self.0
+	}
+}
+
+/// NOR flash wrapper applying an [`AddressMap`] to every address, for designs scrambling
+/// addresses or interleaving data across several dies.
+///
+/// Reads and writes are split into words, whose addresses are mapped, and merged again where the
+/// mapped words are contiguous. An erase must map onto whole erase blocks of the wrapped flash,
+/// and is otherwise rejected with [`RemapError::Scattered`] before erasing anything: with data
+/// interleaved across two dies, every erase block holds the data of two logical blocks, which
+/// must then be erased together.
+pub struct Remap<S, F> {
+	flash: S,
+	map: F,
+}
+
+impl<S, F: AddressMap> Remap<S, F> {
+	/// Wrap `flash`, mapping addresses with `map`.
+	pub fn new(flash: S, map: F) -> Self {
+		Self { flash, map }
+	}
+
+	/// The address map.
+	pub fn map(&self) -> &F {
+		&self.map
+	}
+
+	/// Release the wrapped flash and the address map.
+	pub fn into_inner(self) -> (S, F) {
+		(self.flash, self.map)
+	}
+}
+
+impl<S: NorFlash, F: AddressMap> Remap<S, F> {
+	/// Whether the erase blocks of the wrapped flash overlapped by `from..to` only hold data of
+	/// `from..to`.
+	fn is_whole(&self, from: u32, to: u32) -> bool {
+		let contains = |address: u32| from <= address && address < to;
+		(from..to).step_by(S::WRITE_SIZE).all(|word| {
+			let mapped = self.map.map(word);
+			let block = mapped - mapped % S::ERASE_SIZE as u32;
+			// Every block is checked in full once, from the word mapped onto its start.
+			contains(self.map.unmap(block))
+				&& (mapped != block
+					|| (block..block + S::ERASE_SIZE as u32)
+						.step_by(S::WRITE_SIZE)
+						.all(|address| contains(self.map.unmap(address))))
+		})
+	}
+}
+
+/// Call `f` with the address on the wrapped flash of every contiguous run of the `length`
+/// bytes at `offset`, split into words of `unit` bytes mapped with `map`, and their range in the
+/// bytes.
+fn for_each_run<F: AddressMap, E>(
+	map: &F,
+	unit: usize,
+	offset: u32,
+	length: usize,
+	mut f: impl FnMut(u32, Range<usize>) -> Result<(), E>,
+) -> Result<(), E> {
+	let mut run: Option<(u32, Range<usize>)> = None;
+	let mut done = 0;
+	while done < length {
+		let address = offset as usize + done;
+		let start = address - address % unit;
+		let part = (start + unit - address).min(length - done);
+		let mapped = map.map(start as u32) + (address - start) as u32;
+		match &mut run {
+			Some((base, range)) if *base as usize + range.len() == mapped as usize => {
+				range.end += part;
+			}
+			_ => {
+				if let Some((base, range)) = run.take() {
+					f(base, range)?;
+				}
+				run = Some((mapped, done..done + part));
+			}
+		}
+		done += part;
+	}
+	match run {
+		Some((base, range)) => f(base, range),
+		None => Ok(()),
+	}
+}
+
+impl<S: ErrorType, F> ErrorType for Remap<S, F> {
+	type Error = RemapError<S::Error>;
+}
+
+impl<S: ReadNorFlash, F: AddressMap> ReadNorFlash for Remap<S, F> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Remap::read", offset = offset, length = bytes.len());
+		check_read(self, offset, bytes.len()).map_err(RemapError::Invalid)?;
+		let flash = &mut self.flash;
+		for_each_run(
+			&self.map,
+			S::READ_SIZE,
+			offset,
+			bytes.len(),
+			|address, range| {
+				flash
+					.read(address, &mut bytes[range])
+					.map_err(RemapError::Flash)
+			},
+		)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, F: AddressMap> NorFlash for Remap<S, F> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Remap::erase", from = from, to = to);
+		check_erase(self, from, to).map_err(RemapError::Invalid)?;
+		if !self.is_whole(from, to) {
+			return Err(RemapError::Scattered);
+		}
+		for word in (from..to).step_by(S::WRITE_SIZE) {
+			let block = self.map.map(word);
+			if block % S::ERASE_SIZE as u32 == 0 {
+				self.flash
+					.erase(block, block + S::ERASE_SIZE as u32)
+					.map_err(RemapError::Flash)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Remap::write", offset = offset, length = bytes.len());
+		check_write(self, offset, bytes.len()).map_err(RemapError::Invalid)?;
+		let flash = &mut self.flash;
+		for_each_run(
+			&self.map,
+			S::WRITE_SIZE,
+			offset,
+			bytes.len(),
+			|address, range| {
+				write_chunked(flash, address, &bytes[range]).map_err(RemapError::Flash)
+			},
+		)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Remap::flush");
+		self.flash.flush().map_err(RemapError::Flash)
+	}
+
+	fn info(&self) -> FlashInfo {
+		self.flash.info()
+	}
+}
+
+impl<S: MultiwriteNorFlash, F: AddressMap> MultiwriteNorFlash for Remap<S, F> {}
+
+impl<S: ChipErase, F: AddressMap> ChipErase for Remap<S, F> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("Remap::erase_chip");
+		self.flash.erase_chip().map_err(RemapError::Flash)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn interleave_try_new() {
+		assert!(Interleave::try_new(2, 4, 1024).is_ok());
+		assert!(matches!(
+			Interleave::try_new(0, 4, 1024),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert_eq!(Interleave::try_new(2, 0, 1024), Err(CheckError::NotAligned));
+		assert_eq!(Interleave::try_new(2, 3, 1024), Err(CheckError::NotAligned));
+	}
+}
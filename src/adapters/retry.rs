@@ -0,0 +1,134 @@
+use crate::nor_flash::{
+	ChipErase, ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash, NorFlashError,
+	NorFlashErrorKind, ProgramPolarity, ReadNorFlash,
+};
+
+/// Whether an error of `kind` may go away when the operation is repeated.
+///
+/// Misaligned or out of bounds arguments are rejected again on every attempt, other errors, such
+/// as timeouts or bus errors reported as [`NorFlashErrorKind::Other`], may be transient.
+pub fn is_transient(kind: NorFlashErrorKind) -> bool {
+	!matches!(
+		kind,
+		NorFlashErrorKind::NotAligned | NorFlashErrorKind::OutOfBounds
+	)
+}
+
+/// NOR flash wrapper repeating operations failing with transient errors.
+///
+/// This suits external flashes behind flaky buses, where a single retried transaction usually
+/// succeeds. Operations are attempted up to `retries` more times while their error is
+/// [transient](is_transient), then the last error is returned.
+///
+/// Erases and reads are safe to repeat. A repeated write programs the same data again on top of
+/// the words already programmed by the failed attempt, which flashes with ECC or without
+/// [`MultiwriteNorFlash`] support may not tolerate, so erase the page before writing it again
+/// when in doubt.
+pub struct Retry<'a, S> {
+	flash: S,
+	retries: usize,
+	backoff: Option<&'a mut dyn FnMut(usize, NorFlashErrorKind)>,
+}
+
+impl<'a, S: ErrorType> Retry<'a, S> {
+	/// Wrap `flash`, attempting every operation up to `retries` more times.
+	pub fn new(flash: S, retries: usize) -> Self {
+		Self {
+			flash,
+			retries,
+			backoff: None,
+		}
+	}
+
+	/// Call `hook` with the number of the attempt and the kind of error before every retry, e.g.
+	/// to wait for the bus to settle or to reset it.
+	pub fn with_backoff(mut self, hook: &'a mut dyn FnMut(usize, NorFlashErrorKind)) -> Self {
+		self.backoff = Some(hook);
+		self
+	}
+
+	/// Change the number of retries of subsequent operations.
+	pub fn set_retries(&mut self, retries: usize) {
+		self.retries = retries;
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	fn retry<F>(&mut self, mut operation: F) -> Result<(), S::Error>
+	where
+		F: FnMut(&mut S) -> Result<(), S::Error>,
+	{
+		let mut attempt = 0;
+		loop {
+			match operation(&mut self.flash) {
+				Err(error) if attempt < self.retries && is_transient(error.kind()) => {
+					attempt += 1;
+					if let Some(backoff) = &mut self.backoff {
+						backoff(attempt, error.kind());
+					}
+				}
+				result => return result,
+			}
+		}
+	}
+}
+
+impl<'a, S: ErrorType> ErrorType for Retry<'a, S> {
+	type Error = S::Error;
+}
+
+impl<'a, S: ReadNorFlash> ReadNorFlash for Retry<'a, S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("Retry::read", offset = offset, length = bytes.len());
+		self.retry(|flash| flash.read(offset, bytes))
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<'a, S: NorFlash> NorFlash for Retry<'a, S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("Retry::erase", from = from, to = to);
+		self.retry(|flash| flash.erase(from, to))
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("Retry::write", offset = offset, length = bytes.len());
+		self.retry(|flash| flash.write(offset, bytes))
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("Retry::flush");
+		self.retry(|flash| flash.flush())
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			power_down: false,
+			..self.flash.info()
+		}
+	}
+}
+
+impl<'a, S: MultiwriteNorFlash> MultiwriteNorFlash for Retry<'a, S> {}
+
+impl<'a, S: ChipErase> ChipErase for Retry<'a, S> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("Retry::erase_chip");
+		self.retry(|flash| flash.erase_chip())
+	}
+}
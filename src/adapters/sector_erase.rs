@@ -0,0 +1,82 @@
+use crate::nor_flash::{
+	ChipErase, ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash, ProgramPolarity, ReadNorFlash,
+};
+
+/// NOR flash wrapper implementing [`ChipErase`] by erasing every sector.
+///
+/// This lets flashes without a chip erase command, or adapters exposing part of a device, be
+/// handed to helpers taking [`ChipErase`], at the cost of the slower erase.
+#[derive(Debug)]
+pub struct SectorErase<S> {
+	flash: S,
+}
+
+impl<S> SectorErase<S> {
+	/// Wrap `flash`.
+	pub fn new(flash: S) -> Self {
+		Self { flash }
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+}
+
+impl<S: ErrorType> ErrorType for SectorErase<S> {
+	type Error = S::Error;
+}
+
+impl<S: ReadNorFlash> ReadNorFlash for SectorErase<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("SectorErase::read", offset = offset, length = bytes.len());
+		self.flash.read(offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash> NorFlash for SectorErase<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("SectorErase::erase", from = from, to = to);
+		self.flash.erase(from, to)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("SectorErase::write", offset = offset, length = bytes.len());
+		self.flash.write(offset, bytes)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("SectorErase::flush");
+		self.flash.flush()
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: true,
+			..self.flash.info()
+		}
+	}
+}
+
+impl<S: MultiwriteNorFlash> MultiwriteNorFlash for SectorErase<S> {}
+
+impl<S: NorFlash> ChipErase for SectorErase<S> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("SectorErase::erase_chip");
+		let capacity = self.flash.capacity() as u32;
+		self.flash.erase(0, capacity)
+	}
+}
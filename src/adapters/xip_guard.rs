@@ -0,0 +1,177 @@
+use core::ops::Range;
+
+use crate::nor_flash::{
+	ChipErase, ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash, NorFlashError,
+	NorFlashErrorKind, ProgramPolarity, ReadNorFlash,
+};
+
+/// Errors returned by [`XipGuard`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum XipError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The operation overlaps the range executed in place and was not forwarded.
+	Executing,
+}
+
+impl<E: NorFlashError> NorFlashError for XipError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Executing => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Hooks bracketing the writes and erases of an [`XipGuard`] in a section where modifying the
+/// flash is safe.
+///
+/// While a flash is busy programming or erasing, fetching code from it stalls or returns garbage,
+/// so `enter` typically disables interrupts whose handlers execute from the flash, or suspends
+/// the memory mapping, and `exit` restores them. The unit type implements hooks doing nothing.
+pub trait XipHooks {
+	/// Enter the section, before the operation.
+	fn enter(&mut self);
+
+	/// Leave the section, after the operation whether it succeeded or not.
+	fn exit(&mut self);
+}
+
+impl XipHooks for () {
+	fn enter(&mut self) {}
+
+	fn exit(&mut self) {}
+}
+
+impl<T: XipHooks> XipHooks for &mut T {
+	fn enter(&mut self) {
+		T::enter(self)
+	}
+
+	fn exit(&mut self) {
+		T::exit(self)
+	}
+}
+
+/// NOR flash wrapper protecting the range currently executed in place.
+///
+/// Writing or erasing the internal flash while executing code from the same area is a common
+/// mistake, which ends in a hard fault or corrupted code. Writes and erases overlapping the range
+/// given to [`set_xip_range`](Self::set_xip_range) are rejected with [`XipError::Executing`], so
+/// they can be deferred until the code runs elsewhere, e.g. from RAM. All the other writes and
+/// erases run between the [`XipHooks`]. Reads are always allowed.
+pub struct XipGuard<S, H> {
+	flash: S,
+	hooks: H,
+	xip: Option<Range<u32>>,
+}
+
+impl<S, H: XipHooks> XipGuard<S, H> {
+	/// Wrap `flash`, with no range executed in place yet.
+	pub fn new(flash: S, hooks: H) -> Self {
+		Self {
+			flash,
+			hooks,
+			xip: None,
+		}
+	}
+
+	/// Set the range executed in place, `None` if no code runs from the flash.
+	pub fn set_xip_range(&mut self, range: Option<Range<u32>>) {
+		self.xip = range;
+	}
+
+	/// The range executed in place.
+	pub fn xip_range(&self) -> Option<&Range<u32>> {
+		self.xip.as_ref()
+	}
+
+	/// Release the wrapped flash and the hooks.
+	pub fn into_inner(self) -> (S, H) {
+		(self.flash, self.hooks)
+	}
+
+	/// Whether `from..to` overlaps the range executed in place.
+	pub fn is_executing(&self, from: u32, to: u32) -> bool {
+		self.xip
+			.as_ref()
+			.map_or(false, |range| from < range.end && range.start < to)
+	}
+
+	/// Run `operation` on the flash between the hooks.
+	fn guarded<E>(
+		&mut self,
+		operation: impl FnOnce(&mut S) -> Result<(), E>,
+	) -> Result<(), XipError<E>> {
+		self.hooks.enter();
+		let result = operation(&mut self.flash);
+		self.hooks.exit();
+		result.map_err(XipError::Flash)
+	}
+}
+
+impl<S: ErrorType, H> ErrorType for XipGuard<S, H> {
+	type Error = XipError<S::Error>;
+}
+
+impl<S: ReadNorFlash, H: XipHooks> ReadNorFlash for XipGuard<S, H> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("XipGuard::read", offset = offset, length = bytes.len());
+		self.flash.read(offset, bytes).map_err(XipError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, H: XipHooks> NorFlash for XipGuard<S, H> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("XipGuard::erase", from = from, to = to);
+		if self.is_executing(from, to) {
+			return Err(XipError::Executing);
+		}
+		self.guarded(|flash| flash.erase(from, to))
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("XipGuard::write", offset = offset, length = bytes.len());
+		if self.is_executing(offset, offset.saturating_add(bytes.len() as u32)) {
+			return Err(XipError::Executing);
+		}
+		self.guarded(|flash| flash.write(offset, bytes))
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("XipGuard::flush");
+		self.guarded(|flash| flash.flush())
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			power_down: false,
+			..self.flash.info()
+		}
+	}
+}
+
+impl<S: MultiwriteNorFlash, H: XipHooks> MultiwriteNorFlash for XipGuard<S, H> {}
+
+impl<S: ChipErase, H: XipHooks> ChipErase for XipGuard<S, H> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		span!("XipGuard::erase_chip");
+		if self.xip.is_some() {
+			return Err(XipError::Executing);
+		}
+		self.guarded(|flash| flash.erase_chip())
+	}
+}
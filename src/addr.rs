@@ -0,0 +1,199 @@
+use core::ops::Range;
+
+use crate::nor_flash::{CheckError, NorFlash, OutOfBoundsInfo, RangeError};
+
+/// `value` rounded up to a multiple of `align`.
+///
+/// Like the other geometry helpers this is a `const fn`, to size buffers and lay out partitions
+/// at compile time, e.g. `[u8; align_up(HEADER, WRITE_SIZE)]`.
+pub const fn align_up(value: usize, align: usize) -> usize {
+	(value + align - 1) / align * align
+}
+
+/// `value` rounded down to a multiple of `align`.
+pub const fn align_down(value: usize, align: usize) -> usize {
+	value - value % align
+}
+
+/// Number of sectors of `sector_size` bytes needed to hold `length` bytes.
+pub const fn sectors_in(length: usize, sector_size: usize) -> usize {
+	(length + sector_size - 1) / sector_size
+}
+
+/// Index of the sector of `sector_size` bytes holding `address`.
+pub const fn sector_of(address: u32, sector_size: usize) -> u32 {
+	address / sector_size as u32
+}
+
+/// Byte address in a flash or storage.
+///
+/// Converts from and to `u32`, so the functions taking an `impl Into<FlashAddr>` accept plain
+/// offsets too, while a [`SectorIndex`] has to be converted explicitly with the sector size.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FlashAddr(pub u32);
+
+impl FlashAddr {
+	/// The address `address`.
+	pub const fn new(address: u32) -> Self {
+		Self(address)
+	}
+
+	/// The address as a byte offset.
+	pub const fn get(self) -> u32 {
+		self.0
+	}
+
+	/// The address `bytes` after this one, `None` on overflow.
+	pub fn checked_add(self, bytes: u32) -> Option<Self> {
+		self.0.checked_add(bytes).map(Self)
+	}
+
+	/// The address `bytes` before this one, `None` on underflow.
+	pub fn checked_sub(self, bytes: u32) -> Option<Self> {
+		self.0.checked_sub(bytes).map(Self)
+	}
+
+	/// Number of bytes from `base` to this address, `None` if `base` is after it.
+	pub fn offset_from(self, base: FlashAddr) -> Option<u32> {
+		self.0.checked_sub(base.0)
+	}
+
+	/// Whether the address is a multiple of `align`.
+	pub const fn is_aligned(self, align: usize) -> bool {
+		self.0 % align as u32 == 0
+	}
+
+	/// The address rounded down to a multiple of `align`.
+	pub const fn align_down(self, align: usize) -> Self {
+		Self(self.0 - self.0 % align as u32)
+	}
+
+	/// The address rounded up to a multiple of `align`, `None` on overflow.
+	pub fn align_up(self, align: usize) -> Option<Self> {
+		let align = align as u32;
+		self.0
+			.checked_add(align - 1)
+			.map(|address| Self(address - address % align))
+	}
+
+	/// The sector of `sector_size` bytes holding the address.
+	pub const fn sector(self, sector_size: usize) -> SectorIndex {
+		SectorIndex(sector_of(self.0, sector_size))
+	}
+
+	/// Offset of the address in its sector of `sector_size` bytes.
+	pub const fn sector_offset(self, sector_size: usize) -> u32 {
+		self.0 % sector_size as u32
+	}
+}
+
+impl From<u32> for FlashAddr {
+	fn from(address: u32) -> Self {
+		Self(address)
+	}
+}
+
+impl From<FlashAddr> for u32 {
+	fn from(address: FlashAddr) -> Self {
+		address.0
+	}
+}
+
+/// Index of a sector, i.e. an erase page, of a flash.
+///
+/// Unlike [`FlashAddr`] it does not convert back to `u32` implicitly, so it cannot be passed
+/// where a byte offset is expected.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SectorIndex(pub u32);
+
+impl SectorIndex {
+	/// The sector `index`.
+	pub const fn new(index: u32) -> Self {
+		Self(index)
+	}
+
+	/// The index as a number.
+	pub const fn get(self) -> u32 {
+		self.0
+	}
+
+	/// The sector `count` sectors after this one, `None` on overflow.
+	pub fn checked_add(self, count: u32) -> Option<Self> {
+		self.0.checked_add(count).map(Self)
+	}
+
+	/// The sector `count` sectors before this one, `None` on underflow.
+	pub fn checked_sub(self, count: u32) -> Option<Self> {
+		self.0.checked_sub(count).map(Self)
+	}
+
+	/// Address of the start of the sector with sectors of `sector_size` bytes, `None` on overflow.
+	pub fn addr(self, sector_size: usize) -> Option<FlashAddr> {
+		self.0.checked_mul(sector_size as u32).map(FlashAddr)
+	}
+
+	/// Byte range of the sector with sectors of `sector_size` bytes, `None` on overflow.
+	pub fn range(self, sector_size: usize) -> Option<Range<u32>> {
+		let start = self.addr(sector_size)?.0;
+		Some(start..start.checked_add(sector_size as u32)?)
+	}
+}
+
+impl From<u32> for SectorIndex {
+	fn from(index: u32) -> Self {
+		Self(index)
+	}
+}
+
+/// Erase `count` sectors of `flash` starting at sector `first`, with sectors of `ERASE_SIZE`.
+///
+/// # Errors
+///
+/// Returns [`RangeError::Invalid`] with [`CheckError::OutOfBounds`] if the sectors are beyond the
+/// 32-bit address space, and [`RangeError::Flash`] if the erase fails.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn erase_sectors<S: NorFlash>(
+	flash: &mut S,
+	first: impl Into<SectorIndex>,
+	count: u32,
+) -> Result<(), RangeError<S::Error>> {
+	let first = first.into();
+	let range = first
+		.checked_add(count)
+		.and_then(|last| Some((first.addr(S::ERASE_SIZE)?, last.addr(S::ERASE_SIZE)?)));
+	let (from, to) = range.ok_or_else(|| {
+		RangeError::Invalid(CheckError::OutOfBounds(OutOfBoundsInfo {
+			offset: first.0.saturating_mul(S::ERASE_SIZE as u32),
+			length: (count as usize).saturating_mul(S::ERASE_SIZE),
+			capacity: flash.capacity(),
+		}))
+	})?;
+	flash.erase(from.get(), to.get()).map_err(RangeError::Flash)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	#[test]
+	fn erase_sectors_out_of_range() {
+		let mut flash = MockFlash::<4096, 1, 4, 1024>::new();
+		assert_eq!(erase_sectors(&mut flash, 1, 2), Ok(()));
+		assert!(matches!(
+			erase_sectors(&mut flash, 3, 2),
+			Err(RangeError::Flash(_))
+		));
+		assert!(matches!(
+			erase_sectors(&mut flash, u32::MAX, 1),
+			Err(RangeError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert!(matches!(
+			erase_sectors(&mut flash, 1, u32::MAX),
+			Err(RangeError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+use std::vec;
+
+use crate::test_suite::Rng;
+use crate::Storage;
+
+/// Outcome of a workload.
+///
+/// Workloads can also be run within the timing loop of a harness such as Criterion, which then
+/// ignores the report.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Report {
+	/// Number of writes performed.
+	pub operations: usize,
+	/// Number of bytes written.
+	pub bytes: usize,
+	/// Wall clock time taken by the writes.
+	pub elapsed: Duration,
+}
+
+impl Report {
+	/// Written bytes per second.
+	pub fn throughput(&self) -> f64 {
+		self.bytes as f64 / self.elapsed.as_secs_f64()
+	}
+}
+
+/// Write the whole of `storage` from start to end, `chunk` bytes at a time.
+///
+/// This is the best case for adapters merging writes into erased pages, such as
+/// [`RmwNorFlashStorage`](crate::nor_flash::RmwNorFlashStorage).
+///
+/// **NOTE** This will panic if `chunk` is zero.
+pub fn sequential_write<S: Storage>(storage: &mut S, chunk: usize) -> Result<Report, S::Error> {
+	assert!(chunk > 0, "Empty chunks");
+	let capacity = storage.capacity();
+	let data: vec::Vec<u8> = (0..chunk).map(|index| index as u8).collect();
+	run(
+		storage,
+		capacity / chunk + (capacity % chunk != 0) as usize,
+		|index| {
+			let offset = index * chunk;
+			(offset as u32, &data[..chunk.min(capacity - offset)])
+		},
+	)
+}
+
+/// Write `count` updates of `size` random bytes at random offsets of `storage`.
+///
+/// This is the worst case for read-modify-write adapters, which may have to erase a page for
+/// every update. Runs are reproducible for a given `seed`.
+///
+/// **NOTE** This will panic if `size` is larger than the capacity of `storage`.
+pub fn random_updates<S: Storage>(
+	storage: &mut S,
+	size: usize,
+	count: usize,
+	seed: u64,
+) -> Result<Report, S::Error> {
+	let capacity = storage.capacity();
+	assert!(size <= capacity, "Updates larger than the storage");
+	let mut rng = Rng::new(seed);
+	let mut data = vec![0; size];
+	let mut offsets = vec::Vec::with_capacity(count);
+	for _ in 0..count {
+		offsets.push(rng.below(capacity - size + 1) as u32);
+	}
+	rng.fill(&mut data);
+	run(storage, count, |index| (offsets[index], &data[..]))
+}
+
+/// Append `count` records of `record` bytes one after the other, wrapping around at the end of
+/// `storage`, as a log or a queue would.
+///
+/// **NOTE** This will panic if `record` is zero or larger than the capacity of `storage`.
+pub fn log_append<S: Storage>(
+	storage: &mut S,
+	record: usize,
+	count: usize,
+) -> Result<Report, S::Error> {
+	let slots = storage.capacity() / record.max(1);
+	assert!(record > 0 && slots > 0, "Records do not fit the storage");
+	let data: vec::Vec<u8> = (0..record).map(|index| !(index as u8)).collect();
+	run(storage, count, |index| {
+		(((index % slots) * record) as u32, &data[..])
+	})
+}
+
+/// Time `count` writes given by `write`.
+fn run<'a, S: Storage, W>(storage: &mut S, count: usize, mut write: W) -> Result<Report, S::Error>
+where
+	W: FnMut(usize) -> (u32, &'a [u8]),
+{
+	let mut bytes = 0;
+	let start = Instant::now();
+	for index in 0..count {
+		let (offset, data) = write(index);
+		storage.write(offset, data)?;
+		bytes += data.len();
+	}
+	Ok(Report {
+		operations: count,
+		bytes,
+		elapsed: start.elapsed(),
+	})
+}
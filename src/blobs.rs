@@ -0,0 +1,709 @@
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use crate::addr::align_up;
+use crate::crc::{Checksum, Crc32};
+use crate::digest::hash_region;
+use crate::gc::{GcPolicy, GcStatus};
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, is_erased, write_chunked, CheckError, NorFlash,
+	NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffer used to read and write headers, unaligned tails and copied data.
+const BUFFER: usize = 32;
+
+/// Size of the header of a bank: magic word, sequence number and CRC.
+const BANK_HEADER: usize = 12;
+
+/// Size of the header of a blob: magic word, hash, length, reference count and CRC.
+const BLOB_HEADER: usize = 20;
+
+/// Size of a reference record: magic word, hash, change of the reference count and CRC.
+const REF_RECORD: usize = 16;
+
+const BANK_MAGIC: u32 = 0x4b4e_4142;
+const BLOB_MAGIC: u32 = 0x424f_4c42;
+const REF_MAGIC: u32 = 0x2046_4552;
+
+/// Errors returned by [`BlobStore`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BlobError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The blob does not fit in a bank, or the read is out of the bounds of the blob.
+	Invalid(CheckError),
+	/// The bank has no room for a new blob, [`gc_step`](BlobStore::gc_step) has to reclaim space
+	/// first.
+	Full,
+	/// A different blob with the same hash is stored already.
+	Collision,
+	/// No blob with this hash is referenced.
+	Missing,
+}
+
+impl<E: NorFlashError> NorFlashError for BlobError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Full | Self::Collision | Self::Missing => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// A blob found in the active bank.
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+	hash: u32,
+	/// Address of the data.
+	address: u32,
+	length: u32,
+	refs: u32,
+}
+
+/// Content addressed store of reference counted blobs, in two banks of a flash.
+///
+/// Blobs are keyed by the checksum `H` of their contents, so storing the same data twice only
+/// takes a reference on the existing blob, e.g. for firmware fragments or assets shared by
+/// several images. Every insertion takes a reference and every [`release`](Self::release) drops
+/// one, and blobs left without references are reclaimed by garbage collection.
+///
+/// The active bank is a log of blobs and reference count changes, replayed into an index when
+/// mounting. Garbage collection copies the referenced blobs to the other bank and writes its
+/// header last, so a power loss at any point leaves the previous bank readable. A record cut by a
+/// power loss at the end of the log fills the bank until the next collection.
+pub struct BlobStore<S, H = Crc32> {
+	flash: S,
+	banks: [u32; 2],
+	size: u32,
+	active: usize,
+	sequence: u32,
+	/// Address of the next record.
+	write: u32,
+	entries: Vec<Entry>,
+	hash: PhantomData<H>,
+}
+
+impl<S: NorFlash> BlobStore<S> {
+	/// Mount the store in `range` of `flash`, split into two banks, and format it if empty.
+	///
+	/// # Errors
+	///
+	/// Returns [`BlobError::Invalid`] with [`CheckError::NotAligned`] if the halves of `range`
+	/// are not aligned to `ERASE_SIZE`, and with [`CheckError::OutOfBounds`] if `range` is out of
+	/// bounds, if a bank cannot hold a blob, or if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes.
+	pub fn mount(flash: S, range: Range<u32>) -> Result<Self, BlobError<S::Error>> {
+		Self::mount_with_hash(flash, range)
+	}
+}
+
+impl<S: NorFlash, H: Checksum> BlobStore<S, H> {
+	/// Mount a store keying blobs by the checksum `H` instead of [`Crc32`].
+	///
+	/// # Errors
+	///
+	/// Returns an error in the cases of [`mount`](BlobStore::mount).
+	pub fn mount_with_hash(flash: S, range: Range<u32>) -> Result<Self, BlobError<S::Error>> {
+		check_range(flash.capacity(), 1, range.start, range.end).map_err(BlobError::Invalid)?;
+		let size = (range.end - range.start) / 2;
+		check_slice(flash.capacity(), S::ERASE_SIZE, range.start, size as usize)
+			.and_then(|()| check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE)))
+			.and_then(|()| {
+				check_slice(
+					size as usize,
+					1,
+					0,
+					Self::size(BANK_HEADER) + Self::size(BLOB_HEADER),
+				)
+			})
+			.map_err(BlobError::Invalid)?;
+		let mut store = Self {
+			flash,
+			banks: [range.start, range.start + size],
+			size,
+			active: 0,
+			sequence: 0,
+			write: 0,
+			entries: Vec::new(),
+			hash: PhantomData,
+		};
+		let mut latest = None;
+		for index in 0..2 {
+			let mut buffer = [0; BUFFER];
+			let header = &mut buffer[..Self::size(BANK_HEADER)];
+			store
+				.flash
+				.read(store.banks[index], header)
+				.map_err(BlobError::Flash)?;
+			if let Some([BANK_MAGIC, sequence]) = parse(header) {
+				if latest.map_or(true, |(_, latest)| sequence > latest) {
+					latest = Some((index, sequence));
+				}
+			}
+		}
+		match latest {
+			Some((active, sequence)) => {
+				store.active = active;
+				store.sequence = sequence;
+				store.scan().map_err(BlobError::Flash)?;
+			}
+			None => {
+				let bank = store.banks[0];
+				store
+					.flash
+					.erase(bank, bank + size)
+					.map_err(BlobError::Flash)?;
+				store
+					.write_record(bank, &[BANK_MAGIC, 0], BANK_HEADER)
+					.map_err(BlobError::Flash)?;
+				store.write = bank + Self::size(BANK_HEADER) as u32;
+			}
+		}
+		Ok(store)
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// The largest blob which can be stored.
+	pub fn max_length(&self) -> usize {
+		self.size as usize - Self::size(BANK_HEADER) - Self::size(BLOB_HEADER)
+	}
+
+	/// Whether a blob with `hash` is referenced.
+	pub fn contains(&self, hash: u32) -> bool {
+		self.live(hash).is_some()
+	}
+
+	/// The length of the blob with `hash`, `None` if it is not referenced.
+	pub fn len(&self, hash: u32) -> Option<usize> {
+		self.live(hash)
+			.map(|index| self.entries[index].length as usize)
+	}
+
+	/// The number of references on the blob with `hash`.
+	pub fn refs(&self, hash: u32) -> u32 {
+		self.live(hash).map_or(0, |index| self.entries[index].refs)
+	}
+
+	/// The hashes of the referenced blobs.
+	pub fn hashes(&self) -> impl Iterator<Item = u32> + '_ {
+		self.entries
+			.iter()
+			.filter(|entry| entry.refs > 0)
+			.map(|entry| entry.hash)
+	}
+
+	/// Take a reference on the blob holding `data`, storing it if needed, and return its hash.
+	pub fn insert(&mut self, data: &[u8]) -> Result<u32, BlobError<S::Error>> {
+		let hash = H::checksum(data);
+		if let Some(index) = self.find(hash) {
+			let entry = self.entries[index];
+			if entry.length as usize != data.len() || !self.matches(entry.address, data)? {
+				return Err(BlobError::Collision);
+			}
+			self.add_ref(index, 1)?;
+			return Ok(hash);
+		}
+		if data.len() > self.max_length() {
+			return Err(BlobError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: data.len(),
+					capacity: self.max_length(),
+				},
+			)));
+		}
+		let footprint = Self::footprint(data.len() as u32);
+		if footprint > self.free() {
+			return Err(BlobError::Full);
+		}
+		let header = self.write;
+		let address = header + Self::size(BLOB_HEADER) as u32;
+		self.write = self.end();
+		self.write_record(
+			header,
+			&[BLOB_MAGIC, hash, data.len() as u32, 1],
+			BLOB_HEADER,
+		)
+		.map_err(BlobError::Flash)?;
+		let aligned = data.len() - data.len() % S::WRITE_SIZE;
+		write_chunked(&mut self.flash, address, &data[..aligned]).map_err(BlobError::Flash)?;
+		if aligned < data.len() {
+			let mut tail = [0; BUFFER];
+			fill_erased::<S>(address + aligned as u32, &mut tail[..S::WRITE_SIZE]);
+			tail[..data.len() - aligned].copy_from_slice(&data[aligned..]);
+			self.flash
+				.write(address + aligned as u32, &tail[..S::WRITE_SIZE])
+				.map_err(BlobError::Flash)?;
+		}
+		self.write = header + footprint as u32;
+		self.entries.push(Entry {
+			hash,
+			address,
+			length: data.len() as u32,
+			refs: 1,
+		});
+		Ok(hash)
+	}
+
+	/// Drop a reference on the blob with `hash`, returning the number of references left.
+	pub fn release(&mut self, hash: u32) -> Result<u32, BlobError<S::Error>> {
+		let index = self.live(hash).ok_or(BlobError::Missing)?;
+		let refs = self.entries[index].refs - 1;
+		self.add_ref(index, -1)?;
+		Ok(refs)
+	}
+
+	/// Read `bytes.len()` bytes at `offset` of the blob with `hash`.
+	pub fn read(
+		&mut self,
+		hash: u32,
+		offset: usize,
+		bytes: &mut [u8],
+	) -> Result<(), BlobError<S::Error>> {
+		let entry = self.entries[self.live(hash).ok_or(BlobError::Missing)?];
+		let length = entry.length as usize;
+		if offset > length || bytes.len() > length - offset {
+			return Err(BlobError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: offset as u32,
+					length: bytes.len(),
+					capacity: length,
+				},
+			)));
+		}
+		self.read_at(entry.address + offset as u32, bytes)
+			.map_err(BlobError::Flash)
+	}
+
+	/// The space left in the active bank.
+	pub fn gc_status(&self) -> GcStatus {
+		GcStatus {
+			free: self.free(),
+			discards: false,
+		}
+	}
+
+	/// Copy the referenced blobs to the other bank, which becomes the active one.
+	///
+	/// Returns `false` if there is nothing to reclaim.
+	pub fn gc_step(&mut self) -> Result<bool, BlobError<S::Error>> {
+		let compacted = self
+			.entries
+			.iter()
+			.filter(|entry| entry.refs > 0)
+			.map(|entry| Self::footprint(entry.length))
+			.sum::<usize>()
+			+ Self::size(BANK_HEADER);
+		if (self.write - self.banks[self.active]) as usize == compacted {
+			return Ok(false);
+		}
+		self.compact(None)?;
+		Ok(true)
+	}
+
+	/// Run [`gc_step`](Self::gc_step) if `policy` agrees, returns whether space was reclaimed.
+	pub fn gc<P: GcPolicy>(&mut self, mut policy: P) -> Result<bool, BlobError<S::Error>> {
+		if !policy.should_collect(&self.gc_status()) {
+			return Ok(false);
+		}
+		self.gc_step()
+	}
+
+	fn align() -> usize {
+		S::READ_SIZE.max(S::WRITE_SIZE)
+	}
+
+	/// Size of `length` bytes rounded up to whole read and write units.
+	fn size(length: usize) -> usize {
+		align_up(length, Self::align())
+	}
+
+	/// Space taken by a blob of `length` bytes and its header.
+	fn footprint(length: u32) -> usize {
+		Self::size(BLOB_HEADER) + Self::size(length as usize)
+	}
+
+	fn end(&self) -> u32 {
+		self.banks[self.active] + self.size
+	}
+
+	fn free(&self) -> usize {
+		(self.end() - self.write) as usize
+	}
+
+	fn find(&self, hash: u32) -> Option<usize> {
+		self.entries.iter().position(|entry| entry.hash == hash)
+	}
+
+	fn live(&self, hash: u32) -> Option<usize> {
+		self.find(hash)
+			.filter(|index| self.entries[*index].refs > 0)
+	}
+
+	/// Append a record changing the reference count of blob `index` by `delta`, or compact the
+	/// store if the bank is full.
+	fn add_ref(&mut self, index: usize, delta: i32) -> Result<(), BlobError<S::Error>> {
+		if Self::size(REF_RECORD) > self.free() {
+			return self.compact(Some((index, delta)));
+		}
+		let address = self.write;
+		self.write = self.end();
+		let hash = self.entries[index].hash;
+		self.write_record(address, &[REF_MAGIC, hash, delta as u32], REF_RECORD)
+			.map_err(BlobError::Flash)?;
+		self.write = address + Self::size(REF_RECORD) as u32;
+		let entry = &mut self.entries[index];
+		entry.refs = entry.refs.wrapping_add(delta as u32);
+		Ok(())
+	}
+
+	/// Copy the referenced blobs to the other bank, which becomes the active one.
+	///
+	/// The reference count of blob `index` is changed by `delta` on the way, for when there is no
+	/// room left for a reference record. The index is only updated once the copy is complete.
+	fn compact(&mut self, change: Option<(usize, i32)>) -> Result<(), BlobError<S::Error>> {
+		let refs = |index: usize, entry: &Entry| match change {
+			Some((changed, delta)) if changed == index => entry.refs.wrapping_add(delta as u32),
+			_ => entry.refs,
+		};
+		let target = 1 - self.active;
+		let bank = self.banks[target];
+		self.flash
+			.erase(bank, bank + self.size)
+			.map_err(BlobError::Flash)?;
+		let chunk = BUFFER - BUFFER % Self::align();
+		let mut address = bank + Self::size(BANK_HEADER) as u32;
+		for index in 0..self.entries.len() {
+			let entry = self.entries[index];
+			let count = refs(index, &entry);
+			if count == 0 {
+				continue;
+			}
+			self.write_record(
+				address,
+				&[BLOB_MAGIC, entry.hash, entry.length, count],
+				BLOB_HEADER,
+			)
+			.map_err(BlobError::Flash)?;
+			let data = address + Self::size(BLOB_HEADER) as u32;
+			let length = Self::size(entry.length as usize);
+			let mut buffer = [0; BUFFER];
+			for offset in (0..length).step_by(chunk) {
+				let bytes = &mut buffer[..chunk.min(length - offset)];
+				self.flash
+					.read(entry.address + offset as u32, bytes)
+					.map_err(BlobError::Flash)?;
+				self.flash
+					.write(data + offset as u32, bytes)
+					.map_err(BlobError::Flash)?;
+			}
+			address = data + length as u32;
+		}
+		self.write_record(bank, &[BANK_MAGIC, self.sequence + 1], BANK_HEADER)
+			.map_err(BlobError::Flash)?;
+
+		for index in 0..self.entries.len() {
+			self.entries[index].refs = refs(index, &self.entries[index]);
+		}
+		self.entries.retain(|entry| entry.refs > 0);
+		let mut address = bank + Self::size(BANK_HEADER) as u32;
+		for entry in &mut self.entries {
+			entry.address = address + Self::size(BLOB_HEADER) as u32;
+			address = entry.address + Self::size(entry.length as usize) as u32;
+		}
+		self.active = target;
+		self.sequence += 1;
+		self.write = address;
+		Ok(())
+	}
+
+	/// Write `words` followed by their CRC at `address`, as a record of `length` bytes.
+	fn write_record(&mut self, address: u32, words: &[u32], length: usize) -> Result<(), S::Error> {
+		let mut buffer = [0; BUFFER];
+		fill_erased::<S>(address, &mut buffer[..Self::size(length)]);
+		for (chunk, word) in buffer.chunks_mut(4).zip(words.iter()) {
+			chunk.copy_from_slice(&word.to_le_bytes());
+		}
+		let end = 4 * words.len();
+		let crc = Crc32::checksum(&buffer[..end]);
+		buffer[end..end + 4].copy_from_slice(&crc.to_le_bytes());
+		self.flash.write(address, &buffer[..Self::size(length)])
+	}
+
+	/// Read `bytes.len()` bytes at the unaligned `address`.
+	fn read_at(&mut self, mut address: u32, bytes: &mut [u8]) -> Result<(), S::Error> {
+		let chunk = BUFFER - BUFFER % S::READ_SIZE;
+		let mut buffer = [0; BUFFER];
+		let mut done = 0;
+		while done < bytes.len() {
+			let skip = address as usize % S::READ_SIZE;
+			let length = (chunk - skip).min(bytes.len() - done);
+			let read = align_up(skip + length, S::READ_SIZE);
+			self.flash
+				.read(address - skip as u32, &mut buffer[..read])?;
+			bytes[done..done + length].copy_from_slice(&buffer[skip..skip + length]);
+			done += length;
+			address += length as u32;
+		}
+		Ok(())
+	}
+
+	/// Whether the data stored at `address` is `data`.
+	fn matches(&mut self, address: u32, data: &[u8]) -> Result<bool, BlobError<S::Error>> {
+		let mut buffer = [0; BUFFER];
+		for (index, expected) in data.chunks(BUFFER).enumerate() {
+			let bytes = &mut buffer[..expected.len()];
+			self.read_at(address + (index * BUFFER) as u32, bytes)
+				.map_err(BlobError::Flash)?;
+			if bytes != expected {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
+	/// Replay the log of the active bank into the index.
+	fn scan(&mut self) -> Result<(), S::Error> {
+		let end = self.end();
+		let mut address = self.banks[self.active] + Self::size(BANK_HEADER) as u32;
+		let mut buffer = [0; BUFFER];
+		self.entries.clear();
+		while address < end {
+			let header = &mut buffer[..Self::size(BLOB_HEADER).min((end - address) as usize)];
+			self.flash.read(address, header)?;
+			if is_erased::<S>(address, header) {
+				break;
+			}
+			let record = Self::size(REF_RECORD).min(header.len());
+			match (parse(header), parse(&header[..record])) {
+				(Some([BLOB_MAGIC, hash, length, refs]), _)
+					if Self::footprint(length) <= (end - address) as usize =>
+				{
+					let data = address + Self::size(BLOB_HEADER) as u32;
+					let mut digest = H::default();
+					let mut scratch = [0; BUFFER];
+					hash_region(
+						&mut self.flash,
+						data..data + length,
+						&mut digest,
+						&mut scratch,
+					)?;
+					if digest.finish() == hash && self.find(hash).is_none() {
+						self.entries.push(Entry {
+							hash,
+							address: data,
+							length,
+							refs,
+						});
+					}
+					address += Self::footprint(length) as u32;
+				}
+				(_, Some([REF_MAGIC, hash, delta])) => {
+					if let Some(index) = self.find(hash) {
+						let entry = &mut self.entries[index];
+						entry.refs = entry.refs.wrapping_add(delta);
+					}
+					address += Self::size(REF_RECORD) as u32;
+				}
+				_ => address = end,
+			}
+		}
+		self.write = address.min(end);
+		Ok(())
+	}
+}
+
+/// Decode the `N` words at the start of `bytes`, followed by their CRC.
+///
+/// Returns `None` if `bytes` is too short or the CRC does not match.
+fn parse<const N: usize>(bytes: &[u8]) -> Option<[u32; N]> {
+	if bytes.len() < 4 * N + 4 {
+		return None;
+	}
+	let word = |index: usize| {
+		let mut word = [0; 4];
+		word.copy_from_slice(&bytes[4 * index..4 * index + 4]);
+		u32::from_le_bytes(word)
+	};
+	if Crc32::checksum(&bytes[..4 * N]) != word(N) {
+		return None;
+	}
+	let mut words = [0; N];
+	for (index, value) in words.iter_mut().enumerate() {
+		*value = word(index);
+	}
+	Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gc::{Eager, FreeBelow};
+	use crate::mock::{MockError, MockFlash};
+
+	type Flash = MockFlash<8192, 1, 4, 1024>;
+
+	/// Read back the whole blob with `hash` and compare it to `data`.
+	fn check<S: NorFlash>(store: &mut BlobStore<S>, hash: u32, data: &[u8]) {
+		let mut bytes = [0; 512];
+		assert_eq!(store.len(hash), Some(data.len()));
+		store.read(hash, 0, &mut bytes[..data.len()]).unwrap();
+		assert_eq!(&bytes[..data.len()], data);
+	}
+
+	#[test]
+	fn mount_invalid() {
+		let invalid = |result: Result<BlobStore<Flash>, _>| match result {
+			Err(BlobError::Invalid(error)) => error,
+			_ => panic!("Range accepted"),
+		};
+		assert!(BlobStore::mount(Flash::new(), 0..8192).is_ok());
+		assert_eq!(
+			invalid(BlobStore::mount(Flash::new(), 512..4608)),
+			CheckError::NotAligned
+		);
+		assert!(matches!(
+			invalid(BlobStore::mount(Flash::new(), 0..16384)),
+			CheckError::OutOfBounds(_)
+		));
+		assert!(matches!(
+			invalid(BlobStore::mount(Flash::new(), 0..0)),
+			CheckError::OutOfBounds(_)
+		));
+		assert!(matches!(
+			BlobStore::mount(MockFlash::<8192, 64, 4, 1024>::new(), 0..8192),
+			Err(BlobError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn insert_dedup() {
+		let mut store = BlobStore::mount(Flash::new(), 0..8192).unwrap();
+		let first = store.insert(b"shared firmware fragment").unwrap();
+		let second = store.insert(b"odd").unwrap();
+		assert_eq!(first, Crc32::checksum(b"shared firmware fragment"));
+		assert_eq!(store.insert(b"shared firmware fragment"), Ok(first));
+		assert_eq!(store.refs(first), 2);
+		assert_eq!(store.refs(second), 1);
+		check(&mut store, first, b"shared firmware fragment");
+		check(&mut store, second, b"odd");
+
+		let mut bytes = [0; 8];
+		store.read(first, 7, &mut bytes).unwrap();
+		assert_eq!(&bytes, b"firmware");
+		assert!(matches!(
+			store.read(second, 1, &mut bytes[..3]),
+			Err(BlobError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert_eq!(store.read(0, 0, &mut bytes), Err(BlobError::Missing));
+
+		let mut store = BlobStore::<_, Crc32>::mount(store.into_inner(), 0..8192).unwrap();
+		assert_eq!(store.refs(first), 2);
+		check(&mut store, first, b"shared firmware fragment");
+		check(&mut store, second, b"odd");
+		assert_eq!(store.hashes().count(), 2);
+	}
+
+	#[test]
+	fn release_gc() {
+		let mut store = BlobStore::mount(Flash::new(), 0..8192).unwrap();
+		let kept = store.insert(&[0x11; 300]).unwrap();
+		let dropped = store.insert(&[0x22; 300]).unwrap();
+		assert_eq!(store.insert(&[0x22; 300]), Ok(dropped));
+		assert_eq!(store.release(dropped), Ok(1));
+		assert_eq!(store.release(dropped), Ok(0));
+		assert!(!store.contains(dropped));
+		assert_eq!(store.release(dropped), Err(BlobError::Missing));
+
+		let free = store.gc_status().free;
+		assert_eq!(store.gc(FreeBelow(free - 1)), Ok(false));
+		assert_eq!(store.gc(Eager), Ok(true));
+		assert_eq!(store.gc_status().free, free + 20 + 300 + 16 * 3);
+		assert_eq!(store.gc_step(), Ok(false));
+		check(&mut store, kept, &[0x11; 300]);
+
+		let mut store = BlobStore::<_, Crc32>::mount(store.into_inner(), 0..8192).unwrap();
+		assert!(!store.contains(dropped));
+		check(&mut store, kept, &[0x11; 300]);
+	}
+
+	#[test]
+	fn full() {
+		let mut store = BlobStore::mount(Flash::new(), 0..8192).unwrap();
+		assert!(matches!(
+			store.insert(&[0; 4096]),
+			Err(BlobError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		let mut data = [0; 500];
+		let mut hashes = [0; 7];
+		for (index, hash) in hashes.iter_mut().enumerate() {
+			data[0] = index as u8;
+			*hash = store.insert(&data).unwrap();
+		}
+		data[0] = 7;
+		assert_eq!(store.insert(&data), Err(BlobError::Full));
+		assert_eq!(store.gc_step(), Ok(false));
+
+		// Releases fill the bank with records until they compact it.
+		for hash in &hashes[..3] {
+			assert_eq!(store.release(*hash), Ok(0));
+		}
+		assert_eq!(store.insert(&data), Err(BlobError::Full));
+		assert_eq!(store.gc_step(), Ok(true));
+		let hash = store.insert(&data).unwrap();
+		check(&mut store, hash, &data);
+		data[0] = 6;
+		check(&mut store, hashes[6], &data);
+		assert!(!store.contains(hashes[0]));
+	}
+
+	#[test]
+	fn power_loss() {
+		let mut flash = Flash::new();
+		let mut store = BlobStore::mount(&mut flash, 0..8192).unwrap();
+		let kept = store.insert(b"survives power loss").unwrap();
+		let dropped = store.insert(b"released before collection").unwrap();
+		store.release(dropped).unwrap();
+		drop(store);
+
+		// Power is cut in the header of a blob, which fills the bank until the next collection.
+		flash.cut_next_write(2);
+		let mut store = BlobStore::mount(&mut flash, 0..8192).unwrap();
+		assert_eq!(
+			store.insert(&[0x33; 100]),
+			Err(BlobError::Flash(MockError::PowerLoss))
+		);
+		drop(store);
+		let mut store = BlobStore::mount(&mut flash, 0..8192).unwrap();
+		assert!(!store.contains(Crc32::checksum(&[0x33; 100])));
+		assert!(!store.contains(dropped));
+		check(&mut store, kept, b"survives power loss");
+		assert_eq!(store.insert(&[0x33; 100]), Err(BlobError::Full));
+		assert_eq!(store.gc_step(), Ok(true));
+		let hash = store.insert(&[0x33; 100]).unwrap();
+		let dropped = store.insert(b"released before collection").unwrap();
+		store.release(dropped).unwrap();
+		drop(store);
+
+		// Power is cut while copying to the other bank, before its header is written.
+		flash.cut_next_write(10);
+		let mut store = BlobStore::mount(&mut flash, 0..8192).unwrap();
+		assert_eq!(store.gc_step(), Err(BlobError::Flash(MockError::PowerLoss)));
+		drop(store);
+		let mut store = BlobStore::mount(&mut flash, 0..8192).unwrap();
+		check(&mut store, kept, b"survives power loss");
+		check(&mut store, hash, &[0x33; 100]);
+		assert_eq!(store.gc_step(), Ok(true));
+		drop(store);
+		let mut store = BlobStore::mount(&mut flash, 0..8192).unwrap();
+		check(&mut store, kept, b"survives power loss");
+		check(&mut store, hash, &[0x33; 100]);
+		assert!(!store.contains(dropped));
+	}
+}
@@ -0,0 +1,143 @@
+use crate::nor_flash::{check_slice, erased_byte, fill_erased, CheckError, MultiwriteNorFlash};
+
+/// Size of the buffer used to read and write the counter.
+const BUFFER: usize = 32;
+
+/// Counter of unconfirmed boots of a firmware, kept in one erase page of a flash.
+///
+/// Every boot programs one more bit of the page, relying on the bit programming allowed by
+/// [`MultiwriteNorFlash`], so counting does not wear the flash until the page is erased by
+/// [`confirm_boot`](Self::confirm_boot). A power loss while counting loses at most that boot.
+///
+/// Once `limit` boots have not been confirmed, e.g. because the firmware keeps crashing,
+/// [`record_boot`](Self::record_boot) signals that the previous firmware should be restored. This
+/// is the counterpart of the trial state of [`Updater`](crate::ota::Updater) for bootloaders
+/// which retry an image a few times before reverting it.
+pub struct BootRecord<S> {
+	flash: S,
+	page: u32,
+	limit: u32,
+}
+
+impl<S: MultiwriteNorFlash> BootRecord<S> {
+	/// Count boots in the erase page starting at `page`, allowing `limit` unconfirmed boots.
+	///
+	/// **NOTE** This will panic if `page` is not aligned to `ERASE_SIZE` or out of bounds, if the
+	/// page has fewer bits than `limit`, or if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes, see
+	/// [`try_new`](Self::try_new).
+	pub fn new(flash: S, page: u32, limit: u32) -> Self {
+		match Self::try_new(flash, page, limit) {
+			Ok(record) => record,
+			Err(CheckError::NotAligned) => panic!("Page not aligned to ERASE_SIZE"),
+			Err(_) => panic!("Page out of bounds or too small"),
+		}
+	}
+
+	/// Count boots in the erase page starting at `page`, allowing `limit` unconfirmed boots,
+	/// without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if `page` is not aligned to `ERASE_SIZE`, and
+	/// [`CheckError::OutOfBounds`] if the page is out of bounds or has fewer bits than `limit`,
+	/// or if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(flash: S, page: u32, limit: u32) -> Result<Self, CheckError> {
+		check_slice(flash.capacity(), S::ERASE_SIZE, page, S::ERASE_SIZE)?;
+		check_slice(8 * S::ERASE_SIZE, 1, 0, limit as usize)?;
+		check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE))?;
+		Ok(Self { flash, page, limit })
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Number of boots recorded since the last confirmation.
+	pub fn unconfirmed(&mut self) -> Result<u32, S::Error> {
+		let mut buffer = [0; BUFFER];
+		let chunk = &mut buffer[..S::READ_SIZE];
+		let mut count = 0;
+		for offset in (0..S::ERASE_SIZE).step_by(S::READ_SIZE) {
+			self.flash.read(self.page + offset as u32, chunk)?;
+			for (address, byte) in (self.page + offset as u32..).zip(chunk.iter()) {
+				let programmed = byte ^ erased_byte::<S>(address);
+				count += programmed.count_ones();
+				if programmed != 0xff {
+					return Ok(count);
+				}
+			}
+		}
+		Ok(count)
+	}
+
+	/// Record a boot, returns whether the limit of unconfirmed boots is reached and the previous
+	/// firmware should be restored.
+	///
+	/// Nothing is recorded once the limit is reached.
+	pub fn record_boot(&mut self) -> Result<bool, S::Error> {
+		let count = self.unconfirmed()?;
+		if count >= self.limit {
+			return Ok(true);
+		}
+		let word = count as usize / 8 / S::WRITE_SIZE * S::WRITE_SIZE;
+		let mut buffer = [0; BUFFER];
+		let address = self.page + word as u32;
+		fill_erased::<S>(address, &mut buffer[..S::WRITE_SIZE]);
+		let bit = count as usize - 8 * word;
+		buffer[bit / 8] ^= 1 << (bit % 8);
+		self.flash.write(address, &buffer[..S::WRITE_SIZE])?;
+		Ok(false)
+	}
+
+	/// Whether the limit of unconfirmed boots is reached.
+	pub fn needs_rollback(&mut self) -> Result<bool, S::Error> {
+		Ok(self.unconfirmed()? >= self.limit)
+	}
+
+	/// Confirm that the firmware booted correctly, resetting the counter.
+	pub fn confirm_boot(&mut self) -> Result<(), S::Error> {
+		if self.unconfirmed()? == 0 {
+			return Ok(());
+		}
+		self.flash
+			.erase(self.page, self.page + S::ERASE_SIZE as u32)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{MockFlash, MultiwriteMockFlash};
+
+	type Flash = MultiwriteMockFlash<MockFlash<16384, 1, 4, 1024>>;
+
+	#[test]
+	fn try_new_invalid() {
+		assert!(BootRecord::try_new(Flash::default(), 1024, 8192).is_ok());
+		assert!(matches!(
+			BootRecord::try_new(Flash::default(), 512, 3),
+			Err(CheckError::NotAligned)
+		));
+		assert!(matches!(
+			BootRecord::try_new(Flash::default(), 16384, 3),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			BootRecord::try_new(Flash::default(), 0, 8193),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			BootRecord::try_new(
+				MultiwriteMockFlash::new(MockFlash::<16384, 64, 4, 1024>::new()),
+				0,
+				3
+			),
+			Err(CheckError::OutOfBounds(_))
+		));
+	}
+}
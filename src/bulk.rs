@@ -0,0 +1,175 @@
+use core::ops::Range;
+
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, write_chunked, CheckError, NorFlash, NorFlashError,
+	NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Errors returned by [`copy_region`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CopyError<A, B> {
+	/// The source flash returned an error.
+	Source(A),
+	/// The destination flash returned an error.
+	Destination(B),
+	/// The region is out of bounds of the source, or its copy misaligned or out of bounds of
+	/// the destination.
+	Invalid(CheckError),
+}
+
+impl<A: NorFlashError, B: NorFlashError> NorFlashError for CopyError<A, B> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Source(e) => e.kind(),
+			Self::Destination(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Platform acceleration of the byte moves and comparisons of the bulk helpers.
+///
+/// The provided methods are plain loops. Microcontrollers with a DMA controller can move and
+/// compare large chunks without the CPU by overriding them. Checksums of regions go through
+/// [`hash_region`](crate::digest::hash_region), whose [`Digest`](crate::digest::Digest) can be
+/// backed by a CRC peripheral in the same way.
+pub trait Offload {
+	/// Copy `from` into `to`, which have the same length.
+	fn copy(&mut self, from: &[u8], to: &mut [u8]) {
+		to.copy_from_slice(from)
+	}
+
+	/// Whether `a` and `b`, which have the same length, hold the same bytes.
+	fn equal(&mut self, a: &[u8], b: &[u8]) -> bool {
+		a == b
+	}
+}
+
+impl<T: Offload + ?Sized> Offload for &mut T {
+	fn copy(&mut self, from: &[u8], to: &mut [u8]) {
+		T::copy(self, from, to)
+	}
+
+	fn equal(&mut self, a: &[u8], b: &[u8]) -> bool {
+		T::equal(self, a, b)
+	}
+}
+
+/// [`Offload`] running on the CPU.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Cpu;
+
+impl Offload for Cpu {}
+
+/// Copy `range` of the flash `from` to `offset` of the flash `to`, through `scratch`.
+///
+/// The source region does not need to be aligned. The copy starts at an `offset` aligned to
+/// `WRITE_SIZE` of the destination, whose pages must be erased, and its end is padded with
+/// erased bytes up to `WRITE_SIZE`. Half of `scratch` receives the reads, the other half collects
+/// the writes, the bytes being moved between them by `offload`.
+///
+/// **NOTE** This will panic if half of `scratch` is shorter than the `READ_SIZE` of the source or
+/// the `WRITE_SIZE` of the destination.
+pub fn copy_region<A, B, O>(
+	from: &mut A,
+	range: Range<u32>,
+	to: &mut B,
+	offset: u32,
+	scratch: &mut [u8],
+	mut offload: O,
+) -> Result<(), CopyError<A::Error, B::Error>>
+where
+	A: ReadNorFlash,
+	B: NorFlash,
+	O: Offload,
+{
+	span!(
+		"copy_region",
+		from = range.start,
+		to = range.end,
+		offset = offset
+	);
+	let (input, output) = scratch.split_at_mut(scratch.len() / 2);
+	let chunk_in = input.len() - input.len() % A::READ_SIZE;
+	let chunk_out = output.len() - output.len() % B::WRITE_SIZE;
+	assert!(
+		chunk_in > 0 && chunk_out > 0,
+		"Scratch buffer shorter than READ_SIZE or WRITE_SIZE"
+	);
+	check_range(from.capacity(), 1, range.start, range.end).map_err(CopyError::Invalid)?;
+	let length = (range.end - range.start) as usize;
+	let padded = (length + B::WRITE_SIZE - 1) / B::WRITE_SIZE * B::WRITE_SIZE;
+	check_slice(to.capacity(), B::WRITE_SIZE, offset, padded).map_err(CopyError::Invalid)?;
+
+	let end = (range.end as usize + A::READ_SIZE - 1) / A::READ_SIZE * A::READ_SIZE;
+	let mut address = range.start - range.start % A::READ_SIZE as u32;
+	let (mut pending, mut written) = (0, 0);
+	while (address as usize) < end {
+		let read = chunk_in.min(end - address as usize);
+		from.read(address, &mut input[..read])
+			.map_err(CopyError::Source)?;
+		let start = range.start.saturating_sub(address) as usize;
+		let stop = ((range.end - address) as usize).min(read);
+		let mut data = &input[start..stop];
+		while !data.is_empty() {
+			let part = (chunk_out - pending).min(data.len());
+			offload.copy(&data[..part], &mut output[pending..pending + part]);
+			pending += part;
+			data = &data[part..];
+			if pending == chunk_out {
+				write_chunked(to, offset + written, &output[..pending])
+					.map_err(CopyError::Destination)?;
+				written += pending as u32;
+				pending = 0;
+			}
+		}
+		address += read as u32;
+	}
+	if pending > 0 {
+		let aligned = (pending + B::WRITE_SIZE - 1) / B::WRITE_SIZE * B::WRITE_SIZE;
+		fill_erased::<B>(
+			offset + written + pending as u32,
+			&mut output[pending..aligned],
+		);
+		write_chunked(to, offset + written, &output[..aligned]).map_err(CopyError::Destination)?;
+	}
+	Ok(())
+}
+
+/// Compare the bytes at `offset` of `flash` with `expected`, reading through `scratch`.
+///
+/// The region does not need to be aligned. The comparison stops at the first chunk differing
+/// from `expected`, use [`verify_secret`](crate::secret::verify_secret) for secrets.
+///
+/// **NOTE** This will panic if `scratch` is shorter than `READ_SIZE`.
+pub fn verify_region<S, O>(
+	flash: &mut S,
+	offset: u32,
+	expected: &[u8],
+	scratch: &mut [u8],
+	mut offload: O,
+) -> Result<bool, S::Error>
+where
+	S: ReadNorFlash,
+	O: Offload,
+{
+	span!("verify_region", offset = offset, length = expected.len());
+	let chunk = scratch.len() - scratch.len() % S::READ_SIZE;
+	assert!(chunk > 0, "Scratch buffer shorter than READ_SIZE");
+	let end = offset as usize + expected.len();
+	let end = (end + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+	let mut address = offset - offset % S::READ_SIZE as u32;
+	let mut done = 0;
+	while done < expected.len() {
+		let read = chunk.min(end - address as usize);
+		flash.read(address, &mut scratch[..read])?;
+		let from = offset.saturating_sub(address) as usize;
+		let part = (read - from).min(expected.len() - done);
+		if !offload.equal(&scratch[from..from + part], &expected[done..done + part]) {
+			return Ok(false);
+		}
+		address += read as u32;
+		done += part;
+	}
+	Ok(true)
+}
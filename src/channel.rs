@@ -0,0 +1,262 @@
+use crate::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+pub(crate) const INFO: u8 = 0x01;
+pub(crate) const READ: u8 = 0x02;
+pub(crate) const WRITE: u8 = 0x03;
+pub(crate) const ERASE: u8 = 0x04;
+pub(crate) const FLUSH: u8 = 0x05;
+
+pub(crate) const OK: u8 = 0x00;
+pub(crate) const NOT_ALIGNED: u8 = 0x01;
+pub(crate) const OUT_OF_BOUNDS: u8 = 0x02;
+pub(crate) const OTHER: u8 = 0x03;
+pub(crate) const UNKNOWN: u8 = 0x04;
+
+/// Blocking byte transport to a host, such as a pair of RTT channels or semihosting files.
+pub trait Channel {
+	/// Error type returned by the transport.
+	type Error: core::fmt::Debug;
+
+	/// Send all of `bytes`.
+	fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+	/// Receive exactly as many bytes as `bytes` holds.
+	fn read_exact(&mut self, bytes: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+impl<T: Channel> Channel for &mut T {
+	type Error = T::Error;
+
+	fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+		T::write_all(self, bytes)
+	}
+
+	fn read_exact(&mut self, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		T::read_exact(self, bytes)
+	}
+}
+
+/// Errors returned by [`ChannelFlash`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChannelError<E> {
+	/// The transport returned an error.
+	Channel(E),
+	/// The flash of the host returned an error.
+	Flash(NorFlashErrorKind),
+	/// The host sent an unexpected response, or its flash has another geometry.
+	Protocol,
+}
+
+impl<E: core::fmt::Debug> NorFlashError for ChannelError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(kind) => *kind,
+			Self::Channel(_) | Self::Protocol => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Virtual flash forwarding every operation to a host over a [`Channel`].
+///
+/// The host serves a flash, typically backed by a file, with
+/// [`remote::serve`](crate::remote::serve), whose protocol this implements without allocating.
+/// Firmware can then be tested on the target hardware without wearing its real flash. The
+/// geometry is given by the const parameters, and checked against the one of the host when
+/// connecting.
+pub struct ChannelFlash<C, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+{
+	channel: C,
+	capacity: usize,
+}
+
+impl<C, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+	ChannelFlash<C, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+where
+	C: Channel,
+{
+	/// Connect to the flash served by the host on `channel`.
+	pub fn connect(channel: C) -> Result<Self, ChannelError<C::Error>> {
+		let mut flash = Self {
+			channel,
+			capacity: 0,
+		};
+		let mut info = [0; 16];
+		flash.request(INFO, &[], &[], &mut info)?;
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&info[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word) as usize
+		};
+		if (word(1), word(2), word(3)) != (READ_SIZE, WRITE_SIZE, ERASE_SIZE) {
+			return Err(ChannelError::Protocol);
+		}
+		flash.capacity = word(0);
+		Ok(flash)
+	}
+
+	/// Release the channel.
+	pub fn into_inner(self) -> C {
+		self.channel
+	}
+
+	/// Send a request made of `header` and `data`, and receive the response into `response`.
+	fn request(
+		&mut self,
+		tag: u8,
+		header: &[u8],
+		data: &[u8],
+		response: &mut [u8],
+	) -> Result<(), ChannelError<C::Error>> {
+		let mut frame = [0; 5];
+		frame[0] = tag;
+		frame[1..].copy_from_slice(&((header.len() + data.len()) as u32).to_le_bytes());
+		for part in &[&frame[..], header, data] {
+			self.channel
+				.write_all(part)
+				.map_err(ChannelError::Channel)?;
+		}
+		self.channel
+			.read_exact(&mut frame)
+			.map_err(ChannelError::Channel)?;
+		let length = u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize;
+		if frame[0] == OK && length == response.len() {
+			return self
+				.channel
+				.read_exact(response)
+				.map_err(ChannelError::Channel);
+		}
+		// Drop the payload of any other response, so that the next one is read from its start.
+		self.discard(length)?;
+		Err(match frame[0] {
+			NOT_ALIGNED => ChannelError::Flash(NorFlashErrorKind::NotAligned),
+			OUT_OF_BOUNDS => ChannelError::Flash(NorFlashErrorKind::OutOfBounds),
+			OTHER => ChannelError::Flash(NorFlashErrorKind::Other),
+			UNKNOWN => ChannelError::Protocol,
+			_ => ChannelError::Protocol,
+		})
+	}
+
+	/// Read and drop `length` bytes from the channel.
+	fn discard(&mut self, mut length: usize) -> Result<(), ChannelError<C::Error>> {
+		let mut buffer = [0; 32];
+		while length > 0 {
+			let part = length.min(buffer.len());
+			self.channel
+				.read_exact(&mut buffer[..part])
+				.map_err(ChannelError::Channel)?;
+			length -= part;
+		}
+		Ok(())
+	}
+}
+
+impl<C, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType
+	for ChannelFlash<C, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+where
+	C: Channel,
+{
+	type Error = ChannelError<C::Error>;
+}
+
+impl<C, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+	for ChannelFlash<C, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+where
+	C: Channel,
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		let mut header = [0; 8];
+		header[..4].copy_from_slice(&offset.to_le_bytes());
+		header[4..].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+		self.request(READ, &header, &[], bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity
+	}
+}
+
+impl<C, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+	for ChannelFlash<C, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+where
+	C: Channel,
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		let mut header = [0; 8];
+		header[..4].copy_from_slice(&from.to_le_bytes());
+		header[4..].copy_from_slice(&to.to_le_bytes());
+		self.request(ERASE, &header, &[], &mut [])
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		self.request(WRITE, &offset.to_le_bytes(), bytes, &mut [])
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.request(FLUSH, &[], &[], &mut [])
+	}
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+	use super::*;
+	use alloc::collections::VecDeque;
+	use alloc::vec::Vec;
+
+	/// Channel replaying scripted responses.
+	#[derive(Default)]
+	struct Script {
+		responses: VecDeque<u8>,
+	}
+
+	impl Script {
+		fn respond(&mut self, status: u8, payload: &[u8]) {
+			self.responses.push_back(status);
+			self.responses
+				.extend((payload.len() as u32).to_le_bytes().iter());
+			self.responses.extend(payload.iter());
+		}
+	}
+
+	impl Channel for Script {
+		type Error = ();
+
+		fn write_all(&mut self, _bytes: &[u8]) -> Result<(), Self::Error> {
+			Ok(())
+		}
+
+		fn read_exact(&mut self, bytes: &mut [u8]) -> Result<(), Self::Error> {
+			for byte in bytes {
+				*byte = self.responses.pop_front().ok_or(())?;
+			}
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn unexpected_payloads() {
+		let mut script = Script::default();
+		let info: Vec<u8> = [4096u32, 1, 4, 1024]
+			.iter()
+			.flat_map(|word| word.to_le_bytes().to_vec())
+			.collect();
+		script.respond(OK, &info);
+		script.respond(OK, &[1, 2, 3]);
+		script.respond(OUT_OF_BOUNDS, &[0; 40]);
+		script.respond(OK, &[5, 6]);
+
+		let mut flash = ChannelFlash::<_, 1, 4, 1024>::connect(script).unwrap();
+		let mut bytes = [0; 2];
+		assert_eq!(flash.read(0, &mut bytes), Err(ChannelError::Protocol));
+		assert_eq!(
+			flash.read(0, &mut bytes),
+			Err(ChannelError::Flash(NorFlashErrorKind::OutOfBounds))
+		);
+		assert_eq!(flash.read(0, &mut bytes), Ok(()));
+		assert_eq!(bytes, [5, 6]);
+	}
+}
@@ -0,0 +1,137 @@
+use crate::nor_flash::Geometry;
+
+/// JEDEC identification of a serial flash, as returned by the `9Fh` read ID command.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct JedecId {
+	/// JEDEC manufacturer ID.
+	pub manufacturer: u8,
+	/// Manufacturer specific memory type.
+	pub memory_type: u8,
+	/// Manufacturer specific capacity code.
+	pub capacity: u8,
+}
+
+impl JedecId {
+	/// Build an ID from the three bytes returned by the read ID command.
+	pub const fn from_bytes(bytes: [u8; 3]) -> Self {
+		Self {
+			manufacturer: bytes[0],
+			memory_type: bytes[1],
+			capacity: bytes[2],
+		}
+	}
+}
+
+/// Description of a known flash chip.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Chip {
+	/// Identification of the chip.
+	pub id: JedecId,
+	/// Part number of the chip family.
+	pub name: &'static str,
+	/// Capacity in bytes.
+	pub capacity: u32,
+	/// Supported erase sizes in bytes, by increasing size.
+	pub erase_sizes: &'static [u32],
+	/// Size of the program page in bytes.
+	pub page_size: u32,
+	/// Whether words can be programmed several times between erases, see
+	/// [`MultiwriteNorFlash`](crate::nor_flash::MultiwriteNorFlash).
+	pub multiwrite: bool,
+}
+
+impl Chip {
+	/// Runtime geometry of the chip, erasing with the smallest supported erase size.
+	pub fn geometry(&self) -> Geometry {
+		Geometry {
+			capacity: self.capacity as usize,
+			read_size: 1,
+			write_size: 1,
+			erase_size: self.erase_sizes[0] as usize,
+		}
+	}
+}
+
+const KIB: u32 = 1024;
+const MIB: u32 = 1024 * KIB;
+
+/// Erase sizes of the common 4K sector / 32K and 64K block layout.
+const ERASE_4K_32K_64K: &[u32] = &[4 * KIB, 32 * KIB, 64 * KIB];
+/// Erase sizes of parts without 32K block erase.
+const ERASE_4K_64K: &[u32] = &[4 * KIB, 64 * KIB];
+
+const fn chip(id: [u8; 3], name: &'static str, capacity: u32, erase_sizes: &'static [u32]) -> Chip {
+	Chip {
+		id: JedecId::from_bytes(id),
+		name,
+		capacity,
+		erase_sizes,
+		page_size: 256,
+		multiwrite: true,
+	}
+}
+
+/// Known chips.
+pub const CHIPS: &[Chip] = &[
+	// Winbond
+	chip([0xef, 0x40, 0x14], "W25Q80", MIB, ERASE_4K_32K_64K),
+	chip([0xef, 0x40, 0x15], "W25Q16", 2 * MIB, ERASE_4K_32K_64K),
+	chip([0xef, 0x40, 0x16], "W25Q32", 4 * MIB, ERASE_4K_32K_64K),
+	chip([0xef, 0x40, 0x17], "W25Q64", 8 * MIB, ERASE_4K_32K_64K),
+	chip([0xef, 0x40, 0x18], "W25Q128", 16 * MIB, ERASE_4K_32K_64K),
+	chip([0xef, 0x40, 0x19], "W25Q256", 32 * MIB, ERASE_4K_32K_64K),
+	chip(
+		[0xef, 0x70, 0x18],
+		"W25Q128JV-M",
+		16 * MIB,
+		ERASE_4K_32K_64K,
+	),
+	// Macronix
+	chip([0xc2, 0x20, 0x15], "MX25L1606E", 2 * MIB, ERASE_4K_32K_64K),
+	chip([0xc2, 0x20, 0x16], "MX25L3233F", 4 * MIB, ERASE_4K_32K_64K),
+	chip([0xc2, 0x20, 0x17], "MX25L6433F", 8 * MIB, ERASE_4K_32K_64K),
+	chip(
+		[0xc2, 0x20, 0x18],
+		"MX25L12835F",
+		16 * MIB,
+		ERASE_4K_32K_64K,
+	),
+	chip(
+		[0xc2, 0x20, 0x19],
+		"MX25L25645G",
+		32 * MIB,
+		ERASE_4K_32K_64K,
+	),
+	// GigaDevice
+	chip([0xc8, 0x40, 0x15], "GD25Q16", 2 * MIB, ERASE_4K_32K_64K),
+	chip([0xc8, 0x40, 0x16], "GD25Q32", 4 * MIB, ERASE_4K_32K_64K),
+	chip([0xc8, 0x40, 0x17], "GD25Q64", 8 * MIB, ERASE_4K_32K_64K),
+	chip([0xc8, 0x40, 0x18], "GD25Q128", 16 * MIB, ERASE_4K_32K_64K),
+	// ISSI
+	chip([0x9d, 0x60, 0x16], "IS25LP032", 4 * MIB, ERASE_4K_32K_64K),
+	chip([0x9d, 0x60, 0x17], "IS25LP064", 8 * MIB, ERASE_4K_32K_64K),
+	chip([0x9d, 0x60, 0x18], "IS25LP128", 16 * MIB, ERASE_4K_32K_64K),
+	// Micron
+	chip([0x20, 0xba, 0x18], "MT25QL128", 16 * MIB, ERASE_4K_32K_64K),
+	chip([0x20, 0xba, 0x19], "MT25QL256", 32 * MIB, ERASE_4K_32K_64K),
+	// Infineon / Cypress
+	chip([0x01, 0x60, 0x17], "S25FL064L", 8 * MIB, ERASE_4K_32K_64K),
+	chip([0x01, 0x60, 0x18], "S25FL128L", 16 * MIB, ERASE_4K_32K_64K),
+	// Adesto / Renesas
+	chip([0x1f, 0x86, 0x01], "AT25SF161", 2 * MIB, ERASE_4K_32K_64K),
+	chip([0x1f, 0x87, 0x01], "AT25SF321", 4 * MIB, ERASE_4K_32K_64K),
+	// XMC
+	chip([0x20, 0x40, 0x17], "XM25QH64", 8 * MIB, ERASE_4K_32K_64K),
+	// Puya
+	chip([0x85, 0x60, 0x13], "P25Q40H", MIB / 2, ERASE_4K_32K_64K),
+	chip([0x85, 0x60, 0x15], "P25Q16H", 2 * MIB, ERASE_4K_32K_64K),
+	// Microchip SST, no 32K block erase
+	chip([0xbf, 0x26, 0x41], "SST26VF016B", 2 * MIB, ERASE_4K_64K),
+	chip([0xbf, 0x26, 0x42], "SST26VF032B", 4 * MIB, ERASE_4K_64K),
+];
+
+/// Look up a chip by the bytes returned by the read ID command.
+pub fn lookup(id: [u8; 3]) -> Option<&'static Chip> {
+	let id = JedecId::from_bytes(id);
+	CHIPS.iter().find(|chip| chip.id == id)
+}
@@ -0,0 +1,637 @@
+use crate::crc::Crc32;
+use crate::nor_flash::{
+	check_slice, CheckError, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+};
+use crate::{ReadStorage, Storage};
+
+/// Size of the buffer used to stage compressed data.
+const BUFFER: usize = 64;
+
+/// Size of the header: magic word, uncompressed and compressed lengths, and CRC.
+const HEADER: usize = 16;
+
+const MAGIC: u32 = 0x5a50_4d43;
+
+/// Size of the window of [`Lz`], which is also the largest match distance.
+const WINDOW: usize = 256;
+
+/// Shortest match encoded by [`Lz`].
+const MIN_MATCH: usize = 3;
+
+/// Longest match encoded by [`Lz`].
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+
+/// Longest run of literals encoded by [`Lz`].
+const MAX_LITERALS: usize = 0x80;
+
+/// Errors returned by [`CompressedStorage`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CompressError<E> {
+	/// The storage returned an error.
+	Storage(E),
+	/// The blob does not fit in the storage, or the buffer cannot hold the stored blob.
+	Invalid(CheckError),
+	/// No blob is stored at the offset, or its data is corrupted.
+	Corrupt,
+}
+
+impl<E: NorFlashError> NorFlashError for CompressError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Storage(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Corrupt => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Compression algorithm used by [`CompressedStorage`].
+///
+/// Blobs are compressed whole when stored, but decompressed in pieces when read, so only the
+/// decoder keeps state between calls. [`Lz`] is a small built-in codec, others such as LZ4 or
+/// heatshrink can be plugged in with a wrapper type.
+pub trait Codec {
+	/// State of a streaming decompression.
+	type Decoder: Decoder;
+
+	/// Compress `input`, passing the compressed data to `output` in pieces.
+	fn compress<E, F>(&mut self, input: &[u8], output: F) -> Result<(), E>
+	where
+		F: FnMut(&[u8]) -> Result<(), E>;
+
+	/// Start decompressing a blob.
+	fn decoder(&self) -> Self::Decoder;
+}
+
+/// Streaming decompression of the data produced by a [`Codec`].
+pub trait Decoder {
+	/// Decompress from `input` into `output`, returning the number of bytes consumed and produced.
+	///
+	/// Returns `None` if the data is malformed. Progress must be made as long as `input` and
+	/// `output` are not empty, incomplete sequences at the end of `input` being kept internally.
+	fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Option<(usize, usize)>;
+}
+
+/// Heapless LZ77 codec with a 256 byte window, in the style of heatshrink.
+///
+/// A token byte below `80h` starts a run of that many plus one literal bytes, other tokens copy
+/// their low 7 bits plus 3 bytes from the distance given by the next byte plus one. Compression
+/// searches the window exhaustively, which suits assets and logs of a few kilobytes.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Lz;
+
+impl Codec for Lz {
+	type Decoder = LzDecoder;
+
+	fn compress<E, F>(&mut self, input: &[u8], mut output: F) -> Result<(), E>
+	where
+		F: FnMut(&[u8]) -> Result<(), E>,
+	{
+		let mut literals = [0; MAX_LITERALS + 1];
+		let mut count = 0;
+		let mut index = 0;
+		while index < input.len() {
+			let (length, distance) = longest_match(input, index);
+			if length >= MIN_MATCH {
+				flush_literals(&mut literals, &mut count, &mut output)?;
+				output(&[(0x80 + length - MIN_MATCH) as u8, (distance - 1) as u8])?;
+				index += length;
+				continue;
+			}
+			count += 1;
+			literals[count] = input[index];
+			index += 1;
+			if count == MAX_LITERALS {
+				flush_literals(&mut literals, &mut count, &mut output)?;
+			}
+		}
+		flush_literals(&mut literals, &mut count, &mut output)
+	}
+
+	fn decoder(&self) -> Self::Decoder {
+		LzDecoder {
+			window: [0; WINDOW],
+			written: 0,
+			state: State::Token,
+		}
+	}
+}
+
+/// Find the longest match for the data at `index` in the window before it, as its length and
+/// distance.
+fn longest_match(input: &[u8], index: usize) -> (usize, usize) {
+	let limit = MAX_MATCH.min(input.len() - index);
+	let mut best = (0, 0);
+	for distance in 1..=WINDOW.min(index) {
+		let start = index - distance;
+		let length = (0..limit)
+			.take_while(|&i| input[start + i] == input[index + i])
+			.count();
+		if length > best.0 {
+			best = (length, distance);
+			if length == limit {
+				break;
+			}
+		}
+	}
+	best
+}
+
+/// Emit the `count` literals buffered after the token byte of `literals`.
+fn flush_literals<E, F>(literals: &mut [u8], count: &mut usize, output: &mut F) -> Result<(), E>
+where
+	F: FnMut(&[u8]) -> Result<(), E>,
+{
+	if *count == 0 {
+		return Ok(());
+	}
+	literals[0] = (*count - 1) as u8;
+	output(&literals[..=*count])?;
+	*count = 0;
+	Ok(())
+}
+
+/// Position of an [`LzDecoder`] in the compressed data.
+#[derive(Debug, Copy, Clone)]
+enum State {
+	Token,
+	Literals(usize),
+	Distance(usize),
+	Copy { length: usize, distance: usize },
+}
+
+/// Decoder of the [`Lz`] codec, keeping the window of recently decompressed bytes.
+#[derive(Debug, Clone)]
+pub struct LzDecoder {
+	window: [u8; WINDOW],
+	written: usize,
+	state: State,
+}
+
+impl Decoder for LzDecoder {
+	fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Option<(usize, usize)> {
+		let (mut consumed, mut produced) = (0, 0);
+		loop {
+			let byte = match self.state {
+				State::Token | State::Literals(_) | State::Distance(_)
+					if consumed == input.len() =>
+				{
+					break
+				}
+				State::Token => {
+					let token = input[consumed] as usize;
+					consumed += 1;
+					self.state = match token {
+						0..=0x7f => State::Literals(token + 1),
+						_ => State::Distance(token - 0x80 + MIN_MATCH),
+					};
+					continue;
+				}
+				State::Distance(length) => {
+					let distance = input[consumed] as usize + 1;
+					consumed += 1;
+					if distance > self.written {
+						return None;
+					}
+					self.state = State::Copy { length, distance };
+					continue;
+				}
+				_ if produced == output.len() => break,
+				State::Literals(count) => {
+					self.state = match count {
+						1 => State::Token,
+						_ => State::Literals(count - 1),
+					};
+					consumed += 1;
+					input[consumed - 1]
+				}
+				State::Copy { length, distance } => {
+					self.state = match length {
+						1 => State::Token,
+						_ => State::Copy {
+							length: length - 1,
+							distance,
+						},
+					};
+					self.window[(self.written - distance) % WINDOW]
+				}
+			};
+			self.window[self.written % WINDOW] = byte;
+			self.written += 1;
+			output[produced] = byte;
+			produced += 1;
+		}
+		Some((consumed, produced))
+	}
+}
+
+/// Lengths of a blob stored by [`CompressedStorage`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BlobInfo {
+	/// Length of the blob once decompressed.
+	pub uncompressed: usize,
+	/// Length of the compressed data following the header.
+	pub compressed: usize,
+}
+
+impl BlobInfo {
+	/// Space taken by the blob in the storage, including its header.
+	pub fn stored(&self) -> usize {
+		HEADER + self.compressed
+	}
+}
+
+/// Storage of compressed blobs, for assets and logs which compress well.
+///
+/// Every blob is stored at an offset chosen by the caller, behind a header recording its
+/// uncompressed and compressed lengths and a CRC of its contents. Blobs are compressed whole by
+/// [`store`](Self::store), but can be read back in pieces through a [`BlobReader`], so they do
+/// not need to fit in RAM.
+pub struct CompressedStorage<S, C> {
+	storage: S,
+	codec: C,
+}
+
+impl<S: Storage, C: Codec> CompressedStorage<S, C> {
+	/// Store blobs in `storage`, compressed with `codec`.
+	pub fn new(storage: S, codec: C) -> Self {
+		Self { storage, codec }
+	}
+
+	/// Release the storage and the codec.
+	pub fn into_inner(self) -> (S, C) {
+		(self.storage, self.codec)
+	}
+
+	/// Compress `data` and store it at `offset`.
+	///
+	/// The header is written last and covers the data with a CRC, so an interrupted store is
+	/// detected when reading the blob back.
+	pub fn store(&mut self, offset: u32, data: &[u8]) -> Result<BlobInfo, CompressError<S::Error>> {
+		let capacity = self.storage.capacity();
+		let storage = &mut self.storage;
+		let mut written = HEADER;
+		let mut flush = |bytes: &[u8]| {
+			check_slice(capacity, 1, offset, written + bytes.len())
+				.map_err(CompressError::Invalid)?;
+			storage
+				.write(offset + written as u32, bytes)
+				.map_err(CompressError::Storage)?;
+			written += bytes.len();
+			Ok(())
+		};
+		let mut buffer = [0; BUFFER];
+		let mut fill = 0;
+		self.codec.compress(data, |mut bytes| {
+			while !bytes.is_empty() {
+				let length = bytes.len().min(BUFFER - fill);
+				buffer[fill..fill + length].copy_from_slice(&bytes[..length]);
+				fill += length;
+				bytes = &bytes[length..];
+				if fill == BUFFER {
+					flush(&buffer)?;
+					fill = 0;
+				}
+			}
+			Ok(())
+		})?;
+		flush(&buffer[..fill])?;
+
+		let info = BlobInfo {
+			uncompressed: data.len(),
+			compressed: written - HEADER,
+		};
+		let mut header = [0; HEADER];
+		let words = [
+			MAGIC,
+			info.uncompressed as u32,
+			info.compressed as u32,
+			Crc32::checksum(data),
+		];
+		for (chunk, word) in header.chunks_mut(4).zip(words.iter()) {
+			chunk.copy_from_slice(&word.to_le_bytes());
+		}
+		self.storage
+			.write(offset, &header)
+			.map_err(CompressError::Storage)?;
+		Ok(info)
+	}
+
+	/// The lengths of the blob stored at `offset`.
+	pub fn info(&mut self, offset: u32) -> Result<BlobInfo, CompressError<S::Error>> {
+		self.header(offset).map(|(info, _)| info)
+	}
+
+	/// Decompress the whole blob stored at `offset` into `bytes`, returning its length.
+	pub fn load(
+		&mut self,
+		offset: u32,
+		bytes: &mut [u8],
+	) -> Result<usize, CompressError<S::Error>> {
+		let mut reader = self.reader(offset)?;
+		let length = reader.remaining();
+		if length > bytes.len() {
+			return Err(CompressError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length,
+					capacity: bytes.len(),
+				},
+			)));
+		}
+		reader.read(&mut bytes[..length])
+	}
+
+	/// Start decompressing the blob stored at `offset` in pieces.
+	pub fn reader(
+		&mut self,
+		offset: u32,
+	) -> Result<BlobReader<'_, S, C::Decoder>, CompressError<S::Error>> {
+		let (info, crc) = self.header(offset)?;
+		Ok(BlobReader {
+			storage: &mut self.storage,
+			decoder: self.codec.decoder(),
+			address: offset + HEADER as u32,
+			compressed: info.compressed,
+			remaining: info.uncompressed,
+			crc: Crc32::new(),
+			expected: crc,
+			buffer: [0; BUFFER],
+			start: 0,
+			end: 0,
+		})
+	}
+
+	/// Read and check the header at `offset`, returning the lengths and the CRC of the blob.
+	fn header(&mut self, offset: u32) -> Result<(BlobInfo, u32), CompressError<S::Error>> {
+		let capacity = self.storage.capacity();
+		check_slice(capacity, 1, offset, HEADER).map_err(CompressError::Invalid)?;
+		let mut header = [0; HEADER];
+		self.storage
+			.read(offset, &mut header)
+			.map_err(CompressError::Storage)?;
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&header[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		let info = BlobInfo {
+			uncompressed: word(1) as usize,
+			compressed: word(2) as usize,
+		};
+		if word(0) != MAGIC || check_slice(capacity, 1, offset, info.stored()).is_err() {
+			return Err(CompressError::Corrupt);
+		}
+		Ok((info, word(3)))
+	}
+}
+
+/// Streaming decompression of a blob, returned by [`CompressedStorage::reader`].
+///
+/// The CRC of the blob is checked once its last byte has been read.
+pub struct BlobReader<'a, S, D> {
+	storage: &'a mut S,
+	decoder: D,
+	address: u32,
+	compressed: usize,
+	remaining: usize,
+	crc: Crc32,
+	expected: u32,
+	buffer: [u8; BUFFER],
+	start: usize,
+	end: usize,
+}
+
+impl<'a, S: ReadStorage, D: Decoder> BlobReader<'a, S, D> {
+	/// Number of decompressed bytes left to read.
+	pub fn remaining(&self) -> usize {
+		self.remaining
+	}
+
+	/// Decompress the next bytes of the blob into `bytes`, returning how many were read.
+	///
+	/// This fills `bytes` unless the end of the blob is reached, and returns 0 after it.
+	pub fn read(&mut self, bytes: &mut [u8]) -> Result<usize, CompressError<S::Error>> {
+		let length = bytes.len().min(self.remaining);
+		let mut produced = 0;
+		while produced < length {
+			if self.start == self.end && self.compressed > 0 {
+				let chunk = self.compressed.min(BUFFER);
+				self.storage
+					.read(self.address, &mut self.buffer[..chunk])
+					.map_err(CompressError::Storage)?;
+				self.address += chunk as u32;
+				self.compressed -= chunk;
+				self.start = 0;
+				self.end = chunk;
+			}
+			let (consumed, decoded) = self
+				.decoder
+				.decode(
+					&self.buffer[self.start..self.end],
+					&mut bytes[produced..length],
+				)
+				.ok_or(CompressError::Corrupt)?;
+			if consumed == 0 && decoded == 0 {
+				return Err(CompressError::Corrupt);
+			}
+			self.start += consumed;
+			produced += decoded;
+		}
+		self.crc.update(&bytes[..produced]);
+		self.remaining -= produced;
+		if self.remaining == 0 && self.crc.finish() != self.expected {
+			return Err(CompressError::Corrupt);
+		}
+		Ok(produced)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{MockError, MockFlash};
+	use crate::nor_flash::RmwNorFlashStorageOwned;
+
+	type Flash = MockFlash<4096, 1, 4, 256>;
+
+	/// Pseudo-random bytes, which do not compress.
+	fn noise(bytes: &mut [u8]) {
+		let mut state = 0x2545_f491_u32;
+		for byte in bytes {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			*byte = state as u8;
+		}
+	}
+
+	/// Compress `input` with [`Lz`] into `stream`, returning the compressed length.
+	fn compress(input: &[u8], stream: &mut [u8]) -> usize {
+		let mut length = 0;
+		Lz.compress::<(), _>(input, |bytes| {
+			stream[length..length + bytes.len()].copy_from_slice(bytes);
+			length += bytes.len();
+			Ok(())
+		})
+		.unwrap();
+		length
+	}
+
+	/// Decompress `stream` into `output` in small pieces of both, returning the decoded length.
+	fn decompress(stream: &[u8], output: &mut [u8]) -> Option<usize> {
+		let mut decoder = Lz.decoder();
+		let (mut consumed, mut produced) = (0, 0);
+		loop {
+			let input = &stream[consumed..stream.len().min(consumed + 5)];
+			let end = output.len().min(produced + 7);
+			let (used, decoded) = decoder.decode(input, &mut output[produced..end])?;
+			if used == 0 && decoded == 0 {
+				return Some(produced);
+			}
+			consumed += used;
+			produced += decoded;
+		}
+	}
+
+	fn roundtrip(input: &[u8]) -> usize {
+		let mut stream = [0; 2048];
+		let length = compress(input, &mut stream);
+		let mut output = [0; 1024];
+		assert_eq!(
+			decompress(&stream[..length], &mut output),
+			Some(input.len())
+		);
+		assert_eq!(&output[..input.len()], input);
+		length
+	}
+
+	#[test]
+	fn lz_mixed() {
+		let mut input = [0; 1024];
+		for (index, chunk) in input.chunks_mut(64).enumerate() {
+			chunk[..48].copy_from_slice(b"temperature=21.5 humidity=40 pressure=1013 ok\r\n\0");
+			noise(&mut chunk[48..]);
+			chunk[48] = index as u8;
+		}
+		assert!(roundtrip(&input) < input.len() / 2);
+		assert_eq!(roundtrip(&[]), 0);
+		assert_eq!(roundtrip(b"ab"), 3);
+	}
+
+	#[test]
+	fn lz_incompressible() {
+		let mut input = [0; 1024];
+		noise(&mut input);
+		// One token byte per run of literals at worst.
+		let length = roundtrip(&input);
+		assert!(length <= input.len() + input.len() / MAX_LITERALS);
+	}
+
+	#[test]
+	fn lz_long_runs() {
+		let mut input = [0xaa; 1024];
+		input[300..700].iter_mut().for_each(|byte| *byte = 0);
+		// Runs are longer than the longest match and than the window.
+		assert!(roundtrip(&input) < 40);
+		assert!(roundtrip(&input[..MAX_MATCH + 1]) <= 5);
+	}
+
+	#[test]
+	fn lz_truncated() {
+		let mut input = [0; 512];
+		noise(&mut input[..256]);
+		input.copy_within(..256, 256);
+		let mut stream = [0; 1024];
+		let length = compress(&input, &mut stream);
+		let mut output = [0; 512];
+		for cut in [1, 2, length / 2, length - 2, length - 1].iter() {
+			let decoded = decompress(&stream[..*cut], &mut output).unwrap();
+			assert!(decoded < input.len());
+			assert_eq!(&output[..decoded], &input[..decoded]);
+		}
+		// A match reaching before the start of the data.
+		assert_eq!(Lz.decoder().decode(&[0x80, 0x00], &mut output), None);
+		assert_eq!(
+			Lz.decoder().decode(&[0x00, 0x41, 0x80, 0x01], &mut output),
+			None
+		);
+	}
+
+	#[test]
+	fn store_load() {
+		let mut flash = Flash::new();
+		let mut blobs =
+			CompressedStorage::new(RmwNorFlashStorageOwned::<_, 256>::new(&mut flash), Lz);
+		let mut data = [0x33; 1000];
+		noise(&mut data[..100]);
+		let info = blobs.store(100, &data).unwrap();
+		assert_eq!(info.uncompressed, data.len());
+		assert!(info.compressed < 200);
+		assert_eq!(blobs.info(100), Ok(info));
+
+		let mut bytes = [0; 1024];
+		assert_eq!(blobs.load(100, &mut bytes), Ok(data.len()));
+		assert_eq!(&bytes[..data.len()], &data[..]);
+		assert_eq!(
+			blobs.load(100, &mut bytes[..999]),
+			Err(CompressError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: 1000,
+					capacity: 999,
+				}
+			)))
+		);
+
+		let mut reader = blobs.reader(100).unwrap();
+		let mut read = 0;
+		while reader.remaining() > 0 {
+			let length = reader.read(&mut bytes[read..read + 33]).unwrap();
+			read += length;
+		}
+		assert_eq!(reader.read(&mut bytes[..1]), Ok(0));
+		assert_eq!(&bytes[..read], &data[..]);
+
+		assert_eq!(blobs.info(2000), Err(CompressError::Corrupt));
+		assert!(matches!(
+			blobs.info(4090),
+			Err(CompressError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		let mut noisy = [0; 1000];
+		noise(&mut noisy);
+		assert!(matches!(
+			blobs.store(3200, &noisy),
+			Err(CompressError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn store_corrupted() {
+		let mut flash = Flash::new();
+		let mut data = [0; 600];
+		noise(&mut data[..300]);
+		let info = {
+			let mut blobs =
+				CompressedStorage::new(RmwNorFlashStorageOwned::<_, 256>::new(&mut flash), Lz);
+			let info = blobs.store(0, &data).unwrap();
+			let (mut storage, _) = blobs.into_inner();
+			storage.write(HEADER as u32 + 10, &[0x5a]).unwrap();
+			let mut blobs = CompressedStorage::new(storage, Lz);
+			let mut bytes = [0; 600];
+			assert_eq!(blobs.load(0, &mut bytes), Err(CompressError::Corrupt));
+			info
+		};
+
+		// Power is cut before the header is written.
+		flash.cut_next_write(1);
+		let mut blobs =
+			CompressedStorage::new(RmwNorFlashStorageOwned::<_, 256>::new(&mut flash), Lz);
+		assert_eq!(
+			blobs.store(1024, &data),
+			Err(CompressError::Storage(MockError::PowerLoss))
+		);
+		assert_eq!(blobs.info(1024), Err(CompressError::Corrupt));
+		assert_eq!(blobs.info(0), Ok(info));
+	}
+}
@@ -0,0 +1,418 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use crate::crc::{Checksum, Crc32};
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, write_chunked, CheckError, NorFlash, NorFlashError,
+	NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffer used to read and write headers and unaligned tails.
+const BUFFER: usize = 32;
+
+/// Size of the header: magic word, sequence number, version, length and CRC.
+const HEADER: usize = 20;
+
+const MAGIC: u32 = 0x4746_4e43;
+
+/// Errors returned by [`ConfigStore`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConfigError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The blob does not fit in a bank, or the buffer cannot hold the stored blob.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for ConfigError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Header of a stored blob.
+#[derive(Debug, Copy, Clone)]
+struct Header {
+	sequence: u32,
+	version: u32,
+	length: u32,
+	crc: u32,
+}
+
+impl Header {
+	fn to_bytes(self) -> [u8; HEADER] {
+		let mut bytes = [0; HEADER];
+		let words = [MAGIC, self.sequence, self.version, self.length, self.crc];
+		for (chunk, word) in bytes.chunks_mut(4).zip(words.iter()) {
+			chunk.copy_from_slice(&word.to_le_bytes());
+		}
+		bytes
+	}
+
+	fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&bytes[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		if word(0) != MAGIC {
+			return None;
+		}
+		Some(Self {
+			sequence: word(1),
+			version: word(2),
+			length: word(3),
+			crc: word(4),
+		})
+	}
+}
+
+/// Versioned configuration blob, double buffered in two banks of a flash.
+///
+/// Every [`store`](Self::store) goes to the bank not holding the latest blob, and the header
+/// holding the CRC of the blob is written last, so a power loss at any point leaves either the
+/// new or the previous blob readable.
+///
+/// Blobs are tagged with the version given at creation. [`load`](Self::load) hands the version of
+/// the stored blob to a migration function along with its contents, so firmware updates can
+/// change the layout of their settings and convert the blobs written by older releases.
+pub struct ConfigStore<S, C = Crc32> {
+	flash: S,
+	banks: [u32; 2],
+	size: u32,
+	version: u32,
+	checksum: PhantomData<C>,
+}
+
+impl<S: NorFlash> ConfigStore<S> {
+	/// Store blobs of `version` in `range` of `flash`, split into two banks.
+	///
+	/// **NOTE** This will panic if `range` is out of bounds, if its halves are not aligned to
+	/// `ERASE_SIZE` or cannot hold a header, or if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes,
+	/// see [`try_new`](ConfigStore::try_new).
+	pub fn new(flash: S, range: Range<u32>, version: u32) -> Self {
+		Self::new_with_checksum(flash, range, version)
+	}
+
+	/// Store blobs of `version` in `range` of `flash`, split into two banks, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if the halves of `range` are not aligned to
+	/// `ERASE_SIZE`, and [`CheckError::OutOfBounds`] if `range` is out of bounds, if its halves
+	/// cannot hold a header, or if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(flash: S, range: Range<u32>, version: u32) -> Result<Self, CheckError> {
+		Self::try_new_with_checksum(flash, range, version)
+	}
+}
+
+impl<S: NorFlash, C: Checksum> ConfigStore<S, C> {
+	/// Store blobs protected by the checksum `C` instead of [`Crc32`].
+	///
+	/// **NOTE** This will panic in the cases of [`new`](ConfigStore::new).
+	pub fn new_with_checksum(flash: S, range: Range<u32>, version: u32) -> Self {
+		match Self::try_new_with_checksum(flash, range, version) {
+			Ok(store) => store,
+			Err(CheckError::NotAligned) => panic!("Banks not aligned to ERASE_SIZE"),
+			Err(_) => {
+				panic!("Banks out of bounds or too small, or READ_SIZE or WRITE_SIZE too large")
+			}
+		}
+	}
+
+	/// Store blobs protected by the checksum `C`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns an error in the cases of [`try_new`](ConfigStore::try_new).
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new_with_checksum(
+		flash: S,
+		range: Range<u32>,
+		version: u32,
+	) -> Result<Self, CheckError> {
+		check_range(flash.capacity(), 1, range.start, range.end)?;
+		let size = (range.end - range.start) / 2;
+		check_slice(flash.capacity(), S::ERASE_SIZE, range.start, size as usize)?;
+		check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE))?;
+		check_slice(size as usize, 1, 0, Self::header_size())?;
+		Ok(Self {
+			flash,
+			banks: [range.start, range.start + size],
+			size,
+			version,
+			checksum: PhantomData,
+		})
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// The version tagging the blobs stored from now on.
+	pub fn version(&self) -> u32 {
+		self.version
+	}
+
+	/// The largest blob which can be stored.
+	pub fn max_length(&self) -> usize {
+		self.size as usize - Self::header_size()
+	}
+
+	/// Load the latest valid blob into `buffer` and convert it with `migrate`.
+	///
+	/// `migrate` is given the version the blob was stored with and its contents, and is also
+	/// called for blobs of the current version. Returns `None` if no valid blob is stored.
+	pub fn load<T, F>(
+		&mut self,
+		buffer: &mut [u8],
+		migrate: F,
+	) -> Result<Option<T>, ConfigError<S::Error>>
+	where
+		F: FnOnce(u32, &[u8]) -> T,
+	{
+		let (index, header) = match self.latest()? {
+			Some(latest) => latest,
+			None => return Ok(None),
+		};
+		let length = header.length as usize;
+		let read = (length + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+		if read > buffer.len() {
+			return Err(ConfigError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: read,
+					capacity: buffer.len(),
+				},
+			)));
+		}
+		self.flash
+			.read(self.data(index), &mut buffer[..read])
+			.map_err(ConfigError::Flash)?;
+		Ok(Some(migrate(header.version, &buffer[..length])))
+	}
+
+	/// Store `bytes` as the new blob, tagged with the current version.
+	///
+	/// The flash is [flushed](NorFlash::flush) before the header is written and again afterwards,
+	/// so the blob is durable when this returns, even on buffered flashes.
+	pub fn store(&mut self, bytes: &[u8]) -> Result<(), ConfigError<S::Error>> {
+		if bytes.len() > self.max_length() {
+			return Err(ConfigError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: bytes.len(),
+					capacity: self.max_length(),
+				},
+			)));
+		}
+		// The newest valid blob is kept, even if a corrupted blob has a higher sequence number.
+		let (index, sequence) = match self.latest()? {
+			Some((index, header)) => (1 - index, header.sequence.wrapping_add(1)),
+			None => (0, 0),
+		};
+		let (bank, data) = (self.banks[index], self.data(index));
+		self.flash
+			.erase(bank, bank + self.size)
+			.map_err(ConfigError::Flash)?;
+
+		let aligned = bytes.len() - bytes.len() % S::WRITE_SIZE;
+		write_chunked(&mut self.flash, data, &bytes[..aligned]).map_err(ConfigError::Flash)?;
+		if aligned < bytes.len() {
+			let mut tail = [0; BUFFER];
+			fill_erased::<S>(data + aligned as u32, &mut tail[..S::WRITE_SIZE]);
+			tail[..bytes.len() - aligned].copy_from_slice(&bytes[aligned..]);
+			self.flash
+				.write(data + aligned as u32, &tail[..S::WRITE_SIZE])
+				.map_err(ConfigError::Flash)?;
+		}
+		// Buffered flashes must not write the header before the blob it covers.
+		self.flash.flush().map_err(ConfigError::Flash)?;
+
+		let header = Header {
+			sequence,
+			version: self.version,
+			length: bytes.len() as u32,
+			crc: C::checksum(bytes),
+		};
+		let mut buffer = [0; BUFFER];
+		fill_erased::<S>(bank, &mut buffer[..Self::header_size()]);
+		buffer[..HEADER].copy_from_slice(&header.to_bytes());
+		self.flash
+			.write(bank, &buffer[..Self::header_size()])
+			.map_err(ConfigError::Flash)?;
+		self.flash.flush().map_err(ConfigError::Flash)
+	}
+
+	fn header_size() -> usize {
+		let align = S::READ_SIZE.max(S::WRITE_SIZE);
+		(HEADER + align - 1) / align * align
+	}
+
+	/// Start of the blob of a bank.
+	fn data(&self, index: usize) -> u32 {
+		self.banks[index] + Self::header_size() as u32
+	}
+
+	/// The header of the blob of a bank, if the blob is valid.
+	fn header(&mut self, index: usize) -> Result<Option<Header>, ConfigError<S::Error>> {
+		let mut buffer = [0; BUFFER];
+		self.flash
+			.read(self.banks[index], &mut buffer[..Self::header_size()])
+			.map_err(ConfigError::Flash)?;
+		let header = match Header::from_bytes(&buffer) {
+			Some(header) if header.length as usize <= self.max_length() => header,
+			_ => return Ok(None),
+		};
+		let chunk = BUFFER / S::READ_SIZE * S::READ_SIZE;
+		let mut crc = C::default();
+		let mut done = 0;
+		while done < header.length as usize {
+			let part = chunk.min(header.length as usize - done);
+			let read = (part + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+			self.flash
+				.read(self.data(index) + done as u32, &mut buffer[..read])
+				.map_err(ConfigError::Flash)?;
+			crc.update(&buffer[..part]);
+			done += part;
+		}
+		Ok(Some(header).filter(|header| header.crc == crc.finish()))
+	}
+
+	/// The bank and header of the newest valid blob.
+	fn latest(&mut self) -> Result<Option<(usize, Header)>, ConfigError<S::Error>> {
+		Ok(match (self.header(0)?, self.header(1)?) {
+			(Some(a), Some(b)) if b.sequence.wrapping_sub(a.sequence) as i32 > 0 => Some((1, b)),
+			(Some(a), _) => Some((0, a)),
+			(None, Some(b)) => Some((1, b)),
+			(None, None) => None,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	type Flash = MockFlash<8192, 1, 4, 1024>;
+
+	#[test]
+	fn try_new_invalid() {
+		assert!(ConfigStore::try_new(Flash::new(), 0..4096, 1).is_ok());
+		assert!(matches!(
+			ConfigStore::try_new(Flash::new(), 512..4608, 1),
+			Err(CheckError::NotAligned)
+		));
+		assert!(matches!(
+			ConfigStore::try_new(Flash::new(), 0..3072, 1),
+			Err(CheckError::NotAligned)
+		));
+		assert!(matches!(
+			ConfigStore::try_new(Flash::new(), 4096..12288, 1),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			ConfigStore::try_new(MockFlash::<8192, 64, 4, 1024>::new(), 0..4096, 1),
+			Err(CheckError::OutOfBounds(_))
+		));
+	}
+
+	#[test]
+	fn try_new_too_small() {
+		assert!(matches!(
+			ConfigStore::try_new(Flash::new(), 0..0, 1),
+			Err(CheckError::OutOfBounds(_))
+		));
+		let flash = MockFlash::<64, 1, 4, 16>::new();
+		assert!(matches!(
+			ConfigStore::try_new(flash, 0..32, 1),
+			Err(CheckError::OutOfBounds(_))
+		));
+		let store = ConfigStore::try_new(MockFlash::<64, 1, 4, 16>::new(), 0..64, 1).unwrap();
+		assert_eq!(store.max_length(), 12);
+	}
+
+	#[test]
+	fn store_load() {
+		let mut store = ConfigStore::new(Flash::new(), 0..4096, 1);
+		let mut buffer = [0; 64];
+		let mut load = |store: &mut ConfigStore<Flash>, expected: &[u8]| {
+			store.load(&mut buffer, |version, bytes| (version, bytes == expected))
+		};
+		assert_eq!(load(&mut store, b""), Ok(None));
+		assert_eq!(store.store(b"first"), Ok(()));
+		assert_eq!(load(&mut store, b"first"), Ok(Some((1, true))));
+		assert_eq!(store.store(b"second blob"), Ok(()));
+		assert_eq!(store.store(b""), Ok(()));
+		assert_eq!(load(&mut store, b""), Ok(Some((1, true))));
+		assert_eq!(store.store(b"third"), Ok(()));
+
+		let mut store = ConfigStore::new(store.into_inner(), 0..4096, 1);
+		assert_eq!(load(&mut store, b"third"), Ok(Some((1, true))));
+		let mut buffer = [0; 4];
+		assert!(matches!(
+			store.load(&mut buffer, |_, _| ()),
+			Err(ConfigError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		let long = [0; 2048];
+		assert_eq!(store.max_length(), 2048 - 20);
+		assert!(matches!(
+			store.store(&long),
+			Err(ConfigError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert_eq!(store.store(&long[..2028]), Ok(()));
+	}
+
+	#[test]
+	fn migrate() {
+		let mut store = ConfigStore::new(Flash::new(), 0..4096, 1);
+		assert_eq!(store.store(&[7]), Ok(()));
+		// The new release stores two bytes, defaulting the second one.
+		let mut store = ConfigStore::new(store.into_inner(), 0..4096, 2);
+		assert_eq!(store.version(), 2);
+		let mut buffer = [0; 4];
+		let migrate = |version: u32, bytes: &[u8]| match version {
+			1 => [bytes[0], 0],
+			_ => [bytes[0], bytes[1]],
+		};
+		assert_eq!(store.load(&mut buffer, migrate), Ok(Some([7, 0])));
+		assert_eq!(store.store(&[7, 3]), Ok(()));
+		assert_eq!(store.load(&mut buffer, migrate), Ok(Some([7, 3])));
+	}
+
+	#[test]
+	fn power_cut() {
+		let mut buffer = [0; 64];
+		let mut load = |store: &mut ConfigStore<Flash>, expected: &[u8]| {
+			store.load(&mut buffer, |_, bytes| bytes == expected)
+		};
+		let mut store = ConfigStore::new(Flash::new(), 0..4096, 1);
+		assert_eq!(store.store(b"stable"), Ok(()));
+		// Cut during the blob, then during the header of an empty blob.
+		for &(bytes, words) in [(&b"updated"[..], 1), (&b""[..], 5)].iter() {
+			let mut flash = store.into_inner();
+			flash.cut_next_write(words);
+			store = ConfigStore::new(flash, 0..4096, 1);
+			assert_eq!(
+				store.store(bytes),
+				Err(ConfigError::Flash(crate::mock::MockError::PowerLoss))
+			);
+			store = ConfigStore::new(store.into_inner(), 0..4096, 1);
+			assert_eq!(load(&mut store, b"stable"), Ok(Some(true)));
+		}
+		assert_eq!(store.store(b"updated"), Ok(()));
+		assert_eq!(load(&mut store, b"updated"), Ok(Some(true)));
+	}
+}
@@ -0,0 +1,127 @@
+use core::fmt::{self, Write};
+
+use crate::hexdump::{hexdump, DumpError};
+use crate::nor_flash::{
+	check_slice, write_chunked, CheckError, NorFlash, NorFlashError, NorFlashErrorKind,
+};
+
+/// Largest number of bytes of a `write` command.
+const BUFFER: usize = 256;
+
+const HELP: &str = "\
+read <addr> <len>    dump bytes
+erase <from> <to>    erase pages
+write <addr> <hex>   program bytes
+info                 show the geometry and features
+help                 list the commands
+";
+
+/// Errors returned by [`execute`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConsoleError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The addresses of the command are outside the flash.
+	Invalid(CheckError),
+	/// The command is unknown.
+	Unknown,
+	/// The arguments of the command are missing or malformed.
+	Syntax,
+	/// The output returned an error.
+	Format,
+}
+
+impl<E: NorFlashError> NorFlashError for ConsoleError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Unknown | Self::Syntax | Self::Format => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+impl<E> From<fmt::Error> for ConsoleError<E> {
+	fn from(_: fmt::Error) -> Self {
+		Self::Format
+	}
+}
+
+impl<E> From<DumpError<E>> for ConsoleError<E> {
+	fn from(error: DumpError<E>) -> Self {
+		match error {
+			DumpError::Flash(e) => Self::Flash(e),
+			DumpError::Format => Self::Format,
+		}
+	}
+}
+
+/// Parse a decimal number, or a hexadecimal one with a `0x` prefix.
+fn number(text: &str) -> Option<u32> {
+	match text.strip_prefix("0x") {
+		Some(hex) => u32::from_str_radix(hex, 16).ok(),
+		None => text.parse().ok(),
+	}
+}
+
+/// Decode the hexadecimal digits of `text` into `bytes`, returns the number of bytes.
+fn decode(text: &str, bytes: &mut [u8]) -> Option<usize> {
+	let text = text.as_bytes();
+	if text.len() % 2 != 0 || text.len() / 2 > bytes.len() {
+		return None;
+	}
+	let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+	for (byte, pair) in bytes.iter_mut().zip(text.chunks(2)) {
+		*byte = digit(pair[0])? << 4 | digit(pair[1])?;
+	}
+	Some(text.len() / 2)
+}
+
+/// Parse and execute the command `line` on `flash`, writing its output to `out`.
+///
+/// This gives embedded command line interfaces flash poking for free. The commands are:
+///
+/// - `read <addr> <len>`, dumping `len` bytes at `addr` as a [`hexdump`],
+/// - `erase <from> <to>`, erasing the pages of `from..to`,
+/// - `write <addr> <hex>`, programming the bytes given as hexadecimal digits, up to 256,
+/// - `info`, showing the [`FlashInfo`](crate::nor_flash::FlashInfo) of the flash,
+/// - `help`, listing the commands.
+///
+/// Numbers are decimal, or hexadecimal with a `0x` prefix. Empty lines are ignored.
+pub fn execute<S, W>(flash: &mut S, line: &str, out: &mut W) -> Result<(), ConsoleError<S::Error>>
+where
+	S: NorFlash,
+	W: Write,
+{
+	let mut words = line.split_whitespace();
+	let command = match words.next() {
+		Some(command) => command,
+		None => return Ok(()),
+	};
+	let mut argument = || words.next().ok_or(ConsoleError::Syntax);
+	let capacity = flash.capacity();
+	match command {
+		"read" => {
+			let address = number(argument()?).ok_or(ConsoleError::Syntax)?;
+			let length = number(argument()?).ok_or(ConsoleError::Syntax)?;
+			check_slice(capacity, 1, address, length as usize).map_err(ConsoleError::Invalid)?;
+			hexdump(flash, address..address + length, out, false)?;
+		}
+		"erase" => {
+			let from = number(argument()?).ok_or(ConsoleError::Syntax)?;
+			let to = number(argument()?).ok_or(ConsoleError::Syntax)?;
+			flash.erase(from, to).map_err(ConsoleError::Flash)?;
+		}
+		"write" => {
+			let address = number(argument()?).ok_or(ConsoleError::Syntax)?;
+			let mut bytes = [0; BUFFER];
+			let length = decode(argument()?, &mut bytes).ok_or(ConsoleError::Syntax)?;
+			check_slice(capacity, 1, address, length).map_err(ConsoleError::Invalid)?;
+			write_chunked(flash, address, &bytes[..length]).map_err(ConsoleError::Flash)?;
+		}
+		"info" => writeln!(out, "{}", flash.info())?,
+		"help" => out.write_str(HELP)?,
+		_ => return Err(ConsoleError::Unknown),
+	}
+	Ok(())
+}
@@ -0,0 +1,126 @@
+use crate::digest::Digest;
+
+/// Reflected polynomial of the CRC-32 used by Ethernet, zlib and most image formats.
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// Polynomial of the CRC-16 used by X.25, XMODEM and many bootloaders.
+const POLYNOMIAL_16: u16 = 0x1021;
+
+/// Checksum algorithm protecting the records of the flash data structures.
+///
+/// Bytes are added through [`Digest::update`]. [`Crc32`] is the default of every data structure,
+/// other polynomials, e.g. to match data written by existing tools, or hardware CRC units can be
+/// plugged in by implementing this trait. Checksums narrower than 32 bits are zero extended.
+pub trait Checksum: Digest + Default {
+	/// The checksum of the bytes added so far.
+	fn finish(&self) -> u32;
+
+	/// The checksum of `bytes`.
+	fn checksum(bytes: &[u8]) -> u32 {
+		let mut checksum = Self::default();
+		checksum.update(bytes);
+		checksum.finish()
+	}
+}
+
+/// Incremental CRC-32 (IEEE 802.3) computation.
+///
+/// The computation is bitwise rather than table driven, trading speed for a few bytes of code,
+/// which is negligible next to the flash accesses producing the data.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Crc32 {
+	value: u32,
+}
+
+impl Crc32 {
+	/// Start a new computation.
+	pub const fn new() -> Self {
+		Self { value: !0 }
+	}
+
+	/// Add `bytes` to the computation.
+	pub fn update(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.value ^= *byte as u32;
+			for _ in 0..8 {
+				let mask = (self.value & 1).wrapping_neg();
+				self.value = (self.value >> 1) ^ (POLYNOMIAL & mask);
+			}
+		}
+	}
+
+	/// The CRC of the bytes added so far.
+	pub fn finish(&self) -> u32 {
+		!self.value
+	}
+
+	/// The CRC of `bytes`.
+	pub fn checksum(bytes: &[u8]) -> u32 {
+		let mut crc = Self::new();
+		crc.update(bytes);
+		crc.finish()
+	}
+}
+
+impl Default for Crc32 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Checksum for Crc32 {
+	fn finish(&self) -> u32 {
+		Crc32::finish(self)
+	}
+}
+
+/// Incremental CRC-16 (CCITT) computation, with the initial value `0xffff`.
+///
+/// This halves the size of the checksums, for records small enough that a 16 bit CRC protects
+/// them well.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Crc16 {
+	value: u16,
+}
+
+impl Crc16 {
+	/// Start a new computation.
+	pub const fn new() -> Self {
+		Self { value: !0 }
+	}
+
+	/// Add `bytes` to the computation.
+	pub fn update(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.value ^= (*byte as u16) << 8;
+			for _ in 0..8 {
+				let mask = (self.value >> 15).wrapping_neg();
+				self.value = (self.value << 1) ^ (POLYNOMIAL_16 & mask);
+			}
+		}
+	}
+
+	/// The CRC of the bytes added so far.
+	pub fn finish(&self) -> u16 {
+		self.value
+	}
+
+	/// The CRC of `bytes`.
+	pub fn checksum(bytes: &[u8]) -> u16 {
+		let mut crc = Self::new();
+		crc.update(bytes);
+		crc.finish()
+	}
+}
+
+impl Default for Crc16 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Checksum for Crc16 {
+	fn finish(&self) -> u32 {
+		Crc16::finish(self) as u32
+	}
+}
@@ -0,0 +1,68 @@
+use core::ops::Range;
+
+use crate::crc::{Crc16, Crc32};
+use crate::nor_flash::ReadNorFlash;
+
+/// Incremental computation of a digest, such as a CRC or a cryptographic hash.
+///
+/// This is the minimal interface needed to feed flash contents to a digest without pulling in a
+/// hashing crate. It is implemented by [`Crc32`] and [`Crc16`], other algorithms such as SHA-256 from a crypto
+/// library can be plugged in with a small wrapper type.
+pub trait Digest {
+	/// Add `bytes` to the computation.
+	fn update(&mut self, bytes: &[u8]);
+}
+
+impl<T: Digest + ?Sized> Digest for &mut T {
+	fn update(&mut self, bytes: &[u8]) {
+		T::update(self, bytes)
+	}
+}
+
+impl Digest for Crc32 {
+	fn update(&mut self, bytes: &[u8]) {
+		Crc32::update(self, bytes)
+	}
+}
+
+impl Digest for Crc16 {
+	fn update(&mut self, bytes: &[u8]) {
+		Crc16::update(self, bytes)
+	}
+}
+
+/// Add the contents of `range` of `flash` to `digest`, reading through `scratch`.
+///
+/// The region does not need to be aligned, it is read in chunks as large as `scratch` allows,
+/// so images can be validated without buffering them whole. A `digest` feeding a CRC peripheral
+/// offloads the computation from the CPU.
+///
+/// **NOTE** This will panic if `scratch` is shorter than `READ_SIZE`.
+pub fn hash_region<S, D>(
+	flash: &mut S,
+	range: Range<u32>,
+	digest: &mut D,
+	scratch: &mut [u8],
+) -> Result<(), S::Error>
+where
+	S: ReadNorFlash,
+	D: Digest + ?Sized,
+{
+	span!("hash_region", from = range.start, to = range.end);
+	let chunk = scratch.len() - scratch.len() % S::READ_SIZE;
+	assert!(chunk > 0, "Scratch buffer shorter than READ_SIZE");
+	if range.start >= range.end {
+		return Ok(());
+	}
+	let end = (range.end as usize + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+	let mut address = range.start - range.start % S::READ_SIZE as u32;
+	while (address as usize) < end {
+		let read = chunk.min(end - address as usize);
+		flash.read(address, &mut scratch[..read])?;
+		let from = range.start.saturating_sub(address) as usize;
+		let to = ((range.end - address) as usize).min(read);
+		digest.update(&scratch[from..to]);
+		address += read as u32;
+	}
+	Ok(())
+}
@@ -0,0 +1,278 @@
+use core::ops::Range;
+
+use embedded_hal::i2c::{self, I2c};
+use embedded_hal::spi::{Operation, SpiDevice};
+
+use crate::{ReadStorage, Storage};
+
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS: u8 = 0x05;
+const READ: u8 = 0x03;
+const WRITE: u8 = 0x02;
+
+/// Write in progress bit of the status register.
+const STATUS_BUSY: u8 = 0x01;
+
+/// Bit of the 25xx040 instructions holding the ninth address bit.
+const INSTRUCTION_A8: u8 = 0x08;
+
+/// Number of acknowledge polls before giving up on the end of an I2C write cycle.
+const POLL_ATTEMPTS: usize = 1000;
+
+/// Errors returned by the EEPROM drivers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EepromError<E> {
+	/// The bus returned an error.
+	Bus(E),
+	/// The arguments are out of bounds of the device.
+	OutOfBounds,
+}
+
+fn check<E>(capacity: u32, offset: u32, length: usize) -> Result<(), EepromError<E>> {
+	if length > capacity as usize || offset as usize > capacity as usize - length {
+		return Err(EepromError::OutOfBounds);
+	}
+	Ok(())
+}
+
+/// Split `offset..offset + length` on multiples of `boundary`, passing each part to `f` along
+/// with its range in the buffer.
+fn for_each_page<E>(
+	offset: u32,
+	length: usize,
+	boundary: u32,
+	mut f: impl FnMut(u32, Range<usize>) -> Result<(), E>,
+) -> Result<(), E> {
+	let mut done = 0;
+	while done < length {
+		let address = offset + done as u32;
+		let part = ((boundary - address % boundary) as usize).min(length - done);
+		f(address, done..done + part)?;
+		done += part;
+	}
+	Ok(())
+}
+
+/// Driver for 24xx I2C EEPROMs.
+///
+/// Devices up to 2 KiB (24xx01 to 24xx16) take a 1-byte address, larger devices a 2-byte address.
+/// Address bits beyond these are sent in the low bits of the device address, which `address` must
+/// leave cleared. Writes are split on pages, and the end of every write cycle is detected by
+/// acknowledge polling.
+pub struct Eeprom24x<I2C> {
+	i2c: I2C,
+	address: u8,
+	capacity: u32,
+	page_size: u32,
+}
+
+impl<I2C> Eeprom24x<I2C>
+where
+	I2C: I2c,
+{
+	/// Create a driver for a device at the 7-bit `address`, of `capacity` bytes with pages of
+	/// `page_size` bytes.
+	pub fn new(i2c: I2C, address: u8, capacity: u32, page_size: u32) -> Self {
+		Self {
+			i2c,
+			address,
+			capacity,
+			page_size,
+		}
+	}
+
+	/// Release the I2C bus.
+	pub fn into_inner(self) -> I2C {
+		self.i2c
+	}
+
+	fn address_bytes(&self) -> usize {
+		if self.capacity <= 2048 {
+			1
+		} else {
+			2
+		}
+	}
+
+	/// Split `address` into the device address and the word address bytes.
+	fn encode(&self, address: u32) -> (u8, [u8; 2]) {
+		let device = self.address | (address >> (8 * self.address_bytes())) as u8;
+		let bytes = address.to_be_bytes();
+		(device, [bytes[2], bytes[3]])
+	}
+
+	fn wait_ready(&mut self, device: u8, word: &[u8]) -> Result<(), EepromError<I2C::Error>> {
+		let mut result = Ok(());
+		for _ in 0..POLL_ATTEMPTS {
+			// The device does not acknowledge its address until the write cycle is over.
+			result = self.i2c.write(device, word);
+			if result.is_ok() {
+				break;
+			}
+		}
+		result.map_err(EepromError::Bus)
+	}
+}
+
+impl<I2C> ReadStorage for Eeprom24x<I2C>
+where
+	I2C: I2c,
+{
+	type Error = EepromError<I2C::Error>;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check(self.capacity, offset, bytes.len())?;
+		let skip = 2 - self.address_bytes();
+		// The word address wraps around on the block selected by the device address.
+		let block = 1 << (8 * self.address_bytes());
+		for_each_page(offset, bytes.len(), block, |address, range| {
+			let (device, word) = self.encode(address);
+			self.i2c
+				.write_read(device, &word[skip..], &mut bytes[range])
+				.map_err(EepromError::Bus)
+		})
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity as usize
+	}
+}
+
+impl<I2C> Storage for Eeprom24x<I2C>
+where
+	I2C: I2c,
+{
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check(self.capacity, offset, bytes.len())?;
+		let skip = 2 - self.address_bytes();
+		for_each_page(offset, bytes.len(), self.page_size, |address, range| {
+			let (device, word) = self.encode(address);
+			self.i2c
+				.transaction(
+					device,
+					&mut [
+						i2c::Operation::Write(&word[skip..]),
+						i2c::Operation::Write(&bytes[range]),
+					],
+				)
+				.map_err(EepromError::Bus)?;
+			self.wait_ready(device, &word[skip..])
+		})
+	}
+}
+
+/// Driver for 25xx SPI EEPROMs.
+///
+/// Devices up to 512 bytes take a 1-byte address, with the ninth bit of the 25xx040 sent in the
+/// instruction. Devices up to 64 KiB take a 2-byte address, larger devices a 3-byte address.
+/// Writes are split on pages, and the end of every write cycle is detected by polling the status
+/// register.
+pub struct Eeprom25x<SPI> {
+	spi: SPI,
+	capacity: u32,
+	page_size: u32,
+}
+
+impl<SPI> Eeprom25x<SPI>
+where
+	SPI: SpiDevice,
+{
+	/// Create a driver for a device of `capacity` bytes with pages of `page_size` bytes.
+	pub fn new(spi: SPI, capacity: u32, page_size: u32) -> Self {
+		Self {
+			spi,
+			capacity,
+			page_size,
+		}
+	}
+
+	/// Release the SPI device.
+	pub fn into_inner(self) -> SPI {
+		self.spi
+	}
+
+	/// Build the instruction and address header, returns its length.
+	fn header(&self, instruction: u8, address: u32, header: &mut [u8; 4]) -> usize {
+		let bytes = address.to_be_bytes();
+		if self.capacity <= 512 {
+			header[0] = instruction | if address > 0xff { INSTRUCTION_A8 } else { 0 };
+			header[1] = bytes[3];
+			2
+		} else if self.capacity <= 0x1_0000 {
+			header[0] = instruction;
+			header[1..3].copy_from_slice(&bytes[2..]);
+			3
+		} else {
+			header[0] = instruction;
+			header[1..4].copy_from_slice(&bytes[1..]);
+			4
+		}
+	}
+
+	fn wait_ready(&mut self) -> Result<(), EepromError<SPI::Error>> {
+		let mut status = [0];
+		loop {
+			self.spi
+				.transaction(&mut [
+					Operation::Write(&[READ_STATUS]),
+					Operation::Read(&mut status),
+				])
+				.map_err(EepromError::Bus)?;
+			if status[0] & STATUS_BUSY == 0 {
+				return Ok(());
+			}
+		}
+	}
+}
+
+impl<SPI> ReadStorage for Eeprom25x<SPI>
+where
+	SPI: SpiDevice,
+{
+	type Error = EepromError<SPI::Error>;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check(self.capacity, offset, bytes.len())?;
+		// The ninth address bit of the 25xx040 is only sent at the start of the read.
+		let block = if self.capacity <= 512 {
+			0x100
+		} else {
+			self.capacity
+		};
+		for_each_page(offset, bytes.len(), block, |address, range| {
+			let mut header = [0; 4];
+			let length = self.header(READ, address, &mut header);
+			self.spi
+				.transaction(&mut [
+					Operation::Write(&header[..length]),
+					Operation::Read(&mut bytes[range]),
+				])
+				.map_err(EepromError::Bus)
+		})
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity as usize
+	}
+}
+
+impl<SPI> Storage for Eeprom25x<SPI>
+where
+	SPI: SpiDevice,
+{
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check(self.capacity, offset, bytes.len())?;
+		for_each_page(offset, bytes.len(), self.page_size, |address, range| {
+			let mut header = [0; 4];
+			let length = self.header(WRITE, address, &mut header);
+			self.spi.write(&[WRITE_ENABLE]).map_err(EepromError::Bus)?;
+			self.spi
+				.transaction(&mut [
+					Operation::Write(&header[..length]),
+					Operation::Write(&bytes[range]),
+				])
+				.map_err(EepromError::Bus)?;
+			self.wait_ready()
+		})
+	}
+}
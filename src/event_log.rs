@@ -0,0 +1,737 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use crate::crc::{Checksum, Crc32};
+use crate::gc::{GcPolicy, GcStatus};
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, is_erased, write_chunked, CheckError, NorFlash,
+	NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffer used to read and write headers and unaligned tails.
+const BUFFER: usize = 32;
+
+/// Size of the header of a record (timestamp and CRC) and of a page (magic word, sequence number
+/// and its complement).
+const HEADER: usize = 12;
+
+/// Magic word opening the header of a page.
+const MAGIC: u32 = 0x474F_4C45;
+
+/// Errors returned by [`EventLog`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LogError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The range or the index is not aligned to `ERASE_SIZE`, out of bounds or too small.
+	Invalid(CheckError),
+	/// The timestamp of the appended record is older than the last record.
+	OutOfOrder,
+}
+
+impl<E: NorFlashError> NorFlashError for LogError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::OutOfOrder => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Append-only log of records of `RECORD` bytes with a timestamp, e.g. for data loggers.
+///
+/// The erase pages of the log are used as a ring. Records are appended in order of their
+/// timestamps, and when the log is full the oldest page is erased to make room. Records can also
+/// be dropped explicitly with [`reclaim`](Self::reclaim) once they are past their retention
+/// period. Records in a timestamp range are found by binary search over the pages, then over the
+/// records of a page.
+///
+/// Every page starts with a header holding a sequence number, incremented for every page opened,
+/// so that the page being written is found on mount even when timestamps repeat.
+///
+/// The header of a record, holding its timestamp and a checksum computed by `C`, is written before
+/// its data, so a record cut by a power loss is skipped by reads instead of preventing further
+/// appends.
+///
+/// Appending a record erases a page when the previous one is full, [`gc_step`](Self::gc_step)
+/// erases the next page ahead of time instead, e.g. during idle time.
+///
+/// Mounting reads the header of every page. For devices with tight boot time budgets,
+/// [`mount_indexed`](EventLog::mount_indexed) keeps track of the page being written in an index page,
+/// so that mounting only takes a few reads and binary searches.
+pub struct EventLog<S, const RECORD: usize, C = Crc32> {
+	flash: S,
+	range: Range<u32>,
+	/// Page being written, along with its sequence number.
+	head: u32,
+	sequence: u32,
+	/// Number of records in the page being written.
+	next: u32,
+	/// Oldest page holding records.
+	tail: u32,
+	/// Timestamp of the last record.
+	last: Option<u64>,
+	/// Whether the next page to write is erased already.
+	prepared: bool,
+	/// Address of the index page and number of entries written in it.
+	index: Option<(u32, u32)>,
+	reads: usize,
+	mount_reads: usize,
+	checksum: PhantomData<C>,
+}
+
+impl<S: NorFlash, const RECORD: usize> EventLog<S, RECORD> {
+	/// Mount the log in `range` of `flash`, finding the oldest and newest records.
+	///
+	/// # Errors
+	///
+	/// Returns [`LogError::Invalid`] if `range` is not aligned to `ERASE_SIZE`, is out of bounds or
+	/// holds fewer than two pages, if a page cannot hold a record, or if `READ_SIZE` or
+	/// `WRITE_SIZE` is over 32 bytes.
+	pub fn mount(flash: S, range: Range<u32>) -> Result<Self, LogError<S::Error>> {
+		Self::mount_with_checksum(flash, range)
+	}
+
+	/// Mount the log in `range` of `flash`, with the erase page at `index` recording the page
+	/// being written.
+	///
+	/// The index is checked against the log, and rebuilt by scanning the log if it does not
+	/// match, e.g. on first use or after a power loss while erasing it.
+	///
+	/// # Errors
+	///
+	/// Returns [`LogError::Invalid`] if `index` is not aligned to `ERASE_SIZE`, is out of bounds or
+	/// lies within `range`, and in the cases of [`mount`](EventLog::mount).
+	pub fn mount_indexed(
+		flash: S,
+		range: Range<u32>,
+		index: u32,
+	) -> Result<Self, LogError<S::Error>> {
+		Self::mount_indexed_with_checksum(flash, range, index)
+	}
+}
+
+impl<S: NorFlash, const RECORD: usize, C: Checksum> EventLog<S, RECORD, C> {
+	/// Mount the log with records protected by the checksum `C` instead of [`Crc32`].
+	///
+	/// # Errors
+	///
+	/// Returns [`LogError::Invalid`] in the cases of [`mount`](EventLog::mount).
+	pub fn mount_with_checksum(flash: S, range: Range<u32>) -> Result<Self, LogError<S::Error>> {
+		let mut log = Self::new(flash, range, None).map_err(LogError::Invalid)?;
+		if let Some(head) = log.scan()? {
+			log.locate(head)?;
+		}
+		log.mount_reads = log.reads;
+		Ok(log)
+	}
+
+	/// Mount the indexed log with records protected by the checksum `C` instead of [`Crc32`].
+	///
+	/// # Errors
+	///
+	/// Returns [`LogError::Invalid`] in the cases of [`mount_indexed`](EventLog::mount_indexed).
+	pub fn mount_indexed_with_checksum(
+		flash: S,
+		range: Range<u32>,
+		index: u32,
+	) -> Result<Self, LogError<S::Error>> {
+		check_slice(flash.capacity(), S::ERASE_SIZE, index, S::ERASE_SIZE)
+			.map_err(LogError::Invalid)?;
+		if index < range.end && range.start < index + S::ERASE_SIZE as u32 {
+			return Err(LogError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: index,
+					length: S::ERASE_SIZE,
+					capacity: range.start as usize,
+				},
+			)));
+		}
+		let mut log = Self::new(flash, range, Some((index, 0))).map_err(LogError::Invalid)?;
+		let (entries, hint) = log.read_index()?;
+		log.index = Some((index, entries));
+		let head = match hint {
+			Some(head) if log.is_head(head)? => Some(head),
+			_ => {
+				let head = log.scan()?;
+				if let Some(head) = head {
+					log.write_index(head)?;
+				}
+				head
+			}
+		};
+		if let Some(head) = head {
+			log.locate(head)?;
+		}
+		log.mount_reads = log.reads;
+		Ok(log)
+	}
+
+	/// Number of reads of the flash performed by the mount.
+	///
+	/// This is proportional to the number of pages for [`mount`](EventLog::mount), and to its
+	/// logarithm for [`mount_indexed`](EventLog::mount_indexed) once the index is built.
+	pub fn mount_reads(&self) -> usize {
+		self.mount_reads
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Timestamp of the last record.
+	pub fn last(&self) -> Option<u64> {
+		self.last
+	}
+
+	/// Append a record with `timestamp`, which must not be older than the last record.
+	pub fn append(
+		&mut self,
+		timestamp: u64,
+		record: &[u8; RECORD],
+	) -> Result<(), LogError<S::Error>> {
+		if self.last.map_or(false, |last| timestamp < last) {
+			return Err(LogError::OutOfOrder);
+		}
+		if self.last.is_none() || self.next == Self::per_page() {
+			self.open_page()?;
+		}
+
+		let address = self.address(self.head, self.next);
+		let mut crc = C::default();
+		crc.update(&timestamp.to_le_bytes());
+		crc.update(record);
+		let mut header = [0; BUFFER];
+		fill_erased::<S>(address, &mut header[..Self::header_size()]);
+		header[..8].copy_from_slice(&timestamp.to_le_bytes());
+		header[8..12].copy_from_slice(&crc.finish().to_le_bytes());
+		// Consume the slot before writing the data, so that it is not reused after a failure.
+		self.next += 1;
+		self.last = Some(timestamp);
+		self.flash
+			.write(address, &header[..Self::header_size()])
+			.map_err(LogError::Flash)?;
+
+		let data = address + Self::header_size() as u32;
+		let aligned = RECORD - RECORD % S::WRITE_SIZE;
+		write_chunked(&mut self.flash, data, &record[..aligned]).map_err(LogError::Flash)?;
+		if aligned < RECORD {
+			let mut tail = [0; BUFFER];
+			fill_erased::<S>(data + aligned as u32, &mut tail[..S::WRITE_SIZE]);
+			tail[..RECORD - aligned].copy_from_slice(&record[aligned..]);
+			self.flash
+				.write(data + aligned as u32, &tail[..S::WRITE_SIZE])
+				.map_err(LogError::Flash)?;
+		}
+		Ok(())
+	}
+
+	/// Call `f` with every valid record whose timestamp is in `range`, in order.
+	pub fn read_range<F>(&mut self, range: Range<u64>, mut f: F) -> Result<(), LogError<S::Error>>
+	where
+		F: FnMut(u64, &[u8; RECORD]),
+	{
+		if self.last.is_none() {
+			return Ok(());
+		}
+		let pages = (self.head + self.pages() - self.tail) % self.pages() + 1;
+
+		// Last page starting before the range, as records of a page are not older than its first.
+		let (mut low, mut high) = (0, pages);
+		while high - low > 1 {
+			let middle = (low + high) / 2;
+			match self.timestamp(self.physical(middle), 0)? {
+				Some(first) if first < range.start => low = middle,
+				_ => high = middle,
+			}
+		}
+
+		for logical in low..pages {
+			let page = self.physical(logical);
+			let filled = self.filled(page)?;
+			// First record of the page in the range.
+			let (mut low, mut high) = (0, filled);
+			while low < high {
+				let middle = (low + high) / 2;
+				match self.timestamp(page, middle)? {
+					Some(timestamp) if timestamp < range.start => low = middle + 1,
+					_ => high = middle,
+				}
+			}
+			let mut record = [0; RECORD];
+			for slot in low..filled {
+				if let Some(timestamp) = self.record(page, slot, &mut record)? {
+					if timestamp >= range.end {
+						return Ok(());
+					}
+					f(timestamp, &record);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Erase the oldest pages while all their records are older than `before`, returns the
+	/// number of erased pages.
+	///
+	/// The page being written is never erased.
+	pub fn reclaim(&mut self, before: u64) -> Result<usize, LogError<S::Error>> {
+		let mut erased = 0;
+		while self.last.is_some() && self.tail != self.head {
+			let filled = self.filled(self.tail)?;
+			let newest = match filled {
+				0 => None,
+				filled => self.timestamp(self.tail, filled - 1)?,
+			};
+			if newest.map_or(false, |newest| newest >= before) {
+				break;
+			}
+			let address = self.page_address(self.tail);
+			self.flash
+				.erase(address, address + S::ERASE_SIZE as u32)
+				.map_err(LogError::Flash)?;
+			self.tail = (self.tail + 1) % self.pages();
+			erased += 1;
+		}
+		Ok(erased)
+	}
+
+	/// The space left before a page has to be erased, and whether erasing it drops records.
+	pub fn gc_status(&self) -> GcStatus {
+		let page = Self::per_page() as usize * Self::stride();
+		let mut free = match self.last {
+			Some(_) => (Self::per_page() - self.next) as usize * Self::stride(),
+			None => 0,
+		};
+		if self.prepared {
+			free += page;
+		}
+		GcStatus {
+			free,
+			discards: !self.prepared && self.last.is_some() && self.next_page() == self.tail,
+		}
+	}
+
+	/// Erase the next page to write ahead of time, dropping its records if it is the oldest one.
+	///
+	/// Returns `false` if the next page is erased already.
+	pub fn gc_step(&mut self) -> Result<bool, LogError<S::Error>> {
+		if self.prepared {
+			return Ok(false);
+		}
+		self.prepare()?;
+		self.prepared = true;
+		Ok(true)
+	}
+
+	/// Run [`gc_step`](Self::gc_step) if `policy` agrees, returns whether a page was erased.
+	pub fn gc<P: GcPolicy>(&mut self, mut policy: P) -> Result<bool, LogError<S::Error>> {
+		if !policy.should_collect(&self.gc_status()) {
+			return Ok(false);
+		}
+		self.gc_step()
+	}
+
+	fn new(flash: S, range: Range<u32>, index: Option<(u32, u32)>) -> Result<Self, CheckError> {
+		check_range(flash.capacity(), S::ERASE_SIZE, range.start, range.end)?;
+		check_slice((range.end - range.start) as usize, 1, 0, 2 * S::ERASE_SIZE)?;
+		check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE))?;
+		check_slice(S::ERASE_SIZE, 1, 0, Self::header_size() + Self::stride())?;
+		Ok(Self {
+			flash,
+			range,
+			head: 0,
+			sequence: 0,
+			next: 0,
+			tail: 0,
+			last: None,
+			prepared: false,
+			index,
+			reads: 0,
+			mount_reads: 0,
+			checksum: PhantomData,
+		})
+	}
+
+	/// Find the page being written by reading the header of every page.
+	fn scan(&mut self) -> Result<Option<u32>, LogError<S::Error>> {
+		let mut head = None;
+		for index in 0..self.pages() {
+			if self.is_head(index)? {
+				head = Some(index);
+			}
+		}
+		Ok(head)
+	}
+
+	/// Whether `page` is the page being written: pages are opened in ring order with consecutive
+	/// sequence numbers, and the page after the head is either empty or the tail.
+	fn is_head(&mut self, page: u32) -> Result<bool, LogError<S::Error>> {
+		if page >= self.pages() {
+			return Ok(false);
+		}
+		let sequence = match self.page_sequence(page)? {
+			Some(sequence) => sequence,
+			None => return Ok(false),
+		};
+		let next = self.page_sequence((page + 1) % self.pages())?;
+		Ok(next != Some(sequence.wrapping_add(1)))
+	}
+
+	/// Find the tail and the last record of the log written up to `head`.
+	fn locate(&mut self, head: u32) -> Result<(), LogError<S::Error>> {
+		let pages = self.pages();
+		self.head = head;
+		// The pages after the head are empty up to the tail, then hold records up to the head.
+		let (mut low, mut high) = (1, pages);
+		while low < high {
+			let middle = (low + high) / 2;
+			if self.page_sequence((head + middle) % pages)?.is_some() {
+				high = middle;
+			} else {
+				low = middle + 1;
+			}
+		}
+		self.tail = (head + low) % pages;
+		self.sequence = self.page_sequence(head)?.unwrap_or(0);
+		self.next = self.filled(head)?;
+		// The head is empty if a power loss cut its opening, the last record is then the last one
+		// of the previous page.
+		self.last = match self.next {
+			0 if head == self.tail => None,
+			0 => {
+				let previous = (head + pages - 1) % pages;
+				match self.filled(previous)? {
+					0 => None,
+					filled => self.timestamp(previous, filled - 1)?,
+				}
+			}
+			next => self.timestamp(head, next - 1)?,
+		};
+		Ok(())
+	}
+
+	/// Size of an entry of the index: page number and its complement.
+	fn entry_size() -> usize {
+		(8 + Self::align() - 1) / Self::align() * Self::align()
+	}
+
+	/// Number of entries written in the index and page recorded by the last one.
+	fn read_index(&mut self) -> Result<(u32, Option<u32>), LogError<S::Error>> {
+		let address = match self.index {
+			Some((address, _)) => address,
+			None => return Ok((0, None)),
+		};
+		let size = Self::entry_size();
+		let entry = |log: &mut Self, index: u32| {
+			let mut buffer = [0; BUFFER];
+			log.read_flash(address + index * size as u32, &mut buffer[..size])
+				.map_err(LogError::Flash)?;
+			Ok(buffer)
+		};
+		// Entries are written in order.
+		let (mut low, mut high) = (0, (S::ERASE_SIZE / size) as u32);
+		while low < high {
+			let middle = (low + high) / 2;
+			let buffer = entry(self, middle)?;
+			if is_erased::<S>(address + middle * size as u32, &buffer[..size]) {
+				high = middle;
+			} else {
+				low = middle + 1;
+			}
+		}
+		if low == 0 {
+			return Ok((0, None));
+		}
+		let buffer = entry(self, low - 1)?;
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&buffer[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		Ok((low, Some(word(0)).filter(|page| word(1) == !page)))
+	}
+
+	/// Record `page` as the page being written in the index.
+	fn write_index(&mut self, page: u32) -> Result<(), LogError<S::Error>> {
+		let (address, mut entries) = match self.index {
+			Some(index) => index,
+			None => return Ok(()),
+		};
+		let size = Self::entry_size();
+		if entries as usize >= S::ERASE_SIZE / size {
+			self.flash
+				.erase(address, address + S::ERASE_SIZE as u32)
+				.map_err(LogError::Flash)?;
+			entries = 0;
+		}
+		let offset = address + entries * size as u32;
+		let mut entry = [0; BUFFER];
+		fill_erased::<S>(offset, &mut entry[..size]);
+		entry[..4].copy_from_slice(&page.to_le_bytes());
+		entry[4..8].copy_from_slice(&(!page).to_le_bytes());
+		// Consume the entry before writing it, so that it is not reused after a failure.
+		self.index = Some((address, entries + 1));
+		self.flash
+			.write(offset, &entry[..size])
+			.map_err(LogError::Flash)
+	}
+
+	/// Read from the flash, counting the reads.
+	fn read_flash(&mut self, address: u32, bytes: &mut [u8]) -> Result<(), S::Error> {
+		self.reads += 1;
+		self.flash.read(address, bytes)
+	}
+
+	fn align() -> usize {
+		S::READ_SIZE.max(S::WRITE_SIZE)
+	}
+
+	fn header_size() -> usize {
+		(HEADER + Self::align() - 1) / Self::align() * Self::align()
+	}
+
+	fn stride() -> usize {
+		Self::header_size() + (RECORD + Self::align() - 1) / Self::align() * Self::align()
+	}
+
+	fn per_page() -> u32 {
+		(S::ERASE_SIZE.saturating_sub(Self::header_size()) / Self::stride()) as u32
+	}
+
+	fn pages(&self) -> u32 {
+		(self.range.end - self.range.start) / S::ERASE_SIZE as u32
+	}
+
+	/// Page to write once the current one is full.
+	fn next_page(&self) -> u32 {
+		match self.last {
+			Some(_) => (self.head + 1) % self.pages(),
+			None => self.head,
+		}
+	}
+
+	/// Erase the next page to write, dropping it from the log if it is the oldest one.
+	fn prepare(&mut self) -> Result<(), LogError<S::Error>> {
+		let page = self.next_page();
+		let address = self.page_address(page);
+		self.flash
+			.erase(address, address + S::ERASE_SIZE as u32)
+			.map_err(LogError::Flash)?;
+		if self.last.is_some() && page == self.tail {
+			self.tail = (self.tail + 1) % self.pages();
+		}
+		Ok(())
+	}
+
+	/// Erase the next page to write unless done ahead of time, and write its header.
+	fn open_page(&mut self) -> Result<(), LogError<S::Error>> {
+		if !self.prepared {
+			self.prepare()?;
+		}
+		let page = self.next_page();
+		let sequence = match self.last {
+			Some(_) => self.sequence.wrapping_add(1),
+			None => self.sequence,
+		};
+		self.write_index(page)?;
+		let address = self.page_address(page);
+		let mut header = [0; BUFFER];
+		fill_erased::<S>(address, &mut header[..Self::header_size()]);
+		header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+		header[4..8].copy_from_slice(&sequence.to_le_bytes());
+		header[8..12].copy_from_slice(&(!sequence).to_le_bytes());
+		self.prepared = false;
+		self.flash
+			.write(address, &header[..Self::header_size()])
+			.map_err(LogError::Flash)?;
+		self.head = page;
+		self.sequence = sequence;
+		self.next = 0;
+		Ok(())
+	}
+
+	/// Sequence number of a page, `None` if the page is not opened.
+	fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, LogError<S::Error>> {
+		let address = self.page_address(page);
+		let mut buffer = [0; BUFFER];
+		let header = &mut buffer[..Self::header_size()];
+		self.read_flash(address, header).map_err(LogError::Flash)?;
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&header[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		let sequence = word(1);
+		Ok(Some(sequence).filter(|_| word(0) == MAGIC && word(2) == !sequence))
+	}
+
+	/// Page at `logical` pages from the tail.
+	fn physical(&self, logical: u32) -> u32 {
+		(self.tail + logical) % self.pages()
+	}
+
+	fn page_address(&self, page: u32) -> u32 {
+		self.range.start + page * S::ERASE_SIZE as u32
+	}
+
+	/// Address of a slot, records following the header of the page.
+	fn address(&self, page: u32, slot: u32) -> u32 {
+		self.page_address(page) + (Self::header_size() + slot as usize * Self::stride()) as u32
+	}
+
+	/// Read the header of a slot, `None` if the slot is erased.
+	fn header(&mut self, page: u32, slot: u32) -> Result<Option<(u64, u32)>, LogError<S::Error>> {
+		let address = self.address(page, slot);
+		let mut buffer = [0; BUFFER];
+		let header = &mut buffer[..Self::header_size()];
+		self.read_flash(address, header).map_err(LogError::Flash)?;
+		if is_erased::<S>(address, header) {
+			return Ok(None);
+		}
+		let mut timestamp = [0; 8];
+		timestamp.copy_from_slice(&header[..8]);
+		let mut crc = [0; 4];
+		crc.copy_from_slice(&header[8..12]);
+		Ok(Some((
+			u64::from_le_bytes(timestamp),
+			u32::from_le_bytes(crc),
+		)))
+	}
+
+	fn timestamp(&mut self, page: u32, slot: u32) -> Result<Option<u64>, LogError<S::Error>> {
+		Ok(self.header(page, slot)?.map(|(timestamp, _)| timestamp))
+	}
+
+	/// Number of written slots of a page, which are written in order.
+	fn filled(&mut self, page: u32) -> Result<u32, LogError<S::Error>> {
+		let (mut low, mut high) = (0, Self::per_page());
+		while low < high {
+			let middle = (low + high) / 2;
+			if self.header(page, middle)?.is_some() {
+				low = middle + 1;
+			} else {
+				high = middle;
+			}
+		}
+		Ok(low)
+	}
+
+	/// Read a record into `record`, returns its timestamp or `None` if the slot is erased or the
+	/// record is corrupted.
+	fn record(
+		&mut self,
+		page: u32,
+		slot: u32,
+		record: &mut [u8; RECORD],
+	) -> Result<Option<u64>, LogError<S::Error>> {
+		let (timestamp, expected) = match self.header(page, slot)? {
+			Some(header) => header,
+			None => return Ok(None),
+		};
+		let data = self.address(page, slot) + Self::header_size() as u32;
+		let aligned = RECORD - RECORD % S::READ_SIZE;
+		self.read_flash(data, &mut record[..aligned])
+			.map_err(LogError::Flash)?;
+		if aligned < RECORD {
+			let mut tail = [0; BUFFER];
+			self.read_flash(data + aligned as u32, &mut tail[..S::READ_SIZE])
+				.map_err(LogError::Flash)?;
+			record[aligned..].copy_from_slice(&tail[..RECORD - aligned]);
+		}
+		let mut crc = C::default();
+		crc.update(&timestamp.to_le_bytes());
+		crc.update(record);
+		Ok(Some(timestamp).filter(|_| crc.finish() == expected))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::crc::Crc16;
+	use crate::mock::MockFlash;
+
+	type Flash = MockFlash<16384, 1, 4, 1024>;
+
+	fn records<S: NorFlash, C: Checksum>(log: &mut EventLog<S, 4, C>) -> [u32; 2] {
+		let (mut count, mut last) = (0, 0);
+		log.read_range(0..u64::MAX, |_, record| {
+			count += 1;
+			last = u32::from_le_bytes(*record);
+		})
+		.unwrap();
+		[count, last]
+	}
+
+	#[test]
+	fn mount_invalid() {
+		let invalid = |result: Result<EventLog<Flash, 4>, _>| match result {
+			Err(LogError::Invalid(error)) => error,
+			_ => panic!("Range accepted"),
+		};
+		assert_eq!(
+			invalid(EventLog::mount(Flash::new(), 512..4096)),
+			CheckError::NotAligned
+		);
+		assert!(matches!(
+			invalid(EventLog::mount(Flash::new(), 0..1024)),
+			CheckError::OutOfBounds(_)
+		));
+		assert!(matches!(
+			invalid(EventLog::mount(Flash::new(), 0..32768)),
+			CheckError::OutOfBounds(_)
+		));
+		assert_eq!(
+			invalid(EventLog::mount_indexed(Flash::new(), 0..4096, 4200)),
+			CheckError::NotAligned
+		);
+		assert!(matches!(
+			invalid(EventLog::mount_indexed(Flash::new(), 0..4096, 1024)),
+			CheckError::OutOfBounds(_)
+		));
+		assert!(matches!(
+			EventLog::<_, 1024>::mount(Flash::new(), 0..4096),
+			Err(LogError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn remount_with_repeated_timestamps() {
+		let mut log = EventLog::<_, 4>::mount(Flash::new(), 0..4096).unwrap();
+		for index in 0..300u32 {
+			log.append(7, &index.to_le_bytes()).unwrap();
+		}
+		let before = records(&mut log);
+		assert_eq!(before[1], 299);
+
+		let mut log = EventLog::<_, 4>::mount(log.into_inner(), 0..4096).unwrap();
+		assert_eq!(log.last(), Some(7));
+		assert_eq!(records(&mut log), before);
+		log.append(7, &300u32.to_le_bytes()).unwrap();
+		assert_eq!(records(&mut log), [before[0] + 1, 300]);
+
+		let mut log = EventLog::<_, 4>::mount_indexed(log.into_inner(), 0..4096, 8192).unwrap();
+		assert_eq!(records(&mut log), [before[0] + 1, 300]);
+		let mut log = EventLog::<_, 4>::mount_indexed(log.into_inner(), 0..4096, 8192).unwrap();
+		assert_eq!(log.last(), Some(7));
+		assert_eq!(records(&mut log), [before[0] + 1, 300]);
+	}
+
+	#[test]
+	fn custom_checksum() {
+		let mut log = EventLog::<_, 4, Crc16>::mount_with_checksum(Flash::new(), 0..4096).unwrap();
+		log.append(1, &42u32.to_le_bytes()).unwrap();
+		log.append(2, &43u32.to_le_bytes()).unwrap();
+		let mut log =
+			EventLog::<_, 4, Crc16>::mount_indexed_with_checksum(log.into_inner(), 0..4096, 8192)
+				.unwrap();
+		assert_eq!(records(&mut log), [2, 43]);
+
+		// Records are skipped when read with another checksum.
+		let mut log = EventLog::<_, 4>::mount(log.into_inner(), 0..4096).unwrap();
+		assert_eq!(records(&mut log), [0, 0]);
+	}
+}
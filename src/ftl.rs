@@ -0,0 +1,435 @@
+#[cfg(feature = "alloc")]
+use alloc::collections::VecDeque;
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::marker::PhantomData;
+
+#[cfg(feature = "alloc")]
+use crate::addr::align_up;
+#[cfg(feature = "alloc")]
+use crate::crc::{Checksum, Crc32};
+#[cfg(feature = "alloc")]
+use crate::nor_flash::{
+	check_slice, fill_erased, is_erased, write_chunked, CheckError, NorFlash, NorFlashError,
+	NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of a logical sector of a [`BlockDevice`].
+pub const SECTOR_SIZE: usize = 512;
+
+/// Storage of fixed size sectors which can be rewritten in place, e.g. for FAT filesystems and
+/// USB mass storage.
+///
+/// Offsets are in [`SECTOR_SIZE`] sectors, and buffers hold a whole number of sectors.
+pub trait BlockDevice {
+	/// Errors returned by the device.
+	type Error: core::fmt::Debug;
+
+	/// Number of sectors of the device.
+	fn sector_count(&self) -> u32;
+
+	/// Read the sectors starting at `sector` into `data`.
+	fn read(&mut self, sector: u32, data: &mut [u8]) -> Result<(), Self::Error>;
+
+	/// Write `data` to the sectors starting at `sector`, replacing their contents.
+	fn write(&mut self, sector: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+	/// Make the previous writes durable. The default implementation does nothing.
+	fn sync(&mut self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+impl<T: BlockDevice> BlockDevice for &mut T {
+	type Error = T::Error;
+
+	fn sector_count(&self) -> u32 {
+		T::sector_count(self)
+	}
+
+	fn read(&mut self, sector: u32, data: &mut [u8]) -> Result<(), Self::Error> {
+		T::read(self, sector, data)
+	}
+
+	fn write(&mut self, sector: u32, data: &[u8]) -> Result<(), Self::Error> {
+		T::write(self, sector, data)
+	}
+
+	fn sync(&mut self) -> Result<(), Self::Error> {
+		T::sync(self)
+	}
+}
+
+/// Size of the header of a page: magic, sector, sequence number and CRC.
+#[cfg(feature = "alloc")]
+const HEADER: usize = 16;
+
+/// Magic number of a page holding a sector.
+#[cfg(feature = "alloc")]
+const MAGIC: u32 = 0x5346_544c;
+
+/// Page of an unmapped sector.
+#[cfg(feature = "alloc")]
+const NONE: u32 = u32::MAX;
+
+/// Errors returned by [`Ftl`].
+#[cfg(feature = "alloc")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FtlError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The sectors are not a whole number or out of bounds, or the flash is too small.
+	Invalid(CheckError),
+	/// No block is left for garbage collection, which only happens on damaged flash.
+	Full,
+}
+
+#[cfg(feature = "alloc")]
+impl<E: NorFlashError> NorFlashError for FtlError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Full => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Flash translation layer exposing a NOR flash as a [`BlockDevice`].
+///
+/// Sectors are never rewritten in place: every write goes to the next free page of the flash, with
+/// a header holding the sector number, a sequence number and a checksum computed by `C`, and the
+/// page holding the previous contents of the sector becomes stale. Mounting scans all pages and
+/// maps every sector to its newest valid page, so a write interrupted by a power loss leaves the
+/// previous contents. Sectors never written read as zeros.
+///
+/// Once the free blocks run out, the block with the fewest valid pages is collected: its valid
+/// pages are copied to a free block and it is erased. The `spare` blocks given when mounting are
+/// not exposed as sectors, so there is always room for this. More spare blocks make garbage
+/// collection cheaper, two are the minimum.
+#[cfg(feature = "alloc")]
+pub struct Ftl<S, C = Crc32> {
+	flash: S,
+	/// Size of a page: the header and a sector, padded to the read and write sizes.
+	page_size: usize,
+	/// Pages per erase block.
+	pages: u32,
+	/// Page of every sector.
+	map: Vec<u32>,
+	/// Valid pages of every block.
+	valid: Vec<u32>,
+	/// Erased blocks.
+	free: VecDeque<u32>,
+	/// Next free page of the block being written, if any.
+	next: Option<u32>,
+	/// Sequence number of the next page written.
+	seq: u32,
+	buffer: Vec<u8>,
+	checksum: PhantomData<C>,
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash> Ftl<S> {
+	/// Mount the translation layer on `flash`, keeping `spare` blocks for garbage collection.
+	///
+	/// `spare` sets the number of sectors, so it must be the same on every mount.
+	///
+	/// # Errors
+	///
+	/// Returns [`FtlError::Invalid`] with [`CheckError::OutOfBounds`] if `spare` is less than 2 or
+	/// leaves no sectors, or if `ERASE_SIZE` is too small to hold a sector.
+	pub fn mount(flash: S, spare: usize) -> Result<Self, FtlError<S::Error>> {
+		Self::mount_with_checksum(flash, spare)
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash, C: Checksum> Ftl<S, C> {
+	/// Mount the translation layer with pages protected by the checksum `C` instead of [`Crc32`].
+	///
+	/// # Errors
+	///
+	/// Returns [`FtlError::Invalid`] in the cases of [`mount`](Ftl::mount).
+	pub fn mount_with_checksum(flash: S, spare: usize) -> Result<Self, FtlError<S::Error>> {
+		let page_size = align_up(HEADER + SECTOR_SIZE, S::READ_SIZE.max(S::WRITE_SIZE));
+		check_slice(S::ERASE_SIZE, 1, 0, page_size).map_err(FtlError::Invalid)?;
+		let pages = S::ERASE_SIZE / page_size;
+		let blocks = flash.capacity() / S::ERASE_SIZE;
+		if spare < 2 {
+			return Err(FtlError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: 2,
+					capacity: spare,
+				},
+			)));
+		}
+		check_slice(blocks, 1, 0, spare.saturating_add(1)).map_err(FtlError::Invalid)?;
+		let sectors = (blocks - spare) * pages;
+		let mut ftl = Self {
+			flash,
+			page_size,
+			pages: pages as u32,
+			map: vec![NONE; sectors],
+			valid: vec![0; blocks],
+			free: VecDeque::new(),
+			next: None,
+			seq: 0,
+			buffer: vec![0; page_size],
+			checksum: PhantomData,
+		};
+		ftl.scan()?;
+		while ftl.free.is_empty() {
+			// A garbage collection was interrupted, finish it.
+			ftl.collect()?;
+		}
+		Ok(ftl)
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	fn scan(&mut self) -> Result<(), FtlError<S::Error>> {
+		let mut seqs = vec![0; self.map.len()];
+		let mut last = None;
+		// Last page written in every block.
+		let mut ends = vec![None; self.valid.len()];
+		for page in 0..self.valid.len() as u32 * self.pages {
+			self.read_page(page)?;
+			if is_erased::<S>(self.address(page), &self.buffer) {
+				continue;
+			}
+			ends[(page / self.pages) as usize] = Some(page);
+			let (sector, seq) = match self.parse() {
+				Some(header) => header,
+				None => continue,
+			};
+			let mapped = &mut self.map[sector as usize];
+			if *mapped == NONE || seq > seqs[sector as usize] {
+				*mapped = page;
+				seqs[sector as usize] = seq;
+			}
+			if last.map_or(true, |(_, last)| seq >= last) {
+				last = Some((page, seq));
+			}
+		}
+		for &page in &self.map {
+			if page != NONE {
+				self.valid[(page / self.pages) as usize] += 1;
+			}
+		}
+		for (block, end) in ends.iter().enumerate() {
+			if end.is_none() {
+				self.free.push_back(block as u32);
+			}
+		}
+		if let Some((page, seq)) = last {
+			self.seq = seq.wrapping_add(1);
+			// Go on writing the block of the newest page, if it has erased pages left.
+			let end = ends[(page / self.pages) as usize].unwrap_or(page) + 1;
+			if end % self.pages != 0 {
+				self.next = Some(end);
+			}
+		}
+		Ok(())
+	}
+
+	/// The sector and sequence number of the page in the buffer, if valid.
+	fn parse(&self) -> Option<(u32, u32)> {
+		let word = |index: usize| {
+			let mut bytes = [0; 4];
+			bytes.copy_from_slice(&self.buffer[index * 4..index * 4 + 4]);
+			u32::from_le_bytes(bytes)
+		};
+		let mut crc = C::default();
+		crc.update(&self.buffer[..12]);
+		crc.update(&self.buffer[HEADER..HEADER + SECTOR_SIZE]);
+		let sector = word(1);
+		let valid =
+			word(0) == MAGIC && (sector as usize) < self.map.len() && crc.finish() == word(3);
+		if valid {
+			Some((sector, word(2)))
+		} else {
+			None
+		}
+	}
+
+	fn read_page(&mut self, page: u32) -> Result<(), FtlError<S::Error>> {
+		let address = self.address(page);
+		self.flash
+			.read(address, &mut self.buffer)
+			.map_err(FtlError::Flash)
+	}
+
+	fn address(&self, page: u32) -> u32 {
+		let block = page / self.pages;
+		block * S::ERASE_SIZE as u32 + (page % self.pages) * self.page_size as u32
+	}
+
+	/// Write `data` as the contents of `sector` to the next free page.
+	fn program(&mut self, sector: u32, data: &[u8]) -> Result<(), FtlError<S::Error>> {
+		let page = self.next.ok_or(FtlError::Full)?;
+		let following = page + 1;
+		self.next = if following % self.pages != 0 {
+			Some(following)
+		} else {
+			None
+		};
+
+		let address = self.address(page);
+		fill_erased::<S>(address, &mut self.buffer);
+		self.buffer[..4].copy_from_slice(&MAGIC.to_le_bytes());
+		self.buffer[4..8].copy_from_slice(&sector.to_le_bytes());
+		self.buffer[8..12].copy_from_slice(&self.seq.to_le_bytes());
+		self.buffer[HEADER..HEADER + SECTOR_SIZE].copy_from_slice(data);
+		let mut crc = C::default();
+		crc.update(&self.buffer[..12]);
+		crc.update(data);
+		self.buffer[12..HEADER].copy_from_slice(&crc.finish().to_le_bytes());
+		self.seq = self.seq.wrapping_add(1);
+		write_chunked(&mut self.flash, address, &self.buffer).map_err(FtlError::Flash)?;
+
+		let old = core::mem::replace(&mut self.map[sector as usize], page);
+		if old != NONE {
+			self.valid[(old / self.pages) as usize] -= 1;
+		}
+		self.valid[(page / self.pages) as usize] += 1;
+		Ok(())
+	}
+
+	/// Make sure a page is free for the next write.
+	fn allocate(&mut self) -> Result<(), FtlError<S::Error>> {
+		while self.next.is_none() {
+			if self.free.len() > 1 {
+				self.open()?;
+			} else {
+				self.collect()?;
+			}
+		}
+		Ok(())
+	}
+
+	fn open(&mut self) -> Result<(), FtlError<S::Error>> {
+		let block = self.free.pop_front().ok_or(FtlError::Full)?;
+		self.next = Some(block * self.pages);
+		Ok(())
+	}
+
+	/// Erase the block with the fewest valid pages, copying them to free pages first.
+	fn collect(&mut self) -> Result<(), FtlError<S::Error>> {
+		let head = self.next.map(|page| page / self.pages);
+		let victim = (0..self.valid.len() as u32)
+			.filter(|&block| Some(block) != head && !self.free.contains(&block))
+			.min_by_key(|&block| self.valid[block as usize])
+			.ok_or(FtlError::Full)?;
+		let count = self.valid[victim as usize];
+		if count > 0 {
+			if self.next.is_none() {
+				self.open()?;
+			}
+			let room = self.pages - self.next.unwrap_or(0) % self.pages;
+			if count > room {
+				return Err(FtlError::Full);
+			}
+			let mut data = [0; SECTOR_SIZE];
+			for page in victim * self.pages..(victim + 1) * self.pages {
+				self.read_page(page)?;
+				match self.parse() {
+					Some((sector, _)) if self.map[sector as usize] == page => {
+						data.copy_from_slice(&self.buffer[HEADER..HEADER + SECTOR_SIZE]);
+						self.program(sector, &data)?;
+					}
+					_ => {}
+				}
+			}
+		}
+		let from = victim * S::ERASE_SIZE as u32;
+		self.flash
+			.erase(from, from + S::ERASE_SIZE as u32)
+			.map_err(FtlError::Flash)?;
+		self.free.push_back(victim);
+		Ok(())
+	}
+
+	fn check(&self, sector: u32, length: usize) -> Result<(), FtlError<S::Error>> {
+		let offset = sector.saturating_mul(SECTOR_SIZE as u32);
+		check_slice(self.map.len() * SECTOR_SIZE, SECTOR_SIZE, offset, length)
+			.map_err(FtlError::Invalid)
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash, C: Checksum> BlockDevice for Ftl<S, C> {
+	type Error = FtlError<S::Error>;
+
+	fn sector_count(&self) -> u32 {
+		self.map.len() as u32
+	}
+
+	fn read(&mut self, sector: u32, data: &mut [u8]) -> Result<(), Self::Error> {
+		self.check(sector, data.len())?;
+		for (index, chunk) in data.chunks_mut(SECTOR_SIZE).enumerate() {
+			let page = self.map[sector as usize + index];
+			if page == NONE {
+				chunk.fill(0);
+				continue;
+			}
+			self.read_page(page)?;
+			chunk.copy_from_slice(&self.buffer[HEADER..HEADER + SECTOR_SIZE]);
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, sector: u32, data: &[u8]) -> Result<(), Self::Error> {
+		self.check(sector, data.len())?;
+		for (index, chunk) in data.chunks(SECTOR_SIZE).enumerate() {
+			self.allocate()?;
+			self.program(sector + index as u32, chunk)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+	use super::*;
+	use crate::crc::Crc16;
+	use crate::mock::MockFlash;
+
+	type Flash = MockFlash<16384, 1, 4, 1024>;
+
+	#[test]
+	fn mount_invalid() {
+		let invalid = |result: Result<Ftl<_>, _>| match result {
+			Err(FtlError::Invalid(CheckError::OutOfBounds(_))) => {}
+			_ => panic!("Flash accepted"),
+		};
+		invalid(Ftl::mount(Flash::new(), 1));
+		invalid(Ftl::mount(Flash::new(), 16));
+		assert!(matches!(
+			Ftl::mount(MockFlash::<16384, 1, 4, 256>::new(), 2),
+			Err(FtlError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert!(Ftl::mount(Flash::new(), 2).is_ok());
+	}
+
+	#[test]
+	fn custom_checksum() {
+		let mut ftl = Ftl::<_, Crc16>::mount_with_checksum(Flash::new(), 2).unwrap();
+		ftl.write(3, &[0x42; SECTOR_SIZE]).unwrap();
+		let mut ftl = Ftl::<_, Crc16>::mount_with_checksum(ftl.into_inner(), 2).unwrap();
+		let mut sector = [0; SECTOR_SIZE];
+		ftl.read(3, &mut sector).unwrap();
+		assert_eq!(sector, [0x42; SECTOR_SIZE]);
+
+		// Pages are not mapped when read with another checksum, so the sector reads as zeros.
+		let mut ftl = Ftl::mount(ftl.into_inner(), 2).unwrap();
+		ftl.read(3, &mut sector).unwrap();
+		assert_eq!(sector, [0; SECTOR_SIZE]);
+	}
+}
@@ -0,0 +1,46 @@
+/// State of a flash data structure, as seen by a [`GcPolicy`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct GcStatus {
+	/// Number of bytes which can still be written before a page has to be erased.
+	pub free: usize,
+	/// Whether the next step discards data which is still readable, e.g. the oldest records of a
+	/// log.
+	pub discards: bool,
+}
+
+/// Decision of when to run a step of garbage collection.
+///
+/// Data structures erase pages when they run out of space, which makes the worst case latency of
+/// a write that of an erase. Calling their `gc` method during idle time with a policy erases
+/// pages ahead of time instead, one per call, so that writes only program.
+pub trait GcPolicy {
+	/// Whether to run a step now.
+	fn should_collect(&mut self, status: &GcStatus) -> bool;
+}
+
+impl<T: GcPolicy + ?Sized> GcPolicy for &mut T {
+	fn should_collect(&mut self, status: &GcStatus) -> bool {
+		T::should_collect(self, status)
+	}
+}
+
+/// Collect whenever a step does not discard data.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Eager;
+
+impl GcPolicy for Eager {
+	fn should_collect(&mut self, status: &GcStatus) -> bool {
+		!status.discards
+	}
+}
+
+/// Collect once at most the given number of bytes can still be written, even if this discards
+/// data.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct FreeBelow(pub usize);
+
+impl GcPolicy for FreeBelow {
+	fn should_collect(&mut self, status: &GcStatus) -> bool {
+		status.free <= self.0
+	}
+}
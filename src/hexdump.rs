@@ -0,0 +1,105 @@
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+use crate::nor_flash::{erased_byte, NorFlash, NorFlashError, NorFlashErrorKind};
+
+/// Number of bytes rendered on every line.
+const LINE: usize = 16;
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Errors returned by [`hexdump`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DumpError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The output returned an error.
+	Format,
+}
+
+impl<E: NorFlashError> NorFlashError for DumpError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Format => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+impl<E> From<fmt::Error> for DumpError<E> {
+	fn from(_: fmt::Error) -> Self {
+		Self::Format
+	}
+}
+
+/// Render `range` of `flash` to `out` as a classic hexdump, e.g. on a debug console.
+///
+/// Every line shows the address, 16 bytes in hexadecimal and as ASCII. Erased bytes, following
+/// the `ERASE_PATTERN`, are rendered as `--`, or dimmed when `ansi` is set for terminals supporting ANSI
+/// escape codes, so the programmed bytes stand out. Runs of erased lines are collapsed into a
+/// single `*` line.
+///
+/// **NOTE** This will panic if `READ_SIZE` does not divide 16.
+pub fn hexdump<S, W>(
+	flash: &mut S,
+	range: Range<u32>,
+	out: &mut W,
+	ansi: bool,
+) -> Result<(), DumpError<S::Error>>
+where
+	S: NorFlash,
+	W: Write,
+{
+	assert!(LINE % S::READ_SIZE == 0, "READ_SIZE does not divide 16");
+	let capacity = flash.capacity() as u32;
+	let end = range.end.min(capacity);
+	let mut line = range.start - range.start % LINE as u32;
+	let mut squeezed = false;
+	while line < end {
+		let mut bytes = [0; LINE];
+		let length = (LINE as u32).min(capacity - line) as usize;
+		let length = (length + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+		flash
+			.read(line, &mut bytes[..length])
+			.map_err(DumpError::Flash)?;
+		let shown = |index: usize| (range.start..end).contains(&(line + index as u32));
+		let is_erased = |index: usize| bytes[index] == erased_byte::<S>(line + index as u32);
+		let erased = (0..LINE).all(|index| !shown(index) || is_erased(index));
+		let whole = shown(0) && shown(LINE - 1);
+		if erased && whole && line + (LINE as u32) < end {
+			if !squeezed {
+				writeln!(out, "*")?;
+				squeezed = true;
+			}
+			line += LINE as u32;
+			continue;
+		}
+		squeezed = false;
+
+		write!(out, "{:08x} ", line)?;
+		for (index, byte) in bytes.iter().enumerate() {
+			if index % 8 == 0 {
+				write!(out, " ")?;
+			}
+			match (shown(index), is_erased(index), ansi) {
+				(false, _, _) => write!(out, "   ")?,
+				(true, true, false) => write!(out, "-- ")?,
+				(true, true, true) => write!(out, "{}{:02x}{} ", DIM, byte, RESET)?,
+				(true, false, _) => write!(out, "{:02x} ", byte)?,
+			}
+		}
+		write!(out, " |")?;
+		for (index, byte) in bytes.iter().enumerate() {
+			let c = match (shown(index), is_erased(index)) {
+				(false, _) | (true, true) => ' ',
+				(true, false) if byte.is_ascii_graphic() || *byte == b' ' => *byte as char,
+				(true, false) => '.',
+			};
+			out.write_char(c)?;
+		}
+		writeln!(out, "|")?;
+		line += LINE as u32;
+	}
+	Ok(())
+}
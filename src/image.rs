@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::vec;
+use std::vec::Vec;
+
+use crate::nor_flash::{
+	fill_erased, write_sparse_erased, CheckError, NorFlash, NorFlashError, NorFlashErrorKind,
+	OutOfBoundsInfo, ReadNorFlash,
+};
+
+/// Number of bytes transferred between two progress reports.
+const BLOCK: usize = 4096;
+
+/// Errors returned by the image functions.
+#[derive(Debug)]
+pub enum ImageError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// Reading or writing the image failed.
+	Io(io::Error),
+	/// The image does not fit in the flash.
+	Invalid(CheckError),
+	/// The flash contents differ from the image after programming.
+	Mismatch {
+		/// Address of the first differing byte.
+		address: u32,
+	},
+}
+
+impl<E: NorFlashError> NorFlashError for ImageError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Io(error) => error.kind().into(),
+			Self::Invalid(error) => error.kind(),
+			Self::Mismatch { .. } => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+impl<E> From<io::Error> for ImageError<E> {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+/// Size of a block of `length` bytes once aligned to `align`.
+fn aligned(length: usize, align: usize) -> usize {
+	(length + align - 1) / align * align
+}
+
+/// Copy the whole contents of `flash` to `writer`.
+///
+/// `progress` is called with the number of bytes copied so far and the total.
+pub fn dump<S, W, P>(
+	flash: &mut S,
+	mut writer: W,
+	mut progress: P,
+) -> Result<(), ImageError<S::Error>>
+where
+	S: ReadNorFlash,
+	W: Write,
+	P: FnMut(usize, usize),
+{
+	let capacity = flash.capacity();
+	span!("dump", length = capacity);
+	let mut buffer = vec![0; aligned(BLOCK, S::READ_SIZE)];
+	let mut done = 0;
+	while done < capacity {
+		let part = buffer.len().min(capacity - done);
+		flash
+			.read(done as u32, &mut buffer[..part])
+			.map_err(ImageError::Flash)?;
+		writer.write_all(&buffer[..part])?;
+		done += part;
+		progress(done, capacity);
+	}
+	writer.flush()?;
+	Ok(())
+}
+
+/// Copy the whole contents of `flash` to the file at `path`.
+pub fn dump_to_file<S, P>(flash: &mut S, path: P) -> Result<(), ImageError<S::Error>>
+where
+	S: ReadNorFlash,
+	P: AsRef<Path>,
+{
+	dump(flash, io::BufWriter::new(File::create(path)?), |_, _| {})
+}
+
+/// Program the image read from `reader` at the start of `flash`, then verify it.
+///
+/// The pages covered by the image are erased first, and the end of the image is padded with
+/// erased bytes up to `WRITE_SIZE`. Words of the image left erased are not programmed. `progress` is called with the number of bytes programmed and
+/// verified so far, and the total, which is twice the length of the image.
+pub fn program<S, R, P>(
+	flash: &mut S,
+	mut reader: R,
+	mut progress: P,
+) -> Result<(), ImageError<S::Error>>
+where
+	S: NorFlash,
+	R: Read,
+	P: FnMut(usize, usize),
+{
+	let mut image = Vec::new();
+	reader.read_to_end(&mut image)?;
+	let length = image.len();
+	span!("program", length = length);
+	let capacity = flash.capacity();
+	if aligned(length, S::ERASE_SIZE) > capacity {
+		return Err(ImageError::Invalid(CheckError::OutOfBounds(
+			OutOfBoundsInfo {
+				offset: 0,
+				length,
+				capacity,
+			},
+		)));
+	}
+	image.resize(aligned(length, S::WRITE_SIZE), 0);
+	fill_erased::<S>(length as u32, &mut image[length..]);
+
+	flash
+		.erase(0, aligned(length, S::ERASE_SIZE) as u32)
+		.map_err(ImageError::Flash)?;
+	let block = aligned(BLOCK, S::WRITE_SIZE);
+	let mut done = 0;
+	for chunk in image.chunks(block) {
+		write_sparse_erased(flash, done as u32, chunk).map_err(ImageError::Flash)?;
+		done += chunk.len();
+		progress(done.min(length), 2 * length);
+	}
+
+	let mut buffer = vec![0; aligned(BLOCK, S::READ_SIZE)];
+	let mut done = 0;
+	while done < length {
+		let part = buffer.len().min(aligned(length, S::READ_SIZE) - done);
+		flash
+			.read(done as u32, &mut buffer[..part])
+			.map_err(ImageError::Flash)?;
+		let expected = &image[done..(done + part).min(length)];
+		if let Some(index) = expected.iter().zip(&buffer).position(|(a, b)| a != b) {
+			return Err(ImageError::Mismatch {
+				address: (done + index) as u32,
+			});
+		}
+		done += part;
+		progress(length + done.min(length), 2 * length);
+	}
+	Ok(())
+}
+
+/// Program the image in the file at `path` at the start of `flash`, then verify it.
+pub fn program_from_file<S, P>(flash: &mut S, path: P) -> Result<(), ImageError<S::Error>>
+where
+	S: NorFlash,
+	P: AsRef<Path>,
+{
+	program(flash, io::BufReader::new(File::open(path)?), |_, _| {})
+}
@@ -0,0 +1,441 @@
+use crate::crc::Crc32;
+use crate::nor_flash::{
+	check_erase, check_slice, check_write, fill_erased, is_erased, CheckError, ErrorType,
+	MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ProgramPolarity, ReadNorFlash,
+};
+
+/// Size of the buffer used to read and write records.
+const BUFFER: usize = 32;
+
+/// Size of a record: sequence number, kind, offset, length, CRC of the data and CRC of the record.
+const RECORD: usize = 24;
+
+/// Errors returned by [`OpJournal`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum JournalError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The operation overlaps the journal sector and was not forwarded.
+	Reserved,
+	/// The journal sector is misaligned, out of bounds or too small.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for JournalError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Reserved => NorFlashErrorKind::Other,
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Kind of a journaled operation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum OpKind {
+	/// An erase, see [`NorFlash::erase`].
+	Erase,
+	/// A write, see [`NorFlash::write`].
+	Write,
+}
+
+/// A destructive operation recorded by [`OpJournal`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct OpRecord {
+	/// Number of the operation, increasing by one for every operation.
+	pub sequence: u32,
+	/// Kind of the operation.
+	pub kind: OpKind,
+	/// Start of the erased or written range.
+	pub offset: u32,
+	/// Length of the erased or written range.
+	pub length: u32,
+	/// CRC-32 of the written data, zero for erases.
+	pub crc: u32,
+}
+
+impl OpRecord {
+	fn to_bytes(self) -> [u8; RECORD] {
+		let mut bytes = [0; RECORD];
+		let kind = match self.kind {
+			OpKind::Erase => 1,
+			OpKind::Write => 2,
+		};
+		let words = [self.sequence, kind, self.offset, self.length, self.crc];
+		for (chunk, word) in bytes.chunks_mut(4).zip(words.iter()) {
+			chunk.copy_from_slice(&word.to_le_bytes());
+		}
+		let crc = Crc32::checksum(&bytes[..RECORD - 4]);
+		bytes[RECORD - 4..].copy_from_slice(&crc.to_le_bytes());
+		bytes
+	}
+
+	fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&bytes[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		if Crc32::checksum(&bytes[..RECORD - 4]) != word(5) {
+			return None;
+		}
+		let kind = match word(1) {
+			1 => OpKind::Erase,
+			2 => OpKind::Write,
+			_ => return None,
+		};
+		Some(Self {
+			sequence: word(0),
+			kind,
+			offset: word(2),
+			length: word(3),
+			crc: word(4),
+		})
+	}
+}
+
+/// NOR flash wrapper recording the last destructive operations, for crash forensics.
+///
+/// Every erase and write is recorded in a dedicated sector of the flash before being forwarded,
+/// with its range and the CRC of the written data, so the operations a device was running before
+/// a crash or a field failure can be read back with [`records`](Self::records). The last `N`
+/// records are also kept in RAM: when the sector is full, it is erased and they are written again
+/// first, so that the journal always holds at least the last `N` operations, unless power is lost
+/// while the sector is rewritten.
+///
+/// Operations overlapping the journal sector are rejected with [`JournalError::Reserved`].
+pub struct OpJournal<S, const N: usize> {
+	flash: S,
+	sector: u32,
+	/// Next slot to write in the sector.
+	next: u32,
+	/// Sequence number of the next record.
+	sequence: u32,
+	/// The last records, oldest first.
+	recent: [Option<OpRecord>; N],
+}
+
+impl<S: NorFlash, const N: usize> OpJournal<S, N> {
+	/// Mount the journal in the sector at `sector` of `flash`, reading back its records.
+	///
+	/// # Errors
+	///
+	/// Returns [`JournalError::Invalid`] with [`CheckError::NotAligned`] if `sector` is not
+	/// aligned to `ERASE_SIZE`, and with [`CheckError::OutOfBounds`] if it is out of bounds, if
+	/// the sector cannot hold more than `N` records, or if `READ_SIZE` or `WRITE_SIZE` is over 32
+	/// bytes.
+	pub fn mount(flash: S, sector: u32) -> Result<Self, JournalError<S::Error>> {
+		check_slice(flash.capacity(), S::ERASE_SIZE, sector, S::ERASE_SIZE)
+			.and_then(|()| check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE)))
+			.and_then(|()| check_slice(Self::slots() as usize, 1, 0, N.saturating_add(1)))
+			.map_err(JournalError::Invalid)?;
+		let mut journal = Self {
+			flash,
+			sector,
+			next: 0,
+			sequence: 0,
+			recent: [None; N],
+		};
+		let mut buffer = [0; BUFFER];
+		for slot in 0..Self::slots() {
+			let address = journal.slot(slot);
+			let bytes = &mut buffer[..Self::slot_size()];
+			journal
+				.flash
+				.read(address, bytes)
+				.map_err(JournalError::Flash)?;
+			if is_erased::<S>(address, bytes) {
+				continue;
+			}
+			// Slots cut by a power loss are skipped, but not written again.
+			journal.next = slot + 1;
+			if let Some(record) = OpRecord::from_bytes(bytes) {
+				journal.sequence = record.sequence.wrapping_add(1);
+				journal.push(record);
+			}
+		}
+		Ok(journal)
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// The journal sector.
+	pub fn sector(&self) -> u32 {
+		self.sector
+	}
+
+	/// The last `N` records, oldest first.
+	pub fn records(&self) -> impl Iterator<Item = &OpRecord> + '_ {
+		self.recent.iter().flatten()
+	}
+
+	/// Read every record still in the journal sector, oldest first.
+	///
+	/// This includes the records older than the last `N` which were not erased yet.
+	pub fn read_all<F>(&mut self, mut f: F) -> Result<(), JournalError<S::Error>>
+	where
+		F: FnMut(&OpRecord),
+	{
+		let mut buffer = [0; BUFFER];
+		for slot in 0..self.next {
+			let bytes = &mut buffer[..Self::slot_size()];
+			self.flash
+				.read(self.slot(slot), bytes)
+				.map_err(JournalError::Flash)?;
+			if let Some(record) = OpRecord::from_bytes(bytes) {
+				f(&record);
+			}
+		}
+		Ok(())
+	}
+
+	fn slot_size() -> usize {
+		let align = S::READ_SIZE.max(S::WRITE_SIZE);
+		(RECORD + align - 1) / align * align
+	}
+
+	fn slots() -> u32 {
+		(S::ERASE_SIZE / Self::slot_size()) as u32
+	}
+
+	fn slot(&self, slot: u32) -> u32 {
+		self.sector + slot * Self::slot_size() as u32
+	}
+
+	/// Keep `record` in RAM, dropping the oldest one if needed.
+	fn push(&mut self, record: OpRecord) {
+		if N == 0 {
+			return;
+		}
+		if self.recent[N - 1].is_some() {
+			self.recent.rotate_left(1);
+			self.recent[N - 1] = None;
+		}
+		if let Some(free) = self.recent.iter_mut().find(|recent| recent.is_none()) {
+			*free = Some(record);
+		}
+	}
+
+	fn write_record(&mut self, record: OpRecord) -> Result<(), S::Error> {
+		let mut buffer = [0; BUFFER];
+		let address = self.slot(self.next);
+		let bytes = &mut buffer[..Self::slot_size()];
+		fill_erased::<S>(address, bytes);
+		bytes[..RECORD].copy_from_slice(&record.to_bytes());
+		self.flash.write(address, bytes)?;
+		self.next += 1;
+		Ok(())
+	}
+
+	/// Record an operation before running it.
+	fn record(&mut self, kind: OpKind, offset: u32, length: u32, crc: u32) -> Result<(), S::Error> {
+		if self.next >= Self::slots() {
+			self.flash
+				.erase(self.sector, self.sector + S::ERASE_SIZE as u32)?;
+			self.next = 0;
+			let recent = self.recent;
+			for record in recent.iter().flatten() {
+				self.write_record(*record)?;
+			}
+		}
+		let record = OpRecord {
+			sequence: self.sequence,
+			kind,
+			offset,
+			length,
+			crc,
+		};
+		self.write_record(record)?;
+		self.sequence = self.sequence.wrapping_add(1);
+		self.push(record);
+		Ok(())
+	}
+
+	fn is_reserved(&self, from: u32, to: u32) -> bool {
+		from < self.sector + S::ERASE_SIZE as u32 && self.sector < to
+	}
+}
+
+impl<S: ErrorType, const N: usize> ErrorType for OpJournal<S, N> {
+	type Error = JournalError<S::Error>;
+}
+
+impl<S: ReadNorFlash, const N: usize> ReadNorFlash for OpJournal<S, N> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		span!("OpJournal::read", offset = offset, length = bytes.len());
+		self.flash.read(offset, bytes).map_err(JournalError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, const N: usize> NorFlash for OpJournal<S, N> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		span!("OpJournal::erase", from = from, to = to);
+		if check_erase(self, from, to).is_ok() && from < to {
+			if self.is_reserved(from, to) {
+				return Err(JournalError::Reserved);
+			}
+			self.record(OpKind::Erase, from, to - from, 0)
+				.map_err(JournalError::Flash)?;
+		}
+		self.flash.erase(from, to).map_err(JournalError::Flash)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		span!("OpJournal::write", offset = offset, length = bytes.len());
+		if check_write(self, offset, bytes.len()).is_ok() && !bytes.is_empty() {
+			if self.is_reserved(offset, offset + bytes.len() as u32) {
+				return Err(JournalError::Reserved);
+			}
+			let crc = Crc32::checksum(bytes);
+			self.record(OpKind::Write, offset, bytes.len() as u32, crc)
+				.map_err(JournalError::Flash)?;
+		}
+		self.flash.write(offset, bytes).map_err(JournalError::Flash)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		span!("OpJournal::flush");
+		self.flash.flush().map_err(JournalError::Flash)
+	}
+}
+
+impl<S: MultiwriteNorFlash, const N: usize> MultiwriteNorFlash for OpJournal<S, N> {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{MockError, MockFlash};
+
+	type Flash = MockFlash<4096, 1, 4, 1024>;
+
+	#[test]
+	fn mount_invalid() {
+		let invalid = |result: Result<OpJournal<Flash, 4>, _>| {
+			assert!(matches!(result, Err(JournalError::Invalid(_))));
+		};
+		assert!(OpJournal::<_, 4>::mount(Flash::new(), 3072).is_ok());
+		invalid(OpJournal::mount(Flash::new(), 512));
+		invalid(OpJournal::mount(Flash::new(), 4096));
+		invalid(OpJournal::mount(Flash::new(), u32::MAX));
+		assert!(matches!(
+			OpJournal::<_, 42>::mount(Flash::new(), 0),
+			Err(JournalError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert!(matches!(
+			OpJournal::<_, 4>::mount(MockFlash::<4096, 64, 4, 1024>::new(), 0),
+			Err(JournalError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	/// The sequence numbers of the records in the journal sector.
+	fn sequences<S: NorFlash, const N: usize>(journal: &mut OpJournal<S, N>) -> ([u32; 8], usize) {
+		let mut sequences = [0; 8];
+		let mut count = 0;
+		journal
+			.read_all(|record| {
+				sequences[count] = record.sequence;
+				count += 1;
+			})
+			.unwrap();
+		(sequences, count)
+	}
+
+	#[test]
+	fn record_roundtrip() {
+		let mut journal = OpJournal::<_, 4>::mount(Flash::new(), 3072).unwrap();
+		journal.erase(0, 1024).unwrap();
+		journal.write(16, b"data").unwrap();
+		assert_eq!(journal.write(3072, b"data"), Err(JournalError::Reserved));
+		assert_eq!(journal.erase(2048, 4096), Err(JournalError::Reserved));
+		let mut bytes = [0; 4];
+		journal.read(16, &mut bytes).unwrap();
+		assert_eq!(&bytes, b"data");
+
+		let mut journal = OpJournal::<_, 4>::mount(journal.into_inner(), 3072).unwrap();
+		{
+			let mut records = journal.records();
+			assert_eq!(
+				records.next(),
+				Some(&OpRecord {
+					sequence: 0,
+					kind: OpKind::Erase,
+					offset: 0,
+					length: 1024,
+					crc: 0,
+				})
+			);
+			assert_eq!(
+				records.next(),
+				Some(&OpRecord {
+					sequence: 1,
+					kind: OpKind::Write,
+					offset: 16,
+					length: 4,
+					crc: Crc32::checksum(b"data"),
+				})
+			);
+			assert_eq!(records.next(), None);
+		}
+		assert_eq!(sequences(&mut journal), ([0, 1, 0, 0, 0, 0, 0, 0], 2));
+	}
+
+	#[test]
+	fn sector_rewritten() {
+		let mut journal = OpJournal::<_, 4>::mount(Flash::new(), 3072).unwrap();
+		// The sector holds 42 records, the last 4 are kept when it is rewritten.
+		for index in 0..45 {
+			journal
+				.erase(index % 3 * 1024, index % 3 * 1024 + 1024)
+				.unwrap();
+		}
+		let mut journal = OpJournal::<_, 4>::mount(journal.into_inner(), 3072).unwrap();
+		assert_eq!(journal.records().next().unwrap().sequence, 41);
+		assert_eq!(journal.records().count(), 4);
+		assert_eq!(
+			sequences(&mut journal),
+			([38, 39, 40, 41, 42, 43, 44, 0], 7)
+		);
+		journal.erase(0, 1024).unwrap();
+		assert_eq!(journal.records().last().unwrap().sequence, 45);
+	}
+
+	#[test]
+	fn power_loss() {
+		let mut flash = Flash::new();
+		let mut journal = OpJournal::<_, 4>::mount(&mut flash, 3072).unwrap();
+		journal.write(0, b"data").unwrap();
+
+		// Power is cut while recording the next operation, which is not run.
+		flash.cut_next_write(1);
+		let mut journal = OpJournal::<_, 4>::mount(&mut flash, 3072).unwrap();
+		assert_eq!(
+			journal.write(4, b"next"),
+			Err(JournalError::Flash(MockError::PowerLoss))
+		);
+		let mut journal = OpJournal::<_, 4>::mount(&mut flash, 3072).unwrap();
+		assert_eq!(journal.records().count(), 1);
+		journal.write(4, b"next").unwrap();
+		assert_eq!(journal.records().last().unwrap().sequence, 1);
+		assert_eq!(sequences(&mut journal), ([0, 1, 0, 0, 0, 0, 0, 0], 2));
+		let mut bytes = [0; 8];
+		journal.read(0, &mut bytes).unwrap();
+		assert_eq!(&bytes, b"datanext");
+	}
+}
@@ -8,10 +8,125 @@
 #![deny(missing_docs)]
 #![deny(unsafe_code)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+/// Spans around flash operations, with the `trace` feature
+#[macro_use]
+mod trace;
+
+/// Wrappers changing or restricting the behaviour of storage peripherals
+pub mod adapters;
+/// Typed byte addresses and sector indices
+pub mod addr;
+/// Workloads measuring the performance of `Storage` implementations
+#[cfg(feature = "std")]
+pub mod bench;
+/// Content addressed store of reference counted blobs
+#[cfg(feature = "alloc")]
+pub mod blobs;
+/// Counting of unconfirmed boots for firmware rollback
+pub mod boot;
+/// Copy and comparison of flash regions with pluggable acceleration
+pub mod bulk;
+/// Virtual flash forwarding operations to a host, e.g. over RTT or semihosting
+pub mod channel;
+/// Table of common serial NOR flash chips, indexed by JEDEC ID
+#[cfg(feature = "chips")]
+pub mod chips;
+/// Storage of compressed blobs with a pluggable codec
+#[cfg(feature = "compress")]
+pub mod compress;
+/// Versioned configuration blobs surviving power loss and firmware updates
+pub mod config;
+/// Command handler for interactive flash debugging from embedded consoles
+#[cfg(feature = "console")]
+pub mod console;
+/// Incremental CRC computation for checking data stored in flash
+pub mod crc;
+/// Computation of digests over flash regions
+pub mod digest;
+/// Reference drivers for 24xx I2C and 25xx SPI EEPROMs over `embedded-hal`
+#[cfg(feature = "eeprom")]
+pub mod eeprom;
+/// Append-only log of timestamped records
+pub mod event_log;
+/// Block devices with 512-byte sectors, and a flash translation layer exposing NOR flash as one
+pub mod ftl;
+/// Policies for garbage collection of the flash data structures during idle time
+pub mod gc;
+/// Rendering of flash regions as hexdumps for debug consoles
+pub mod hexdump;
+/// Export and import of flash images through the `std` I/O traits
+#[cfg(feature = "std")]
+pub mod image;
 /// Currently contains [`OverlapIterator`]
 pub mod iter;
+/// Journal of the last destructive operations, for crash forensics
+pub mod journal;
+/// Programming of Intel HEX and Motorola S-record images
+#[cfg(feature = "loader")]
+pub mod loader;
+/// Flash backed by a memory mapped file, for host simulation of XIP
+#[cfg(feature = "mmap")]
+pub mod mmap;
+/// Simulated flash devices for testing code built on the storage traits
+pub mod mock;
+/// Abstract model of a NOR flash checking operations against the trait invariants
+#[cfg(feature = "model-checking")]
+pub mod model;
+/// Backing store adapter for USB mass storage class devices
+pub mod msc;
+/// Linux MTD character device backend
+#[cfg(all(feature = "mtd", target_os = "linux"))]
+pub mod mtd;
 /// Technology specific traits for NOR Flashes
 pub mod nor_flash;
+/// Typed access to objects with a fixed size encoding
+pub mod obj;
+/// Vendor neutral firmware update flow with download and state partitions
+pub mod ota;
+/// Delta patches building a new image from an old one, for small OTA downloads
+pub mod patch;
+/// Exhaustive power loss testing of storage layers
+#[cfg(feature = "alloc")]
+pub mod power_loss;
+/// Reader and generator of factory data partitions
+pub mod provisioning;
+/// Persistent FIFO queue of records consumed on acknowledgement
+pub mod queue;
+/// Volatile storage for running without persistence
+pub mod ram;
+/// Access to a flash over a byte stream, e.g. from a host in hardware in the loop tests
+#[cfg(feature = "std")]
+pub mod remote;
+/// Long erases and writes performed in small resumable steps
+pub mod resumable;
+/// Handling of secret material stored in flash
+pub mod secret;
+/// Parsing of JEDEC Serial Flash Discoverable Parameters
+pub mod sfdp;
+/// Power-down-safe shutdown of a flash, e.g. from brown-out handlers
+pub mod shutdown;
+/// Fixed-size objects stored in CRC protected slots
+#[cfg(feature = "alloc")]
+pub mod slots;
+/// Reference driver for serial NOR flashes over an `embedded-hal` SPI device
+#[cfg(feature = "spinor")]
+pub mod spinor;
+/// Debug wrappers checking that clients follow the `NorFlash` contract
+#[cfg(feature = "alloc")]
+pub mod strict;
+/// Conformance checks for `NorFlash` implementations
+#[cfg(feature = "std")]
+pub mod test_suite;
+/// Type, length and value records protected by a CRC
+pub mod tlv;
+/// Debug wrapper checking that drivers behave as NOR flashes
+#[cfg(feature = "alloc")]
+pub mod validating;
 
 /// A region denotes a contiguous piece of memory between two addresses.
 pub trait Region {
@@ -44,4 +159,13 @@ pub trait Storage: ReadStorage {
 	/// This function will automatically erase any pages necessary to write the given data,
 	/// and might as such do RMW operations at an undesirable performance impact.
 	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+
+	/// Make the previous writes durable.
+	///
+	/// Buffered implementations, such as caches or write-behind adapters, may keep writes in RAM
+	/// until this is called. Generic code calls it at the end of a commit or before power-down.
+	/// The default implementation does nothing, for storages completing writes before returning.
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		Ok(())
+	}
 }
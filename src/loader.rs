@@ -0,0 +1,471 @@
+use crate::nor_flash::{
+	check_slice, fill_erased, write_chunked, CheckError, NorFlash, NorFlashError,
+	NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffer collecting contiguous data before it is programmed.
+const BUFFER: usize = 256;
+
+/// Errors returned by [`Loader`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LoadError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The data of a record lies outside the flash.
+	Invalid(CheckError),
+	/// A line is not a valid record.
+	Syntax {
+		/// Number of the offending line, starting from 1.
+		line: usize,
+	},
+	/// The checksum of a record does not match its contents.
+	Checksum {
+		/// Number of the offending line, starting from 1.
+		line: usize,
+	},
+	/// A record goes back to an address which has already been programmed.
+	OutOfOrder {
+		/// Number of the offending line, starting from 1.
+		line: usize,
+	},
+}
+
+impl<E: NorFlashError> NorFlashError for LoadError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Syntax { .. } | Self::Checksum { .. } | Self::OutOfOrder { .. } => {
+				NorFlashErrorKind::Other
+			}
+		}
+	}
+}
+
+/// Decode the hexadecimal digits of `text` into `bytes`, returns the number of bytes.
+fn decode(text: &[u8], bytes: &mut [u8]) -> Option<usize> {
+	let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+	if text.len() % 2 != 0 || text.len() / 2 > bytes.len() {
+		return None;
+	}
+	for (byte, pair) in bytes.iter_mut().zip(text.chunks(2)) {
+		*byte = digit(pair[0])? << 4 | digit(pair[1])?;
+	}
+	Some(text.len() / 2)
+}
+
+fn trim(line: &[u8]) -> &[u8] {
+	let end = line
+		.iter()
+		.rposition(|c| !c.is_ascii_whitespace())
+		.map_or(0, |end| end + 1);
+	let start = line[..end]
+		.iter()
+		.position(|c| !c.is_ascii_whitespace())
+		.unwrap_or(end);
+	&line[start..end]
+}
+
+/// Programmer of Intel HEX or Motorola S-record images into a flash.
+///
+/// Lines are fed one by one, e.g. as received by a bootloader, and data records must come in
+/// increasing address order as produced by toolchains. Data is collected until it stops being
+/// contiguous, then programmed with the end padded to `WRITE_SIZE` with erased bytes. Every page
+/// is erased before the first data is programmed into it, so pages entirely within gaps of the
+/// image are left untouched.
+pub struct Loader<'a, S> {
+	flash: &'a mut S,
+	/// Address of the image mapped to the start of the flash.
+	base: u32,
+	/// Address of the first byte of the buffer, relative to the flash.
+	start: u32,
+	buffer: [u8; BUFFER],
+	/// Number of bytes of the buffer holding data or padding between data.
+	length: usize,
+	/// End of the erased pages.
+	erased: u32,
+	/// Upper bits of Intel HEX addresses, set by extended address records.
+	upper: u32,
+	line: usize,
+	entry: Option<u32>,
+}
+
+impl<'a, S: NorFlash> Loader<'a, S> {
+	/// Program `flash`, with the image address `base` mapped to the start of the flash.
+	///
+	/// **NOTE** This will panic if `WRITE_SIZE` is over 256 bytes, see
+	/// [`try_new`](Loader::try_new).
+	pub fn new(flash: &'a mut S, base: u32) -> Self {
+		match Self::try_new(flash, base) {
+			Ok(loader) => loader,
+			Err(_) => panic!("WRITE_SIZE too large"),
+		}
+	}
+
+	/// Program `flash`, with the image address `base` mapped to the start of the flash, without
+	/// panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::OutOfBounds`] if `WRITE_SIZE` is over 256 bytes.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(flash: &'a mut S, base: u32) -> Result<Self, CheckError> {
+		check_slice(BUFFER, 1, 0, S::WRITE_SIZE)?;
+		let mut loader = Self {
+			flash,
+			base,
+			start: 0,
+			buffer: [0; BUFFER],
+			length: 0,
+			erased: 0,
+			upper: 0,
+			line: 0,
+			entry: None,
+		};
+		fill_erased::<S>(0, &mut loader.buffer);
+		Ok(loader)
+	}
+
+	/// Feed a line of an Intel HEX file, returns whether it is the end of file record.
+	///
+	/// Blank lines are ignored.
+	pub fn ihex_line(&mut self, line: &[u8]) -> Result<bool, LoadError<S::Error>> {
+		self.line += 1;
+		let line = trim(line);
+		if line.is_empty() {
+			return Ok(false);
+		}
+		let number = self.line;
+		let syntax = || LoadError::Syntax { line: number };
+		if line[0] != b':' {
+			return Err(syntax());
+		}
+		let mut record = [0; 5 + 255];
+		let length = decode(&line[1..], &mut record).ok_or_else(syntax)?;
+		if length < 5 || length != 5 + record[0] as usize {
+			return Err(syntax());
+		}
+		let record = &record[..length];
+		if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+			return Err(LoadError::Checksum { line: self.line });
+		}
+		let address = u16::from_be_bytes([record[1], record[2]]) as u32;
+		let data = &record[4..length - 1];
+		let word = |data: &[u8]| {
+			data.iter()
+				.fold(0u32, |word, byte| word << 8 | *byte as u32)
+		};
+		match (record[3], data.len()) {
+			(0x00, _) => self.put(self.upper.wrapping_add(address), data)?,
+			(0x01, 0) => return Ok(true),
+			(0x02, 2) => self.upper = word(data) << 4,
+			(0x03, 4) => self.entry = Some((word(&data[..2]) << 4).wrapping_add(word(&data[2..]))),
+			(0x04, 2) => self.upper = word(data) << 16,
+			(0x05, 4) => self.entry = Some(word(data)),
+			_ => return Err(syntax()),
+		}
+		Ok(false)
+	}
+
+	/// Feed a line of a Motorola S-record file, returns whether it is a termination record.
+	///
+	/// Blank lines are ignored.
+	pub fn srec_line(&mut self, line: &[u8]) -> Result<bool, LoadError<S::Error>> {
+		self.line += 1;
+		let line = trim(line);
+		if line.is_empty() {
+			return Ok(false);
+		}
+		let number = self.line;
+		let syntax = || LoadError::Syntax { line: number };
+		if line.len() < 4 || line[0] != b'S' {
+			return Err(syntax());
+		}
+		let mut record = [0; 1 + 255];
+		let length = decode(&line[2..], &mut record).ok_or_else(syntax)?;
+		if length < 2 || length != 1 + record[0] as usize {
+			return Err(syntax());
+		}
+		let record = &record[..length];
+		let sum = record[..length - 1]
+			.iter()
+			.fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+		if !sum != record[length - 1] {
+			return Err(LoadError::Checksum { line: self.line });
+		}
+		let (kind, address_bytes) = match line[1] {
+			b'0' | b'1' | b'5' | b'9' => (line[1], 2),
+			b'2' | b'6' | b'8' => (line[1], 3),
+			b'3' | b'7' => (line[1], 4),
+			_ => return Err(syntax()),
+		};
+		if length < 2 + address_bytes {
+			return Err(syntax());
+		}
+		let address = record[1..1 + address_bytes]
+			.iter()
+			.fold(0u32, |word, byte| word << 8 | *byte as u32);
+		let data = &record[1 + address_bytes..length - 1];
+		match kind {
+			b'1' | b'2' | b'3' => self.put(address, data)?,
+			b'7' | b'8' | b'9' => {
+				self.entry = Some(address);
+				return Ok(true);
+			}
+			_ => {}
+		}
+		Ok(false)
+	}
+
+	/// Program the data collected so far, returns the entry point of the image if it has one.
+	pub fn finish(mut self) -> Result<Option<u32>, LoadError<S::Error>> {
+		self.flush()?;
+		Ok(self.entry)
+	}
+
+	/// Collect the data of a record at the image `address`.
+	fn put(&mut self, address: u32, mut data: &[u8]) -> Result<(), LoadError<S::Error>> {
+		let out_of_order = LoadError::OutOfOrder { line: self.line };
+		let below = CheckError::OutOfBounds(OutOfBoundsInfo {
+			offset: address,
+			length: data.len(),
+			capacity: self.flash.capacity(),
+		});
+		let mut offset = address
+			.checked_sub(self.base)
+			.ok_or(LoadError::Invalid(below))?;
+		check_slice(self.flash.capacity(), 1, offset, data.len()).map_err(LoadError::Invalid)?;
+		while !data.is_empty() {
+			if offset < self.start + self.length as u32 {
+				return Err(out_of_order);
+			}
+			if offset - self.start >= BUFFER as u32 {
+				self.flush()?;
+				if offset < self.start {
+					return Err(out_of_order);
+				}
+				self.start = offset - offset % S::WRITE_SIZE as u32;
+				fill_erased::<S>(self.start, &mut self.buffer);
+			}
+			let index = (offset - self.start) as usize;
+			let part = (BUFFER - index).min(data.len());
+			self.buffer[index..index + part].copy_from_slice(&data[..part]);
+			self.length = index + part;
+			offset += part as u32;
+			data = &data[part..];
+		}
+		Ok(())
+	}
+
+	/// Program the collected data, erasing the pages it covers if needed.
+	fn flush(&mut self) -> Result<(), LoadError<S::Error>> {
+		if self.length == 0 {
+			return Ok(());
+		}
+		let length = (self.length + S::WRITE_SIZE - 1) / S::WRITE_SIZE * S::WRITE_SIZE;
+		let end = self.start + length as u32;
+		let page = S::ERASE_SIZE as u32;
+		let from = self.erased.max(self.start - self.start % page);
+		let to = (end + page - 1) / page * page;
+		if from < to {
+			self.flash.erase(from, to).map_err(LoadError::Flash)?;
+			self.erased = to;
+		}
+		write_chunked(self.flash, self.start, &self.buffer[..length]).map_err(LoadError::Flash)?;
+		self.start = end;
+		fill_erased::<S>(self.start, &mut self.buffer);
+		self.length = 0;
+		Ok(())
+	}
+}
+
+/// Program the Intel HEX file `text` into `flash`, with the image address `base` mapped to the
+/// start of the flash. Returns the entry point of the image if it has one.
+pub fn program_ihex<S: NorFlash>(
+	flash: &mut S,
+	base: u32,
+	text: &[u8],
+) -> Result<Option<u32>, LoadError<S::Error>> {
+	let mut loader = Loader::try_new(flash, base).map_err(LoadError::Invalid)?;
+	for line in text.split(|c| *c == b'\n') {
+		if loader.ihex_line(line)? {
+			break;
+		}
+	}
+	loader.finish()
+}
+
+/// Program the Motorola S-record file `text` into `flash`, with the image address `base` mapped
+/// to the start of the flash. Returns the entry point of the image if it has one.
+pub fn program_srec<S: NorFlash>(
+	flash: &mut S,
+	base: u32,
+	text: &[u8],
+) -> Result<Option<u32>, LoadError<S::Error>> {
+	let mut loader = Loader::try_new(flash, base).map_err(LoadError::Invalid)?;
+	for line in text.split(|c| *c == b'\n') {
+		if loader.srec_line(line)? {
+			break;
+		}
+	}
+	loader.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	#[test]
+	fn try_new_invalid() {
+		let mut flash = MockFlash::<4096, 1, 4, 1024>::new();
+		assert!(Loader::try_new(&mut flash, 0).is_ok());
+		let mut flash = MockFlash::<4096, 1, 512, 1024>::new();
+		assert!(matches!(
+			Loader::try_new(&mut flash, 0),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			program_ihex(&mut flash, 0, b":00000001FF\n"),
+			Err(LoadError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	/// Flash with recognizable contents, to tell the pages left untouched.
+	fn marked<const WRITE_SIZE: usize>() -> MockFlash<4096, 1, WRITE_SIZE, 1024> {
+		MockFlash::from_bytes([0x55; 4096])
+	}
+
+	fn all(bytes: &[u8], value: u8) -> bool {
+		bytes.iter().all(|byte| *byte == value)
+	}
+
+	#[test]
+	fn ihex_linear() {
+		let mut flash = marked::<4>();
+		let text = b":020000040800F2\r\n:0400000001020304F2\n\n:020010000506E3\n\
+			:0400000508000101ED\n:00000001FF\nnot a record\n";
+		assert_eq!(
+			program_ihex(&mut flash, 0x0800_0000, text),
+			Ok(Some(0x0800_0101))
+		);
+		let bytes = flash.as_bytes();
+		assert_eq!(bytes[..4], [1, 2, 3, 4]);
+		assert!(all(&bytes[4..0x10], 0xff));
+		assert_eq!(bytes[0x10..0x12], [5, 6]);
+		assert!(all(&bytes[0x12..1024], 0xff));
+		assert!(all(&bytes[1024..], 0x55));
+	}
+
+	#[test]
+	fn ihex_segment() {
+		let mut flash = marked::<4>();
+		let text = b":020000021000EC\n:02001000AABB89\n:0400000312340010A3\n:00000001FF\n";
+		assert_eq!(program_ihex(&mut flash, 0x1_0000, text), Ok(Some(0x1_2350)));
+		assert_eq!(flash.as_bytes()[0x10..0x12], [0xaa, 0xbb]);
+	}
+
+	#[test]
+	fn srec_addresses() {
+		let mut flash = marked::<4>();
+		let text = b"S0060000686472BB\nS1060010010203E3\nS5030003F9\nS9030010EC\n";
+		assert_eq!(program_srec(&mut flash, 0, text), Ok(Some(0x10)));
+		assert_eq!(flash.as_bytes()[0x10..0x13], [1, 2, 3]);
+
+		let mut flash = marked::<4>();
+		let text = b"S2060100200405CF\nS804010020DA\n";
+		assert_eq!(program_srec(&mut flash, 0x1_0000, text), Ok(Some(0x1_0020)));
+		assert_eq!(flash.as_bytes()[0x20..0x22], [4, 5]);
+
+		let mut flash = marked::<4>();
+		let text = b"S3060800003006BB\nS70508000030C2\n";
+		assert_eq!(
+			program_srec(&mut flash, 0x0800_0000, text),
+			Ok(Some(0x0800_0030))
+		);
+		assert_eq!(flash.as_bytes()[0x30], 6);
+		assert!(all(&flash.as_bytes()[1024..], 0x55));
+	}
+
+	#[test]
+	fn malformed_records() {
+		let mut flash = marked::<4>();
+		let mut loader = Loader::new(&mut flash, 0);
+		assert_eq!(loader.ihex_line(b""), Ok(false));
+		let bad = [
+			&b"0400000001020304F2"[..],
+			b":0400000001020304F",
+			b":0500000001020304F2",
+			b":00000006FA",
+			b":0300000408000F1",
+		];
+		for (line, text) in (2..).zip(bad.iter()) {
+			assert_eq!(loader.ihex_line(text), Err(LoadError::Syntax { line }));
+		}
+		assert_eq!(
+			loader.ihex_line(b":0400000001020304F3"),
+			Err(LoadError::Checksum { line: 7 })
+		);
+
+		let mut loader = Loader::new(&mut flash, 0);
+		let bad = [
+			&b"X1060010010203E3"[..],
+			b"S4030000FC",
+			b"S10600100102E3",
+			b"S1",
+		];
+		for (line, text) in (1..).zip(bad.iter()) {
+			assert_eq!(loader.srec_line(text), Err(LoadError::Syntax { line }));
+		}
+		assert_eq!(
+			loader.srec_line(b"S1060010010203E4"),
+			Err(LoadError::Checksum { line: 5 })
+		);
+	}
+
+	#[test]
+	fn out_of_order_and_bounds() {
+		let mut flash = marked::<4>();
+		assert_eq!(
+			program_ihex(&mut flash, 0, b":0100200001DE\n:0100100002ED\n"),
+			Err(LoadError::OutOfOrder { line: 2 })
+		);
+		assert!(matches!(
+			program_ihex(&mut flash, 0, b":0120000001DE\n"),
+			Err(LoadError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert!(matches!(
+			program_ihex(&mut flash, 0x100, b":0100200001DE\n"),
+			Err(LoadError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn gaps() {
+		let mut flash = marked::<4>();
+		let text = b":0400000001020304F2\n:020C00000909E0\n:00000001FF\n";
+		assert_eq!(program_ihex(&mut flash, 0, text), Ok(None));
+		let bytes = flash.as_bytes();
+		assert_eq!(bytes[..4], [1, 2, 3, 4]);
+		assert!(all(&bytes[4..1024], 0xff));
+		assert!(all(&bytes[1024..3072], 0x55));
+		assert_eq!(bytes[3072..3074], [9, 9]);
+		assert!(all(&bytes[3074..], 0xff));
+	}
+
+	#[test]
+	fn write_size_padding() {
+		let mut flash = marked::<16>();
+		let text = b":030001000A0B0CDB\n\
+			:140010000102030405060708090A0B0C0D0E0F10111213140A\n:00000001FF\n";
+		assert_eq!(program_ihex(&mut flash, 0, text), Ok(None));
+		let bytes = flash.as_bytes();
+		assert_eq!(bytes[..4], [0xff, 0x0a, 0x0b, 0x0c]);
+		assert!(all(&bytes[4..0x10], 0xff));
+		assert!(bytes[0x10..0x24].iter().copied().eq(1..=20));
+		assert!(all(&bytes[0x24..1024], 0xff));
+	}
+}
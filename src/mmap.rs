@@ -0,0 +1,193 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use memmap2::MmapMut;
+
+use crate::nor_flash::{
+	check_erase, check_read, check_write, erased_byte, fill_erased, program_byte, CheckError,
+	ErrorType, FlashInfo, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind,
+	ReadNorFlash, ReadSlice,
+};
+
+/// Errors returned by [`MmapFlash`].
+#[derive(Debug)]
+pub enum MmapError {
+	/// Writing the mapping back to the file failed.
+	Io(io::Error),
+	/// The arguments are not properly aligned or out of bounds.
+	Invalid(CheckError),
+}
+
+impl NorFlashError for MmapError {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Io(error) => error.kind().into(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+impl From<CheckError> for MmapError {
+	fn from(error: CheckError) -> Self {
+		Self::Invalid(error)
+	}
+}
+
+/// Flash stored in a memory mapped host file.
+///
+/// Besides the usual traits, this implements [`ReadSlice`] straight from the mapping, so firmware
+/// logic assuming execute in place flash can be tested on the host, and the contents persist
+/// between runs. Writes only clear bits, like on a real NOR flash.
+pub struct MmapFlash<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> {
+	map: MmapMut,
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+	MmapFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	/// Map the file at `path` holding a flash of `capacity` bytes.
+	///
+	/// A missing or empty file is created fully erased. Fails with
+	/// [`std::io::ErrorKind::InvalidInput`] if `capacity` is not a multiple of `ERASE_SIZE`, and
+	/// with [`std::io::ErrorKind::InvalidData`] if the file has another length. The file must not
+	/// be resized or modified by other processes while it is mapped.
+	///
+	/// Writes reach the file when the flash is flushed with [`NorFlash::flush`].
+	pub fn open<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<Self> {
+		if capacity % ERASE_SIZE != 0 {
+			return Err(io::ErrorKind::InvalidInput.into());
+		}
+		let file = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(path)?;
+		let empty = match file.metadata()?.len() as usize {
+			0 => true,
+			length if length == capacity => false,
+			_ => return Err(io::ErrorKind::InvalidData.into()),
+		};
+		file.set_len(capacity as u64)?;
+		let mut flash = Self { map: map(&file)? };
+		if empty {
+			fill_erased::<Self>(0, &mut flash.map);
+		}
+		Ok(flash)
+	}
+
+	/// The current contents of the flash.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.map
+	}
+}
+
+#[allow(unsafe_code)]
+fn map(file: &File) -> io::Result<MmapMut> {
+	// SAFETY: the file is private to the flash, as required by `MmapFlash::open`.
+	unsafe { MmapMut::map_mut(file) }
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType
+	for MmapFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	type Error = MmapError;
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+	for MmapFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		bytes.copy_from_slice(self.read_slice(offset, bytes.len())?);
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.map.len()
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadSlice
+	for MmapFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn read_slice(&self, offset: u32, length: usize) -> Result<&[u8], Self::Error> {
+		check_read(self, offset, length)?;
+		Ok(&self.map[offset as usize..offset as usize + length])
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+	for MmapFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to)?;
+		fill_erased::<Self>(from, &mut self.map[from as usize..to as usize]);
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len())?;
+		let data = &mut self.map[offset as usize..offset as usize + bytes.len()];
+		for ((address, old), new) in (offset..).zip(data.iter_mut()).zip(bytes) {
+			*old = program_byte(erased_byte::<Self>(address), *old, *new);
+		}
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.map.flush().map_err(MmapError::Io)
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			multiwrite: true,
+			..FlashInfo::new(self)
+		}
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> MultiwriteNorFlash
+	for MmapFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	type Flash = MmapFlash<1, 4, 1024>;
+
+	/// Write through the generic traits, as the storage modules do.
+	fn store<S: NorFlash>(flash: &mut S, bytes: &[u8]) -> Result<(), S::Error> {
+		flash.erase(0, S::ERASE_SIZE as u32)?;
+		flash.write(0, bytes)?;
+		flash.flush()
+	}
+
+	#[test]
+	fn open_and_flush() {
+		let path = std::env::temp_dir().join(std::format!("mmap-flash-{}", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		assert_eq!(
+			Flash::open(&path, 1000).err().map(|error| error.kind()),
+			Some(io::ErrorKind::InvalidInput)
+		);
+
+		let mut flash = Flash::open(&path, 4096).unwrap();
+		store(&mut flash, &[1, 2, 3, 4]).unwrap();
+		drop(flash);
+		let contents = std::fs::read(&path).unwrap();
+		assert_eq!(&contents[..6], &[1, 2, 3, 4, 0xff, 0xff]);
+		assert_eq!(
+			Flash::open(&path, 8192).err().map(|error| error.kind()),
+			Some(io::ErrorKind::InvalidData)
+		);
+		std::fs::remove_file(&path).unwrap();
+	}
+}
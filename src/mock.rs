@@ -0,0 +1,1855 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+#[cfg(feature = "alloc")]
+use core::ops::Range;
+
+#[cfg(feature = "model-checking")]
+use crate::model::{FlashModel, WordState};
+#[cfg(feature = "alloc")]
+use crate::nor_flash::erased_byte;
+
+use crate::nor_flash::{
+	check_erase, check_read, check_write, fill_erased, is_erased, programmed_byte, CheckError,
+	ChipErase, ErrorType, FlashInfo, MountableStorage, MultiwriteNorFlash, NorFlash, NorFlashError,
+	NorFlashErrorKind, ProgramPolarity, ReadNorFlash, ReadSlice,
+};
+
+/// Errors returned by [`MockFlash`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum MockError {
+	/// The arguments are not properly aligned.
+	NotAligned,
+	/// The arguments are out of bounds.
+	OutOfBounds,
+	/// A write targeted a word which is not erased.
+	DirtyWrite,
+	/// Power was cut during a write, see [`MockFlash::cut_next_write`].
+	PowerLoss,
+	/// The operation did not complete in time.
+	Timeout,
+	/// The supply is too low to start the operation.
+	LowPower,
+	/// Any other error, e.g. an injected fault.
+	Other,
+}
+
+impl NorFlashError for MockError {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::NotAligned => NorFlashErrorKind::NotAligned,
+			Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+			Self::Timeout => NorFlashErrorKind::Timeout,
+			Self::LowPower => NorFlashErrorKind::LowPower,
+			Self::DirtyWrite | Self::PowerLoss | Self::Other => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+impl From<CheckError> for MockError {
+	fn from(error: CheckError) -> Self {
+		error.kind().into()
+	}
+}
+
+impl From<NorFlashErrorKind> for MockError {
+	fn from(kind: NorFlashErrorKind) -> Self {
+		match kind {
+			NorFlashErrorKind::NotAligned => Self::NotAligned,
+			NorFlashErrorKind::OutOfBounds => Self::OutOfBounds,
+			NorFlashErrorKind::Timeout => Self::Timeout,
+			NorFlashErrorKind::LowPower => Self::LowPower,
+			_ => Self::Other,
+		}
+	}
+}
+
+/// Simulated NOR flash storing its contents inline.
+///
+/// Geometry is given by the const parameters, all of which are in bytes. Writes to words which
+/// are not erased are rejected with [`MockError::DirtyWrite`].
+#[derive(Debug, Clone)]
+pub struct MockFlash<
+	const CAPACITY: usize,
+	const READ_SIZE: usize,
+	const WRITE_SIZE: usize,
+	const ERASE_SIZE: usize,
+> {
+	data: [u8; CAPACITY],
+	cut: Option<usize>,
+	model: Model,
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	/// Create a fully erased flash.
+	pub fn new() -> Self {
+		let mut flash = Self {
+			data: [0; CAPACITY],
+			cut: None,
+			model: NO_MODEL,
+		};
+		fill_erased::<Self>(0, &mut flash.data);
+		flash
+	}
+
+	/// Create a flash holding `data`, e.g. a golden image of a device.
+	pub const fn from_bytes(data: [u8; CAPACITY]) -> Self {
+		Self {
+			data,
+			cut: None,
+			model: NO_MODEL,
+		}
+	}
+
+	/// The current contents of the flash.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.data
+	}
+
+	/// Cut power during the next write, leaving its last `words` words of `WRITE_SIZE` partially
+	/// programmed.
+	///
+	/// The words before them are programmed and the rest of the flash is unchanged, while the bits
+	/// of the trailing words are a mix of their old and new values, as a real NOR flash leaves
+	/// words being programmed when power is lost. The write then fails with
+	/// [`MockError::PowerLoss`], and the following operations work normally.
+	pub fn cut_next_write(&mut self, words: usize) {
+		self.cut = Some(words);
+	}
+
+	/// Create a flash holding the contents of the file at `path`.
+	///
+	/// Fails with [`std::io::ErrorKind::InvalidData`] if the file is not `CAPACITY` bytes long.
+	#[cfg(feature = "std")]
+	pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+		let mut flash = Self::new();
+		let data = std::fs::read(path)?;
+		if data.len() != CAPACITY {
+			return Err(std::io::ErrorKind::InvalidData.into());
+		}
+		flash.data.copy_from_slice(&data);
+		Ok(flash)
+	}
+
+	/// Save the contents of the flash to the file at `path`, e.g. to inspect it after a failure.
+	#[cfg(feature = "std")]
+	pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+		std::fs::write(path, self.as_bytes())
+	}
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> Default for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> ErrorType for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	type Error = MockError;
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> ReadNorFlash for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len())?;
+		read_data::<Self>(&self.data, &mut self.model, offset, bytes);
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		CAPACITY
+	}
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> ReadSlice for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn read_slice(&self, offset: u32, length: usize) -> Result<&[u8], Self::Error> {
+		check_read(self, offset, length)?;
+		Ok(&self.data[offset as usize..offset as usize + length])
+	}
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> NorFlash for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to)?;
+		erase_data::<Self>(&mut self.data, &mut self.model, from, to);
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len())?;
+		write_data::<Self>(
+			&mut self.data,
+			&mut self.model,
+			offset,
+			bytes,
+			self.cut.take(),
+			false,
+		)
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: true,
+			..FlashInfo::new(self)
+		}
+	}
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> sealed::Mock for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn write_parts(&mut self) -> (&mut [u8], &mut Model, Option<usize>) {
+		(&mut self.data, &mut self.model, self.cut.take())
+	}
+
+	fn parts(&mut self) -> (&mut [u8], &mut Model) {
+		(&mut self.data, &mut self.model)
+	}
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> MountableStorage for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+}
+
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> ChipErase for MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		let capacity = self.capacity() as u32;
+		erase_data::<Self>(&mut self.data, &mut self.model, 0, capacity);
+		Ok(())
+	}
+}
+
+/// Model checking the mocks with the `model-checking` feature, built from their contents on the
+/// first operation.
+#[cfg(feature = "model-checking")]
+type Model = Option<FlashModel>;
+#[cfg(not(feature = "model-checking"))]
+type Model = ();
+
+#[cfg(feature = "model-checking")]
+const NO_MODEL: Model = None;
+#[cfg(not(feature = "model-checking"))]
+const NO_MODEL: Model = ();
+
+#[cfg(feature = "model-checking")]
+fn model_of<'a, S: NorFlash>(model: &'a mut Model, data: &[u8]) -> &'a mut FlashModel {
+	model.get_or_insert_with(|| FlashModel::from_contents::<S>(data))
+}
+
+#[cfg_attr(
+	not(feature = "model-checking"),
+	allow(unused_variables, clippy::extra_unused_type_parameters)
+)]
+fn read_data<S: NorFlash>(data: &[u8], model: &mut Model, offset: u32, bytes: &mut [u8]) {
+	let start = offset as usize;
+	bytes.copy_from_slice(&data[start..start + bytes.len()]);
+	#[cfg(feature = "model-checking")]
+	model_of::<S>(model, data).observe::<S>(offset, bytes);
+}
+
+#[cfg_attr(not(feature = "model-checking"), allow(unused_variables))]
+fn erase_data<S: NorFlash>(data: &mut [u8], model: &mut Model, from: u32, to: u32) {
+	#[cfg(feature = "model-checking")]
+	let model = model_of::<S>(model, data);
+	fill_erased::<S>(from, &mut data[from as usize..to as usize]);
+	#[cfg(feature = "model-checking")]
+	{
+		model.erase(from, to);
+		model.observe::<S>(from, &data[from as usize..to as usize]);
+	}
+}
+
+#[cfg_attr(not(feature = "model-checking"), allow(unused_variables))]
+fn write_data<S: NorFlash>(
+	data: &mut [u8],
+	model: &mut Model,
+	offset: u32,
+	bytes: &[u8],
+	cut: Option<usize>,
+	multiwrite: bool,
+) -> Result<(), MockError> {
+	#[cfg(feature = "model-checking")]
+	let (model, erased, programmed) = {
+		let model = model_of::<S>(model, data);
+		let states = model.states(offset, offset + bytes.len() as u32);
+		let erased = states.iter().all(|state| *state == WordState::Erased);
+		let programmed = states.contains(&WordState::Programmed);
+		(model, erased, programmed)
+	};
+	let result = program_data::<S>(data, offset, bytes, cut, multiwrite);
+	#[cfg(feature = "model-checking")]
+	{
+		let end = offset + bytes.len() as u32;
+		match result {
+			Ok(()) if multiwrite => {
+				model.lose(offset, end);
+				model.observe::<S>(offset, &data[offset as usize..end as usize]);
+			}
+			Ok(()) => {
+				assert!(
+					!programmed,
+					"Write to programmed word at {:#x} accepted",
+					offset
+				);
+				assert!(
+					data[offset as usize..end as usize] == *bytes,
+					"Write at {:#x} not programmed",
+					offset
+				);
+				if erased {
+					model.write::<S>(offset, bytes);
+				} else {
+					// Unknown words happened to be erased, they now hold whatever was written.
+					model.lose(offset, end);
+					model.observe::<S>(offset, bytes);
+				}
+			}
+			Err(MockError::DirtyWrite) => {
+				assert!(!erased, "Write to erased words at {:#x} rejected", offset)
+			}
+			Err(_) => model.lose(offset, end),
+		}
+	}
+	result
+}
+
+/// Program `bytes` at `offset` of `data`, combining them with the previous contents if
+/// `multiwrite`, or rejecting writes to words which are not erased otherwise.
+fn program_data<S: NorFlash>(
+	data: &mut [u8],
+	offset: u32,
+	bytes: &[u8],
+	cut: Option<usize>,
+	multiwrite: bool,
+) -> Result<(), MockError> {
+	let start = offset as usize;
+	let data = &mut data[start..start + bytes.len()];
+	if !multiwrite && !is_erased::<S>(offset, data) {
+		return Err(MockError::DirtyWrite);
+	}
+	let partial = match cut {
+		Some(words) => bytes.len() - (words * S::WRITE_SIZE).min(bytes.len()),
+		None => bytes.len(),
+	};
+	for (index, (byte, new)) in data.iter_mut().zip(bytes).enumerate() {
+		let address = (start + index) as u32;
+		let old = *byte;
+		*byte = if multiwrite {
+			programmed_byte::<S>(address, old, *new)
+		} else {
+			*new
+		};
+		if index >= partial {
+			// Some of the bits still to be programmed are left as they were, in a pattern varying
+			// with the address.
+			let mask = (address.wrapping_mul(0x9e37_79b1) >> 24) as u8;
+			*byte = *byte & !mask | old & mask;
+		}
+	}
+	if cut.is_some() {
+		return Err(MockError::PowerLoss);
+	}
+	Ok(())
+}
+
+mod sealed {
+	/// Access to the contents of a mock, for [`MultiwriteMockFlash`](super::MultiwriteMockFlash)
+	/// and [`MemoryMockFlash`](super::MemoryMockFlash).
+	pub trait Mock: super::NorFlash<Error = super::MockError> {
+		/// The contents and the model of the flash, and the power cut planned for the next write.
+		fn write_parts(&mut self) -> (&mut [u8], &mut super::Model, Option<usize>);
+
+		/// The contents and the model of the flash.
+		fn parts(&mut self) -> (&mut [u8], &mut super::Model);
+	}
+}
+
+/// [`MockFlash`] or [`MockFlashBox`] accepting writes to words already written, as a
+/// [`MultiwriteNorFlash`].
+///
+/// Written bytes are combined with the previous contents following the
+/// [`PROGRAM_POLARITY`](NorFlash::PROGRAM_POLARITY) of the flash, e.g. ANDed with them when
+/// erasing to `0xff`, as a real multiwrite flash only changes bits from their erased value.
+#[derive(Debug, Clone, Default)]
+pub struct MultiwriteMockFlash<S> {
+	flash: S,
+}
+
+impl<S: sealed::Mock> MultiwriteMockFlash<S> {
+	/// Accept multiple writes to the words of `flash`.
+	pub fn new(flash: S) -> Self {
+		Self { flash }
+	}
+
+	/// The simulated flash.
+	pub fn flash(&self) -> &S {
+		&self.flash
+	}
+
+	/// The simulated flash, e.g. to [cut power](MockFlash::cut_next_write) during the next write.
+	pub fn flash_mut(&mut self) -> &mut S {
+		&mut self.flash
+	}
+
+	/// Release the simulated flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+}
+
+impl<S: sealed::Mock> ErrorType for MultiwriteMockFlash<S> {
+	type Error = MockError;
+}
+
+impl<S: sealed::Mock> ReadNorFlash for MultiwriteMockFlash<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		self.flash.read(offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: sealed::Mock> NorFlash for MultiwriteMockFlash<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		self.flash.erase(from, to)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len())?;
+		let (data, model, cut) = self.flash.write_parts();
+		write_data::<S>(data, model, offset, bytes, cut, true)
+	}
+
+	fn info(&self) -> FlashInfo {
+		self.flash.info()
+	}
+}
+
+impl<S: sealed::Mock> MultiwriteNorFlash for MultiwriteMockFlash<S> {}
+
+impl<S: sealed::Mock + MountableStorage> MountableStorage for MultiwriteMockFlash<S> {}
+
+impl<S: sealed::Mock + ChipErase> ChipErase for MultiwriteMockFlash<S> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		self.flash.erase_chip()
+	}
+}
+
+/// Erased contents and program polarity of the memory simulated by [`MemoryMockFlash`].
+pub trait MockMemory {
+	/// See [`NorFlash::ERASE_BYTE`].
+	const ERASE_BYTE: u8;
+
+	/// See [`NorFlash::ERASE_PATTERN`].
+	const ERASE_PATTERN: &'static [u8] = &[Self::ERASE_BYTE];
+
+	/// See [`NorFlash::PROGRAM_POLARITY`].
+	const PROGRAM_POLARITY: ProgramPolarity = ProgramPolarity::FromErased;
+}
+
+/// Memory erasing every byte to `0x00`, for [`MemoryMockFlash`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ErasedToZero;
+
+impl MockMemory for ErasedToZero {
+	const ERASE_BYTE: u8 = 0x00;
+}
+
+/// Memory erasing every byte to `0x00` whose writes only set bits, with
+/// [`ProgramPolarity::SetBits`], for [`MemoryMockFlash`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct SetBitsMemory;
+
+impl MockMemory for SetBitsMemory {
+	const ERASE_BYTE: u8 = 0x00;
+	const PROGRAM_POLARITY: ProgramPolarity = ProgramPolarity::SetBits;
+}
+
+/// [`MockFlash`] or [`MockFlashBox`] erasing to the contents of the memory `M` instead of `0xff`,
+/// and following its program polarity.
+///
+/// This tests the code following [`ERASE_BYTE`](NorFlash::ERASE_BYTE),
+/// [`ERASE_PATTERN`](NorFlash::ERASE_PATTERN) and
+/// [`PROGRAM_POLARITY`](NorFlash::PROGRAM_POLARITY), e.g. with [`ErasedToZero`], [`SetBitsMemory`]
+/// or a memory erasing words to a pattern. The polarity applies once wrapped in a
+/// [`MultiwriteMockFlash`].
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMockFlash<S, M> {
+	flash: S,
+	memory: PhantomData<M>,
+}
+
+impl<S: sealed::Mock, M: MockMemory> MemoryMockFlash<S, M> {
+	/// Simulate the memory `M` with `flash`, which is erased to the contents of `M`.
+	pub fn new(mut flash: S) -> Self {
+		let (data, model) = flash.parts();
+		*model = NO_MODEL;
+		fill_erased::<Self>(0, data);
+		Self {
+			flash,
+			memory: PhantomData,
+		}
+	}
+
+	/// The simulated flash.
+	pub fn flash(&self) -> &S {
+		&self.flash
+	}
+
+	/// The simulated flash, e.g. to [cut power](MockFlash::cut_next_write) during the next write.
+	pub fn flash_mut(&mut self) -> &mut S {
+		&mut self.flash
+	}
+
+	/// Release the simulated flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+}
+
+impl<S: sealed::Mock, M: MockMemory> ErrorType for MemoryMockFlash<S, M> {
+	type Error = MockError;
+}
+
+impl<S: sealed::Mock, M: MockMemory> ReadNorFlash for MemoryMockFlash<S, M> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len())?;
+		let (data, model) = self.flash.parts();
+		read_data::<Self>(data, model, offset, bytes);
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: sealed::Mock + ReadSlice, M: MockMemory> ReadSlice for MemoryMockFlash<S, M> {
+	fn read_slice(&self, offset: u32, length: usize) -> Result<&[u8], Self::Error> {
+		self.flash.read_slice(offset, length)
+	}
+}
+
+impl<S: sealed::Mock, M: MockMemory> NorFlash for MemoryMockFlash<S, M> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = M::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = M::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = M::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to)?;
+		let (data, model) = self.flash.parts();
+		erase_data::<Self>(data, model, from, to);
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len())?;
+		let (data, model, cut) = self.flash.write_parts();
+		write_data::<Self>(data, model, offset, bytes, cut, false)
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			erase_byte: M::ERASE_BYTE,
+			..self.flash.info()
+		}
+	}
+}
+
+impl<S: sealed::Mock, M: MockMemory> sealed::Mock for MemoryMockFlash<S, M> {
+	fn write_parts(&mut self) -> (&mut [u8], &mut Model, Option<usize>) {
+		self.flash.write_parts()
+	}
+
+	fn parts(&mut self) -> (&mut [u8], &mut Model) {
+		self.flash.parts()
+	}
+}
+
+impl<S: sealed::Mock + MountableStorage, M: MockMemory> MountableStorage for MemoryMockFlash<S, M> {}
+
+impl<S: sealed::Mock + ChipErase, M: MockMemory> ChipErase for MemoryMockFlash<S, M> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		let capacity = self.capacity() as u32;
+		let (data, model) = self.flash.parts();
+		erase_data::<Self>(data, model, 0, capacity);
+		Ok(())
+	}
+}
+
+/// Heap allocated [`MockFlash`], for simulating devices too large to be stored inline.
+///
+/// The capacity is chosen at runtime, while the read, write and erase sizes remain const
+/// parameters as required by the `NorFlash` traits.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct MockFlashBox<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> {
+	data: Vec<u8>,
+	cut: Option<usize>,
+	model: Model,
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+	MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	/// Create a fully erased flash of `capacity` bytes.
+	///
+	/// **NOTE** This will panic if `capacity` is not a multiple of `ERASE_SIZE`.
+	pub fn new(capacity: usize) -> Self {
+		match Self::try_new(capacity) {
+			Ok(flash) => flash,
+			Err(_) => panic!("Capacity is not a multiple of the erase size"),
+		}
+	}
+
+	/// Create a fully erased flash of `capacity` bytes, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if `capacity` is not a multiple of `ERASE_SIZE`.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(capacity: usize) -> Result<Self, CheckError> {
+		let mut flash = Self::try_from_bytes(alloc::vec![0; capacity])?;
+		fill_erased::<Self>(0, &mut flash.data);
+		Ok(flash)
+	}
+
+	/// Create a flash holding `data`, e.g. a golden image of a device.
+	///
+	/// **NOTE** This will panic if the length of `data` is not a multiple of `ERASE_SIZE`.
+	pub fn from_bytes(data: Vec<u8>) -> Self {
+		match Self::try_from_bytes(data) {
+			Ok(flash) => flash,
+			Err(_) => panic!("Capacity is not a multiple of the erase size"),
+		}
+	}
+
+	/// Create a flash holding `data`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if the length of `data` is not a multiple of
+	/// `ERASE_SIZE`.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_from_bytes(data: Vec<u8>) -> Result<Self, CheckError> {
+		if data.len().checked_rem(ERASE_SIZE) != Some(0) {
+			return Err(CheckError::NotAligned);
+		}
+		Ok(Self {
+			data,
+			cut: None,
+			model: NO_MODEL,
+		})
+	}
+
+	/// The current contents of the flash.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.data
+	}
+
+	/// Cut power during the next write, leaving its last `words` words of `WRITE_SIZE` partially
+	/// programmed.
+	///
+	/// The words before them are programmed and the rest of the flash is unchanged, while the bits
+	/// of the trailing words are a mix of their old and new values, as a real NOR flash leaves
+	/// words being programmed when power is lost. The write then fails with
+	/// [`MockError::PowerLoss`], and the following operations work normally.
+	pub fn cut_next_write(&mut self, words: usize) {
+		self.cut = Some(words);
+	}
+
+	/// Create a flash holding the contents of the file at `path`.
+	///
+	/// Fails with [`std::io::ErrorKind::InvalidData`] if the length of the file is not a multiple
+	/// of `ERASE_SIZE`.
+	#[cfg(feature = "std")]
+	pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+		let data = std::fs::read(path)?;
+		Self::try_from_bytes(data).map_err(|_| std::io::ErrorKind::InvalidData.into())
+	}
+
+	/// Save the contents of the flash to the file at `path`, e.g. to inspect it after a failure.
+	#[cfg(feature = "std")]
+	pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+		std::fs::write(path, self.as_bytes())
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	type Error = MockError;
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len())?;
+		read_data::<Self>(&self.data, &mut self.model, offset, bytes);
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.data.len()
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadSlice
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn read_slice(&self, offset: u32, length: usize) -> Result<&[u8], Self::Error> {
+		check_read(self, offset, length)?;
+		Ok(&self.data[offset as usize..offset as usize + length])
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to)?;
+		erase_data::<Self>(&mut self.data, &mut self.model, from, to);
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len())?;
+		write_data::<Self>(
+			&mut self.data,
+			&mut self.model,
+			offset,
+			bytes,
+			self.cut.take(),
+			false,
+		)
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: true,
+			..FlashInfo::new(self)
+		}
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> sealed::Mock
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn write_parts(&mut self) -> (&mut [u8], &mut Model, Option<usize>) {
+		(&mut self.data, &mut self.model, self.cut.take())
+	}
+
+	fn parts(&mut self) -> (&mut [u8], &mut Model) {
+		(&mut self.data, &mut self.model)
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> MountableStorage
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+}
+
+#[cfg(feature = "alloc")]
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ChipErase
+	for MockFlashBox<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		let capacity = self.capacity() as u32;
+		erase_data::<Self>(&mut self.data, &mut self.model, 0, capacity);
+		Ok(())
+	}
+}
+
+/// An operation recorded by [`JournalingMockFlash`].
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Operation {
+	/// A read of `length` bytes at `offset`.
+	Read {
+		/// Offset of the read.
+		offset: u32,
+		/// Length of the read.
+		length: usize,
+	},
+	/// A write of `data` at `offset`.
+	Write {
+		/// Offset of the write.
+		offset: u32,
+		/// Written data.
+		data: Vec<u8>,
+	},
+	/// An erase of `from..to`.
+	Erase {
+		/// Start of the erased range.
+		from: u32,
+		/// End of the erased range.
+		to: u32,
+	},
+}
+
+/// A journal entry of [`JournalingMockFlash`].
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Entry {
+	/// The requested operation.
+	pub operation: Operation,
+	/// Contents of the accessed range before the operation, empty if it was out of bounds.
+	pub before: Vec<u8>,
+	/// Contents of the accessed range after the operation, empty if it was out of bounds.
+	pub after: Vec<u8>,
+	/// Outcome of the operation.
+	pub result: Result<(), MockError>,
+}
+
+#[cfg(feature = "alloc")]
+impl Entry {
+	/// Apply the recorded operation to `flash` again, reads and failed operations are skipped.
+	pub fn replay<S: NorFlash>(&self, flash: &mut S) -> Result<(), S::Error> {
+		match (&self.operation, self.result) {
+			(Operation::Write { offset, data }, Ok(())) => flash.write(*offset, data),
+			(Operation::Erase { from, to }, Ok(())) => flash.erase(*from, *to),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Apply the operations of `journal` to `flash` in order.
+///
+/// Replaying a prefix of a journal onto a fresh flash reproduces the state after that many
+/// operations, which allows shrinking failing operation sequences.
+#[cfg(feature = "alloc")]
+pub fn replay<S: NorFlash>(flash: &mut S, journal: &[Entry]) -> Result<(), S::Error> {
+	journal.iter().try_for_each(|entry| entry.replay(flash))
+}
+
+/// [`MockFlash`] recording every operation along with the contents of the accessed range before
+/// and after it.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct JournalingMockFlash<
+	const CAPACITY: usize,
+	const READ_SIZE: usize,
+	const WRITE_SIZE: usize,
+	const ERASE_SIZE: usize,
+> {
+	flash: MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>,
+	journal: Vec<Entry>,
+}
+
+#[cfg(feature = "alloc")]
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> JournalingMockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	/// Create a fully erased flash with an empty journal.
+	pub fn new() -> Self {
+		Self::from_flash(MockFlash::new())
+	}
+
+	/// Start journaling operations on `flash`.
+	pub fn from_flash(flash: MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>) -> Self {
+		Self {
+			flash,
+			journal: Vec::new(),
+		}
+	}
+
+	/// The operations recorded so far.
+	pub fn journal(&self) -> &[Entry] {
+		&self.journal
+	}
+
+	/// Take the operations recorded so far, leaving the journal empty.
+	pub fn take_journal(&mut self) -> Vec<Entry> {
+		core::mem::take(&mut self.journal)
+	}
+
+	/// The simulated flash.
+	pub fn flash(&self) -> &MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE> {
+		&self.flash
+	}
+
+	/// Stop journaling and release the simulated flash.
+	pub fn into_inner(self) -> MockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE> {
+		self.flash
+	}
+
+	fn image(&self, from: u32, length: usize) -> Vec<u8> {
+		let from = from as usize;
+		match from.checked_add(length) {
+			Some(to) if to <= CAPACITY => self.flash.data[from..to].to_vec(),
+			_ => Vec::new(),
+		}
+	}
+
+	fn record(&mut self, operation: Operation, before: Vec<u8>, result: Result<(), MockError>) {
+		let after = match operation {
+			Operation::Read { offset, length } => self.image(offset, length),
+			Operation::Write { offset, ref data } => self.image(offset, data.len()),
+			Operation::Erase { from, to } => self.image(from, to.saturating_sub(from) as usize),
+		};
+		self.journal.push(Entry {
+			operation,
+			before,
+			after,
+			result,
+		});
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> Default for JournalingMockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> ErrorType for JournalingMockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	type Error = MockError;
+}
+
+#[cfg(feature = "alloc")]
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> ReadNorFlash for JournalingMockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		let before = self.image(offset, bytes.len());
+		let result = self.flash.read(offset, bytes);
+		let length = bytes.len();
+		self.record(Operation::Read { offset, length }, before, result);
+		result
+	}
+
+	fn capacity(&self) -> usize {
+		CAPACITY
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<
+		const CAPACITY: usize,
+		const READ_SIZE: usize,
+		const WRITE_SIZE: usize,
+		const ERASE_SIZE: usize,
+	> NorFlash for JournalingMockFlash<CAPACITY, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		let before = self.image(from, to.saturating_sub(from) as usize);
+		let result = self.flash.erase(from, to);
+		self.record(Operation::Erase { from, to }, before, result);
+		result
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		let before = self.image(offset, bytes.len());
+		let result = self.flash.write(offset, bytes);
+		let data = bytes.to_vec();
+		self.record(Operation::Write { offset, data }, before, result);
+		result
+	}
+}
+
+/// An operation expected by [`ExpectingMockFlash`], with the range of bytes it accesses.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Expectation {
+	/// A read of the range.
+	Read(Range<u32>),
+	/// A write of the range.
+	Write(Range<u32>),
+	/// An erase of the range.
+	Erase(Range<u32>),
+}
+
+/// Flash checking that the operations it receives are exactly the expected ones.
+///
+/// Operations are expected with [`expect_read`](Self::expect_read),
+/// [`expect_write`](Self::expect_write) and [`expect_erase`](Self::expect_erase), then forwarded
+/// to the wrapped flash as they come. By default they must come in the order they were expected,
+/// [`unordered`](Self::unordered) accepts them in any order. An unexpected operation panics, and
+/// [`done`](Self::done) panics if some expected operations did not happen, so unit tests can
+/// assert the exact traffic generated by the layers above.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct ExpectingMockFlash<S> {
+	flash: S,
+	expected: Vec<Expectation>,
+	ordered: bool,
+	check_reads: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl<S> ExpectingMockFlash<S> {
+	/// Check the operations on `flash`, in order, starting with no expected operations.
+	pub fn new(flash: S) -> Self {
+		Self {
+			flash,
+			expected: Vec::new(),
+			ordered: true,
+			check_reads: true,
+		}
+	}
+
+	/// Accept the expected operations in any order.
+	pub fn unordered(mut self) -> Self {
+		self.ordered = false;
+		self
+	}
+
+	/// Let reads through without checking them, to only assert the writes and erases.
+	pub fn ignore_reads(mut self) -> Self {
+		self.check_reads = false;
+		self
+	}
+
+	/// Expect a read of `range`.
+	pub fn expect_read(&mut self, range: Range<u32>) -> &mut Self {
+		self.expected.push(Expectation::Read(range));
+		self
+	}
+
+	/// Expect a write of `range`.
+	pub fn expect_write(&mut self, range: Range<u32>) -> &mut Self {
+		self.expected.push(Expectation::Write(range));
+		self
+	}
+
+	/// Expect an erase of `range`.
+	pub fn expect_erase(&mut self, range: Range<u32>) -> &mut Self {
+		self.expected.push(Expectation::Erase(range));
+		self
+	}
+
+	/// The expected operations which did not happen yet.
+	pub fn pending(&self) -> &[Expectation] {
+		&self.expected
+	}
+
+	/// Check that all the expected operations happened.
+	///
+	/// **NOTE** This will panic if some of them did not.
+	pub fn done(&self) {
+		assert!(
+			self.expected.is_empty(),
+			"Expected operations did not happen: {:?}",
+			self.expected
+		);
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	fn check(&mut self, operation: Expectation) {
+		let index = if self.ordered {
+			self.expected
+				.first()
+				.filter(|e| **e == operation)
+				.map(|_| 0)
+		} else {
+			self.expected.iter().position(|e| *e == operation)
+		};
+		match index {
+			Some(index) => {
+				self.expected.remove(index);
+			}
+			None => panic!(
+				"Unexpected operation {:?}, expected {:?}",
+				operation, self.expected
+			),
+		}
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: ErrorType> ErrorType for ExpectingMockFlash<S> {
+	type Error = S::Error;
+}
+
+#[cfg(feature = "alloc")]
+impl<S: ReadNorFlash> ReadNorFlash for ExpectingMockFlash<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		if self.check_reads {
+			self.check(Expectation::Read(offset..offset + bytes.len() as u32));
+		}
+		self.flash.read(offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash> NorFlash for ExpectingMockFlash<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		self.check(Expectation::Erase(from..to));
+		self.flash.erase(from, to)
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		self.check(Expectation::Write(offset..offset + bytes.len() as u32));
+		self.flash.write(offset, bytes)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush()
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: false,
+			power_down: false,
+			..self.flash.info()
+		}
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: MultiwriteNorFlash> MultiwriteNorFlash for ExpectingMockFlash<S> {}
+
+/// Errors returned by [`WearingMockFlash`].
+#[cfg(feature = "alloc")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WearError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The erase of a sector past its endurance failed.
+	WornOut {
+		/// Address of the worn out sector.
+		address: u32,
+	},
+}
+
+#[cfg(feature = "alloc")]
+impl<E: NorFlashError> NorFlashError for WearError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::WornOut { .. } => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Failure of the sectors of a [`WearingMockFlash`] erased more times than their endurance.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum WearOut {
+	/// Erases fail with [`WearError::WornOut`], leaving the sector untouched.
+	EraseFails,
+	/// Erases succeed but leave the bits selected by the mask stuck at their programmed value,
+	/// which is only caught by checking the data.
+	StuckBits(u8),
+}
+
+/// Flash wrapper counting the erases of every sector and simulating their end of life.
+///
+/// Once a sector has been erased `endurance` times, further erases of it fail as configured by
+/// [`WearOut`]. Running a workload over it checks that wear leveling spreads the erases and that
+/// the layers above cope with worn out sectors, e.g. by remapping them.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct WearingMockFlash<S> {
+	flash: S,
+	endurance: u32,
+	wear_out: WearOut,
+	erases: Vec<u32>,
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash> WearingMockFlash<S> {
+	/// Wrap `flash`, with sectors of `ERASE_SIZE` lasting `endurance` erases.
+	pub fn new(flash: S, endurance: u32, wear_out: WearOut) -> Self {
+		let sectors = flash.capacity() / S::ERASE_SIZE;
+		Self {
+			flash,
+			endurance,
+			wear_out,
+			erases: alloc::vec![0; sectors],
+		}
+	}
+
+	/// Number of erases of every sector so far, including the failed ones.
+	pub fn erase_counts(&self) -> &[u32] {
+		&self.erases
+	}
+
+	/// Whether sector `index` has been erased more times than its endurance.
+	///
+	/// **NOTE** This will panic if `index` is out of bounds of the flash.
+	pub fn is_worn_out(&self, index: usize) -> bool {
+		self.erases[index] > self.endurance
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	fn erase_sector(&mut self, address: u32) -> Result<(), WearError<S::Error>> {
+		let index = address as usize / S::ERASE_SIZE;
+		self.erases[index] += 1;
+		let mask = match self.wear_out {
+			_ if !self.is_worn_out(index) => 0,
+			WearOut::EraseFails => return Err(WearError::WornOut { address }),
+			WearOut::StuckBits(mask) => mask,
+		};
+		let to = address + S::ERASE_SIZE as u32;
+		let mut data = alloc::vec![0; if mask != 0 { S::ERASE_SIZE } else { 0 }];
+		self.flash
+			.read(address, &mut data)
+			.map_err(WearError::Flash)?;
+		self.flash.erase(address, to).map_err(WearError::Flash)?;
+		if data.is_empty() {
+			return Ok(());
+		}
+		for (address, byte) in (address..).zip(&mut data) {
+			*byte = *byte & mask | erased_byte::<S>(address) & !mask;
+		}
+		crate::nor_flash::write_chunked(&mut self.flash, address, &data).map_err(WearError::Flash)
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: ErrorType> ErrorType for WearingMockFlash<S> {
+	type Error = WearError<S::Error>;
+}
+
+#[cfg(feature = "alloc")]
+impl<S: ReadNorFlash> ReadNorFlash for WearingMockFlash<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		self.flash.read(offset, bytes).map_err(WearError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash> NorFlash for WearingMockFlash<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		if check_erase(self, from, to).is_err() {
+			// Let the wrapped flash report the error in its own terms.
+			return self.flash.erase(from, to).map_err(WearError::Flash);
+		}
+		for address in (from..to).step_by(S::ERASE_SIZE) {
+			self.erase_sector(address)?;
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		self.flash.write(offset, bytes).map_err(WearError::Flash)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush().map_err(WearError::Flash)
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: false,
+			power_down: false,
+			..self.flash.info()
+		}
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: MultiwriteNorFlash> MultiwriteNorFlash for WearingMockFlash<S> {}
+
+#[cfg(feature = "alloc")]
+impl<S: MountableStorage> MountableStorage for WearingMockFlash<S> {
+	fn erase_cycles(&self) -> Option<u32> {
+		Some(self.endurance)
+	}
+
+	fn sync(&mut self) -> Result<(), Self::Error> {
+		self.flash.sync().map_err(WearError::Flash)
+	}
+}
+
+/// Flash wrapper simulating read disturb, i.e. bits flipping in sectors read many times.
+///
+/// Every sector counts its reads since it was last erased. Once it has been read `threshold`
+/// times, every further read of it clears one more bit, chosen pseudo-randomly in the sector, as
+/// charge slowly builds up in the cells. Erasing the sector restores it. Long running read-heavy
+/// workloads over it validate that scrubbing, i.e. rewriting data before it degrades, happens
+/// often enough.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct DisturbingMockFlash<S> {
+	flash: S,
+	threshold: u32,
+	reads: Vec<u32>,
+	/// Flipped bits, as the address of their byte and a mask of the bits cleared in it.
+	flips: Vec<(u32, u8)>,
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash> DisturbingMockFlash<S> {
+	/// Wrap `flash`, with sectors of `ERASE_SIZE` starting to flip bits after `threshold` reads.
+	pub fn new(flash: S, threshold: u32) -> Self {
+		let sectors = flash.capacity() / S::ERASE_SIZE;
+		Self {
+			flash,
+			threshold,
+			reads: alloc::vec![0; sectors],
+			flips: Vec::new(),
+		}
+	}
+
+	/// Number of reads of every sector since it was last erased.
+	pub fn read_counts(&self) -> &[u32] {
+		&self.reads
+	}
+
+	/// The flipped bits, as the address of their byte and a mask of the bits cleared in it.
+	pub fn flips(&self) -> &[(u32, u8)] {
+		&self.flips
+	}
+
+	/// Release the flash, without the flipped bits.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	fn disturb(&mut self, index: usize) {
+		self.reads[index] += 1;
+		let excess = match self.reads[index].checked_sub(self.threshold) {
+			Some(excess) if excess > 0 => excess,
+			_ => return,
+		};
+		let mut hash =
+			((index as u32).wrapping_mul(0x85eb_ca6b) ^ excess).wrapping_mul(0x9e37_79b1);
+		hash ^= hash >> 15;
+		let address = (index * S::ERASE_SIZE) as u32 + (hash >> 3) % S::ERASE_SIZE as u32;
+		let mask = 1 << (hash & 7);
+		match self.flips.iter_mut().find(|(a, _)| *a == address) {
+			Some((_, bits)) => *bits |= mask,
+			None => self.flips.push((address, mask)),
+		}
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: ErrorType> ErrorType for DisturbingMockFlash<S> {
+	type Error = S::Error;
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash> ReadNorFlash for DisturbingMockFlash<S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		self.flash.read(offset, bytes)?;
+		if bytes.is_empty() {
+			return Ok(());
+		}
+		let first = offset as usize / S::ERASE_SIZE;
+		let last = (offset as usize + bytes.len() - 1) / S::ERASE_SIZE;
+		(first..=last).for_each(|index| self.disturb(index));
+		let range = offset..offset + bytes.len() as u32;
+		for (address, mask) in self.flips.iter().filter(|(a, _)| range.contains(a)) {
+			let byte = &mut bytes[(address - offset) as usize];
+			let erased = erased_byte::<S>(*address);
+			*byte = crate::nor_flash::program_byte(erased, *byte, erased ^ mask);
+		}
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: NorFlash> NorFlash for DisturbingMockFlash<S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		self.flash.erase(from, to)?;
+		let sectors = from as usize / S::ERASE_SIZE..to as usize / S::ERASE_SIZE;
+		self.reads[sectors].iter_mut().for_each(|reads| *reads = 0);
+		self.flips
+			.retain(|(address, _)| !(from..to).contains(address));
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		self.flash.write(offset, bytes)
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush()
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: false,
+			power_down: false,
+			..self.flash.info()
+		}
+	}
+}
+
+#[cfg(feature = "alloc")]
+impl<S: MultiwriteNorFlash> MultiwriteNorFlash for DisturbingMockFlash<S> {}
+
+/// Source of simulated time advanced by [`TimedMockFlash`].
+pub trait Clock {
+	/// Let `ns` nanoseconds elapse.
+	fn advance(&mut self, ns: u64);
+}
+
+/// Counter of the elapsed nanoseconds.
+impl Clock for u64 {
+	fn advance(&mut self, ns: u64) {
+		*self += ns;
+	}
+}
+
+impl<T: Clock> Clock for &mut T {
+	fn advance(&mut self, ns: u64) {
+		T::advance(self, ns)
+	}
+}
+
+/// Durations of the operations simulated by [`TimedMockFlash`], in nanoseconds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Timing {
+	/// Fixed cost of every read and write, e.g. sending the command and address.
+	pub command_ns: u64,
+	/// Transfer of every byte read or written.
+	pub byte_ns: u64,
+	/// Programming of every chunk of up to `MAX_WRITE_CHUNK` bytes, after its transfer. Flashes
+	/// without a limit, such as [`MockFlash`], program every write as a single chunk.
+	pub program_ns: u64,
+	/// Erase of every page of `ERASE_SIZE` bytes.
+	pub erase_ns: u64,
+}
+
+impl Timing {
+	/// Typical serial NOR flash on a 40 MHz bus, with 256 byte pages and 4K sectors.
+	pub const SPI_NOR: Self = Self {
+		command_ns: 800,
+		byte_ns: 200,
+		program_ns: 600_000,
+		erase_ns: 45_000_000,
+	};
+}
+
+/// Flash wrapper advancing a simulated clock by the duration of every operation.
+///
+/// The durations are given by a [`Timing`], and only successful operations take time. Running
+/// the same workload over a [`MockFlash`] wrapped this way compares algorithms such as wear
+/// leveling or garbage collection strategies by the time they would take on a real device.
+#[derive(Debug, Clone)]
+pub struct TimedMockFlash<S, C> {
+	flash: S,
+	timing: Timing,
+	clock: C,
+}
+
+impl<S, C: Clock> TimedMockFlash<S, C> {
+	/// Wrap `flash`, advancing `clock` as configured by `timing`.
+	pub fn new(flash: S, timing: Timing, clock: C) -> Self {
+		Self {
+			flash,
+			timing,
+			clock,
+		}
+	}
+
+	/// The simulated clock.
+	pub fn clock(&self) -> &C {
+		&self.clock
+	}
+
+	/// Release the flash and the clock.
+	pub fn into_inner(self) -> (S, C) {
+		(self.flash, self.clock)
+	}
+}
+
+impl<S: ErrorType, C> ErrorType for TimedMockFlash<S, C> {
+	type Error = S::Error;
+}
+
+impl<S: ReadNorFlash, C: Clock> ReadNorFlash for TimedMockFlash<S, C> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		self.flash.read(offset, bytes)?;
+		let timing = self.timing;
+		self.clock
+			.advance(timing.command_ns + bytes.len() as u64 * timing.byte_ns);
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash, C: Clock> NorFlash for TimedMockFlash<S, C> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		self.flash.erase(from, to)?;
+		let pages = (to - from) as u64 / S::ERASE_SIZE as u64;
+		self.clock.advance(pages * self.timing.erase_ns);
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		self.flash.write(offset, bytes)?;
+		if bytes.is_empty() {
+			return Ok(());
+		}
+		let chunk = S::MAX_WRITE_CHUNK as u64;
+		let last = offset as u64 + bytes.len() as u64 - 1;
+		let chunks = last / chunk - offset as u64 / chunk + 1;
+		let timing = self.timing;
+		self.clock.advance(
+			timing.command_ns + bytes.len() as u64 * timing.byte_ns + chunks * timing.program_ns,
+		);
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush()
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			chip_erase: false,
+			power_down: false,
+			..self.flash.info()
+		}
+	}
+}
+
+impl<S: MultiwriteNorFlash, C: Clock> MultiwriteNorFlash for TimedMockFlash<S, C> {}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+	use super::*;
+	use crate::nor_flash::RmwMultiwriteNorFlashStorage;
+	use crate::{ReadStorage, Storage};
+
+	#[test]
+	fn mock_flash_box_try_new() {
+		type Flash = MockFlashBox<1, 4, 1024>;
+		assert_eq!(Flash::try_new(4096).unwrap().capacity(), 4096);
+		assert!(matches!(Flash::try_new(4000), Err(CheckError::NotAligned)));
+		assert!(Flash::try_from_bytes(alloc::vec![0; 2048]).is_ok());
+		assert!(matches!(
+			Flash::try_from_bytes(alloc::vec![0; 100]),
+			Err(CheckError::NotAligned)
+		));
+	}
+
+	/// Memory erasing words to `FFh FFh FFh 0Fh`, as with ECC bits.
+	struct EccWords;
+
+	impl MockMemory for EccWords {
+		const ERASE_BYTE: u8 = 0xff;
+		const ERASE_PATTERN: &'static [u8] = &[0xff, 0xff, 0xff, 0x0f];
+	}
+
+	type ZeroFlash = MemoryMockFlash<MockFlash<4096, 1, 4, 1024>, ErasedToZero>;
+	type EccFlash = MemoryMockFlash<MockFlashBox<1, 4, 1024>, EccWords>;
+
+	#[test]
+	fn memory_erased_contents() {
+		let mut flash = ZeroFlash::new(MockFlash::new());
+		assert!(flash.flash().as_bytes().iter().all(|byte| *byte == 0));
+		assert_eq!(flash.info().erase_byte, 0);
+		assert!(is_erased::<ZeroFlash>(0, &[0; 8]));
+		assert!(!is_erased::<ZeroFlash>(0, &[0xff]));
+		let mut bytes = [0xff; 6];
+		fill_erased::<ZeroFlash>(3, &mut bytes);
+		assert_eq!(bytes, [0; 6]);
+		assert_eq!(flash.write(0, &[1, 2, 3, 4]), Ok(()));
+		assert_eq!(flash.write(0, &[1, 2, 3, 4]), Err(MockError::DirtyWrite));
+		assert_eq!(flash.erase(0, 1024), Ok(()));
+		assert_eq!(flash.write(0, &[1, 2, 3, 4]), Ok(()));
+
+		let mut flash = EccFlash::new(MockFlashBox::new(4096));
+		assert_eq!(
+			&flash.flash().as_bytes()[..8],
+			&[0xff, 0xff, 0xff, 0x0f].repeat(2)[..]
+		);
+		assert!(is_erased::<EccFlash>(4, &[0xff, 0xff, 0xff, 0x0f]));
+		assert!(is_erased::<EccFlash>(2, &[0xff, 0x0f, 0xff]));
+		assert!(!is_erased::<EccFlash>(0, &[0xff; 4]));
+		let mut bytes = [0; 6];
+		fill_erased::<EccFlash>(2, &mut bytes);
+		assert_eq!(bytes, [0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f]);
+		assert_eq!(flash.write(4, &[0; 4]), Ok(()));
+		assert_eq!(flash.write(4, &[0; 4]), Err(MockError::DirtyWrite));
+		assert_eq!(flash.erase(0, 1024), Ok(()));
+		let mut bytes = [0; 8];
+		assert_eq!(flash.read(0, &mut bytes), Ok(()));
+		assert!(is_erased::<EccFlash>(0, &bytes));
+	}
+
+	#[test]
+	fn memory_multiwrite_subset() {
+		let flash = MultiwriteMockFlash::new(ZeroFlash::new(MockFlash::new()));
+		let mut flash = ExpectingMockFlash::new(flash).ignore_reads();
+		// Setting bits from the erased `00h` needs no erase, clearing one of them does.
+		flash
+			.expect_write(0..4)
+			.expect_write(0..4)
+			.expect_erase(0..1024)
+			.expect_write(0..1024);
+		let mut buffer = [0; 1024];
+		{
+			let mut storage = RmwMultiwriteNorFlashStorage::new(&mut flash, &mut buffer);
+			assert_eq!(storage.write(1, &[0x01]), Ok(()));
+			assert_eq!(storage.write(1, &[0x03]), Ok(()));
+			assert_eq!(storage.write(1, &[0x02]), Ok(()));
+			let mut byte = [0];
+			assert_eq!(storage.read(1, &mut byte), Ok(()));
+			assert_eq!(byte, [0x02]);
+		}
+		flash.done();
+
+		let flash = MultiwriteMockFlash::new(EccFlash::new(MockFlashBox::new(4096)));
+		let mut flash = ExpectingMockFlash::new(flash).ignore_reads();
+		// Bits of the ECC byte change from `0Fh` by setting them, the others by clearing them.
+		flash
+			.expect_write(0..4)
+			.expect_write(0..4)
+			.expect_erase(0..1024)
+			.expect_write(0..1024);
+		{
+			let mut storage = RmwMultiwriteNorFlashStorage::new(&mut flash, &mut buffer);
+			assert_eq!(storage.write(3, &[0x1f]), Ok(()));
+			assert_eq!(storage.write(0, &[0xfe]), Ok(()));
+			assert_eq!(storage.write(3, &[0x0f]), Ok(()));
+			let mut bytes = [0; 4];
+			assert_eq!(storage.read(0, &mut bytes), Ok(()));
+			assert_eq!(bytes, [0xfe, 0xff, 0xff, 0x0f]);
+		}
+		flash.done();
+	}
+
+	/// Memory erasing to `FFh` whose writes only clear bits.
+	struct ClearBitsMemory;
+
+	impl MockMemory for ClearBitsMemory {
+		const ERASE_BYTE: u8 = 0xff;
+		const PROGRAM_POLARITY: ProgramPolarity = ProgramPolarity::ClearBits;
+	}
+
+	/// Write the `(value, erase)` pairs in turn to byte 1 of `flash` through
+	/// [`RmwMultiwriteNorFlashStorage`], checking that the page is erased only if `erase`.
+	fn rmw_writes<S: MultiwriteNorFlash<Error = MockError>>(flash: S, writes: &[(u8, bool)]) {
+		let mut flash = ExpectingMockFlash::new(flash).ignore_reads();
+		for &(_, erase) in writes {
+			if erase {
+				flash.expect_erase(0..1024).expect_write(0..1024);
+			} else {
+				flash.expect_write(0..4);
+			}
+		}
+		let mut buffer = [0; 1024];
+		{
+			let mut storage = RmwMultiwriteNorFlashStorage::new(&mut flash, &mut buffer);
+			for &(value, _) in writes {
+				assert_eq!(storage.write(1, &[value]), Ok(()));
+				let mut byte = [0];
+				assert_eq!(storage.read(1, &mut byte), Ok(()));
+				assert_eq!(byte, [value]);
+			}
+		}
+		flash.done();
+	}
+
+	#[test]
+	fn rmw_polarity() {
+		type Flash = MockFlash<4096, 1, 4, 1024>;
+		let writes = [(0xfe, false), (0xfc, false), (0xfd, true)];
+		rmw_writes(MultiwriteMockFlash::new(Flash::new()), &writes);
+		let flash = MemoryMockFlash::<_, ClearBitsMemory>::new(Flash::new());
+		rmw_writes(MultiwriteMockFlash::new(flash), &writes);
+		let writes = [(0x01, false), (0x03, false), (0x02, true)];
+		let flash = MemoryMockFlash::<_, SetBitsMemory>::new(Flash::new());
+		rmw_writes(MultiwriteMockFlash::new(flash), &writes);
+	}
+
+	#[test]
+	fn multiwrite_polarity() {
+		type Flash = MockFlash<4096, 1, 4, 1024>;
+		let mut bytes = [0; 4];
+		let flash = MemoryMockFlash::<_, SetBitsMemory>::new(Flash::new());
+		let mut flash = MultiwriteMockFlash::new(flash);
+		assert_eq!(flash.write(0, &[0x0f, 0x00, 0x11, 0x80]), Ok(()));
+		assert_eq!(flash.write(0, &[0xf0, 0x00, 0x22, 0x01]), Ok(()));
+		assert_eq!(flash.read(0, &mut bytes), Ok(()));
+		assert_eq!(bytes, [0xff, 0x00, 0x33, 0x81]);
+
+		let flash = MemoryMockFlash::<_, ClearBitsMemory>::new(Flash::new());
+		let mut flash = MultiwriteMockFlash::new(flash);
+		assert_eq!(flash.write(0, &[0xf0, 0xff, 0xee, 0x7f]), Ok(()));
+		assert_eq!(flash.write(0, &[0x0f, 0xff, 0xdd, 0xfe]), Ok(()));
+		assert_eq!(flash.read(0, &mut bytes), Ok(()));
+		assert_eq!(bytes, [0x00, 0xff, 0xcc, 0x7e]);
+
+		// Bits of the ECC byte erased to `0Fh` change both ways, back to it only by erasing.
+		let flash = EccFlash::new(MockFlashBox::new(4096));
+		let mut flash = MultiwriteMockFlash::new(flash);
+		assert_eq!(flash.write(0, &[0xff, 0xff, 0xfe, 0x1f]), Ok(()));
+		assert_eq!(flash.write(0, &[0xff, 0xff, 0xff, 0x0e]), Ok(()));
+		assert_eq!(flash.read(0, &mut bytes), Ok(()));
+		assert_eq!(bytes, [0xff, 0xff, 0xfe, 0x1e]);
+	}
+}
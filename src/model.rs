@@ -0,0 +1,306 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::nor_flash::{
+	is_erased, programmed_byte, ErrorType, NorFlash, ProgramPolarity, ReadNorFlash,
+};
+
+/// Abstract state of a word of `WRITE_SIZE` bytes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum WordState {
+	/// The word was erased and not programmed since.
+	Erased,
+	/// The word was programmed since it was last erased.
+	Programmed,
+	/// The contents of the word are undefined, e.g. after an interrupted program.
+	Unknown,
+}
+
+/// Abstract model of a NOR flash, tracking the state of every word to check the operations
+/// against the `NorFlash` contract.
+///
+/// Every method updating the model asserts the invariants of the operation, and panics with the
+/// address of the first violation:
+///
+/// - erases and writes are aligned and in bounds;
+/// - writes only target erased words, or for a `MultiwriteNorFlash`, words whose bits can be
+///   programmed to the new value;
+/// - reads return the erase pattern for erased words, and not for programmed words.
+///
+/// The mocks and the RMW storage adapters check themselves with a model when the
+/// `model-checking` feature is enabled.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FlashModel {
+	words: Vec<WordState>,
+	write_size: usize,
+	erase_size: usize,
+}
+
+impl FlashModel {
+	/// Model a flash of `capacity` bytes with the geometry of `S`, whose contents are unknown.
+	pub fn new<S: NorFlash>(capacity: usize) -> Self {
+		Self {
+			words: vec![WordState::Unknown; capacity / S::WRITE_SIZE],
+			write_size: S::WRITE_SIZE,
+			erase_size: S::ERASE_SIZE,
+		}
+	}
+
+	/// Model a flash of `S` holding `data`, words holding the erase pattern being erased.
+	pub fn from_contents<S: NorFlash>(data: &[u8]) -> Self {
+		let mut model = Self::new::<S>(data.len());
+		model.observe::<S>(0, data);
+		model
+	}
+
+	/// The state of the word holding `address`.
+	pub fn state(&self, address: u32) -> WordState {
+		self.words[address as usize / self.write_size]
+	}
+
+	/// The states of the words overlapping `from..to`.
+	pub fn states(&self, from: u32, to: u32) -> &[WordState] {
+		&self.words[self.word_range(from, to)]
+	}
+
+	/// Check `bytes` read at `offset` against the model, learning the state of unknown words.
+	///
+	/// Unknown words fully covered by the read become erased or programmed, depending on whether
+	/// they hold the erase pattern.
+	pub fn observe<S: NorFlash>(&mut self, offset: u32, bytes: &[u8]) {
+		let end = offset as usize + bytes.len();
+		assert!(
+			end <= self.capacity(),
+			"Read of {} bytes at {:#x} out of bounds",
+			bytes.len(),
+			offset
+		);
+		let range = self.word_range(offset, end as u32);
+		for index in range {
+			let start = (index * self.write_size).max(offset as usize);
+			let stop = ((index + 1) * self.write_size).min(end);
+			let part = &bytes[start - offset as usize..stop - offset as usize];
+			let erased = is_erased::<S>(start as u32, part);
+			let whole = stop - start == self.write_size;
+			match self.words[index] {
+				WordState::Erased => {
+					assert!(erased, "Erased word at {:#x} read as programmed", start)
+				}
+				WordState::Programmed if whole => {
+					assert!(!erased, "Programmed word at {:#x} read as erased", start)
+				}
+				WordState::Unknown if whole => {
+					self.words[index] = if erased {
+						WordState::Erased
+					} else {
+						WordState::Programmed
+					};
+				}
+				_ => {}
+			}
+		}
+	}
+
+	/// Record the erase of `from..to`.
+	pub fn erase(&mut self, from: u32, to: u32) {
+		assert!(
+			from as usize % self.erase_size == 0 && to as usize % self.erase_size == 0,
+			"Erase of {:#x}..{:#x} not aligned",
+			from,
+			to
+		);
+		assert!(
+			from <= to && to as usize <= self.capacity(),
+			"Erase of {:#x}..{:#x} out of bounds",
+			from,
+			to
+		);
+		let range = self.word_range(from, to);
+		self.words[range].fill(WordState::Erased);
+	}
+
+	/// Record the write of `bytes` at `offset` to a `NorFlash`, asserting that it only targets
+	/// erased words.
+	pub fn write<S: NorFlash>(&mut self, offset: u32, bytes: &[u8]) {
+		self.check_write(offset, bytes.len());
+		let end = offset + bytes.len() as u32;
+		if let Some(index) = self
+			.states(offset, end)
+			.iter()
+			.position(|state| *state != WordState::Erased)
+		{
+			panic!(
+				"Write to word at {:#x} which is not erased",
+				offset as usize + index * self.write_size
+			);
+		}
+		self.programmed::<S>(offset, bytes);
+	}
+
+	/// Record the write of `bytes` at `offset` to a `MultiwriteNorFlash` holding `old`, asserting
+	/// that the bits of every word can be programmed to their new value.
+	pub fn rewrite<S: NorFlash>(&mut self, offset: u32, old: &[u8], bytes: &[u8]) {
+		self.check_write(offset, bytes.len());
+		let end = offset + bytes.len() as u32;
+		if let Some(index) = self
+			.states(offset, end)
+			.iter()
+			.position(|state| *state == WordState::Unknown)
+		{
+			panic!(
+				"Write to word at {:#x} whose contents are unknown",
+				offset as usize + index * self.write_size
+			);
+		}
+		for (address, (old, new)) in (offset..).zip(old.iter().zip(bytes)) {
+			assert!(
+				programmed_byte::<S>(address, *old, *new) == *new,
+				"Write of {:#04x} at {:#x} holding {:#04x} needs an erase",
+				new,
+				address,
+				old
+			);
+		}
+		self.programmed::<S>(offset, bytes);
+	}
+
+	/// Record that the contents of the words overlapping `from..to` are undefined, e.g. after an
+	/// operation interrupted by a power loss.
+	pub fn lose(&mut self, from: u32, to: u32) {
+		let range = self.word_range(from, to);
+		self.words[range].fill(WordState::Unknown);
+	}
+
+	fn capacity(&self) -> usize {
+		self.words.len() * self.write_size
+	}
+
+	fn word_range(&self, from: u32, to: u32) -> core::ops::Range<usize> {
+		let start = from as usize / self.write_size;
+		let end = (to as usize + self.write_size - 1) / self.write_size;
+		start..end.max(start)
+	}
+
+	fn check_write(&self, offset: u32, length: usize) {
+		assert!(
+			offset as usize % self.write_size == 0 && length % self.write_size == 0,
+			"Write of {} bytes at {:#x} not aligned",
+			length,
+			offset
+		);
+		assert!(
+			offset as usize + length <= self.capacity(),
+			"Write of {} bytes at {:#x} out of bounds",
+			length,
+			offset
+		);
+	}
+
+	/// Words left holding the erase pattern by a write stay erased.
+	fn programmed<S: NorFlash>(&mut self, offset: u32, bytes: &[u8]) {
+		for (index, word) in bytes.chunks(self.write_size).enumerate() {
+			let address = offset as usize + index * self.write_size;
+			let erased = is_erased::<S>(address as u32, word);
+			self.words[address / self.write_size] = if erased {
+				WordState::Erased
+			} else {
+				WordState::Programmed
+			};
+		}
+	}
+}
+
+/// NOR flash wrapper checking the operations of the RMW storage adapters against their model.
+pub(crate) struct Checked<'a, S> {
+	flash: S,
+	model: &'a mut FlashModel,
+	multiwrite: bool,
+}
+
+impl<'a, S: NorFlash> Checked<'a, S> {
+	/// Check the operations on `flash`, allowing to write programmed words if `multiwrite`.
+	pub(crate) fn new(flash: S, model: &'a mut FlashModel, multiwrite: bool) -> Self {
+		Self {
+			flash,
+			model,
+			multiwrite,
+		}
+	}
+}
+
+impl<S: ErrorType> ErrorType for Checked<'_, S> {
+	type Error = S::Error;
+}
+
+impl<S: NorFlash> ReadNorFlash for Checked<'_, S> {
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		self.flash.read(offset, bytes)?;
+		self.model.observe::<S>(offset, bytes);
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S: NorFlash> NorFlash for Checked<'_, S> {
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		self.model.erase(from, to);
+		let result = self.flash.erase(from, to);
+		if result.is_err() {
+			self.model.lose(from, to);
+		}
+		result
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		if self.multiwrite {
+			let mut old = vec![0; bytes.len()];
+			self.read(offset, &mut old)?;
+			self.model.rewrite::<S>(offset, &old, bytes);
+		} else {
+			self.model.write::<S>(offset, bytes);
+		}
+		let result = self.flash.write(offset, bytes);
+		if result.is_err() {
+			self.model.lose(offset, offset + bytes.len() as u32);
+		}
+		result
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush()
+	}
+}
+
+/// Assert that `bytes` are stored at `offset` of `flash`, after a successful `Storage::write`.
+pub(crate) fn assert_stored<S: ReadNorFlash>(
+	flash: &mut S,
+	offset: u32,
+	bytes: &[u8],
+) -> Result<(), S::Error> {
+	let start = offset as usize - offset as usize % S::READ_SIZE;
+	let end = offset as usize + bytes.len();
+	let end = (end + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+	let mut stored = vec![0; end - start];
+	flash.read(start as u32, &mut stored)?;
+	let skip = offset as usize - start;
+	if let Some(index) = (0..bytes.len()).find(|index| stored[skip + index] != bytes[*index]) {
+		panic!(
+			"Storage write at {:#x} not stored at {:#x}",
+			offset,
+			offset as usize + index
+		);
+	}
+	Ok(())
+}
@@ -0,0 +1,95 @@
+use crate::ftl::{BlockDevice, SECTOR_SIZE};
+
+/// Errors returned by [`MscStorage`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MscError<E> {
+	/// The blocks are out of range, or the buffer does not hold a whole number of blocks.
+	OutOfRange,
+	/// The device failed to read.
+	Read(E),
+	/// The device failed to write.
+	Write(E),
+}
+
+impl<E> MscError<E> {
+	/// The SCSI sense key, additional sense code and qualifier reported to the host.
+	pub fn sense(&self) -> (u8, u8, u8) {
+		match self {
+			// ILLEGAL REQUEST, LOGICAL BLOCK ADDRESS OUT OF RANGE
+			Self::OutOfRange => (0x05, 0x21, 0x00),
+			// MEDIUM ERROR, UNRECOVERED READ ERROR
+			Self::Read(_) => (0x03, 0x11, 0x00),
+			// MEDIUM ERROR, WRITE ERROR
+			Self::Write(_) => (0x03, 0x0c, 0x00),
+		}
+	}
+}
+
+/// Backing store of a USB mass storage class device, on top of a [`BlockDevice`].
+///
+/// This has the shape expected by the common USB mass storage crates: blocks addressed by their
+/// logical block address, `read_block` and `write_block` callbacks, the last address for the READ
+/// CAPACITY command, and errors mapped to SCSI sense data. Hooking it up is a matter of forwarding
+/// the callbacks, e.g. to expose external flash as a USB drive through the translation layer of
+/// [`ftl`](crate::ftl).
+///
+/// Blocks are [`SECTOR_SIZE`] bytes. A callback may transfer several consecutive blocks at once.
+pub struct MscStorage<D> {
+	device: D,
+}
+
+impl<D: BlockDevice> MscStorage<D> {
+	/// Size of a block in bytes.
+	pub const BLOCK_SIZE: u32 = SECTOR_SIZE as u32;
+
+	/// Expose `device` as a mass storage.
+	pub fn new(device: D) -> Self {
+		Self { device }
+	}
+
+	/// Release the device.
+	pub fn into_inner(self) -> D {
+		self.device
+	}
+
+	/// Number of blocks of the storage.
+	pub fn block_count(&self) -> u32 {
+		self.device.sector_count()
+	}
+
+	/// Address of the last block, as returned by the READ CAPACITY command.
+	///
+	/// **NOTE** This will panic if the device has no sectors.
+	pub fn max_lba(&self) -> u32 {
+		self.block_count()
+			.checked_sub(1)
+			.expect("Device without sectors")
+	}
+
+	/// Read the blocks starting at `lba` into `block`.
+	pub fn read_block(&mut self, lba: u32, block: &mut [u8]) -> Result<(), MscError<D::Error>> {
+		self.check(lba, block.len())?;
+		self.device.read(lba, block).map_err(MscError::Read)
+	}
+
+	/// Write the blocks starting at `lba` from `block`.
+	pub fn write_block(&mut self, lba: u32, block: &[u8]) -> Result<(), MscError<D::Error>> {
+		self.check(lba, block.len())?;
+		self.device.write(lba, block).map_err(MscError::Write)
+	}
+
+	/// Make the previous writes durable, for the SYNCHRONIZE CACHE command or when the host ejects
+	/// the drive.
+	pub fn flush(&mut self) -> Result<(), MscError<D::Error>> {
+		self.device.sync().map_err(MscError::Write)
+	}
+
+	fn check(&self, lba: u32, length: usize) -> Result<(), MscError<D::Error>> {
+		let blocks = length / SECTOR_SIZE;
+		let available = self.block_count().saturating_sub(lba) as usize;
+		if length % SECTOR_SIZE != 0 || lba >= self.block_count() || blocks > available {
+			return Err(MscError::OutOfRange);
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,171 @@
+#![allow(unsafe_code)]
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::nor_flash::{
+	check_erase, check_read, check_write, CheckError, ErrorType, MountableStorage, NorFlash,
+	NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// `struct mtd_info_user` from `<mtd/mtd-abi.h>`.
+#[repr(C)]
+#[derive(Default)]
+struct MtdInfo {
+	kind: u8,
+	flags: u32,
+	size: u32,
+	erase_size: u32,
+	write_size: u32,
+	oob_size: u32,
+	padding: u64,
+}
+
+/// `struct erase_info_user` from `<mtd/mtd-abi.h>`.
+#[repr(C)]
+struct EraseInfo {
+	start: u32,
+	length: u32,
+}
+
+/// `_IOR('M', 1, struct mtd_info_user)`.
+const MEMGETINFO: u32 = 0x8020_4d01;
+/// `_IOW('M', 2, struct erase_info_user)`.
+const MEMERASE: u32 = 0x4008_4d02;
+
+/// Errors returned by [`MtdFlash`].
+#[derive(Debug)]
+pub enum MtdError {
+	/// The device returned an error.
+	Io(io::Error),
+	/// The arguments are not properly aligned or out of bounds.
+	Invalid(CheckError),
+}
+
+impl NorFlashError for MtdError {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Io(error) => error.kind().into(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+impl From<io::Error> for MtdError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+/// Flash exposed by the Linux kernel as an MTD character device, such as `/dev/mtd0`.
+///
+/// Reads and writes go through the device file, erases through the `MEMERASE` ioctl, so storage
+/// stacks written against these traits run unchanged on embedded Linux. Flushing syncs the device
+/// file. The geometry is given by
+/// the const parameters, and checked against the one reported by the kernel when opening: the
+/// write and erase sizes must be multiples of the ones of the device.
+pub struct MtdFlash<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> {
+	file: File,
+	capacity: usize,
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+	MtdFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	/// Open the MTD character device at `path` for reading and writing.
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		Self::from_file(OpenOptions::new().read(true).write(true).open(path)?)
+	}
+
+	/// Use an already opened MTD character device.
+	///
+	/// Fails with [`std::io::ErrorKind::InvalidData`] if the geometry of the device does not fit
+	/// the const parameters.
+	pub fn from_file(file: File) -> io::Result<Self> {
+		let mut info = MtdInfo::default();
+		// SAFETY: `MEMGETINFO` fills a `struct mtd_info_user`, which `MtdInfo` mirrors.
+		if unsafe { libc::ioctl(file.as_raw_fd(), MEMGETINFO as _, &mut info) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		match (info.write_size as usize, info.erase_size as usize) {
+			(write, erase)
+				if write != 0
+					&& erase != 0 && WRITE_SIZE % write == 0
+					&& ERASE_SIZE % erase == 0
+					&& info.size as usize % ERASE_SIZE == 0 =>
+			{
+				Ok(Self {
+					file,
+					capacity: info.size as usize,
+				})
+			}
+			_ => Err(io::ErrorKind::InvalidData.into()),
+		}
+	}
+
+	/// Release the device file.
+	pub fn into_inner(self) -> File {
+		self.file
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType
+	for MtdFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	type Error = MtdError;
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+	for MtdFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len()).map_err(MtdError::Invalid)?;
+		self.file.read_exact_at(bytes, offset as u64)?;
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+	for MtdFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to).map_err(MtdError::Invalid)?;
+		let info = EraseInfo {
+			start: from,
+			length: to - from,
+		};
+		// SAFETY: `MEMERASE` reads a `struct erase_info_user`, which `EraseInfo` mirrors.
+		if unsafe { libc::ioctl(self.file.as_raw_fd(), MEMERASE as _, &info) } < 0 {
+			return Err(io::Error::last_os_error().into());
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len()).map_err(MtdError::Invalid)?;
+		self.file.write_all_at(bytes, offset as u64)?;
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.file.sync_all()?;
+		Ok(())
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> MountableStorage
+	for MtdFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+}
@@ -1,3 +1,5 @@
+use core::ops::{Bound, Range, RangeBounds};
+
 use crate::{iter::IterableByOverlaps, ReadStorage, Region, Storage};
 
 /// NOR flash errors.
@@ -34,6 +36,12 @@ pub enum NorFlashErrorKind {
 	/// The arguments are out of bounds.
 	OutOfBounds,
 
+	/// The operation did not complete in time.
+	Timeout,
+
+	/// The supply is too low to start the operation without risking to tear it.
+	LowPower,
+
 	/// Error specific to the implementation.
 	Other,
 }
@@ -49,11 +57,115 @@ impl core::fmt::Display for NorFlashErrorKind {
 		match self {
 			Self::NotAligned => write!(f, "Arguments are not properly aligned"),
 			Self::OutOfBounds => write!(f, "Arguments are out of bounds"),
+			Self::Timeout => write!(f, "The operation did not complete in time"),
+			Self::LowPower => write!(f, "The supply is too low to start the operation"),
 			Self::Other => write!(f, "An implementation specific error occurred"),
 		}
 	}
 }
 
+/// Details of an access rejected for being out of bounds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct OutOfBoundsInfo {
+	/// Start of the access.
+	pub offset: u32,
+	/// Length of the access in bytes.
+	pub length: usize,
+	/// Capacity of the peripheral in bytes.
+	pub capacity: usize,
+}
+
+impl core::fmt::Display for OutOfBoundsInfo {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		write!(
+			f,
+			"Access of {} bytes at {:#x} is out of bounds of {} bytes",
+			self.length, self.offset, self.capacity
+		)
+	}
+}
+
+/// Errors returned by the argument checking helpers, such as [`check_read`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CheckError {
+	/// The arguments are not properly aligned.
+	NotAligned,
+	/// The arguments are out of bounds.
+	OutOfBounds(OutOfBoundsInfo),
+	/// The combined devices do not agree on their erased contents or their program polarity.
+	Incompatible,
+	/// The arguments use a value reserved by the format, e.g. the record type marking the end of
+	/// TLV records.
+	Reserved,
+}
+
+impl NorFlashError for CheckError {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::NotAligned => NorFlashErrorKind::NotAligned,
+			Self::OutOfBounds(_) => NorFlashErrorKind::OutOfBounds,
+			Self::Incompatible | Self::Reserved => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+impl From<CheckError> for NorFlashErrorKind {
+	fn from(error: CheckError) -> Self {
+		error.kind()
+	}
+}
+
+impl core::fmt::Display for CheckError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::NotAligned => write!(f, "{}", NorFlashErrorKind::NotAligned),
+			Self::OutOfBounds(info) => write!(f, "{}", info),
+			Self::Incompatible => write!(f, "Devices with different erased contents or polarity"),
+			Self::Reserved => write!(f, "Reserved value"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NorFlashErrorKind {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CheckError {}
+
+#[cfg(feature = "std")]
+impl From<NorFlashErrorKind> for std::io::Error {
+	fn from(kind: NorFlashErrorKind) -> Self {
+		let io_kind = match kind {
+			NorFlashErrorKind::NotAligned => std::io::ErrorKind::InvalidInput,
+			NorFlashErrorKind::OutOfBounds => std::io::ErrorKind::UnexpectedEof,
+			NorFlashErrorKind::Timeout => std::io::ErrorKind::TimedOut,
+			NorFlashErrorKind::LowPower | NorFlashErrorKind::Other => std::io::ErrorKind::Other,
+		};
+		Self::new(io_kind, kind)
+	}
+}
+
+#[cfg(feature = "std")]
+impl From<CheckError> for std::io::Error {
+	fn from(error: CheckError) -> Self {
+		let io_kind = std::io::Error::from(error.kind()).kind();
+		Self::new(io_kind, error)
+	}
+}
+
+/// Best-effort mapping of I/O errors, e.g. from a file holding a flash image.
+#[cfg(feature = "std")]
+impl From<std::io::ErrorKind> for NorFlashErrorKind {
+	fn from(kind: std::io::ErrorKind) -> Self {
+		match kind {
+			std::io::ErrorKind::InvalidInput => Self::NotAligned,
+			std::io::ErrorKind::UnexpectedEof => Self::OutOfBounds,
+			std::io::ErrorKind::TimedOut => Self::Timeout,
+			_ => Self::Other,
+		}
+	}
+}
+
 /// Read only NOR flash trait.
 pub trait ReadNorFlash: ErrorType {
 	/// The minumum number of bytes the storage peripheral can read
@@ -73,12 +185,16 @@ pub trait ReadNorFlash: ErrorType {
 }
 
 /// Return whether a read operation is within bounds.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
 pub fn check_read<T: ReadNorFlash>(
 	flash: &T,
 	offset: u32,
 	length: usize,
-) -> Result<(), NorFlashErrorKind> {
-	check_slice(flash, T::READ_SIZE, offset, length)
+) -> Result<(), CheckError> {
+	check_slice(flash.capacity(), T::READ_SIZE, offset, length)
 }
 
 /// NOR flash trait.
@@ -89,8 +205,41 @@ pub trait NorFlash: ReadNorFlash {
 	/// The minumum number of bytes the storage peripheral can erase
 	const ERASE_SIZE: usize;
 
+	/// The most common value of the bytes of an erased range
+	///
+	/// Every byte of an erased range holds this value unless
+	/// [`ERASE_PATTERN`](Self::ERASE_PATTERN) is overridden, so code checking or producing erased
+	/// contents should go through [`erased_byte`], [`is_erased`] or [`fill_erased`] instead.
+	const ERASE_BYTE: u8 = 0xff;
+
+	/// The contents of an erased range, repeated from the start of every page
+	///
+	/// This is for memories erasing words to a pattern rather than every byte to the same value,
+	/// e.g. with ECC bits. Byte `address` of an erased range is
+	/// `ERASE_PATTERN[address % ERASE_PATTERN.len()]`, see [`erased_byte`], and the length of the
+	/// pattern divides `ERASE_SIZE`. It defaults to [`ERASE_BYTE`](Self::ERASE_BYTE) alone, which
+	/// should then be the most common byte of the pattern.
+	const ERASE_PATTERN: &'static [u8] = &[Self::ERASE_BYTE];
+
+	/// How writing to a word already written combines the old and the new data
+	///
+	/// This only matters for [`MultiwriteNorFlash`]. It defaults to
+	/// [`ProgramPolarity::FromErased`], bits only changing from their erased value, which covers
+	/// NOR flashes. Memories whose writes set or clear bits whatever they are erased to, such as
+	/// some FRAM or EEPROM emulations and NVRAM, state it here, see [`programmed_byte`].
+	const PROGRAM_POLARITY: ProgramPolarity = ProgramPolarity::FromErased;
+
+	/// The maximum number of bytes the storage peripheral can program in one operation
+	///
+	/// This is e.g. the 256 byte page program buffer of a SPI NOR flash: writes crossing a multiple
+	/// of this size have to be split by the driver, or may be rejected. It is a multiple of
+	/// `WRITE_SIZE`, and defaults to no limit. Generic code can use [`write_chunked`] to only issue
+	/// writes handled in one operation.
+	const MAX_WRITE_CHUNK: usize = usize::MAX;
+
 	/// Erase the given storage range, clearing all data within `[from..to]`.
-	/// The given range will contain all 1s afterwards.
+	/// Every byte of the given range will be [`ERASE_BYTE`](Self::ERASE_BYTE) afterwards, or
+	/// follow the [`ERASE_PATTERN`](Self::ERASE_PATTERN).
 	///
 	/// If power is lost during erase, contents of the page are undefined.
 	///
@@ -110,41 +259,495 @@ pub trait NorFlash: ReadNorFlash {
 	/// Returns an error if the arguments are not aligned or out of bounds. The implementation
 	/// can use the [`check_write`] helper function.
 	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+
+	/// Make the previous writes and erases durable.
+	///
+	/// Buffered implementations, such as caches or write-behind adapters, may keep writes and
+	/// erases in RAM until this is called. Generic code calls it at the end of a commit or before
+	/// power-down. The default implementation does nothing, for flashes completing writes and
+	/// erases before returning.
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	/// Describe the geometry and capabilities of the flash, e.g. for diagnostics.
+	///
+	/// The default implementation reports the geometry with no optional features, see
+	/// [`FlashInfo::new`]. Implementations supporting some of them override it.
+	fn info(&self) -> FlashInfo {
+		FlashInfo::new(self)
+	}
+}
+
+/// Geometry and capabilities of a flash, as returned by [`NorFlash::info`].
+///
+/// This lets diagnostic shells and host tools print the capabilities of any device the same way,
+/// e.g. with the [`Display`](core::fmt::Display) implementation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct FlashInfo {
+	/// Size of the flash in bytes.
+	pub capacity: usize,
+	/// See [`ReadNorFlash::READ_SIZE`].
+	pub read_size: usize,
+	/// See [`NorFlash::WRITE_SIZE`].
+	pub write_size: usize,
+	/// See [`NorFlash::ERASE_SIZE`].
+	pub erase_size: usize,
+	/// See [`NorFlash::ERASE_BYTE`].
+	pub erase_byte: u8,
+	/// See [`NorFlash::MAX_WRITE_CHUNK`].
+	pub max_write_chunk: usize,
+	/// Whether words can be written several times, see [`MultiwriteNorFlash`].
+	pub multiwrite: bool,
+	/// Whether the whole flash can be erased with a single command, see [`ChipErase`].
+	pub chip_erase: bool,
+	/// Whether the flash has a low power mode, see [`PowerControl`].
+	pub power_down: bool,
+	/// Whether program and erase operations can be suspended to read in the meantime.
+	pub suspend: bool,
+	/// Whether the flash has a one-time programmable area.
+	pub otp: bool,
+}
+
+impl FlashInfo {
+	/// The geometry of `flash`, with no optional features.
+	pub fn new<S: NorFlash + ?Sized>(flash: &S) -> Self {
+		Self {
+			capacity: flash.capacity(),
+			read_size: S::READ_SIZE,
+			write_size: S::WRITE_SIZE,
+			erase_size: S::ERASE_SIZE,
+			erase_byte: S::ERASE_BYTE,
+			max_write_chunk: S::MAX_WRITE_CHUNK,
+			multiwrite: false,
+			chip_erase: false,
+			power_down: false,
+			suspend: false,
+			otp: false,
+		}
+	}
+}
+
+impl core::fmt::Display for FlashInfo {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		write!(
+			f,
+			"{} bytes, read {}, write {}, erase {}, erase byte {:#04x}",
+			self.capacity, self.read_size, self.write_size, self.erase_size, self.erase_byte
+		)?;
+		if self.max_write_chunk != usize::MAX {
+			write!(f, ", write chunk {}", self.max_write_chunk)?;
+		}
+		let features = [
+			(self.multiwrite, "multiwrite"),
+			(self.chip_erase, "chip erase"),
+			(self.power_down, "power down"),
+			(self.suspend, "suspend"),
+			(self.otp, "OTP"),
+		];
+		for (_, name) in features.iter().filter(|(supported, _)| *supported) {
+			write!(f, ", {}", name)?;
+		}
+		Ok(())
+	}
 }
 
 /// Return whether an erase operation is aligned and within bounds.
-pub fn check_erase<T: NorFlash>(flash: &T, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
-	let (from, to) = (from as usize, to as usize);
-	if from > to || to > flash.capacity() {
-		return Err(NorFlashErrorKind::OutOfBounds);
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn check_erase<T: NorFlash>(flash: &T, from: u32, to: u32) -> Result<(), CheckError> {
+	check_range(flash.capacity(), T::ERASE_SIZE, from, to)
+}
+
+/// Plan the erase of `from..to` with the fewest commands among the erase `sizes`.
+///
+/// `sizes` are sorted by increasing size, each one a multiple of the previous one, and the range
+/// is aligned to the smallest one. Every step erases the largest aligned block which fits in the
+/// rest of the range, which for the usual 4K/32K/64K layouts of SPI NOR flashes is optimal.
+///
+/// # Errors
+///
+/// Returns [`CheckError::NotAligned`] if `sizes` is empty or holds a zero size, or if the range is
+/// not aligned to the smallest size, and [`CheckError::OutOfBounds`] if `from` is after `to`. The
+/// range is checked before planning any step, so that nothing is erased for an invalid range.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn optimize_erase(sizes: &[u32], from: u32, to: u32) -> Result<EraseSteps<'_>, CheckError> {
+	let smallest = match sizes.iter().min() {
+		Some(smallest) if *smallest > 0 => *smallest,
+		_ => return Err(CheckError::NotAligned),
+	};
+	check_range(to as usize, smallest as usize, from, to)?;
+	Ok(EraseSteps {
+		sizes,
+		address: from,
+		to,
+	})
+}
+
+/// Iterator over the `(address, size)` erase commands planned by [`optimize_erase`].
+#[derive(Debug, Clone)]
+pub struct EraseSteps<'a> {
+	sizes: &'a [u32],
+	address: u32,
+	to: u32,
+}
+
+impl Iterator for EraseSteps<'_> {
+	type Item = (u32, u32);
+
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.address >= self.to {
+			return None;
+		}
+		let (address, rest) = (self.address, self.to - self.address);
+		// The smallest size always fits, as the range is aligned to it.
+		let size = *self
+			.sizes
+			.iter()
+			.rev()
+			.find(|size| address.checked_rem(**size) == Some(0) && rest >= **size)?;
+		self.address += size;
+		Some((address, size))
 	}
-	if from % T::ERASE_SIZE != 0 || to % T::ERASE_SIZE != 0 {
-		return Err(NorFlashErrorKind::NotAligned);
+}
+
+/// Return whether a write operation is aligned and within bounds.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn check_write<T: NorFlash>(flash: &T, offset: u32, length: usize) -> Result<(), CheckError> {
+	check_slice(flash.capacity(), T::WRITE_SIZE, offset, length)
+}
+
+/// Check that the flashes `A` and `B` can be combined into one, having the same erased contents
+/// and program polarity.
+pub(crate) fn check_compatible<A: NorFlash, B: NorFlash>() -> Result<(), CheckError> {
+	if A::ERASE_BYTE != B::ERASE_BYTE
+		|| A::ERASE_PATTERN != B::ERASE_PATTERN
+		|| A::PROGRAM_POLARITY != B::PROGRAM_POLARITY
+	{
+		return Err(CheckError::Incompatible);
 	}
 	Ok(())
 }
 
-/// Return whether a write operation is aligned and within bounds.
-pub fn check_write<T: NorFlash>(
-	flash: &T,
+/// Write `bytes` at `offset`, split so that no write crosses a multiple of `MAX_WRITE_CHUNK`.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn write_chunked<T: NorFlash>(
+	flash: &mut T,
 	offset: u32,
-	length: usize,
-) -> Result<(), NorFlashErrorKind> {
-	check_slice(flash, T::WRITE_SIZE, offset, length)
+	bytes: &[u8],
+) -> Result<(), T::Error> {
+	span!("write_chunked", offset = offset, length = bytes.len());
+	let mut address = offset as usize;
+	let mut rest = bytes;
+	while !rest.is_empty() {
+		let part = (T::MAX_WRITE_CHUNK - address % T::MAX_WRITE_CHUNK).min(rest.len());
+		let (chunk, tail) = rest.split_at(part);
+		flash.write(address as u32, chunk)?;
+		address += part;
+		rest = tail;
+	}
+	Ok(())
 }
 
-fn check_slice<T: ReadNorFlash>(
-	flash: &T,
+/// Write `bytes` at `offset` like [`write_chunked`], skipping the words made of `skip_byte` only.
+///
+/// Programming words to the erased value does not change them, so when writing to erased flash
+/// with `skip_byte` set to `ERASE_BYTE`, skipping them saves program time and wear. This pays off
+/// for images with large holes, such as firmware binaries padded to the sector size. Words are
+/// `WRITE_SIZE` bytes, so `offset` and the length of `bytes` must be aligned as for
+/// [`NorFlash::write`].
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn write_sparse<T: NorFlash>(
+	flash: &mut T,
+	offset: u32,
+	bytes: &[u8],
+	skip_byte: u8,
+) -> Result<(), T::Error> {
+	span!("write_sparse", offset = offset, length = bytes.len());
+	write_skipping(flash, offset, bytes, |_, word| {
+		word.iter().all(|&byte| byte == skip_byte)
+	})
+}
+
+/// Write `bytes` at `offset` like [`write_sparse`], skipping the words holding their erased
+/// contents, following the [`ERASE_PATTERN`](NorFlash::ERASE_PATTERN) of the flash.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn write_sparse_erased<T: NorFlash>(
+	flash: &mut T,
+	offset: u32,
+	bytes: &[u8],
+) -> Result<(), T::Error> {
+	span!("write_sparse_erased", offset = offset, length = bytes.len());
+	write_skipping(flash, offset, bytes, is_erased::<T>)
+}
+
+/// Write `bytes` at `offset`, skipping the words for which `skip` returns `true` given their
+/// address and contents.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+fn write_skipping<T: NorFlash>(
+	flash: &mut T,
+	offset: u32,
+	bytes: &[u8],
+	skip: impl Fn(u32, &[u8]) -> bool,
+) -> Result<(), T::Error> {
+	let mut start = 0;
+	let mut end = 0;
+	for word in bytes.chunks(T::WRITE_SIZE) {
+		if skip(offset + end as u32, word) {
+			if let Some(run) = bytes.get(start..end).filter(|run| !run.is_empty()) {
+				write_chunked(flash, offset + start as u32, run)?;
+			}
+			start = end + word.len();
+		}
+		end += word.len();
+	}
+	match bytes.get(start..end) {
+		Some(run) if !run.is_empty() => write_chunked(flash, offset + start as u32, run),
+		_ => Ok(()),
+	}
+}
+
+/// The value of the erased byte at `address` of a flash of type `T`, following its
+/// [`ERASE_PATTERN`](NorFlash::ERASE_PATTERN).
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn erased_byte<T: NorFlash + ?Sized>(address: u32) -> u8 {
+	let pattern = T::ERASE_PATTERN;
+	address
+		.checked_rem(pattern.len() as u32)
+		.and_then(|index| pattern.get(index as usize).copied())
+		.unwrap_or(T::ERASE_BYTE)
+}
+
+/// Return whether `bytes`, read at `offset` of a flash of type `T`, are erased.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn is_erased<T: NorFlash + ?Sized>(offset: u32, bytes: &[u8]) -> bool {
+	(offset..)
+		.zip(bytes)
+		.all(|(address, byte)| *byte == erased_byte::<T>(address))
+}
+
+/// Fill `bytes`, to be written at `offset` of a flash of type `T`, with its erased contents.
+///
+/// Such bytes are left as erased when written, e.g. to pad writes up to `WRITE_SIZE`.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn fill_erased<T: NorFlash + ?Sized>(offset: u32, bytes: &mut [u8]) {
+	for (address, byte) in (offset..).zip(bytes) {
+		*byte = erased_byte::<T>(address);
+	}
+}
+
+/// The value of a byte at `old` after writing `new` to it, on a [`MultiwriteNorFlash`] whose
+/// byte is `erased` when erased.
+///
+/// Bits can only be changed from their erased value, so the result has the bits changed in either
+/// value. With `erased` being `0xff`, this is the logical AND of both values. The byte can be
+/// written as is if the result is `new`.
+pub const fn program_byte(erased: u8, old: u8, new: u8) -> u8 {
+	erased ^ ((old ^ erased) | (new ^ erased))
+}
+
+/// How the bits of a word change when written, see [`NorFlash::PROGRAM_POLARITY`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ProgramPolarity {
+	/// Bits can only be changed from their erased value, see [`program_byte`].
+	FromErased,
+	/// Bits can only be cleared, 1 bits to 0 bits, the result being the logical AND.
+	ClearBits,
+	/// Bits can only be set, 0 bits to 1 bits, the result being the logical OR.
+	SetBits,
+}
+
+impl ProgramPolarity {
+	/// The value of a byte at `old`, which is `erased` when erased, after writing `new` to it.
+	pub const fn program(self, erased: u8, old: u8, new: u8) -> u8 {
+		match self {
+			Self::FromErased => program_byte(erased, old, new),
+			Self::ClearBits => old & new,
+			Self::SetBits => old | new,
+		}
+	}
+}
+
+/// The value of the byte at `address` of a flash of type `T` after writing `new` over `old`,
+/// following its [`PROGRAM_POLARITY`](NorFlash::PROGRAM_POLARITY).
+///
+/// The byte can be written as is if the result is `new`.
+pub fn programmed_byte<T: NorFlash + ?Sized>(address: u32, old: u8, new: u8) -> u8 {
+	T::PROGRAM_POLARITY.program(erased_byte::<T>(address), old, new)
+}
+
+/// Resolve `range` to the bytes it covers in a flash of `capacity` bytes, open ends being the
+/// start and the end of the flash.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn resolve_range(range: impl RangeBounds<u32>, capacity: usize) -> Range<u32> {
+	let from = match range.start_bound() {
+		Bound::Included(from) => *from,
+		Bound::Excluded(from) => from.saturating_add(1),
+		Bound::Unbounded => 0,
+	};
+	let to = match range.end_bound() {
+		Bound::Included(to) => to.saturating_add(1),
+		Bound::Excluded(to) => *to,
+		Bound::Unbounded => capacity as u32,
+	};
+	from..to
+}
+
+/// Errors returned by [`read_range`] and [`erase_sectors`](crate::addr::erase_sectors).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RangeError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The buffer is not as long as the range, or the range does not fit in the address space.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for RangeError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Read the bytes of `range` into `bytes`.
+///
+/// # Errors
+///
+/// Returns [`RangeError::Invalid`] with [`CheckError::OutOfBounds`] if `bytes` is not as long as
+/// the range, and [`RangeError::Flash`] if the read fails.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn read_range<T: ReadNorFlash>(
+	flash: &mut T,
+	range: impl RangeBounds<u32>,
+	bytes: &mut [u8],
+) -> Result<(), RangeError<T::Error>> {
+	let range = resolve_range(range, flash.capacity());
+	span!("read_range", from = range.start, to = range.end);
+	let length = range.end.saturating_sub(range.start) as usize;
+	if length != bytes.len() {
+		return Err(RangeError::Invalid(CheckError::OutOfBounds(
+			OutOfBoundsInfo {
+				offset: range.start,
+				length,
+				capacity: bytes.len(),
+			},
+		)));
+	}
+	flash.read(range.start, bytes).map_err(RangeError::Flash)
+}
+
+/// Erase the bytes of `range`, e.g. `..` for the whole flash or `..end` for its start.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn erase_range<T: NorFlash>(
+	flash: &mut T,
+	range: impl RangeBounds<u32>,
+) -> Result<(), T::Error> {
+	let range = resolve_range(range, flash.capacity());
+	span!("erase_range", from = range.start, to = range.end);
+	flash.erase(range.start, range.end)
+}
+
+/// Erase the whole flash.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn erase_all<T: NorFlash>(flash: &mut T) -> Result<(), T::Error> {
+	erase_range(flash, ..)
+}
+
+/// Return whether accessing `length` bytes at `offset` is aligned to `align` and within
+/// `capacity`.
+///
+/// This is the building block of [`check_read`] and [`check_write`], for implementations not
+/// going through the traits of this module.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn check_slice(
+	capacity: usize,
 	align: usize,
 	offset: u32,
 	length: usize,
-) -> Result<(), NorFlashErrorKind> {
-	let offset = offset as usize;
-	if length > flash.capacity() || offset > flash.capacity() - length {
-		return Err(NorFlashErrorKind::OutOfBounds);
+) -> Result<(), CheckError> {
+	let start = offset as usize;
+	if length > capacity || start > capacity - length {
+		return Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+			offset,
+			length,
+			capacity,
+		}));
 	}
-	if offset % align != 0 || length % align != 0 {
-		return Err(NorFlashErrorKind::NotAligned);
+	if start.checked_rem(align) != Some(0) || length.checked_rem(align) != Some(0) {
+		return Err(CheckError::NotAligned);
+	}
+	Ok(())
+}
+
+/// Return whether the range `from..to` is aligned to `align` and within `capacity`.
+///
+/// This is the building block of [`check_erase`], for implementations not going through the traits
+/// of this module. A range with `from > to` is considered out of bounds.
+#[cfg_attr(
+	feature = "no-panic",
+	deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+)]
+pub fn check_range(capacity: usize, align: usize, from: u32, to: u32) -> Result<(), CheckError> {
+	if from > to || to as usize > capacity {
+		return Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+			offset: from,
+			length: to.saturating_sub(from) as usize,
+			capacity,
+		}));
+	}
+	if (from as usize).checked_rem(align) != Some(0) || (to as usize).checked_rem(align) != Some(0)
+	{
+		return Err(CheckError::NotAligned);
 	}
 	Ok(())
 }
@@ -168,6 +771,10 @@ impl<T: ReadNorFlash> ReadNorFlash for &mut T {
 impl<T: NorFlash> NorFlash for &mut T {
 	const WRITE_SIZE: usize = T::WRITE_SIZE;
 	const ERASE_SIZE: usize = T::ERASE_SIZE;
+	const ERASE_BYTE: u8 = T::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = T::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = T::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = T::MAX_WRITE_CHUNK;
 
 	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
 		T::erase(self, from, to)
@@ -176,20 +783,317 @@ impl<T: NorFlash> NorFlash for &mut T {
 	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
 		T::write(self, offset, bytes)
 	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		T::flush(self)
+	}
+
+	fn info(&self) -> FlashInfo {
+		T::info(self)
+	}
 }
 
 /// Marker trait for NorFlash relaxing the restrictions on `write`.
 ///
 /// Writes to the same word twice are now allowed. The result is the logical AND of the
 /// previous data and the written data. That is, it is only possible to change 1 bits to 0 bits.
+/// This holds for flash erasing to `0xff`, in general bits can only be changed from their erased
+/// value, or as stated by [`PROGRAM_POLARITY`](NorFlash::PROGRAM_POLARITY), see
+/// [`programmed_byte`].
 ///
 /// If power is lost during write:
-/// - Bits that were 1 on flash and are written to 1 are guaranteed to stay as 1
-/// - Bits that were 1 on flash and are written to 0 are undefined
-/// - Bits that were 0 on flash are guaranteed to stay as 0
+/// - Bits that were erased on flash and are written erased are guaranteed to stay erased
+/// - Bits that were erased on flash and are written programmed are undefined
+/// - Bits that were programmed on flash are guaranteed to stay programmed
 /// - Rest of the bits in the page are guaranteed to be unchanged
 pub trait MultiwriteNorFlash: NorFlash {}
 
+impl<T: MultiwriteNorFlash> MultiwriteNorFlash for &mut T {}
+
+/// Flash mapped into the address space, readable in place without copying.
+///
+/// Memory mapped flashes, such as the internal flash of MCUs or external flashes in XIP mode, can
+/// hand out their contents as slices, saving the buffer and copy of [`ReadNorFlash::read`]. The
+/// usual read alignment applies, so implementations can use the [`check_read`] helper function.
+pub trait ReadSlice: ReadNorFlash {
+	/// Borrow `length` bytes at `offset`.
+	fn read_slice(&self, offset: u32, length: usize) -> Result<&[u8], Self::Error>;
+}
+
+impl<T: ReadSlice> ReadSlice for &mut T {
+	fn read_slice(&self, offset: u32, length: usize) -> Result<&[u8], Self::Error> {
+		T::read_slice(self, offset, length)
+	}
+}
+
+/// Flash a filesystem can be mounted on.
+///
+/// This is the contract between this crate and filesystem crates such as `littlefs2`, `ekv` or
+/// `sequential-storage`: on top of [`NorFlash`], it gives the geometry in blocks, a way to make
+/// writes durable and hints about erasing, so a single adapter per filesystem covers every flash.
+/// Filesystem blocks are erase blocks, `ERASE_SIZE` bytes each.
+///
+/// Every method has a default suitable for flashes which complete writes and erases before
+/// returning, so implementing the trait is usually a one-liner.
+pub trait MountableStorage: NorFlash {
+	/// Number of erase blocks of the flash.
+	fn block_count(&self) -> usize {
+		self.capacity() / Self::ERASE_SIZE
+	}
+
+	/// Number of erase cycles a block endures, if known.
+	///
+	/// Filesystems doing wear levelling use this to decide how often to move data around.
+	fn erase_cycles(&self) -> Option<u32> {
+		None
+	}
+
+	/// Make the previous writes and erases durable, e.g. by flushing caches to the medium.
+	///
+	/// Filesystems call this at the end of a commit. The default implementation calls
+	/// [`NorFlash::flush`].
+	fn sync(&mut self) -> Result<(), Self::Error> {
+		self.flush()
+	}
+
+	/// Hint that the blocks in `from..to` hold no useful data anymore.
+	///
+	/// The range is aligned as for [`NorFlash::erase`]. Implementations may erase the blocks ahead
+	/// of time or in the background, or ignore the hint as the default implementation does: the
+	/// filesystem still erases the blocks before writing them again.
+	fn discard(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		let _ = (from, to);
+		Ok(())
+	}
+}
+
+impl<T: MountableStorage> MountableStorage for &mut T {
+	fn block_count(&self) -> usize {
+		T::block_count(self)
+	}
+
+	fn erase_cycles(&self) -> Option<u32> {
+		T::erase_cycles(self)
+	}
+
+	fn sync(&mut self) -> Result<(), Self::Error> {
+		T::sync(self)
+	}
+
+	fn discard(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		T::discard(self, from, to)
+	}
+}
+
+/// Readiness of a flash running program and erase operations in the background.
+///
+/// External flashes accept a program or erase command, then report being busy in their status
+/// register until it completes. Drivers wait for that before returning from `write` and `erase`,
+/// this trait lets higher layers poll the same state generically, e.g. to do other work in the
+/// meantime.
+pub trait FlashStatus: ErrorType {
+	/// Whether the flash is still running an operation.
+	fn is_busy(&mut self) -> Result<bool, Self::Error>;
+
+	/// Wait until the flash is ready to accept commands.
+	///
+	/// The default implementation polls [`is_busy`](Self::is_busy) in a loop.
+	fn wait_ready(&mut self) -> Result<(), Self::Error> {
+		while self.is_busy()? {}
+		Ok(())
+	}
+}
+
+impl<T: FlashStatus> FlashStatus for &mut T {
+	fn is_busy(&mut self) -> Result<bool, Self::Error> {
+		T::is_busy(self)
+	}
+
+	fn wait_ready(&mut self) -> Result<(), Self::Error> {
+		T::wait_ready(self)
+	}
+}
+
+/// Control of the low power mode of a flash.
+///
+/// External flashes draw a fraction of their standby current in deep power-down, in which they
+/// ignore every command but the wake up one. Battery-powered applications put them to sleep
+/// between bursts of operations, [`AutoWake`](crate::adapters::AutoWake) takes care of waking
+/// them before the next operation.
+pub trait PowerControl: ErrorType {
+	/// Enter the low power mode.
+	fn sleep(&mut self) -> Result<(), Self::Error>;
+
+	/// Leave the low power mode, the flash accepts every command again once this returns.
+	fn wake(&mut self) -> Result<(), Self::Error>;
+
+	/// Whether the flash is in the low power mode, `None` if this is not tracked.
+	fn is_asleep(&self) -> Option<bool> {
+		None
+	}
+}
+
+impl<T: PowerControl> PowerControl for &mut T {
+	fn sleep(&mut self) -> Result<(), Self::Error> {
+		T::sleep(self)
+	}
+
+	fn wake(&mut self) -> Result<(), Self::Error> {
+		T::wake(self)
+	}
+
+	fn is_asleep(&self) -> Option<bool> {
+		T::is_asleep(self)
+	}
+}
+
+/// NorFlash able to erase its whole contents with a single command.
+///
+/// Chip erase commands are typically 10 to 50 times faster than erasing every sector in turn.
+/// Helpers erasing a whole device, such as [`secure_erase`](crate::secret::secure_erase), take
+/// this trait, flashes without such a command can be wrapped in
+/// [`SectorErase`](crate::adapters::SectorErase).
+pub trait ChipErase: NorFlash {
+	/// Erase the whole flash, with the same effect as `erase(0, capacity)`.
+	///
+	/// If power is lost during erase, contents of the whole flash are undefined.
+	fn erase_chip(&mut self) -> Result<(), Self::Error>;
+}
+
+impl<T: ChipErase> ChipErase for &mut T {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		T::erase_chip(self)
+	}
+}
+
+/// Access to the configuration bits of a flash, outside of its data array.
+///
+/// These are the option bytes of MCU flash controllers, or the status and configuration registers
+/// of external flashes, holding e.g. their quad enable and block protection bits. Update and
+/// provisioning flows need them, and go through this trait to stay vendor neutral. Registers are
+/// bytes numbered from 0 as defined by the implementation.
+///
+/// Such bits are often non-volatile with a low endurance, so they should only be written when
+/// they change, as [`update_config`](Self::update_config) does.
+pub trait ConfigBits: ErrorType {
+	/// Read configuration register `register`.
+	fn read_config(&mut self, register: u8) -> Result<u8, Self::Error>;
+
+	/// Write `value` to configuration register `register`.
+	fn write_config(&mut self, register: u8, value: u8) -> Result<(), Self::Error>;
+
+	/// Set the bits of `mask` in configuration register `register` to those of `value`, writing
+	/// it only if they differ. Returns whether the register was written.
+	fn update_config(&mut self, register: u8, mask: u8, value: u8) -> Result<bool, Self::Error> {
+		let old = self.read_config(register)?;
+		let new = old & !mask | value & mask;
+		if new == old {
+			return Ok(false);
+		}
+		self.write_config(register, new)?;
+		Ok(true)
+	}
+}
+
+impl<T: ConfigBits> ConfigBits for &mut T {
+	fn read_config(&mut self, register: u8) -> Result<u8, Self::Error> {
+		T::read_config(self, register)
+	}
+
+	fn write_config(&mut self, register: u8, value: u8) -> Result<(), Self::Error> {
+		T::write_config(self, register, value)
+	}
+
+	fn update_config(&mut self, register: u8, mask: u8, value: u8) -> Result<bool, Self::Error> {
+		T::update_config(self, register, mask, value)
+	}
+}
+
+/// Guard keeping protection bits of a flash cleared, restoring them when dropped.
+///
+/// The guard dereferences to the flash, so writes and erases go through it while the range is
+/// unlocked. Dropping the guard, including while unwinding from a panic, writes the register back
+/// to its previous value, ignoring errors; [`restore`](Self::restore) does the same and reports
+/// them. See [`with_unlocked`] for running a closure this way.
+pub struct Unlocked<'a, S: ConfigBits> {
+	flash: &'a mut S,
+	register: u8,
+	saved: u8,
+	changed: bool,
+}
+
+impl<'a, S: ConfigBits> Unlocked<'a, S> {
+	/// Set the bits of `mask` in configuration register `register` of `flash` to those of `value`,
+	/// e.g. clear the block protection bits, until the guard is dropped.
+	pub fn new(flash: &'a mut S, register: u8, mask: u8, value: u8) -> Result<Self, S::Error> {
+		let saved = flash.read_config(register)?;
+		let changed = flash.update_config(register, mask, value)?;
+		Ok(Self {
+			flash,
+			register,
+			saved,
+			changed,
+		})
+	}
+
+	/// Restore the configuration register, reporting errors.
+	pub fn restore(mut self) -> Result<(), S::Error> {
+		self.restore_config()
+	}
+
+	fn restore_config(&mut self) -> Result<(), S::Error> {
+		if !self.changed {
+			return Ok(());
+		}
+		self.changed = false;
+		self.flash.write_config(self.register, self.saved)
+	}
+}
+
+impl<S: ConfigBits> core::ops::Deref for Unlocked<'_, S> {
+	type Target = S;
+
+	fn deref(&self) -> &S {
+		self.flash
+	}
+}
+
+impl<S: ConfigBits> core::ops::DerefMut for Unlocked<'_, S> {
+	fn deref_mut(&mut self) -> &mut S {
+		self.flash
+	}
+}
+
+impl<S: ConfigBits> Drop for Unlocked<'_, S> {
+	fn drop(&mut self) {
+		self.restore_config().ok();
+	}
+}
+
+/// Run `operation` on `flash` with the bits of `mask` in configuration register `register` set
+/// to those of `value`, then restore the register even if `operation` failed.
+///
+/// This lifts the write protection of a range for the time of a write or an erase, so the device
+/// is never left unlocked. An error of `operation` takes precedence over one restoring the
+/// register.
+pub fn with_unlocked<S, R, F>(
+	flash: &mut S,
+	register: u8,
+	mask: u8,
+	value: u8,
+	operation: F,
+) -> Result<R, S::Error>
+where
+	S: ConfigBits,
+	F: FnOnce(&mut S) -> Result<R, S::Error>,
+{
+	let mut guard = Unlocked::new(flash, register, mask, value)?;
+	let result = operation(&mut guard);
+	let restored = guard.restore();
+	let result = result?;
+	restored.map(|_| result)
+}
+
 struct Page {
 	pub start: u32,
 	pub size: usize,
@@ -216,10 +1120,14 @@ impl Region for Page {
 	}
 }
 
-///
+/// Generic `Storage` adapter for a `NorFlash` peripheral, merging writes into erased pages
+/// using a read/modify/write cycle.
 pub struct RmwNorFlashStorage<'a, S> {
 	storage: S,
 	merge_buffer: &'a mut [u8],
+	page_hook: Option<&'a mut dyn FnMut()>,
+	#[cfg(feature = "model-checking")]
+	model: crate::model::FlashModel,
 }
 
 impl<'a, S> RmwNorFlashStorage<'a, S>
@@ -231,15 +1139,98 @@ where
 	/// **NOTE** This will panic if the provided merge buffer,
 	/// is smaller than the erase size of the flash peripheral
 	pub fn new(nor_flash: S, merge_buffer: &'a mut [u8]) -> Self {
+		match Self::try_new(nor_flash, merge_buffer) {
+			Ok(storage) => storage,
+			Err(_) => panic!("Merge buffer is too small"),
+		}
+	}
+
+	/// Instantiate a new generic `Storage` from a `NorFlash` peripheral, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::OutOfBounds`] if the provided merge buffer is smaller than the erase
+	/// size of the flash peripheral.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(nor_flash: S, merge_buffer: &'a mut [u8]) -> Result<Self, CheckError> {
 		if merge_buffer.len() < S::ERASE_SIZE {
-			panic!("Merge buffer is too small");
+			return Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+				offset: 0,
+				length: S::ERASE_SIZE,
+				capacity: merge_buffer.len(),
+			}));
 		}
 
-		Self {
+		Ok(Self {
+			#[cfg(feature = "model-checking")]
+			model: crate::model::FlashModel::new::<S>(nor_flash.capacity()),
 			storage: nor_flash,
 			merge_buffer,
+			page_hook: None,
+		})
+	}
+
+	/// Call `hook` after every page written, e.g. to feed a watchdog or yield to other tasks
+	/// during large writes.
+	pub fn with_page_hook(mut self, hook: &'a mut dyn FnMut()) -> Self {
+		self.page_hook = Some(hook);
+		self
+	}
+
+	/// Merge `bytes` into the pages at `offset`.
+	fn merge(&mut self, offset: u32, bytes: &[u8]) -> Result<(), S::Error> {
+		#[cfg(feature = "model-checking")]
+		let storage = &mut crate::model::Checked::new(&mut self.storage, &mut self.model, false);
+		#[cfg(not(feature = "model-checking"))]
+		let storage = &mut self.storage;
+		merge_pages(
+			storage,
+			self.merge_buffer,
+			self.page_hook.as_deref_mut(),
+			offset,
+			bytes,
+		)
+	}
+}
+
+/// Merge `bytes` into the pages of `storage` at `offset`, erasing and programming every page
+/// again through `merge_buffer`, and calling `page_hook` after every page written.
+fn merge_pages<S: NorFlash>(
+	storage: &mut S,
+	merge_buffer: &mut [u8],
+	mut page_hook: Option<&mut (dyn FnMut() + '_)>,
+	offset: u32,
+	bytes: &[u8],
+) -> Result<(), S::Error> {
+	// Perform read/modify/write operations on the byte slice.
+	let last_page = storage.capacity() / S::ERASE_SIZE;
+
+	// `data` is the part of `bytes` contained within `page`,
+	// and `addr` in the address offset of `page` + any offset into the page as requested by `address`
+	for (data, page, addr) in (0..last_page as u32)
+		.map(move |i| Page::new(i, S::ERASE_SIZE))
+		.overlaps(bytes, offset)
+	{
+		let offset_into_page = addr.saturating_sub(page.start) as usize;
+
+		storage.read(page.start, &mut merge_buffer[..S::ERASE_SIZE])?;
+
+		// If we cannot write multiple times to the same page, we will have to erase it
+		storage.erase(page.start, page.end())?;
+		merge_buffer[..S::ERASE_SIZE]
+			.iter_mut()
+			.skip(offset_into_page)
+			.zip(data)
+			.for_each(|(byte, input)| *byte = *input);
+		write_chunked(storage, page.start, &merge_buffer[..S::ERASE_SIZE])?;
+		if let Some(hook) = &mut page_hook {
+			hook();
 		}
 	}
+	Ok(())
 }
 
 impl<'a, S> ReadStorage for RmwNorFlashStorage<'a, S>
@@ -263,61 +1254,59 @@ where
 	S: NorFlash,
 {
 	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
-		// Perform read/modify/write operations on the byte slice.
-		let last_page = self.storage.capacity() / S::ERASE_SIZE;
-
-		// `data` is the part of `bytes` contained within `page`,
-		// and `addr` in the address offset of `page` + any offset into the page as requested by `address`
-		for (data, page, addr) in (0..last_page as u32)
-			.map(move |i| Page::new(i, S::ERASE_SIZE))
-			.overlaps(bytes, offset)
-		{
-			let offset_into_page = addr.saturating_sub(page.start) as usize;
-
-			self.storage
-				.read(page.start, &mut self.merge_buffer[..S::ERASE_SIZE])?;
-
-			// If we cannot write multiple times to the same page, we will have to erase it
-			self.storage.erase(page.start, page.end())?;
-			self.merge_buffer[..S::ERASE_SIZE]
-				.iter_mut()
-				.skip(offset_into_page)
-				.zip(data)
-				.for_each(|(byte, input)| *byte = *input);
-			self.storage
-				.write(page.start, &self.merge_buffer[..S::ERASE_SIZE])?;
+		let result = self.merge(offset, bytes);
+		#[cfg(feature = "zeroize")]
+		crate::secret::wipe(self.merge_buffer);
+		#[cfg(feature = "model-checking")]
+		if result.is_ok() {
+			crate::model::assert_stored(&mut self.storage, offset, bytes)?;
 		}
-		Ok(())
+		result
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.storage.flush()
 	}
 }
 
+/// Generic `Storage` adapter for a `NorFlash` peripheral like [`RmwNorFlashStorage`], owning a
+/// merge buffer of `N` bytes.
 ///
-pub struct RmwMultiwriteNorFlashStorage<'a, S> {
+/// This suits the common case of a statically sized buffer: the adapter has no lifetime, e.g. to
+/// be kept in a `static`, and the size of the buffer is checked at compile time, a buffer smaller
+/// than the erase size of the flash peripheral failing to build.
+pub struct RmwNorFlashStorageOwned<S, const N: usize> {
 	storage: S,
-	merge_buffer: &'a mut [u8],
+	merge_buffer: [u8; N],
+	#[cfg(feature = "model-checking")]
+	model: crate::model::FlashModel,
 }
 
-impl<'a, S> RmwMultiwriteNorFlashStorage<'a, S>
+impl<S, const N: usize> RmwNorFlashStorageOwned<S, N>
 where
-	S: MultiwriteNorFlash,
+	S: NorFlash,
 {
-	/// Instantiate a new generic `Storage` from a `NorFlash` peripheral
-	///
-	/// **NOTE** This will panic if the provided merge buffer,
-	/// is smaller than the erase size of the flash peripheral
-	pub fn new(nor_flash: S, merge_buffer: &'a mut [u8]) -> Self {
-		if merge_buffer.len() < S::ERASE_SIZE {
-			panic!("Merge buffer is too small");
-		}
+	/// Underflows, failing to build, if the merge buffer is smaller than the erase size.
+	const MERGE_BUFFER_SPARE: usize = N - S::ERASE_SIZE;
 
+	/// Instantiate a new generic `Storage` from a `NorFlash` peripheral
+	pub fn new(nor_flash: S) -> Self {
+		let _ = Self::MERGE_BUFFER_SPARE;
 		Self {
+			#[cfg(feature = "model-checking")]
+			model: crate::model::FlashModel::new::<S>(nor_flash.capacity()),
 			storage: nor_flash,
-			merge_buffer,
+			merge_buffer: [0; N],
 		}
 	}
+
+	/// Release the `NorFlash` peripheral.
+	pub fn into_inner(self) -> S {
+		self.storage
+	}
 }
 
-impl<'a, S> ReadStorage for RmwMultiwriteNorFlashStorage<'a, S>
+impl<S, const N: usize> ReadStorage for RmwNorFlashStorageOwned<S, N>
 where
 	S: ReadNorFlash,
 {
@@ -333,13 +1322,99 @@ where
 	}
 }
 
-impl<'a, S> Storage for RmwMultiwriteNorFlashStorage<'a, S>
+impl<S, const N: usize> Storage for RmwNorFlashStorageOwned<S, N>
 where
-	S: MultiwriteNorFlash,
+	S: NorFlash,
 {
 	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		#[cfg(feature = "model-checking")]
+		let storage = &mut crate::model::Checked::new(&mut self.storage, &mut self.model, false);
+		#[cfg(not(feature = "model-checking"))]
+		let storage = &mut self.storage;
+		let result = merge_pages(storage, &mut self.merge_buffer, None, offset, bytes);
+		#[cfg(feature = "zeroize")]
+		crate::secret::wipe(&mut self.merge_buffer);
+		#[cfg(feature = "model-checking")]
+		if result.is_ok() {
+			crate::model::assert_stored(&mut self.storage, offset, bytes)?;
+		}
+		result
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.storage.flush()
+	}
+}
+
+/// Generic `Storage` adapter for a `MultiwriteNorFlash` peripheral, only erasing pages when
+/// the written data cannot be programmed on top of the existing contents.
+pub struct RmwMultiwriteNorFlashStorage<'a, S> {
+	storage: S,
+	merge_buffer: &'a mut [u8],
+	page_hook: Option<&'a mut dyn FnMut()>,
+	#[cfg(feature = "model-checking")]
+	model: crate::model::FlashModel,
+}
+
+impl<'a, S> RmwMultiwriteNorFlashStorage<'a, S>
+where
+	S: MultiwriteNorFlash,
+{
+	/// Instantiate a new generic `Storage` from a `NorFlash` peripheral
+	///
+	/// **NOTE** This will panic if the provided merge buffer,
+	/// is smaller than the erase size of the flash peripheral
+	pub fn new(nor_flash: S, merge_buffer: &'a mut [u8]) -> Self {
+		match Self::try_new(nor_flash, merge_buffer) {
+			Ok(storage) => storage,
+			Err(_) => panic!("Merge buffer is too small"),
+		}
+	}
+
+	/// Instantiate a new generic `Storage` from a `NorFlash` peripheral, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::OutOfBounds`] if the provided merge buffer is smaller than the erase
+	/// size of the flash peripheral.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(nor_flash: S, merge_buffer: &'a mut [u8]) -> Result<Self, CheckError> {
+		if merge_buffer.len() < S::ERASE_SIZE {
+			return Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+				offset: 0,
+				length: S::ERASE_SIZE,
+				capacity: merge_buffer.len(),
+			}));
+		}
+
+		Ok(Self {
+			#[cfg(feature = "model-checking")]
+			model: crate::model::FlashModel::new::<S>(nor_flash.capacity()),
+			storage: nor_flash,
+			merge_buffer,
+			page_hook: None,
+		})
+	}
+
+	/// Call `hook` after every page written, e.g. to feed a watchdog or yield to other tasks
+	/// during large writes.
+	pub fn with_page_hook(mut self, hook: &'a mut dyn FnMut()) -> Self {
+		self.page_hook = Some(hook);
+		self
+	}
+
+	/// Merge `bytes` into the pages at `offset`.
+	fn merge(&mut self, offset: u32, bytes: &[u8]) -> Result<(), S::Error> {
+		#[cfg(feature = "model-checking")]
+		let storage = &mut crate::model::Checked::new(&mut self.storage, &mut self.model, true);
+		#[cfg(not(feature = "model-checking"))]
+		let storage = &mut self.storage;
+
 		// Perform read/modify/write operations on the byte slice.
-		let last_page = self.storage.capacity() / S::ERASE_SIZE;
+		let last_page = storage.capacity() / S::ERASE_SIZE;
 
 		// `data` is the part of `bytes` contained within `page`,
 		// and `addr` in the address offset of `page` + any offset into the page as requested by `address`
@@ -349,33 +1424,231 @@ where
 		{
 			let offset_into_page = addr.saturating_sub(page.start) as usize;
 
-			self.storage
-				.read(page.start, &mut self.merge_buffer[..S::ERASE_SIZE])?;
+			storage.read(page.start, &mut self.merge_buffer[..S::ERASE_SIZE])?;
 
 			let rhs = &self.merge_buffer[offset_into_page..S::ERASE_SIZE];
-			let is_subset = data.iter().zip(rhs.iter()).all(|(a, b)| *a & *b == *a);
+			let is_subset = (addr..)
+				.zip(data.iter().zip(rhs.iter()))
+				.all(|(address, (a, b))| programmed_byte::<S>(address, *b, *a) == *a);
 
 			// Check if we can write the data block directly, under the limitations imposed by NorFlash:
-			// - We can only change 1's to 0's
+			// - We can only change bits as allowed by `PROGRAM_POLARITY`
 			if is_subset {
-				// Use `merge_buffer` as allocation for padding `data` to `WRITE_SIZE`
-				let offset = addr as usize % S::WRITE_SIZE;
-				let aligned_end = data.len() % S::WRITE_SIZE + offset + data.len();
-				self.merge_buffer[..aligned_end].fill(0xff);
-				self.merge_buffer[offset..offset + data.len()].copy_from_slice(data);
-				self.storage
-					.write(addr - offset as u32, &self.merge_buffer[..aligned_end])?;
+				// Pad `data` to `WRITE_SIZE` with the current contents of the page, which are left
+				// unchanged when written again whatever the polarity
+				let start = offset_into_page - offset_into_page % S::WRITE_SIZE;
+				let end = offset_into_page + data.len();
+				let end = (end + S::WRITE_SIZE - 1) / S::WRITE_SIZE * S::WRITE_SIZE;
+				self.merge_buffer[offset_into_page..offset_into_page + data.len()]
+					.copy_from_slice(data);
+				write_chunked(
+					storage,
+					page.start + start as u32,
+					&self.merge_buffer[start..end],
+				)?;
 			} else {
-				self.storage.erase(page.start, page.end())?;
+				storage.erase(page.start, page.end())?;
 				self.merge_buffer[..S::ERASE_SIZE]
 					.iter_mut()
 					.skip(offset_into_page)
 					.zip(data)
 					.for_each(|(byte, input)| *byte = *input);
-				self.storage
-					.write(page.start, &self.merge_buffer[..S::ERASE_SIZE])?;
+				write_chunked(storage, page.start, &self.merge_buffer[..S::ERASE_SIZE])?;
+			}
+			if let Some(hook) = &mut self.page_hook {
+				hook();
 			}
 		}
 		Ok(())
 	}
 }
+
+impl<'a, S> ReadStorage for RmwMultiwriteNorFlashStorage<'a, S>
+where
+	S: ReadNorFlash,
+{
+	type Error = S::Error;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		// Nothing special to be done for reads
+		self.storage.read(offset, bytes)
+	}
+
+	fn capacity(&self) -> usize {
+		self.storage.capacity()
+	}
+}
+
+impl<'a, S> Storage for RmwMultiwriteNorFlashStorage<'a, S>
+where
+	S: MultiwriteNorFlash,
+{
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		let result = self.merge(offset, bytes);
+		#[cfg(feature = "zeroize")]
+		crate::secret::wipe(self.merge_buffer);
+		#[cfg(feature = "model-checking")]
+		if result.is_ok() {
+			crate::model::assert_stored(&mut self.storage, offset, bytes)?;
+		}
+		result
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.storage.flush()
+	}
+}
+
+/// Geometry of a NOR flash, known at runtime.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Geometry {
+	/// The capacity of the peripheral in bytes.
+	pub capacity: usize,
+	/// The minumum number of bytes the storage peripheral can read
+	pub read_size: usize,
+	/// The minumum number of bytes the storage peripheral can write
+	pub write_size: usize,
+	/// The minumum number of bytes the storage peripheral can erase
+	pub erase_size: usize,
+}
+
+/// Object safe version of [`NorFlash`].
+///
+/// The geometry is queried at runtime and errors are reduced to their [`NorFlashErrorKind`], so
+/// that different flash devices can be used through `&mut dyn AnyNorFlash`, e.g. in an array or
+/// registry. It is implemented for every [`NorFlash`].
+///
+/// The methods are prefixed with `any_` so they do not clash with the [`NorFlash`] ones when both
+/// traits are in scope.
+pub trait AnyNorFlash {
+	/// The geometry of the peripheral.
+	fn geometry(&self) -> Geometry;
+
+	/// See [`ReadNorFlash::read`].
+	fn any_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind>;
+
+	/// See [`NorFlash::erase`].
+	fn any_erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind>;
+
+	/// See [`NorFlash::write`].
+	fn any_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind>;
+
+	/// See [`NorFlash::flush`].
+	fn any_flush(&mut self) -> Result<(), NorFlashErrorKind> {
+		Ok(())
+	}
+
+	/// The minumum number of bytes the storage peripheral can read
+	fn read_size(&self) -> usize {
+		self.geometry().read_size
+	}
+
+	/// The minumum number of bytes the storage peripheral can write
+	fn write_size(&self) -> usize {
+		self.geometry().write_size
+	}
+
+	/// The minumum number of bytes the storage peripheral can erase
+	fn erase_size(&self) -> usize {
+		self.geometry().erase_size
+	}
+}
+
+impl<T: NorFlash> AnyNorFlash for T {
+	fn geometry(&self) -> Geometry {
+		Geometry {
+			capacity: ReadNorFlash::capacity(self),
+			read_size: T::READ_SIZE,
+			write_size: T::WRITE_SIZE,
+			erase_size: T::ERASE_SIZE,
+		}
+	}
+
+	fn any_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+		ReadNorFlash::read(self, offset, bytes).map_err(|e| e.kind())
+	}
+
+	fn any_erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+		NorFlash::erase(self, from, to).map_err(|e| e.kind())
+	}
+
+	fn any_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+		NorFlash::write(self, offset, bytes).map_err(|e| e.kind())
+	}
+
+	fn any_flush(&mut self) -> Result<(), NorFlashErrorKind> {
+		NorFlash::flush(self).map_err(|e| e.kind())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	#[test]
+	fn optimize_erase_steps() {
+		let sizes = [0x1000, 0x8000, 0x10000];
+		let mut steps = optimize_erase(&sizes, 0x7000, 0x19000).unwrap();
+		assert_eq!(steps.next(), Some((0x7000, 0x1000)));
+		assert_eq!(steps.next(), Some((0x8000, 0x8000)));
+		assert_eq!(steps.next(), Some((0x10000, 0x8000)));
+		assert_eq!(steps.next(), Some((0x18000, 0x1000)));
+		assert_eq!(steps.next(), None);
+		assert_eq!(optimize_erase(&sizes, 0, 0).unwrap().next(), None);
+
+		let error = |sizes: &[u32], from, to| optimize_erase(sizes, from, to).err();
+		assert_eq!(error(&sizes, 0x800, 0x2000), Some(CheckError::NotAligned));
+		assert_eq!(error(&sizes, 0x1000, 0x2800), Some(CheckError::NotAligned));
+		assert!(matches!(
+			error(&sizes, 0x2000, 0x1000),
+			Some(CheckError::OutOfBounds(_))
+		));
+		assert_eq!(error(&[], 0, 0x1000), Some(CheckError::NotAligned));
+		assert_eq!(error(&[0, 0x1000], 0, 0x1000), Some(CheckError::NotAligned));
+	}
+
+	#[test]
+	fn read_range_length() {
+		let mut flash = MockFlash::<4096, 1, 4, 1024>::new();
+		let mut bytes = [0; 16];
+		assert_eq!(read_range(&mut flash, 16..32, &mut bytes), Ok(()));
+		assert!(matches!(
+			read_range(&mut flash, 16..48, &mut bytes),
+			Err(RangeError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert!(matches!(
+			read_range(&mut flash, 4090.., &mut bytes),
+			Err(RangeError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert!(matches!(
+			read_range(&mut flash, 4090..4106, &mut bytes),
+			Err(RangeError::Flash(_))
+		));
+	}
+
+	#[cfg(feature = "no-panic")]
+	#[test]
+	fn checks_no_panic() {
+		let extremes = [0, 1, u32::MAX - 1, u32::MAX];
+		for &offset in extremes.iter() {
+			for &length in [0, 1, usize::MAX - 1, usize::MAX].iter() {
+				for &align in [0, 1, 3, usize::MAX].iter() {
+					let _ = check_slice(usize::MAX, align, offset, length);
+					let _ = check_slice(0, align, offset, length);
+				}
+			}
+			for &to in extremes.iter() {
+				let _ = check_range(usize::MAX, 0, offset, to);
+				let _ = check_range(4096, 1024, offset, to);
+				let _ = optimize_erase(&[0x1000, 0x10000], offset, to).map(|steps| steps.count());
+				let _ = optimize_erase(&[0, 0x1000], offset, to);
+			}
+		}
+		let mut flash = MockFlash::<4096, 1, 4, 1024>::new();
+		let mut bytes = [0; 16];
+		assert!(read_range(&mut flash, u32::MAX.., &mut bytes).is_err());
+		assert!(read_range(&mut flash, ..=u32::MAX, &mut bytes).is_err());
+		assert!(crate::addr::erase_sectors(&mut flash, u32::MAX, u32::MAX).is_err());
+	}
+}
@@ -0,0 +1,394 @@
+use crate::addr::{align_up, FlashAddr};
+use crate::nor_flash::{NorFlash, ReadNorFlash};
+use crate::{ReadStorage, Storage};
+
+/// Largest encoded size of the objects handled by the helpers.
+pub const MAX_SIZE: usize = 256;
+
+/// Size of the buffer holding an object along with its unaligned head and tail.
+const BUFFER: usize = MAX_SIZE + 64;
+
+/// Types with a fixed size encoding, which can be written to a storage.
+///
+/// Primitives are encoded in little endian, arrays as their elements in order. Structs are
+/// implemented by encoding their fields one after the other, which keeps the layout independent
+/// of the compiler and of the target.
+pub trait AsBytes {
+	/// Size of the encoding in bytes.
+	const SIZE: usize;
+
+	/// Encode `self` into `bytes`, which are `SIZE` bytes long.
+	fn write_bytes(&self, bytes: &mut [u8]);
+}
+
+/// Types with a fixed size encoding, which can be read from a storage.
+///
+/// Every sequence of bytes decodes to a value, e.g. to `true` for any non-zero byte of a `bool`,
+/// so a value read from erased or corrupted flash is garbage but never undefined behaviour.
+pub trait FromBytes {
+	/// Size of the encoding in bytes.
+	const SIZE: usize;
+
+	/// Decode a value from `bytes`, which are `SIZE` bytes long.
+	fn read_bytes(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_primitive {
+	($($type:ty),*) => {
+		$(
+			impl AsBytes for $type {
+				const SIZE: usize = core::mem::size_of::<$type>();
+
+				fn write_bytes(&self, bytes: &mut [u8]) {
+					bytes.copy_from_slice(&self.to_le_bytes());
+				}
+			}
+
+			impl FromBytes for $type {
+				const SIZE: usize = core::mem::size_of::<$type>();
+
+				fn read_bytes(bytes: &[u8]) -> Self {
+					let mut array = [0; core::mem::size_of::<$type>()];
+					array.copy_from_slice(bytes);
+					Self::from_le_bytes(array)
+				}
+			}
+		)*
+	};
+}
+
+impl_primitive!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl AsBytes for bool {
+	const SIZE: usize = 1;
+
+	fn write_bytes(&self, bytes: &mut [u8]) {
+		bytes[0] = *self as u8;
+	}
+}
+
+impl FromBytes for bool {
+	const SIZE: usize = 1;
+
+	fn read_bytes(bytes: &[u8]) -> Self {
+		bytes[0] != 0
+	}
+}
+
+impl<T: AsBytes, const N: usize> AsBytes for [T; N] {
+	const SIZE: usize = T::SIZE * N;
+
+	fn write_bytes(&self, bytes: &mut [u8]) {
+		for (item, bytes) in self.iter().zip(bytes.chunks_mut(T::SIZE)) {
+			item.write_bytes(bytes);
+		}
+	}
+}
+
+impl<T: FromBytes + Copy + Default, const N: usize> FromBytes for [T; N] {
+	const SIZE: usize = T::SIZE * N;
+
+	fn read_bytes(bytes: &[u8]) -> Self {
+		let mut array = [T::default(); N];
+		for (item, bytes) in array.iter_mut().zip(bytes.chunks(T::SIZE)) {
+			*item = T::read_bytes(bytes);
+		}
+		array
+	}
+}
+
+/// Read an object at `offset` of `storage`.
+///
+/// **NOTE** This will panic if the object is larger than [`MAX_SIZE`].
+pub fn load_obj<S: ReadStorage, T: FromBytes>(
+	storage: &mut S,
+	offset: impl Into<FlashAddr>,
+) -> Result<T, S::Error> {
+	let offset = offset.into().get();
+	assert!(T::SIZE <= MAX_SIZE, "Object too large");
+	let mut buffer = [0; MAX_SIZE];
+	storage.read(offset, &mut buffer[..T::SIZE])?;
+	Ok(T::read_bytes(&buffer[..T::SIZE]))
+}
+
+/// Write `value` at `offset` of `storage`.
+///
+/// **NOTE** This will panic if the object is larger than [`MAX_SIZE`].
+pub fn store_obj<S: Storage, T: AsBytes>(
+	storage: &mut S,
+	offset: impl Into<FlashAddr>,
+	value: &T,
+) -> Result<(), S::Error> {
+	let offset = offset.into().get();
+	assert!(T::SIZE <= MAX_SIZE, "Object too large");
+	let mut buffer = [0; MAX_SIZE];
+	value.write_bytes(&mut buffer[..T::SIZE]);
+	storage.write(offset, &buffer[..T::SIZE])
+}
+
+/// Read an object at `offset` of `flash`, which does not need to be aligned to `READ_SIZE`.
+///
+/// **NOTE** This will panic if the object is larger than [`MAX_SIZE`], or if `READ_SIZE` is over
+/// 32 bytes.
+pub fn read_obj<S: ReadNorFlash, T: FromBytes>(
+	flash: &mut S,
+	offset: impl Into<FlashAddr>,
+) -> Result<T, S::Error> {
+	let offset = offset.into().get();
+	assert!(T::SIZE <= MAX_SIZE, "Object too large");
+	assert!(
+		S::READ_SIZE <= (BUFFER - MAX_SIZE) / 2,
+		"READ_SIZE too large"
+	);
+	let size = S::READ_SIZE;
+	let head = offset as usize % size;
+	let length = align_up(head + T::SIZE, size);
+	let mut buffer = [0; BUFFER];
+	flash.read(offset - head as u32, &mut buffer[..length])?;
+	Ok(T::read_bytes(&buffer[head..head + T::SIZE]))
+}
+
+/// Write `value` at `offset` of `flash`, which must be aligned to `WRITE_SIZE`.
+///
+/// The end of the object is padded with erased bytes up to `WRITE_SIZE`, so the whole padded size
+/// must be erased.
+///
+/// **NOTE** This will panic if the object is larger than [`MAX_SIZE`], or if `WRITE_SIZE` is over
+/// 32 bytes.
+pub fn write_obj<S: NorFlash, T: AsBytes>(
+	flash: &mut S,
+	offset: impl Into<FlashAddr>,
+	value: &T,
+) -> Result<(), S::Error> {
+	let offset = offset.into().get();
+	assert!(T::SIZE <= MAX_SIZE, "Object too large");
+	assert!(
+		S::WRITE_SIZE <= (BUFFER - MAX_SIZE) / 2,
+		"WRITE_SIZE too large"
+	);
+	let size = S::WRITE_SIZE;
+	let length = align_up(T::SIZE, size);
+	let mut buffer = [0; BUFFER];
+	crate::nor_flash::fill_erased::<S>(offset, &mut buffer[..length]);
+	value.write_bytes(&mut buffer[..T::SIZE]);
+	crate::nor_flash::write_chunked(flash, offset, &buffer[..length])
+}
+
+macro_rules! read_method {
+	($(#[$doc:meta])* $name:ident, $type:ty, $from:ident) => {
+		$(#[$doc])*
+		fn $name(&mut self, offset: impl Into<FlashAddr>) -> Result<$type, Self::Error> {
+			let mut bytes = [0; core::mem::size_of::<$type>()];
+			self.read(offset.into().get(), &mut bytes)?;
+			Ok(<$type>::$from(bytes))
+		}
+	};
+}
+
+macro_rules! write_method {
+	($(#[$doc:meta])* $name:ident, $type:ty, $to:ident) => {
+		$(#[$doc])*
+		fn $name(&mut self, offset: impl Into<FlashAddr>, value: $type) -> Result<(), Self::Error> {
+			self.write(offset.into().get(), &value.$to())
+		}
+	};
+}
+
+/// Reads of integers with an explicit byte order, implemented for every [`ReadStorage`].
+///
+/// Storages are byte addressed, so the offsets do not need any alignment.
+pub trait ReadStorageExt: ReadStorage {
+	read_method!(
+		/// Read a little endian `u16` at `offset`.
+		read_u16_le, u16, from_le_bytes
+	);
+	read_method!(
+		/// Read a big endian `u16` at `offset`.
+		read_u16_be, u16, from_be_bytes
+	);
+	read_method!(
+		/// Read a little endian `u32` at `offset`.
+		read_u32_le, u32, from_le_bytes
+	);
+	read_method!(
+		/// Read a big endian `u32` at `offset`.
+		read_u32_be, u32, from_be_bytes
+	);
+	read_method!(
+		/// Read a little endian `u64` at `offset`.
+		read_u64_le, u64, from_le_bytes
+	);
+	read_method!(
+		/// Read a big endian `u64` at `offset`.
+		read_u64_be, u64, from_be_bytes
+	);
+	read_method!(
+		/// Read a little endian `i16` at `offset`.
+		read_i16_le, i16, from_le_bytes
+	);
+	read_method!(
+		/// Read a big endian `i16` at `offset`.
+		read_i16_be, i16, from_be_bytes
+	);
+	read_method!(
+		/// Read a little endian `i32` at `offset`.
+		read_i32_le, i32, from_le_bytes
+	);
+	read_method!(
+		/// Read a big endian `i32` at `offset`.
+		read_i32_be, i32, from_be_bytes
+	);
+	read_method!(
+		/// Read a little endian `i64` at `offset`.
+		read_i64_le, i64, from_le_bytes
+	);
+	read_method!(
+		/// Read a big endian `i64` at `offset`.
+		read_i64_be, i64, from_be_bytes
+	);
+}
+
+impl<S: ReadStorage + ?Sized> ReadStorageExt for S {}
+
+/// Writes of integers with an explicit byte order, implemented for every [`Storage`].
+///
+/// Storages are byte addressed, so the offsets do not need any alignment.
+pub trait StorageExt: Storage {
+	write_method!(
+		/// Write `value` as a little endian `u16` at `offset`.
+		write_u16_le, u16, to_le_bytes
+	);
+	write_method!(
+		/// Write `value` as a big endian `u16` at `offset`.
+		write_u16_be, u16, to_be_bytes
+	);
+	write_method!(
+		/// Write `value` as a little endian `u32` at `offset`.
+		write_u32_le, u32, to_le_bytes
+	);
+	write_method!(
+		/// Write `value` as a big endian `u32` at `offset`.
+		write_u32_be, u32, to_be_bytes
+	);
+	write_method!(
+		/// Write `value` as a little endian `u64` at `offset`.
+		write_u64_le, u64, to_le_bytes
+	);
+	write_method!(
+		/// Write `value` as a big endian `u64` at `offset`.
+		write_u64_be, u64, to_be_bytes
+	);
+	write_method!(
+		/// Write `value` as a little endian `i16` at `offset`.
+		write_i16_le, i16, to_le_bytes
+	);
+	write_method!(
+		/// Write `value` as a big endian `i16` at `offset`.
+		write_i16_be, i16, to_be_bytes
+	);
+	write_method!(
+		/// Write `value` as a little endian `i32` at `offset`.
+		write_i32_le, i32, to_le_bytes
+	);
+	write_method!(
+		/// Write `value` as a big endian `i32` at `offset`.
+		write_i32_be, i32, to_be_bytes
+	);
+	write_method!(
+		/// Write `value` as a little endian `i64` at `offset`.
+		write_i64_le, i64, to_le_bytes
+	);
+	write_method!(
+		/// Write `value` as a big endian `i64` at `offset`.
+		write_i64_be, i64, to_be_bytes
+	);
+}
+
+impl<S: Storage + ?Sized> StorageExt for S {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+	use crate::nor_flash::RmwNorFlashStorageOwned;
+
+	#[derive(Debug, Copy, Clone, Default, PartialEq)]
+	struct Calibration {
+		offset: i16,
+		gain: f32,
+		enabled: bool,
+		points: [u16; 3],
+	}
+
+	impl AsBytes for Calibration {
+		const SIZE: usize = 2 + 4 + 1 + 6;
+
+		fn write_bytes(&self, bytes: &mut [u8]) {
+			self.offset.write_bytes(&mut bytes[..2]);
+			self.gain.write_bytes(&mut bytes[2..6]);
+			self.enabled.write_bytes(&mut bytes[6..7]);
+			self.points.write_bytes(&mut bytes[7..]);
+		}
+	}
+
+	impl FromBytes for Calibration {
+		const SIZE: usize = 2 + 4 + 1 + 6;
+
+		fn read_bytes(bytes: &[u8]) -> Self {
+			Self {
+				offset: FromBytes::read_bytes(&bytes[..2]),
+				gain: FromBytes::read_bytes(&bytes[2..6]),
+				enabled: FromBytes::read_bytes(&bytes[6..7]),
+				points: FromBytes::read_bytes(&bytes[7..]),
+			}
+		}
+	}
+
+	const CALIBRATION: Calibration = Calibration {
+		offset: -12,
+		gain: 1.5,
+		enabled: true,
+		points: [100, 2000, 0xabcd],
+	};
+
+	#[test]
+	fn flash_roundtrip() {
+		let mut flash = MockFlash::<1024, 4, 8, 256>::new();
+		write_obj(&mut flash, 64, &CALIBRATION).unwrap();
+		assert_eq!(read_obj::<_, Calibration>(&mut flash, 64), Ok(CALIBRATION));
+		assert_eq!(&flash.as_bytes()[64..66], &(-12i16).to_le_bytes());
+		// The object is padded to WRITE_SIZE with erased bytes.
+		assert_eq!(&flash.as_bytes()[64 + 13..64 + 16], &[0xff; 3]);
+
+		// Reads do not need to be aligned.
+		assert_eq!(read_obj::<_, f32>(&mut flash, 66), Ok(1.5));
+		assert_eq!(read_obj::<_, [u16; 2]>(&mut flash, 73), Ok([2000, 0xabcd]));
+		assert_eq!(read_obj::<_, bool>(&mut flash, 70), Ok(true));
+		assert_eq!(read_obj::<_, u32>(&mut flash, 78), Ok(u32::MAX));
+		write_obj(&mut flash, 128, &[0x0123_4567_89ab_cdefu64, 1]).unwrap();
+		assert_eq!(read_obj::<_, u8>(&mut flash, 135), Ok(0x01));
+		assert!(write_obj(&mut flash, 132, &1u32).is_err());
+	}
+
+	#[test]
+	fn storage_roundtrip() {
+		let mut storage =
+			RmwNorFlashStorageOwned::<_, 256>::new(MockFlash::<1024, 1, 4, 256>::new());
+		store_obj(&mut storage, 3, &CALIBRATION).unwrap();
+		assert_eq!(load_obj::<_, Calibration>(&mut storage, 3), Ok(CALIBRATION));
+		store_obj(&mut storage, 250, &[-1i64, 2]).unwrap();
+		assert_eq!(load_obj::<_, [i64; 2]>(&mut storage, 250), Ok([-1, 2]));
+
+		storage.write_u32_be(513, 0x0102_0304).unwrap();
+		assert_eq!(storage.read_u32_le(513), Ok(0x0403_0201));
+		assert_eq!(storage.read_u16_be(514), Ok(0x0203));
+		storage.write_i64_le(600, -2).unwrap();
+		assert_eq!(
+			storage.read_i64_be(600),
+			Ok(0xfeff_ffff_ffff_ffffu64 as i64)
+		);
+		storage.write_i16_be(700, -300).unwrap();
+		assert_eq!(storage.read_i16_be(700), Ok(-300));
+		assert_eq!(storage.read_u64_le(800), Ok(u64::MAX));
+	}
+}
@@ -0,0 +1,511 @@
+use core::ops::Range;
+
+use crate::crc::{Checksum, Crc32};
+use crate::digest::hash_region;
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, write_chunked, CheckError, NorFlash, NorFlashError,
+	NorFlashErrorKind,
+};
+
+/// Size of the buffers used for the flags of the state partition and the unaligned end of images.
+const SLOT_BUFFER: usize = 32;
+
+/// Minimum size of a flag of the state partition: a magic word, the image length and CRC.
+const SLOT_CONTENT: usize = 12;
+
+const UPDATED: u32 = 0x5444_5055;
+const TRIAL: u32 = 0x4c41_4954;
+const BOOTED: u32 = 0x544f_4f42;
+
+/// Errors returned by [`Updater`] and the [`BankSwap`] helpers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OtaError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The firmware chunk does not fit in the download partition.
+	Invalid(CheckError),
+	/// A firmware chunk starts after the end of the image received so far.
+	OutOfOrder,
+	/// The length or digest of the image received does not match the one announced, or the
+	/// image read back after programming differs.
+	Mismatch,
+}
+
+impl<E: NorFlashError> NorFlashError for OtaError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::OutOfOrder | Self::Mismatch => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// State of an update, as recorded in the state partition.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum State {
+	/// No update is pending, the running firmware is confirmed.
+	Idle,
+	/// A new firmware has been downloaded, the bootloader should install it.
+	Updated,
+	/// The bootloader has started the new firmware, which has not confirmed itself yet.
+	///
+	/// When the bootloader finds this state after a reset, the new firmware failed and must be
+	/// reverted.
+	Trial,
+}
+
+/// Destination of a firmware image received in chunks, so that transports such as USB DFU, BLE or
+/// XMODEM over a UART can target any firmware storage.
+pub trait FirmwareSink {
+	/// Error type returned by the sink.
+	type Error;
+
+	/// Store the chunk `bytes` received at `offset` in the image.
+	///
+	/// Chunks come in order, but a chunk retransmitted by the transport, entirely or partly before
+	/// the end of the image received so far, is accepted and its known part ignored.
+	fn write_chunk(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+
+	/// Complete the image, checking it against its total `length` and `digest`, the checksum of
+	/// the image, computed by [`Crc32`] unless the sink is configured otherwise.
+	fn finalize(&mut self, length: u32, digest: u32) -> Result<(), Self::Error>;
+}
+
+/// Firmware update flow over a download partition and a state partition of a flash.
+///
+/// The running firmware streams the new image with [`write_firmware`](Self::write_firmware),
+/// which erases the download partition as it goes and computes the CRC of the image, then calls
+/// [`mark_updated`](Self::mark_updated). The bootloader checks the image with
+/// [`verify`](Self::verify), installs it by whatever means the platform uses, e.g. swapping
+/// partitions, and calls [`mark_trial`](Self::mark_trial) before starting it. Once the new
+/// firmware is up and running it calls [`mark_booted`](Self::mark_booted); if it resets before,
+/// the bootloader finds the [`State::Trial`] state and reverts.
+///
+/// Every flag is written once in its own word of the state partition, which is only erased when
+/// a new update is recorded, so a power loss at any point leaves a consistent state.
+///
+/// The updater is also a [`FirmwareSink`], recording the update once the image is finalized.
+pub struct Updater<S, C = Crc32> {
+	flash: S,
+	dfu: Range<u32>,
+	state: Range<u32>,
+	/// Number of bytes programmed into the download partition, a multiple of `WRITE_SIZE`.
+	written: u32,
+	/// Received bytes waiting for a whole `WRITE_SIZE` word to be programmed.
+	tail: [u8; SLOT_BUFFER],
+	pending: usize,
+	crc: C,
+}
+
+impl<S: NorFlash> Updater<S> {
+	/// Manage the download partition `dfu` and the state partition `state` of `flash`.
+	///
+	/// **NOTE** This will panic if the partitions are not aligned to `ERASE_SIZE` or out of bounds,
+	/// if the state partition cannot hold three flags, or if `READ_SIZE` or `WRITE_SIZE` is over
+	/// 32 bytes, see [`try_new`](Updater::try_new).
+	pub fn new(flash: S, dfu: Range<u32>, state: Range<u32>) -> Self {
+		Self::new_with_checksum(flash, dfu, state)
+	}
+
+	/// Manage the download partition `dfu` and the state partition `state` of `flash`, without
+	/// panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if the partitions are not aligned to `ERASE_SIZE`, and
+	/// [`CheckError::OutOfBounds`] if they are out of bounds, if the state partition cannot hold
+	/// three flags, or if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(flash: S, dfu: Range<u32>, state: Range<u32>) -> Result<Self, CheckError> {
+		Self::try_new_with_checksum(flash, dfu, state)
+	}
+}
+
+impl<S: NorFlash, C: Checksum> Updater<S, C> {
+	/// Manage the partitions, checking the image with the checksum `C` instead of [`Crc32`].
+	///
+	/// **NOTE** This will panic in the cases of [`new`](Updater::new).
+	pub fn new_with_checksum(flash: S, dfu: Range<u32>, state: Range<u32>) -> Self {
+		match Self::try_new_with_checksum(flash, dfu, state) {
+			Ok(updater) => updater,
+			Err(CheckError::NotAligned) => panic!("Partition not aligned to ERASE_SIZE"),
+			Err(_) => panic!("Partition out of bounds or too small"),
+		}
+	}
+
+	/// Manage the partitions, checking the image with the checksum `C`, without panicking.
+	///
+	/// # Errors
+	///
+	/// Returns an error in the cases of [`try_new`](Updater::try_new).
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new_with_checksum(
+		flash: S,
+		dfu: Range<u32>,
+		state: Range<u32>,
+	) -> Result<Self, CheckError> {
+		check_range(flash.capacity(), S::ERASE_SIZE, dfu.start, dfu.end)?;
+		check_range(flash.capacity(), S::ERASE_SIZE, state.start, state.end)?;
+		check_slice(SLOT_BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE))?;
+		check_slice(
+			(state.end - state.start) as usize,
+			1,
+			0,
+			3 * Self::slot_size(),
+		)?;
+		Ok(Self {
+			flash,
+			dfu,
+			state,
+			written: 0,
+			tail: [0; SLOT_BUFFER],
+			pending: 0,
+			crc: C::default(),
+		})
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Number of bytes of the new firmware written so far.
+	pub fn written(&self) -> u32 {
+		self.written + self.pending as u32
+	}
+
+	/// Discard the firmware written so far, to start the download over.
+	pub fn restart(&mut self) {
+		self.written = 0;
+		self.pending = 0;
+		self.crc = C::default();
+	}
+
+	/// Append `bytes` to the new firmware in the download partition, erasing pages as needed.
+	///
+	/// Bytes not filling a whole `WRITE_SIZE` word are kept until the next call, the end of the
+	/// image is padded with erased bytes by [`mark_updated`](Self::mark_updated).
+	pub fn write_firmware(&mut self, mut bytes: &[u8]) -> Result<(), OtaError<S::Error>> {
+		let capacity = (self.dfu.end - self.dfu.start) as usize;
+		check_slice(capacity, 1, self.written(), bytes.len()).map_err(OtaError::Invalid)?;
+		self.crc.update(bytes);
+		if self.pending > 0 {
+			let part = (S::WRITE_SIZE - self.pending).min(bytes.len());
+			self.tail[self.pending..self.pending + part].copy_from_slice(&bytes[..part]);
+			self.pending += part;
+			bytes = &bytes[part..];
+			if self.pending < S::WRITE_SIZE {
+				return Ok(());
+			}
+			self.flush()?;
+		}
+		let aligned = bytes.len() - bytes.len() % S::WRITE_SIZE;
+		self.program(&bytes[..aligned])?;
+		self.pending = bytes.len() - aligned;
+		self.tail[..self.pending].copy_from_slice(&bytes[aligned..]);
+		Ok(())
+	}
+
+	/// Record the firmware written so far as an update to install.
+	///
+	/// Call [`restart`](Self::restart) before writing another firmware.
+	pub fn mark_updated(&mut self) -> Result<(), OtaError<S::Error>> {
+		let length = self.written();
+		self.flush()?;
+		self.flash
+			.erase(self.state.start, self.state.end)
+			.map_err(OtaError::Flash)?;
+		self.write_slot(0, UPDATED, length, self.crc.finish())
+	}
+
+	/// Record that the bootloader is starting the new firmware.
+	///
+	/// This does nothing unless the state is [`State::Updated`].
+	pub fn mark_trial(&mut self) -> Result<(), OtaError<S::Error>> {
+		if self.state()? != State::Updated {
+			return Ok(());
+		}
+		self.write_slot(1, TRIAL, 0, 0)
+	}
+
+	/// Confirm that the new firmware is running correctly, completing the update.
+	///
+	/// This does nothing unless the state is [`State::Trial`], so it can be called on every boot.
+	pub fn mark_booted(&mut self) -> Result<(), OtaError<S::Error>> {
+		if self.state()? != State::Trial {
+			return Ok(());
+		}
+		self.write_slot(2, BOOTED, 0, 0)
+	}
+
+	/// The state of the update.
+	pub fn state(&mut self) -> Result<State, OtaError<S::Error>> {
+		if self.read_slot(0)?.0 != UPDATED || self.read_slot(2)?.0 == BOOTED {
+			return Ok(State::Idle);
+		}
+		if self.read_slot(1)?.0 == TRIAL {
+			return Ok(State::Trial);
+		}
+		Ok(State::Updated)
+	}
+
+	/// Whether the bootloader must revert the update, i.e. the new firmware was started but did
+	/// not confirm itself.
+	pub fn needs_revert(&mut self) -> Result<bool, OtaError<S::Error>> {
+		Ok(self.state()? == State::Trial)
+	}
+
+	/// Check the recorded update against the CRC computed while downloading it.
+	///
+	/// `buffer` is used to read the download partition. Returns `false` when no update is
+	/// recorded.
+	///
+	/// **NOTE** This will panic if `buffer` is shorter than `READ_SIZE`.
+	pub fn verify(&mut self, buffer: &mut [u8]) -> Result<bool, OtaError<S::Error>> {
+		let (magic, length, crc) = self.read_slot(0)?;
+		if magic != UPDATED || length > self.dfu.end - self.dfu.start {
+			return Ok(false);
+		}
+		let mut computed = C::default();
+		let image = self.dfu.start..self.dfu.start + length;
+		hash_region(&mut self.flash, image, &mut computed, buffer).map_err(OtaError::Flash)?;
+		Ok(computed.finish() == crc)
+	}
+
+	/// Program whole `WRITE_SIZE` words at the end of the download partition.
+	fn program(&mut self, bytes: &[u8]) -> Result<(), OtaError<S::Error>> {
+		let page = S::ERASE_SIZE as u32;
+		let mut done = 0;
+		while done < bytes.len() {
+			let address = self.dfu.start + self.written;
+			if self.written % page == 0 {
+				self.flash
+					.erase(address, address + page)
+					.map_err(OtaError::Flash)?;
+			}
+			let part = ((page - self.written % page) as usize).min(bytes.len() - done);
+			write_chunked(&mut self.flash, address, &bytes[done..done + part])
+				.map_err(OtaError::Flash)?;
+			self.written += part as u32;
+			done += part;
+		}
+		Ok(())
+	}
+
+	/// Program the pending bytes, padded with their erased value.
+	fn flush(&mut self) -> Result<(), OtaError<S::Error>> {
+		if self.pending == 0 {
+			return Ok(());
+		}
+		let mut tail = [0; SLOT_BUFFER];
+		fill_erased::<S>(self.dfu.start + self.written, &mut tail[..S::WRITE_SIZE]);
+		tail[..self.pending].copy_from_slice(&self.tail[..self.pending]);
+		self.pending = 0;
+		self.program(&tail[..S::WRITE_SIZE])
+	}
+
+	fn slot_size() -> usize {
+		let align = S::READ_SIZE.max(S::WRITE_SIZE);
+		(SLOT_CONTENT + align - 1) / align * align
+	}
+
+	fn write_slot(
+		&mut self,
+		index: u32,
+		magic: u32,
+		length: u32,
+		crc: u32,
+	) -> Result<(), OtaError<S::Error>> {
+		let size = Self::slot_size();
+		let mut slot = [0; SLOT_BUFFER];
+		fill_erased::<S>(self.state.start + index * size as u32, &mut slot[..size]);
+		slot[..4].copy_from_slice(&magic.to_le_bytes());
+		slot[4..8].copy_from_slice(&length.to_le_bytes());
+		slot[8..12].copy_from_slice(&crc.to_le_bytes());
+		self.flash
+			.write(self.state.start + index * size as u32, &slot[..size])
+			.map_err(OtaError::Flash)
+	}
+
+	/// Read the magic word, length and CRC of a flag.
+	fn read_slot(&mut self, index: u32) -> Result<(u32, u32, u32), OtaError<S::Error>> {
+		let size = Self::slot_size();
+		let mut slot = [0; SLOT_BUFFER];
+		self.flash
+			.read(self.state.start + index * size as u32, &mut slot[..size])
+			.map_err(OtaError::Flash)?;
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&slot[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		Ok((word(0), word(1), word(2)))
+	}
+}
+
+impl<S: NorFlash, C: Checksum> FirmwareSink for Updater<S, C> {
+	type Error = OtaError<S::Error>;
+
+	fn write_chunk(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		let known = self
+			.written()
+			.checked_sub(offset)
+			.ok_or(OtaError::OutOfOrder)? as usize;
+		if known < bytes.len() {
+			self.write_firmware(&bytes[known..])?;
+		}
+		Ok(())
+	}
+
+	fn finalize(&mut self, length: u32, digest: u32) -> Result<(), Self::Error> {
+		if length != self.written() || digest != self.crc.finish() {
+			return Err(OtaError::Mismatch);
+		}
+		self.mark_updated()
+	}
+}
+
+/// Bank of a dual-bank flash controller.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Bank {
+	/// The bank at the lower addresses.
+	First,
+	/// The bank at the higher addresses.
+	Second,
+}
+
+impl Bank {
+	/// The other bank.
+	pub fn other(self) -> Self {
+		match self {
+			Self::First => Self::Second,
+			Self::Second => Self::First,
+		}
+	}
+}
+
+/// Flash controller with two banks, either of which can be mapped at the boot address.
+///
+/// This models the dual-bank controllers of MCUs such as STM32 or NXP parts: the firmware runs
+/// from the active bank, the new firmware is programmed into the inactive one, then swapping the
+/// banks boots it, usually from the next reset. Bootloaders built on this trait stay vendor
+/// neutral, with [`program_inactive_bank`] and [`program_and_swap`] doing the update.
+pub trait BankSwap: NorFlash {
+	/// The bank currently mapped at the boot address.
+	fn active_bank(&self) -> Bank;
+
+	/// The addresses of `bank` in the flash.
+	fn bank_range(&self, bank: Bank) -> Range<u32>;
+
+	/// Map the other bank at the boot address.
+	fn swap_banks(&mut self) -> Result<(), Self::Error>;
+
+	/// The addresses of the inactive bank, which can be programmed safely.
+	fn inactive_range(&self) -> Range<u32> {
+		self.bank_range(self.active_bank().other())
+	}
+}
+
+impl<T: BankSwap> BankSwap for &mut T {
+	fn active_bank(&self) -> Bank {
+		T::active_bank(self)
+	}
+
+	fn bank_range(&self, bank: Bank) -> Range<u32> {
+		T::bank_range(self, bank)
+	}
+
+	fn swap_banks(&mut self) -> Result<(), Self::Error> {
+		T::swap_banks(self)
+	}
+}
+
+/// Program `image` at the start of the inactive bank of `flash`, then verify its CRC.
+///
+/// The pages covered by the image are erased first, and its end is padded with erased bytes.
+/// `scratch` is used to read the image back. For images received in chunks, an [`Updater`] with
+/// the inactive bank as download partition does the same.
+///
+/// **NOTE** This will panic if `scratch` is shorter than `READ_SIZE`, or if `WRITE_SIZE` is over
+/// 32 bytes.
+pub fn program_inactive_bank<S: BankSwap>(
+	flash: &mut S,
+	image: &[u8],
+	scratch: &mut [u8],
+) -> Result<(), OtaError<S::Error>> {
+	assert!(S::WRITE_SIZE <= SLOT_BUFFER, "WRITE_SIZE too large");
+	let bank = flash.inactive_range();
+	let capacity = (bank.end - bank.start) as usize;
+	check_slice(capacity, 1, 0, image.len()).map_err(OtaError::Invalid)?;
+	let erased = (image.len() + S::ERASE_SIZE - 1) / S::ERASE_SIZE * S::ERASE_SIZE;
+	flash
+		.erase(bank.start, bank.start + erased as u32)
+		.map_err(OtaError::Flash)?;
+	let aligned = image.len() - image.len() % S::WRITE_SIZE;
+	write_chunked(flash, bank.start, &image[..aligned]).map_err(OtaError::Flash)?;
+	if aligned < image.len() {
+		let mut tail = [0; SLOT_BUFFER];
+		fill_erased::<S>(bank.start + aligned as u32, &mut tail[..S::WRITE_SIZE]);
+		tail[..image.len() - aligned].copy_from_slice(&image[aligned..]);
+		flash
+			.write(bank.start + aligned as u32, &tail[..S::WRITE_SIZE])
+			.map_err(OtaError::Flash)?;
+	}
+	let mut computed = Crc32::new();
+	let range = bank.start..bank.start + image.len() as u32;
+	hash_region(flash, range, &mut computed, scratch).map_err(OtaError::Flash)?;
+	if computed.finish() != Crc32::checksum(image) {
+		return Err(OtaError::Mismatch);
+	}
+	Ok(())
+}
+
+/// Program `image` into the inactive bank of `flash` as [`program_inactive_bank`] does, then swap
+/// the banks to boot it.
+///
+/// **NOTE** This will panic if `scratch` is shorter than `READ_SIZE`, or if `WRITE_SIZE` is over
+/// 32 bytes.
+pub fn program_and_swap<S: BankSwap>(
+	flash: &mut S,
+	image: &[u8],
+	scratch: &mut [u8],
+) -> Result<(), OtaError<S::Error>> {
+	program_inactive_bank(flash, image, scratch)?;
+	flash.swap_banks().map_err(OtaError::Flash)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	type Flash = MockFlash<16384, 1, 4, 1024>;
+
+	#[test]
+	fn try_new_invalid() {
+		let error =
+			|dfu: Range<u32>, state: Range<u32>| Updater::try_new(Flash::new(), dfu, state).err();
+		assert_eq!(error(0..8192, 8192..9216), None);
+		assert_eq!(error(512..8192, 8192..9216), Some(CheckError::NotAligned));
+		assert_eq!(error(0..8192, 8192..8704), Some(CheckError::NotAligned));
+		assert!(matches!(
+			error(0..8192, 16384..17408),
+			Some(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			Updater::try_new(MockFlash::<16384, 1, 4, 32>::new(), 0..8192, 8192..8224),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			Updater::try_new(MockFlash::<16384, 64, 4, 1024>::new(), 0..8192, 8192..9216),
+			Err(CheckError::OutOfBounds(_))
+		));
+	}
+}
@@ -0,0 +1,579 @@
+#[cfg(feature = "std")]
+use std::vec::Vec;
+
+use core::ops::Range;
+
+use crate::addr::align_up;
+use crate::crc::Crc32;
+use crate::digest::hash_region;
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, write_sparse_erased, CheckError, NorFlash,
+	NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffers collecting output and reading the old image.
+const BUFFER: usize = 256;
+
+/// Size of the header of a patch.
+pub const HEADER: usize = 20;
+
+/// Magic number starting a patch.
+pub const MAGIC: u32 = 0x4843_5450;
+
+/// Command copying a range of the old image: offset and length.
+pub const COPY: u8 = 0x01;
+
+/// Command adding literal data: length, followed by the data.
+pub const ADD: u8 = 0x02;
+
+/// Errors returned by [`Patcher`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PatchError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// An image does not fit in its partition, or a copy is out of the old image.
+	Invalid(CheckError),
+	/// The patch is malformed or truncated.
+	Format,
+	/// The old image is not the one the patch was made for.
+	Base,
+	/// The CRC of the new image is wrong.
+	Checksum,
+}
+
+impl<E: NorFlashError> NorFlashError for PatchError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Format | Self::Base | Self::Checksum => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+	Header,
+	Command,
+	Add(u32),
+}
+
+/// Applier of delta patches, building a new image in a partition from the old image in another.
+///
+/// Patches start with a header of little endian `u32`: [`MAGIC`], the length and CRC-32 of the old
+/// image, and the length and CRC-32 of the new image. Then come commands until the new image is
+/// complete: [`COPY`] followed by the offset and length of a range of the old image, or [`ADD`]
+/// followed by a length and as many bytes of literal data. Parts unchanged by an update are thus
+/// copied from the old image, and only the changes are downloaded.
+///
+/// The patch is fed in chunks of any size, e.g. as received. The old image is checked against its
+/// CRC once the header is complete, so nothing is written if the patch is for another image. Pages
+/// of the new partition are erased as the image reaches them, and words left erased are not
+/// programmed. Once finished, the new image is checked against its CRC.
+pub struct Patcher<'a, S> {
+	flash: &'a mut S,
+	old: Range<u32>,
+	new: Range<u32>,
+	state: State,
+	header: [u8; HEADER],
+	/// Number of bytes of the header or command collected.
+	pending: usize,
+	old_length: u32,
+	new_length: u32,
+	new_crc: u32,
+	/// Number of bytes of the new image programmed.
+	start: u32,
+	buffer: [u8; BUFFER],
+	/// Number of bytes of the new image collected in the buffer.
+	length: usize,
+	/// End of the erased pages.
+	erased: u32,
+	scratch: [u8; BUFFER],
+}
+
+impl<'a, S: NorFlash> Patcher<'a, S> {
+	/// Build the new image in `new` of `flash`, from the old image at the start of `old`.
+	///
+	/// **NOTE** This will panic if the partitions overlap, are out of bounds or are not aligned to
+	/// `ERASE_SIZE`, or if `READ_SIZE` or `WRITE_SIZE` is over 256 bytes, see
+	/// [`try_new`](Patcher::try_new).
+	pub fn new(flash: &'a mut S, old: Range<u32>, new: Range<u32>) -> Self {
+		match Self::try_new(flash, old, new) {
+			Ok(patcher) => patcher,
+			Err(CheckError::NotAligned) => panic!("Partitions not aligned to ERASE_SIZE"),
+			Err(_) => panic!("Partitions overlap or out of bounds"),
+		}
+	}
+
+	/// Build the new image in `new` of `flash`, from the old image at the start of `old`, without
+	/// panicking.
+	///
+	/// # Errors
+	///
+	/// Returns [`CheckError::NotAligned`] if the partitions are not aligned to `ERASE_SIZE`, and
+	/// [`CheckError::OutOfBounds`] if they overlap or are out of bounds, or if `READ_SIZE` or
+	/// `WRITE_SIZE` is over 256 bytes.
+	#[cfg_attr(
+		feature = "no-panic",
+		deny(clippy::missing_panics_doc, clippy::indexing_slicing)
+	)]
+	pub fn try_new(flash: &'a mut S, old: Range<u32>, new: Range<u32>) -> Result<Self, CheckError> {
+		check_range(flash.capacity(), S::ERASE_SIZE, old.start, old.end)?;
+		check_range(flash.capacity(), S::ERASE_SIZE, new.start, new.end)?;
+		if old.start < new.end && new.start < old.end {
+			return Err(CheckError::OutOfBounds(OutOfBoundsInfo {
+				offset: new.start,
+				length: (new.end - new.start) as usize,
+				capacity: flash.capacity(),
+			}));
+		}
+		check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE))?;
+		Ok(Self {
+			erased: new.start,
+			flash,
+			old,
+			new,
+			state: State::Header,
+			header: [0; HEADER],
+			pending: 0,
+			old_length: 0,
+			new_length: 0,
+			new_crc: 0,
+			start: 0,
+			buffer: [0; BUFFER],
+			length: 0,
+			scratch: [0; BUFFER],
+		})
+	}
+
+	/// Feed the next bytes of the patch.
+	pub fn write(&mut self, mut patch: &[u8]) -> Result<(), PatchError<S::Error>> {
+		while !patch.is_empty() {
+			match self.state {
+				State::Add(remaining) => {
+					let part = (remaining as usize).min(patch.len());
+					self.output(&patch[..part])?;
+					patch = &patch[part..];
+					self.state = match remaining - part as u32 {
+						0 => State::Command,
+						remaining => State::Add(remaining),
+					};
+				}
+				State::Header => {
+					if self.collect(&mut patch, HEADER) {
+						self.begin()?;
+					}
+				}
+				State::Command => {
+					let tag = if self.pending > 0 {
+						self.header[0]
+					} else if self.produced() < self.new_length {
+						patch[0]
+					} else {
+						return Err(PatchError::Format);
+					};
+					let length = match tag {
+						COPY => 9,
+						ADD => 5,
+						_ => return Err(PatchError::Format),
+					};
+					if self.collect(&mut patch, length) {
+						self.command()?;
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Program the rest of the new image and check it, returns its length.
+	pub fn finish(mut self) -> Result<u32, PatchError<S::Error>> {
+		if self.state != State::Command || self.pending > 0 || self.produced() != self.new_length {
+			return Err(PatchError::Format);
+		}
+		self.flush()?;
+		let mut crc = Crc32::new();
+		let start = self.new.start;
+		hash_region(
+			self.flash,
+			start..start + self.new_length,
+			&mut crc,
+			&mut self.scratch,
+		)
+		.map_err(PatchError::Flash)?;
+		if crc.finish() != self.new_crc {
+			return Err(PatchError::Checksum);
+		}
+		Ok(self.new_length)
+	}
+
+	/// Collect the bytes of a header or command up to `length`, returns whether it is complete.
+	fn collect(&mut self, patch: &mut &[u8], length: usize) -> bool {
+		let part = (length - self.pending).min(patch.len());
+		self.header[self.pending..self.pending + part].copy_from_slice(&patch[..part]);
+		self.pending += part;
+		*patch = &patch[part..];
+		if self.pending < length {
+			return false;
+		}
+		self.pending = 0;
+		true
+	}
+
+	fn word(&self, index: usize) -> u32 {
+		let mut bytes = [0; 4];
+		bytes.copy_from_slice(&self.header[index..index + 4]);
+		u32::from_le_bytes(bytes)
+	}
+
+	/// Check the header and the old image.
+	fn begin(&mut self) -> Result<(), PatchError<S::Error>> {
+		if self.word(0) != MAGIC {
+			return Err(PatchError::Format);
+		}
+		let (old_length, old_crc) = (self.word(4), self.word(8));
+		self.new_length = self.word(12);
+		self.new_crc = self.word(16);
+		for (length, range) in [(old_length, &self.old), (self.new_length, &self.new)].iter() {
+			let capacity = (range.end - range.start) as usize;
+			if *length as usize > capacity {
+				return Err(PatchError::Invalid(CheckError::OutOfBounds(
+					OutOfBoundsInfo {
+						offset: 0,
+						length: *length as usize,
+						capacity,
+					},
+				)));
+			}
+		}
+		let mut crc = Crc32::new();
+		let start = self.old.start;
+		hash_region(
+			self.flash,
+			start..start + old_length,
+			&mut crc,
+			&mut self.scratch,
+		)
+		.map_err(PatchError::Flash)?;
+		if crc.finish() != old_crc {
+			return Err(PatchError::Base);
+		}
+		self.old_length = old_length;
+		self.state = State::Command;
+		Ok(())
+	}
+
+	/// Run the command collected in the header buffer.
+	fn command(&mut self) -> Result<(), PatchError<S::Error>> {
+		if self.header[0] == ADD {
+			let length = self.word(1);
+			if length > 0 {
+				self.state = State::Add(length);
+			}
+			return Ok(());
+		}
+		let (offset, length) = (self.word(1), self.word(5));
+		if offset
+			.checked_add(length)
+			.map_or(true, |end| end > self.old_length)
+		{
+			return Err(PatchError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset,
+					length: length as usize,
+					capacity: self.old_length as usize,
+				},
+			)));
+		}
+		let chunk = BUFFER - BUFFER % S::READ_SIZE;
+		let mut address = self.old.start + offset;
+		let end = address + length;
+		while address < end {
+			let skip = address as usize % S::READ_SIZE;
+			let part = (chunk - skip).min((end - address) as usize);
+			let read = align_up(skip + part, S::READ_SIZE);
+			self.flash
+				.read(address - skip as u32, &mut self.scratch[..read])
+				.map_err(PatchError::Flash)?;
+			let data = self.scratch;
+			self.output(&data[skip..skip + part])?;
+			address += part as u32;
+		}
+		Ok(())
+	}
+
+	/// Number of bytes of the new image produced.
+	fn produced(&self) -> u32 {
+		self.start + self.length as u32
+	}
+
+	/// Append `data` to the new image.
+	fn output(&mut self, mut data: &[u8]) -> Result<(), PatchError<S::Error>> {
+		if self.produced() as usize + data.len() > self.new_length as usize {
+			return Err(PatchError::Format);
+		}
+		while !data.is_empty() {
+			let part = (BUFFER - self.length).min(data.len());
+			self.buffer[self.length..self.length + part].copy_from_slice(&data[..part]);
+			self.length += part;
+			data = &data[part..];
+			if self.length == BUFFER {
+				self.flush()?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Program the collected data, erasing the pages it covers if needed.
+	fn flush(&mut self) -> Result<(), PatchError<S::Error>> {
+		if self.length == 0 {
+			return Ok(());
+		}
+		let length = align_up(self.length, S::WRITE_SIZE);
+		let address = self.new.start + self.start;
+		let end = address + length as u32;
+		if self.erased < end {
+			let to = align_up(end as usize, S::ERASE_SIZE) as u32;
+			self.flash
+				.erase(self.erased, to)
+				.map_err(PatchError::Flash)?;
+			self.erased = to;
+		}
+		fill_erased::<S>(
+			address + self.length as u32,
+			&mut self.buffer[self.length..length],
+		);
+		write_sparse_erased(self.flash, address, &self.buffer[..length])
+			.map_err(PatchError::Flash)?;
+		self.start += self.length as u32;
+		self.length = 0;
+		Ok(())
+	}
+}
+
+/// Apply `patch` to build the new image in `new` of `flash`, from the old image at the start of
+/// `old`. Returns the length of the new image.
+///
+/// # Errors
+///
+/// Returns [`PatchError::Invalid`] in the cases of [`Patcher::try_new`], besides the errors of
+/// the patch itself.
+pub fn apply<S: NorFlash>(
+	flash: &mut S,
+	old: Range<u32>,
+	new: Range<u32>,
+	patch: &[u8],
+) -> Result<u32, PatchError<S::Error>> {
+	let mut patcher = Patcher::try_new(flash, old, new).map_err(PatchError::Invalid)?;
+	patcher.write(patch)?;
+	patcher.finish()
+}
+
+/// Generator of patches applied by [`Patcher`], for update tooling.
+///
+/// Commands are added in the order of the new image, which is tracked to fill in the header.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct PatchWriter<'a> {
+	old: &'a [u8],
+	commands: Vec<u8>,
+	crc: Crc32,
+	length: usize,
+}
+
+#[cfg(feature = "std")]
+impl<'a> PatchWriter<'a> {
+	/// Generate a patch from the `old` image.
+	pub fn new(old: &'a [u8]) -> Self {
+		Self {
+			old,
+			commands: Vec::new(),
+			crc: Crc32::new(),
+			length: 0,
+		}
+	}
+
+	/// Copy `length` bytes at `offset` of the old image.
+	///
+	/// **NOTE** This will panic if the range is out of the old image.
+	pub fn copy(&mut self, offset: usize, length: usize) -> &mut Self {
+		let data = &self.old[offset..offset + length];
+		self.commands.push(COPY);
+		self.commands
+			.extend_from_slice(&(offset as u32).to_le_bytes());
+		self.commands
+			.extend_from_slice(&(length as u32).to_le_bytes());
+		self.crc.update(data);
+		self.length += length;
+		self
+	}
+
+	/// Add literal `data`.
+	pub fn add(&mut self, data: &[u8]) -> &mut Self {
+		self.commands.push(ADD);
+		self.commands
+			.extend_from_slice(&(data.len() as u32).to_le_bytes());
+		self.commands.extend_from_slice(data);
+		self.crc.update(data);
+		self.length += data.len();
+		self
+	}
+
+	/// Length of the new image so far.
+	pub fn len(&self) -> usize {
+		self.length
+	}
+
+	/// Whether the new image is empty so far.
+	pub fn is_empty(&self) -> bool {
+		self.length == 0
+	}
+
+	/// The patch.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(HEADER + self.commands.len());
+		for word in [
+			MAGIC,
+			self.old.len() as u32,
+			Crc32::checksum(self.old),
+			self.length as u32,
+			self.crc.finish(),
+		]
+		.iter()
+		{
+			bytes.extend_from_slice(&word.to_le_bytes());
+		}
+		bytes.extend_from_slice(&self.commands);
+		bytes
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	type Flash = MockFlash<8192, 1, 4, 1024>;
+
+	#[test]
+	fn try_new_invalid() {
+		let mut flash = Flash::new();
+		assert!(Patcher::try_new(&mut flash, 0..4096, 4096..8192).is_ok());
+		assert!(matches!(
+			Patcher::try_new(&mut flash, 0..4096, 4608..8192),
+			Err(CheckError::NotAligned)
+		));
+		assert!(matches!(
+			Patcher::try_new(&mut flash, 0..5120, 4096..8192),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			Patcher::try_new(&mut flash, 0..4096, 4096..12288),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			apply(&mut flash, 4096..8192, 0..5120, &[]),
+			Err(PatchError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		let mut flash = MockFlash::<8192, 512, 4, 1024>::new();
+		assert!(matches!(
+			Patcher::try_new(&mut flash, 0..4096, 4096..8192),
+			Err(CheckError::OutOfBounds(_))
+		));
+	}
+
+	/// A flash holding a 1000 byte old image at 0, and the image.
+	#[cfg(feature = "std")]
+	fn old_image() -> (Flash, [u8; 1000]) {
+		let mut old = [0; 1000];
+		for (index, byte) in old.iter_mut().enumerate() {
+			*byte = (index * 7 % 251) as u8;
+		}
+		let mut flash = Flash::new();
+		flash.write(0, &old).unwrap();
+		(flash, old)
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn roundtrip() {
+		let (mut flash, old) = old_image();
+		let mut writer = PatchWriter::new(&old);
+		writer
+			.copy(0, 300)
+			.add(b"new header")
+			.copy(500, 490)
+			.add(&[0x42; 300])
+			.copy(999, 1);
+		let patch = writer.to_bytes();
+		assert_eq!(writer.len(), 1101);
+		assert!(patch.len() < 400);
+
+		let mut patcher = Patcher::new(&mut flash, 0..4096, 4096..8192);
+		for chunk in patch.chunks(7) {
+			patcher.write(chunk).unwrap();
+		}
+		assert_eq!(patcher.finish(), Ok(1101));
+		let new = &flash.as_bytes()[4096..4096 + 1101];
+		assert_eq!(&new[..300], &old[..300]);
+		assert_eq!(&new[300..310], b"new header");
+		assert_eq!(&new[310..800], &old[500..990]);
+		assert!(new[800..1100].iter().all(|&byte| byte == 0x42));
+		assert_eq!(new[1100], old[999]);
+		// The rest of the partition is left erased.
+		assert!(flash.as_bytes()[4096 + 1104..]
+			.iter()
+			.all(|&byte| byte == 0xff));
+
+		assert_eq!(apply(&mut flash, 0..4096, 4096..8192, &patch), Ok(1101));
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn malformed() {
+		let (mut flash, old) = old_image();
+		let mut writer = PatchWriter::new(&old);
+		writer.copy(0, 100).add(b"data");
+		let patch = writer.to_bytes();
+		let apply = |flash: &mut Flash, patch: &[u8]| apply(flash, 0..4096, 4096..8192, patch);
+
+		assert_eq!(
+			apply(&mut flash, &patch[..patch.len() - 1]),
+			Err(PatchError::Format)
+		);
+		assert_eq!(
+			apply(&mut flash, &patch[..HEADER + 3]),
+			Err(PatchError::Format)
+		);
+		let mut extra = patch.clone();
+		extra.push(ADD);
+		assert_eq!(apply(&mut flash, &extra), Err(PatchError::Format));
+		let mut corrupt = patch.clone();
+		*corrupt.last_mut().unwrap() ^= 1;
+		assert_eq!(apply(&mut flash, &corrupt), Err(PatchError::Checksum));
+		let mut unknown = patch.clone();
+		unknown[HEADER] = 0x7f;
+		assert_eq!(apply(&mut flash, &unknown), Err(PatchError::Format));
+		let mut magic = patch.clone();
+		magic[0] ^= 1;
+		assert_eq!(apply(&mut flash, &magic), Err(PatchError::Format));
+
+		let mut other = old;
+		other[500] ^= 1;
+		let patch = PatchWriter::new(&other).copy(0, 100).to_bytes();
+		assert_eq!(apply(&mut flash, &patch), Err(PatchError::Base));
+
+		let mut beyond = PatchWriter::new(&old).copy(0, 100).to_bytes();
+		beyond[HEADER + 1..HEADER + 5].copy_from_slice(&950u32.to_le_bytes());
+		assert!(matches!(
+			apply(&mut flash, &beyond),
+			Err(PatchError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		let large = [0; 4097];
+		let patch = PatchWriter::new(&old).add(&large).to_bytes();
+		assert!(matches!(
+			apply(&mut flash, &patch),
+			Err(PatchError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+}
@@ -0,0 +1,219 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::nor_flash::{
+	check_erase, check_write, ErrorType, NorFlash, NorFlashError, NorFlashErrorKind,
+	ProgramPolarity, ReadNorFlash,
+};
+
+/// Errors returned by [`PowerCut`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PowerCutError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// Power was cut, no further operations are possible.
+	PowerLoss,
+}
+
+impl<E: NorFlashError> NorFlashError for PowerCutError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::PowerLoss => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Pattern of the bits left untouched by an interrupted word program or page erase.
+const UNTOUCHED_BITS: u8 = 0xaa;
+
+/// Wrapper simulating a power cut after a given number of program or erase steps.
+///
+/// Writes are performed one `WRITE_SIZE` word at a time, and erases one `ERASE_SIZE` page at a
+/// time. Each step has two cut points: before the step starts, and half way through it. A cut half
+/// way through a program leaves the word partially programmed, and a cut half way through an erase
+/// leaves the page partially erased. After the cut every operation fails with
+/// [`PowerCutError::PowerLoss`].
+pub struct PowerCut<S> {
+	flash: S,
+	budget: Option<usize>,
+	used: usize,
+	lost: bool,
+}
+
+impl<S> PowerCut<S>
+where
+	S: NorFlash,
+{
+	/// Wrap `flash`, cutting power once `cut` cut points have been passed.
+	pub fn new(flash: S, cut: usize) -> Self {
+		Self {
+			flash,
+			budget: Some(cut),
+			used: 0,
+			lost: false,
+		}
+	}
+
+	/// Wrap `flash` without ever cutting power, e.g. to count the cut points of an operation.
+	pub fn unlimited(flash: S) -> Self {
+		Self {
+			flash,
+			budget: None,
+			used: 0,
+			lost: false,
+		}
+	}
+
+	/// Number of cut points passed so far.
+	pub fn cut_points(&self) -> usize {
+		self.used
+	}
+
+	/// Whether power has been cut.
+	pub fn is_lost(&self) -> bool {
+		self.lost
+	}
+
+	/// Release the wrapped flash, e.g. to check its state after the cut.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Pass the two cut points of a step if possible, returns how many were passed.
+	fn step(&mut self) -> usize {
+		let passed = match self.budget {
+			Some(budget) => (budget - self.used).min(2),
+			None => 2,
+		};
+		self.used += passed;
+		self.lost = passed < 2;
+		passed
+	}
+}
+
+impl<S: ErrorType> ErrorType for PowerCut<S> {
+	type Error = PowerCutError<S::Error>;
+}
+
+impl<S> ReadNorFlash for PowerCut<S>
+where
+	S: NorFlash,
+{
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		if self.lost {
+			return Err(PowerCutError::PowerLoss);
+		}
+		self.flash.read(offset, bytes).map_err(PowerCutError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S> NorFlash for PowerCut<S>
+where
+	S: NorFlash,
+{
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		if self.lost {
+			return Err(PowerCutError::PowerLoss);
+		}
+		if check_erase(self, from, to).is_err() {
+			return self.flash.erase(from, to).map_err(PowerCutError::Flash);
+		}
+		for page in (from..to).step_by(S::ERASE_SIZE) {
+			let end = page + S::ERASE_SIZE as u32;
+			match self.step() {
+				2 => self.flash.erase(page, end).map_err(PowerCutError::Flash)?,
+				1 => {
+					// Some of the bits programmed before the erase are still programmed.
+					let mut buf = vec![0; S::ERASE_SIZE];
+					self.flash
+						.read(page, &mut buf)
+						.map_err(PowerCutError::Flash)?;
+					self.flash.erase(page, end).map_err(PowerCutError::Flash)?;
+					buf.iter_mut().for_each(|byte| *byte |= UNTOUCHED_BITS);
+					self.flash.write(page, &buf).map_err(PowerCutError::Flash)?;
+					return Err(PowerCutError::PowerLoss);
+				}
+				_ => return Err(PowerCutError::PowerLoss),
+			}
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		if self.lost {
+			return Err(PowerCutError::PowerLoss);
+		}
+		if check_write(self, offset, bytes.len()).is_err() {
+			return self
+				.flash
+				.write(offset, bytes)
+				.map_err(PowerCutError::Flash);
+		}
+		for (index, word) in bytes.chunks(S::WRITE_SIZE).enumerate() {
+			let address = offset + (index * S::WRITE_SIZE) as u32;
+			match self.step() {
+				2 => self
+					.flash
+					.write(address, word)
+					.map_err(PowerCutError::Flash)?,
+				1 => {
+					// Only some of the bits of the word have been programmed.
+					let partial: Vec<u8> = word.iter().map(|byte| *byte | UNTOUCHED_BITS).collect();
+					self.flash
+						.write(address, &partial)
+						.map_err(PowerCutError::Flash)?;
+					return Err(PowerCutError::PowerLoss);
+				}
+				_ => return Err(PowerCutError::PowerLoss),
+			}
+		}
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		if self.lost {
+			return Err(PowerCutError::PowerLoss);
+		}
+		self.flash.flush().map_err(PowerCutError::Flash)
+	}
+}
+
+/// Run `operations` on copies of `initial`, cutting power at every possible point.
+///
+/// `operations` is first run without interruption to count its cut points, then once for every
+/// cut point, each time on a fresh copy of `initial`. After every run the resulting flash is
+/// handed to `check` along with the index of the cut point, or `None` for the uninterrupted run,
+/// which must recover the stored data and verify the invariants of the storage layer.
+///
+/// `operations` must behave deterministically, and stop at the first `PowerLoss` error.
+pub fn check_power_loss<S, O, C>(initial: &S, mut operations: O, mut check: C)
+where
+	S: NorFlash + Clone,
+	O: FnMut(&mut PowerCut<S>),
+	C: FnMut(S, Option<usize>),
+{
+	let mut flash = PowerCut::unlimited(initial.clone());
+	operations(&mut flash);
+	let cut_points = flash.cut_points();
+	check(flash.into_inner(), None);
+
+	for cut in 0..cut_points {
+		let mut flash = PowerCut::new(initial.clone(), cut);
+		operations(&mut flash);
+		check(flash.into_inner(), Some(cut));
+	}
+}
@@ -0,0 +1,302 @@
+#[cfg(feature = "std")]
+use std::vec::Vec;
+
+use core::ops::Range;
+
+#[cfg(feature = "std")]
+use crate::nor_flash::{check_erase, write_chunked};
+use crate::nor_flash::{CheckError, NorFlash, OutOfBoundsInfo};
+#[cfg(feature = "std")]
+use crate::tlv::{encode, record_len};
+use crate::tlv::{flash_records, FlashRecord, FlashRecords, TlvError};
+
+/// Types of the records commonly found in factory data.
+///
+/// Types from `8000h` up are left for the application.
+pub mod tag {
+	/// Serial number of the device, as text.
+	pub const SERIAL: u16 = 0x0001;
+	/// Revision of the hardware, as text.
+	pub const HARDWARE_REVISION: u16 = 0x0002;
+	/// Date of manufacturing, as seconds since the Unix epoch in a little endian `u64`.
+	pub const MANUFACTURED: u16 = 0x0003;
+	/// MAC address of the network interface.
+	pub const MAC_ADDRESS: u16 = 0x0004;
+	/// Key unique to the device.
+	pub const DEVICE_KEY: u16 = 0x0010;
+	/// Certificate of the device, e.g. in DER.
+	pub const CERTIFICATE: u16 = 0x0011;
+	/// Calibration data of the device.
+	pub const CALIBRATION: u16 = 0x0020;
+}
+
+/// Reader of a factory data partition, written once during manufacturing.
+///
+/// The partition holds records in the [`tlv`](crate::tlv) format, such as serial numbers, keys
+/// and calibration data, looked up by their type. Every record is checked when opening the
+/// partition, and the reader never writes to it. With the `std` feature, the partition is
+/// generated by a `ProvisioningWriter` on the manufacturing side, so both share the same format.
+pub struct Provisioning<S> {
+	flash: S,
+	range: Range<u32>,
+	count: usize,
+}
+
+impl<S: NorFlash> Provisioning<S> {
+	/// Open the partition in `range` of `flash`, checking the CRC of every record.
+	///
+	/// # Errors
+	///
+	/// Returns [`TlvError::Invalid`] in the cases of [`flash_records`], and
+	/// [`TlvError::Corrupt`] if a record is corrupted.
+	pub fn open(mut flash: S, range: Range<u32>) -> Result<Self, TlvError<S::Error>> {
+		let mut count = 0;
+		for record in flash_records(&mut flash, range.clone()).map_err(TlvError::Invalid)? {
+			record?;
+			count += 1;
+		}
+		Ok(Self {
+			flash,
+			range,
+			count,
+		})
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Number of records in the partition.
+	pub fn len(&self) -> usize {
+		self.count
+	}
+
+	/// Whether the partition holds no records, e.g. because the device was not provisioned.
+	pub fn is_empty(&self) -> bool {
+		self.count == 0
+	}
+
+	/// Iterate over the records of the partition.
+	pub fn records(&mut self) -> FlashRecords<'_, S> {
+		FlashRecords::new(&mut self.flash, self.range.clone())
+	}
+
+	/// The first record of type `tag`, if any.
+	pub fn find(&mut self, tag: u16) -> Result<Option<FlashRecord>, TlvError<S::Error>> {
+		for record in self.records() {
+			let record = record?;
+			if record.tag == tag {
+				return Ok(Some(record));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Read the value of the first record of type `tag` into `bytes`, returning its length.
+	///
+	/// Returns `None` if the partition holds no such record.
+	pub fn read(
+		&mut self,
+		tag: u16,
+		bytes: &mut [u8],
+	) -> Result<Option<usize>, TlvError<S::Error>> {
+		let mut records = self.records();
+		while let Some(record) = records.next() {
+			let record = record?;
+			if record.tag != tag {
+				continue;
+			}
+			if record.length > bytes.len() {
+				return Err(TlvError::Invalid(CheckError::OutOfBounds(
+					OutOfBoundsInfo {
+						offset: 0,
+						length: record.length,
+						capacity: bytes.len(),
+					},
+				)));
+			}
+			records.read_value(&record, &mut bytes[..record.length])?;
+			return Ok(Some(record.length));
+		}
+		Ok(None)
+	}
+}
+
+/// Generator of factory data partitions, for manufacturing tooling.
+///
+/// Records are encoded as read by [`Provisioning`], padded to the alignment of the target flash,
+/// and the resulting image is either programmed directly or saved to be flashed by another tool.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct ProvisioningWriter {
+	align: usize,
+	bytes: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl ProvisioningWriter {
+	/// Generate a partition padding records to `align`, the larger of the read and write sizes of
+	/// the target flash.
+	pub fn new(align: usize) -> Self {
+		Self {
+			align,
+			bytes: Vec::new(),
+		}
+	}
+
+	/// Generate a partition for the flash `S`.
+	pub fn for_flash<S: NorFlash>() -> Self {
+		Self::new(S::READ_SIZE.max(S::WRITE_SIZE))
+	}
+
+	/// Add a record of type `tag` holding `value`.
+	///
+	/// **NOTE** This will panic in the cases of [`encode`].
+	pub fn add(&mut self, tag: u16, value: &[u8]) -> &mut Self {
+		let start = self.bytes.len();
+		self.bytes
+			.resize(start + record_len(value.len(), self.align), 0);
+		encode(tag, value, self.align, &mut self.bytes[start..])
+			.expect("Record type reserved or value too long");
+		self
+	}
+
+	/// The image of the partition.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.bytes
+	}
+
+	/// Save the image of the partition to the file at `path`.
+	pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+		std::fs::write(path, self.as_bytes())
+	}
+
+	/// Erase `range` of `flash` and program the partition into it.
+	///
+	/// # Errors
+	///
+	/// Returns [`TlvError::Invalid`] with [`CheckError::NotAligned`] if `range` is not aligned to
+	/// `ERASE_SIZE` or if the alignment of the writer is not the one of `S`, and with
+	/// [`CheckError::OutOfBounds`] if `range` is out of bounds or too small for the partition.
+	pub fn program<S: NorFlash>(
+		&self,
+		flash: &mut S,
+		range: Range<u32>,
+	) -> Result<(), TlvError<S::Error>> {
+		check_erase(flash, range.start, range.end).map_err(TlvError::Invalid)?;
+		if self.align != S::READ_SIZE.max(S::WRITE_SIZE) {
+			return Err(TlvError::Invalid(CheckError::NotAligned));
+		}
+		let capacity = (range.end - range.start) as usize;
+		if self.bytes.len() > capacity {
+			return Err(TlvError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: self.bytes.len(),
+					capacity,
+				},
+			)));
+		}
+		flash
+			.erase(range.start, range.end)
+			.map_err(TlvError::Flash)?;
+		write_chunked(flash, range.start, &self.bytes).map_err(TlvError::Flash)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+	use crate::tlv::write_record;
+
+	type Flash = MockFlash<4096, 1, 4, 1024>;
+
+	#[test]
+	fn open_records() {
+		let mut flash = Flash::new();
+		let provisioning = Provisioning::open(&mut flash, 1024..2048).unwrap();
+		assert!(provisioning.is_empty());
+
+		let offset = write_record(&mut flash, 1024, tag::SERIAL, b"SN-0042").unwrap();
+		let offset =
+			write_record(&mut flash, offset, tag::MAC_ADDRESS, &[2, 0, 0, 1, 2, 3]).unwrap();
+		write_record(&mut flash, offset, tag::SERIAL, b"SN-0043").unwrap();
+		let mut provisioning = Provisioning::open(&mut flash, 1024..2048).unwrap();
+		assert_eq!(provisioning.len(), 3);
+		assert_eq!(provisioning.records().count(), 3);
+
+		let mut bytes = [0; 16];
+		assert_eq!(provisioning.read(tag::SERIAL, &mut bytes), Ok(Some(7)));
+		assert_eq!(&bytes[..7], b"SN-0042");
+		assert_eq!(provisioning.read(tag::MAC_ADDRESS, &mut bytes), Ok(Some(6)));
+		assert_eq!(&bytes[..6], &[2, 0, 0, 1, 2, 3]);
+		assert_eq!(provisioning.read(tag::DEVICE_KEY, &mut bytes), Ok(None));
+		assert!(matches!(
+			provisioning.read(tag::SERIAL, &mut bytes[..6]),
+			Err(TlvError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		let record = provisioning.find(tag::MAC_ADDRESS).unwrap().unwrap();
+		assert_eq!((record.offset, record.length), (1024 + 16 + 4, 6));
+		assert_eq!(provisioning.find(tag::CALIBRATION), Ok(None));
+	}
+
+	#[test]
+	fn open_invalid() {
+		let mut bytes = [0xff; 4096];
+		bytes[1024..1028].copy_from_slice(&[1, 0, 4, 0]);
+		let mut flash = Flash::from_bytes(bytes);
+		assert!(matches!(
+			Provisioning::open(&mut flash, 1024..2048),
+			Err(TlvError::Corrupt)
+		));
+		assert!(matches!(
+			Provisioning::open(&mut flash, 1026..2048),
+			Err(TlvError::Invalid(CheckError::NotAligned))
+		));
+		assert!(Provisioning::open(&mut flash, 0..1024).is_ok());
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn writer_roundtrip() {
+		let mut writer = ProvisioningWriter::for_flash::<Flash>();
+		writer
+			.add(tag::SERIAL, b"SN-0042")
+			.add(tag::MANUFACTURED, &1_700_000_000u64.to_le_bytes())
+			.add(tag::CALIBRATION, &[0x5a; 100]);
+		assert_eq!(writer.as_bytes().len(), 16 + 16 + 108);
+
+		let mut flash = Flash::from_bytes([0x55; 4096]);
+		writer.program(&mut flash, 1024..2048).unwrap();
+		let mut provisioning = Provisioning::open(&mut flash, 1024..2048).unwrap();
+		assert_eq!(provisioning.len(), 3);
+		let mut bytes = [0; 100];
+		assert_eq!(
+			provisioning.read(tag::MANUFACTURED, &mut bytes),
+			Ok(Some(8))
+		);
+		assert_eq!(&bytes[..8], &1_700_000_000u64.to_le_bytes());
+		assert_eq!(
+			provisioning.read(tag::CALIBRATION, &mut bytes),
+			Ok(Some(100))
+		);
+		assert_eq!(bytes, [0x5a; 100]);
+
+		assert_eq!(
+			writer.program(&mut flash, 1024..1536),
+			Err(TlvError::Invalid(CheckError::NotAligned))
+		);
+		assert_eq!(
+			ProvisioningWriter::new(8).program(&mut flash, 1024..2048),
+			Err(TlvError::Invalid(CheckError::NotAligned))
+		);
+		let mut small = MockFlash::<4096, 1, 4, 64>::new();
+		writer.add(tag::CERTIFICATE, &[0; 200]);
+		assert!(matches!(
+			writer.program(&mut small, 0..64),
+			Err(TlvError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+}
@@ -0,0 +1,494 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use crate::crc::{Checksum, Crc32};
+use crate::gc::{GcPolicy, GcStatus};
+use crate::nor_flash::{
+	check_range, check_slice, erased_byte, fill_erased, is_erased, write_chunked, CheckError,
+	NorFlash, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffer used to read and write headers and unaligned tails.
+const BUFFER: usize = 32;
+
+/// Size of the header of a page (magic word, sequence number and its complement) and of a record
+/// (length, its complement and CRC).
+const HEADER: usize = 12;
+
+const MAGIC: u32 = 0x5545_5551;
+
+/// Errors returned by [`FlashQueue`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum QueueError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The range is not aligned to `ERASE_SIZE` or too small, the record does not fit in a page,
+	/// or the buffer cannot hold the next record.
+	Invalid(CheckError),
+	/// Every page holds records which are not acknowledged yet.
+	Full,
+}
+
+impl<E: NorFlashError> NorFlashError for QueueError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Full => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Header of a record.
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+	length: u32,
+	crc: u32,
+	acked: bool,
+}
+
+/// Persistent FIFO queue of variable length records, e.g. for telemetry waiting to be uploaded.
+///
+/// Records are appended with [`push`](Self::push) and read in order with [`peek`](Self::peek).
+/// A record is only consumed once the consumer acknowledges it with
+/// [`pop_ack`](Self::pop_ack), e.g. after the server confirmed the upload, so records survive
+/// resets until they have been handled. The erase pages of the queue are used as a ring, a page
+/// being reusable once all its records are acknowledged, and [`push`](Self::push) fails when all
+/// pages hold pending records rather than dropping them.
+///
+/// Reusable pages are erased when a new page is opened by [`push`](Self::push), or ahead of time
+/// by [`gc_step`](Self::gc_step), e.g. during idle time.
+///
+/// Every record is protected by a checksum in its header, computed by `C`, so a record cut by a
+/// power loss is skipped.
+pub struct FlashQueue<S, C = Crc32> {
+	flash: S,
+	range: Range<u32>,
+	/// Page being written, along with its sequence number.
+	head: u32,
+	sequence: u32,
+	/// Offset of the next record in the page being written, zero if it is not opened yet.
+	write: u32,
+	/// Page holding the oldest pending record, and offset of that record.
+	tail: u32,
+	read: u32,
+	/// Number of reusable pages after the head which are erased already.
+	erased: u32,
+	reads: usize,
+	mount_reads: usize,
+	checksum: PhantomData<C>,
+}
+
+impl<S: NorFlash> FlashQueue<S> {
+	/// Mount the queue in `range` of `flash`, finding the pending records.
+	///
+	/// # Errors
+	///
+	/// Returns [`QueueError::Invalid`] if `range` is not aligned to `ERASE_SIZE`, is out of bounds
+	/// or holds fewer than two pages, or if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes.
+	pub fn mount(flash: S, range: Range<u32>) -> Result<Self, QueueError<S::Error>> {
+		Self::mount_with_checksum(flash, range)
+	}
+}
+
+impl<S: NorFlash, C: Checksum> FlashQueue<S, C> {
+	/// Mount the queue with records protected by the checksum `C` instead of [`Crc32`].
+	///
+	/// # Errors
+	///
+	/// Returns [`QueueError::Invalid`] in the cases of [`mount`](FlashQueue::mount).
+	pub fn mount_with_checksum(flash: S, range: Range<u32>) -> Result<Self, QueueError<S::Error>> {
+		check_range(flash.capacity(), S::ERASE_SIZE, range.start, range.end)
+			.and_then(|_| check_slice((range.end - range.start) as usize, 1, 0, 2 * S::ERASE_SIZE))
+			.and_then(|_| check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE)))
+			.map_err(QueueError::Invalid)?;
+		let mut queue = Self {
+			flash,
+			range,
+			head: 0,
+			sequence: 0,
+			write: 0,
+			tail: 0,
+			read: 0,
+			erased: 0,
+			reads: 0,
+			mount_reads: 0,
+			checksum: PhantomData,
+		};
+
+		// Pages are opened in ring order with consecutive sequence numbers.
+		let pages = queue.pages();
+		let mut head = None;
+		for index in 0..pages {
+			if let Some(sequence) = queue.page_sequence(index)? {
+				let next = queue.page_sequence((index + 1) % pages)?;
+				if next != Some(sequence.wrapping_add(1)) {
+					head = Some((index, sequence));
+				}
+			}
+		}
+		let (head, sequence) = match head {
+			Some(head) => head,
+			None => {
+				queue.mount_reads = queue.reads;
+				return Ok(queue);
+			}
+		};
+		queue.head = head;
+		queue.sequence = sequence;
+		queue.tail = head;
+		for offset in 1..pages {
+			let index = (head + pages - offset) % pages;
+			match queue.page_sequence(index)? {
+				Some(previous) if previous == sequence.wrapping_sub(offset) => queue.tail = index,
+				_ => break,
+			}
+		}
+
+		let mut write = Self::header_size() as u32;
+		while let Some(entry) = queue.entry(head, write)? {
+			write += Self::footprint(entry.length);
+		}
+		// Nothing can be written after a header cut by a power loss.
+		if write + Self::footprint(0) <= S::ERASE_SIZE as u32 {
+			let address = queue.page_address(head) + write;
+			let mut header = [0; BUFFER];
+			let header = &mut header[..Self::header_size()];
+			queue
+				.read_flash(address, header)
+				.map_err(QueueError::Flash)?;
+			if !is_erased::<S>(address, header) {
+				write = S::ERASE_SIZE as u32;
+			}
+		}
+		queue.write = write;
+		queue.read = Self::header_size() as u32;
+		queue.skip_consumed()?;
+		queue.mount_reads = queue.reads;
+		Ok(queue)
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Number of reads of the flash performed by the mount.
+	///
+	/// This is bounded by the geometry of the queue and the number of acknowledged records it holds, which gives the worst case mount time.
+	pub fn mount_reads(&self) -> usize {
+		self.mount_reads
+	}
+
+	/// The largest record which can be pushed.
+	pub fn max_length(&self) -> usize {
+		S::ERASE_SIZE - Self::header_size() - Self::footprint(0) as usize
+	}
+
+	/// Whether no record is pending.
+	pub fn is_empty(&self) -> bool {
+		self.tail == self.head && self.read >= self.write
+	}
+
+	/// Append `bytes` as a new record.
+	pub fn push(&mut self, bytes: &[u8]) -> Result<(), QueueError<S::Error>> {
+		if bytes.len() > self.max_length() {
+			return Err(QueueError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: bytes.len(),
+					capacity: self.max_length(),
+				},
+			)));
+		}
+		let footprint = Self::footprint(bytes.len() as u32);
+		if self.write == 0 || self.write + footprint > S::ERASE_SIZE as u32 {
+			self.open_page()?;
+		}
+
+		let address = self.page_address(self.head) + self.write;
+		let mut header = [0; BUFFER];
+		fill_erased::<S>(address, &mut header[..Self::header_size()]);
+		header[..4].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+		header[4..8].copy_from_slice(&(!(bytes.len() as u32)).to_le_bytes());
+		header[8..12].copy_from_slice(&C::checksum(bytes).to_le_bytes());
+		// Consume the space before writing, so that it is not reused after a failure.
+		self.write += footprint;
+		self.flash
+			.write(address, &header[..Self::header_size()])
+			.map_err(QueueError::Flash)?;
+
+		let data = address + (Self::header_size() + Self::align()) as u32;
+		let aligned = bytes.len() - bytes.len() % S::WRITE_SIZE;
+		write_chunked(&mut self.flash, data, &bytes[..aligned]).map_err(QueueError::Flash)?;
+		if aligned < bytes.len() {
+			let mut tail = [0; BUFFER];
+			fill_erased::<S>(data + aligned as u32, &mut tail[..S::WRITE_SIZE]);
+			tail[..bytes.len() - aligned].copy_from_slice(&bytes[aligned..]);
+			self.flash
+				.write(data + aligned as u32, &tail[..S::WRITE_SIZE])
+				.map_err(QueueError::Flash)?;
+		}
+		Ok(())
+	}
+
+	/// Read the oldest pending record into `buffer`, returns its length or `None` if the queue is
+	/// empty.
+	///
+	/// Corrupted records are acknowledged and skipped.
+	pub fn peek(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, QueueError<S::Error>> {
+		loop {
+			self.skip_consumed()?;
+			let entry = match self.entry(self.tail, self.read)? {
+				Some(entry) => entry,
+				None => return Ok(None),
+			};
+			let length = entry.length as usize;
+			let read = (length + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+			if read > buffer.len() {
+				return Err(QueueError::Invalid(CheckError::OutOfBounds(
+					OutOfBoundsInfo {
+						offset: 0,
+						length: read,
+						capacity: buffer.len(),
+					},
+				)));
+			}
+			let data = self.page_address(self.tail)
+				+ self.read + (Self::header_size() + Self::align()) as u32;
+			self.read_flash(data, &mut buffer[..read])
+				.map_err(QueueError::Flash)?;
+			if C::checksum(&buffer[..length]) == entry.crc {
+				return Ok(Some(length));
+			}
+			self.ack(entry)?;
+		}
+	}
+
+	/// Acknowledge the oldest pending record, as returned by [`peek`](Self::peek), making its
+	/// page reusable once all its records are acknowledged.
+	///
+	/// Returns `false` if the queue is empty.
+	pub fn pop_ack(&mut self) -> Result<bool, QueueError<S::Error>> {
+		self.skip_consumed()?;
+		let entry = match self.entry(self.tail, self.read)? {
+			Some(entry) => entry,
+			None => return Ok(false),
+		};
+		self.ack(entry)?;
+		self.skip_consumed()?;
+		Ok(true)
+	}
+
+	/// The space left before a page has to be erased.
+	pub fn gc_status(&self) -> GcStatus {
+		let page = S::ERASE_SIZE - Self::header_size();
+		let left = match self.write {
+			0 => 0,
+			write => S::ERASE_SIZE - write as usize,
+		};
+		GcStatus {
+			free: left + self.erased as usize * page,
+			discards: false,
+		}
+	}
+
+	/// Erase the next reusable page ahead of time.
+	///
+	/// Returns `false` if every reusable page is erased already.
+	pub fn gc_step(&mut self) -> Result<bool, QueueError<S::Error>> {
+		if self.erased >= self.reusable() {
+			return Ok(false);
+		}
+		let page = (self.next_page() + self.erased) % self.pages();
+		let address = self.page_address(page);
+		self.flash
+			.erase(address, address + S::ERASE_SIZE as u32)
+			.map_err(QueueError::Flash)?;
+		self.erased += 1;
+		Ok(true)
+	}
+
+	/// Run [`gc_step`](Self::gc_step) if `policy` agrees, returns whether a page was erased.
+	pub fn gc<P: GcPolicy>(&mut self, mut policy: P) -> Result<bool, QueueError<S::Error>> {
+		if !policy.should_collect(&self.gc_status()) {
+			return Ok(false);
+		}
+		self.gc_step()
+	}
+
+	/// Mark the record at the read position as acknowledged, and move past it.
+	fn ack(&mut self, entry: Entry) -> Result<(), QueueError<S::Error>> {
+		let address = self.page_address(self.tail) + self.read + Self::header_size() as u32;
+		let mut ack = [0; BUFFER];
+		for (address, byte) in (address..).zip(&mut ack[..Self::align()]) {
+			*byte = !erased_byte::<S>(address);
+		}
+		self.flash
+			.write(address, &ack[..Self::align()])
+			.map_err(QueueError::Flash)?;
+		self.read += Self::footprint(entry.length);
+		Ok(())
+	}
+
+	fn align() -> usize {
+		S::READ_SIZE.max(S::WRITE_SIZE)
+	}
+
+	fn header_size() -> usize {
+		(HEADER + Self::align() - 1) / Self::align() * Self::align()
+	}
+
+	/// Space taken by a record: header, acknowledgement word and data.
+	fn footprint(length: u32) -> u32 {
+		let align = Self::align() as u32;
+		Self::header_size() as u32 + align + (length + align - 1) / align * align
+	}
+
+	fn pages(&self) -> u32 {
+		(self.range.end - self.range.start) / S::ERASE_SIZE as u32
+	}
+
+	fn page_address(&self, page: u32) -> u32 {
+		self.range.start + page * S::ERASE_SIZE as u32
+	}
+
+	/// Sequence number of a page, `None` if the page is not opened.
+	fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, QueueError<S::Error>> {
+		let (magic, sequence, check) = self.read_header(self.page_address(page))?;
+		Ok(Some(sequence).filter(|_| magic == MAGIC && check == !sequence))
+	}
+
+	/// Page to open once the current one is full.
+	fn next_page(&self) -> u32 {
+		match self.write {
+			0 => self.head,
+			_ => (self.head + 1) % self.pages(),
+		}
+	}
+
+	/// Number of pages which can be opened before reaching the tail.
+	fn reusable(&self) -> u32 {
+		match self.write {
+			0 => self.pages(),
+			_ => (self.tail + self.pages() - self.head - 1) % self.pages(),
+		}
+	}
+
+	/// Erase the page after the head, unless done ahead of time, and open it for writing.
+	fn open_page(&mut self) -> Result<(), QueueError<S::Error>> {
+		if self.reusable() == 0 {
+			return Err(QueueError::Full);
+		}
+		let page = self.next_page();
+		let sequence = match self.write {
+			0 => self.sequence,
+			_ => self.sequence.wrapping_add(1),
+		};
+		let address = self.page_address(page);
+		if self.erased > 0 {
+			self.erased -= 1;
+		} else {
+			self.flash
+				.erase(address, address + S::ERASE_SIZE as u32)
+				.map_err(QueueError::Flash)?;
+		}
+		let mut header = [0; BUFFER];
+		fill_erased::<S>(address, &mut header[..Self::header_size()]);
+		header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+		header[4..8].copy_from_slice(&sequence.to_le_bytes());
+		header[8..12].copy_from_slice(&(!sequence).to_le_bytes());
+		self.flash
+			.write(address, &header[..Self::header_size()])
+			.map_err(QueueError::Flash)?;
+		if self.write == 0 {
+			self.read = Self::header_size() as u32;
+		}
+		self.head = page;
+		self.sequence = sequence;
+		self.write = Self::header_size() as u32;
+		Ok(())
+	}
+
+	/// Move the read position past acknowledged records, and the tail past the pages left without
+	/// pending records.
+	fn skip_consumed(&mut self) -> Result<(), QueueError<S::Error>> {
+		loop {
+			match self.entry(self.tail, self.read)? {
+				Some(entry) if entry.acked => self.read += Self::footprint(entry.length),
+				Some(_) => return Ok(()),
+				None if self.tail == self.head => return Ok(()),
+				None => {
+					self.tail = (self.tail + 1) % self.pages();
+					self.read = Self::header_size() as u32;
+				}
+			}
+		}
+	}
+
+	/// Read from the flash, counting the reads.
+	fn read_flash(&mut self, address: u32, bytes: &mut [u8]) -> Result<(), S::Error> {
+		self.reads += 1;
+		self.flash.read(address, bytes)
+	}
+
+	fn read_header(&mut self, address: u32) -> Result<(u32, u32, u32), QueueError<S::Error>> {
+		let mut buffer = [0; BUFFER];
+		self.read_flash(address, &mut buffer[..Self::header_size()])
+			.map_err(QueueError::Flash)?;
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&buffer[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		Ok((word(0), word(1), word(2)))
+	}
+
+	/// The record at `offset` of `page`, `None` past the last record of the page or at a header
+	/// cut by a power loss.
+	fn entry(&mut self, page: u32, offset: u32) -> Result<Option<Entry>, QueueError<S::Error>> {
+		if page == self.head && offset >= self.write && self.write != 0 {
+			return Ok(None);
+		}
+		if offset + Self::footprint(0) > S::ERASE_SIZE as u32 {
+			return Ok(None);
+		}
+		let address = self.page_address(page) + offset;
+		let (length, check, crc) = self.read_header(address)?;
+		if check != !length
+			|| length as usize > self.max_length()
+			|| offset + Self::footprint(length) > S::ERASE_SIZE as u32
+		{
+			return Ok(None);
+		}
+		let address = address + Self::header_size() as u32;
+		let mut ack = [0; BUFFER];
+		self.read_flash(address, &mut ack[..Self::align()])
+			.map_err(QueueError::Flash)?;
+		Ok(Some(Entry {
+			length,
+			crc,
+			acked: !is_erased::<S>(address, &ack[..Self::align()]),
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	type Flash = MockFlash<16384, 1, 4, 1024>;
+
+	#[test]
+	fn mount_invalid() {
+		let invalid = |range: Range<u32>| match FlashQueue::mount(Flash::new(), range) {
+			Err(QueueError::Invalid(error)) => error,
+			_ => panic!("Range accepted"),
+		};
+		assert_eq!(invalid(512..4096), CheckError::NotAligned);
+		assert!(matches!(invalid(0..1024), CheckError::OutOfBounds(_)));
+		assert!(matches!(invalid(0..32768), CheckError::OutOfBounds(_)));
+		assert!(FlashQueue::mount(Flash::new(), 0..4096).is_ok());
+	}
+}
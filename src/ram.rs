@@ -0,0 +1,64 @@
+use crate::nor_flash::NorFlashErrorKind;
+use crate::{ReadStorage, Storage};
+
+/// Volatile storage of `N` bytes kept in an internal array.
+///
+/// Writes simply overwrite the array, there are no erase semantics. This allows running code
+/// written for a [`Storage`] with no persistence, e.g. on boards without a storage peripheral or
+/// in tests.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RamStorage<const N: usize> {
+	data: [u8; N],
+}
+
+impl<const N: usize> RamStorage<N> {
+	/// Create a storage filled with zeroes.
+	pub const fn new() -> Self {
+		Self { data: [0; N] }
+	}
+
+	/// Create a storage holding `data`.
+	pub const fn from_bytes(data: [u8; N]) -> Self {
+		Self { data }
+	}
+
+	/// The current contents of the storage.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.data
+	}
+
+	fn range(offset: u32, length: usize) -> Result<core::ops::Range<usize>, NorFlashErrorKind> {
+		if length > N || offset as usize > N - length {
+			return Err(NorFlashErrorKind::OutOfBounds);
+		}
+		Ok(offset as usize..offset as usize + length)
+	}
+}
+
+impl<const N: usize> Default for RamStorage<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> ReadStorage for RamStorage<N> {
+	type Error = NorFlashErrorKind;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		let range = Self::range(offset, bytes.len())?;
+		bytes.copy_from_slice(&self.data[range]);
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		N
+	}
+}
+
+impl<const N: usize> Storage for RamStorage<N> {
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		let range = Self::range(offset, bytes.len())?;
+		self.data[range].copy_from_slice(bytes);
+		Ok(())
+	}
+}
@@ -0,0 +1,480 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::vec;
+use std::vec::Vec;
+
+use crate::channel::{
+	ERASE, FLUSH, INFO, NOT_ALIGNED, OK, OTHER, OUT_OF_BOUNDS, READ, UNKNOWN, WRITE,
+};
+use crate::nor_flash::{
+	check_erase, check_read, check_write, erased_byte, fill_erased, program_byte, CheckError,
+	ErrorType, MountableStorage, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind,
+	ReadNorFlash,
+};
+
+/// Largest payload accepted in a frame.
+const MAX_PAYLOAD: usize = 1 << 20;
+
+/// Send a frame: a tag byte, the length of the payload as a little endian `u32`, and the payload.
+fn send<T: Write>(stream: &mut T, tag: u8, payload: &[u8]) -> io::Result<()> {
+	let mut frame = Vec::with_capacity(5 + payload.len());
+	frame.push(tag);
+	frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+	frame.extend_from_slice(payload);
+	stream.write_all(&frame)?;
+	stream.flush()
+}
+
+/// Receive a frame, `None` if the stream ended before it.
+///
+/// Fails with [`io::ErrorKind::InvalidData`] if the payload is over `MAX_PAYLOAD` bytes, after
+/// discarding it.
+fn receive<T: Read>(stream: &mut T) -> io::Result<Option<(u8, Vec<u8>)>> {
+	let mut header = [0; 5];
+	match stream.read_exact(&mut header) {
+		Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		result => result?,
+	}
+	let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+	if length > MAX_PAYLOAD {
+		discard(stream, length)?;
+		return Err(io::ErrorKind::InvalidData.into());
+	}
+	let mut payload = vec![0; length];
+	stream.read_exact(&mut payload)?;
+	Ok(Some((header[0], payload)))
+}
+
+/// Read and drop `length` bytes, keeping the stream in sync after a rejected frame.
+fn discard<T: Read>(stream: &mut T, length: usize) -> io::Result<()> {
+	let copied = io::copy(&mut stream.take(length as u64), &mut io::sink())?;
+	if copied != length as u64 {
+		return Err(io::ErrorKind::UnexpectedEof.into());
+	}
+	Ok(())
+}
+
+fn word(payload: &[u8], index: usize) -> Option<u32> {
+	let bytes = payload.get(4 * index..4 * index + 4)?;
+	Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn status(kind: NorFlashErrorKind) -> u8 {
+	match kind {
+		NorFlashErrorKind::NotAligned => NOT_ALIGNED,
+		NorFlashErrorKind::OutOfBounds => OUT_OF_BOUNDS,
+		_ => OTHER,
+	}
+}
+
+/// Serve the requests for `flash` received on `stream` until it is closed.
+///
+/// This runs next to the flash, e.g. on a host attached to the device by a probe or on the
+/// device itself behind a TCP or serial link. Every request is a frame made of a tag byte, the
+/// length of the payload as a little endian `u32`, and the payload:
+///
+/// - `01h` info, answered with the capacity, read, write and erase sizes as `u32` words,
+/// - `02h` read, with the offset and length, answered with the bytes,
+/// - `03h` write, with the offset followed by the bytes,
+/// - `04h` erase, with the start and end of the range,
+/// - `05h` flush, with no payload.
+///
+/// Responses are frames with a status tag: `00h` for success, `01h` not aligned, `02h` out of
+/// bounds, `03h` other flash errors, `04h` unknown or malformed request. Payloads are at most
+/// 1 MiB, larger requests are discarded and answered with `04h`.
+pub fn serve<S, T>(flash: &mut S, stream: &mut T) -> io::Result<()>
+where
+	S: NorFlash,
+	T: Read + Write,
+{
+	loop {
+		let (tag, payload) = match receive(stream) {
+			Ok(Some(frame)) => frame,
+			Ok(None) => return Ok(()),
+			Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+				send(stream, UNKNOWN, &[])?;
+				continue;
+			}
+			Err(error) => return Err(error),
+		};
+		let result = match (tag, word(&payload, 0), word(&payload, 1)) {
+			(INFO, _, _) => {
+				let mut info = Vec::new();
+				for value in &[flash.capacity(), S::READ_SIZE, S::WRITE_SIZE, S::ERASE_SIZE] {
+					info.extend_from_slice(&(*value as u32).to_le_bytes());
+				}
+				Ok(info)
+			}
+			(READ, Some(offset), Some(length)) if length as usize <= MAX_PAYLOAD => {
+				let mut bytes = vec![0; length as usize];
+				flash.read(offset, &mut bytes).map(|_| bytes)
+			}
+			(WRITE, Some(offset), _) => flash.write(offset, &payload[4..]).map(|_| Vec::new()),
+			(ERASE, Some(from), Some(to)) => flash.erase(from, to).map(|_| Vec::new()),
+			(FLUSH, _, _) => flash.flush().map(|_| Vec::new()),
+			_ => {
+				send(stream, UNKNOWN, &[])?;
+				continue;
+			}
+		};
+		match result {
+			Ok(response) => send(stream, OK, &response)?,
+			Err(error) => send(stream, status(error.kind()), &[])?,
+		}
+	}
+}
+
+/// Errors returned by [`RemoteFlash`].
+#[derive(Debug)]
+pub enum RemoteError {
+	/// The stream returned an error.
+	Io(io::Error),
+	/// The remote flash returned an error.
+	Flash(NorFlashErrorKind),
+	/// The server did not understand the request.
+	Protocol,
+}
+
+impl NorFlashError for RemoteError {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Io(error) => error.kind().into(),
+			Self::Flash(kind) => *kind,
+			Self::Protocol => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+impl From<io::Error> for RemoteError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+/// Flash served by [`serve`] at the other end of `stream`.
+///
+/// This lets hardware in the loop tests drive a real flash from the host through the same traits
+/// as the firmware. The geometry is given by the const parameters, and checked against the one
+/// of the remote flash when connecting. Reads and writes are split into requests of at most
+/// 1 MiB.
+pub struct RemoteFlash<T, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+{
+	stream: T,
+	capacity: usize,
+}
+
+impl<T, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+	RemoteFlash<T, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+where
+	T: Read + Write,
+{
+	/// Connect to the flash served on `stream`.
+	///
+	/// Fails with [`io::ErrorKind::InvalidData`] if the geometry of the remote flash differs.
+	pub fn connect(stream: T) -> io::Result<Self> {
+		let mut flash = Self {
+			stream,
+			capacity: 0,
+		};
+		let info = flash
+			.request(INFO, &[])
+			.map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+		let word = |index| word(&info, index).map(|word| word as usize);
+		match (word(0), word(1), word(2), word(3)) {
+			(Some(capacity), Some(read), Some(write), Some(erase))
+				if (read, write, erase) == (READ_SIZE, WRITE_SIZE, ERASE_SIZE) =>
+			{
+				flash.capacity = capacity;
+				Ok(flash)
+			}
+			_ => Err(io::ErrorKind::InvalidData.into()),
+		}
+	}
+
+	/// Release the stream.
+	pub fn into_inner(self) -> T {
+		self.stream
+	}
+
+	fn request(&mut self, tag: u8, payload: &[u8]) -> Result<Vec<u8>, RemoteError> {
+		send(&mut self.stream, tag, payload)?;
+		let (status, response) = receive(&mut self.stream)?
+			.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+		match status {
+			OK => Ok(response),
+			NOT_ALIGNED => Err(RemoteError::Flash(NorFlashErrorKind::NotAligned)),
+			OUT_OF_BOUNDS => Err(RemoteError::Flash(NorFlashErrorKind::OutOfBounds)),
+			OTHER => Err(RemoteError::Flash(NorFlashErrorKind::Other)),
+			_ => Err(RemoteError::Protocol),
+		}
+	}
+}
+
+impl<T, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType
+	for RemoteFlash<T, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	type Error = RemoteError;
+}
+
+impl<T, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+	for RemoteFlash<T, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+where
+	T: Read + Write,
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		let chunk = MAX_PAYLOAD - MAX_PAYLOAD % READ_SIZE;
+		let length = bytes.len();
+		let mut done = 0;
+		loop {
+			let part = &mut bytes[done..(done + chunk).min(length)];
+			let mut payload = [0; 8];
+			payload[..4].copy_from_slice(&(offset + done as u32).to_le_bytes());
+			payload[4..].copy_from_slice(&(part.len() as u32).to_le_bytes());
+			let response = self.request(READ, &payload)?;
+			if response.len() != part.len() {
+				return Err(RemoteError::Protocol);
+			}
+			part.copy_from_slice(&response);
+			done += part.len();
+			if done == length {
+				return Ok(());
+			}
+		}
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity
+	}
+}
+
+impl<T, const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+	for RemoteFlash<T, READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+where
+	T: Read + Write,
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		let mut payload = [0; 8];
+		payload[..4].copy_from_slice(&from.to_le_bytes());
+		payload[4..].copy_from_slice(&to.to_le_bytes());
+		self.request(ERASE, &payload).map(|_| ())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		// The offset takes the first word of the payload.
+		let chunk = (MAX_PAYLOAD - 4) - (MAX_PAYLOAD - 4) % WRITE_SIZE;
+		let mut done = 0;
+		loop {
+			let part = &bytes[done..(done + chunk).min(bytes.len())];
+			let mut payload = (offset + done as u32).to_le_bytes().to_vec();
+			payload.extend_from_slice(part);
+			self.request(WRITE, &payload)?;
+			done += part.len();
+			if done == bytes.len() {
+				return Ok(());
+			}
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.request(FLUSH, &[]).map(|_| ())
+	}
+}
+
+/// Errors returned by [`FileFlash`].
+#[derive(Debug)]
+pub enum FileError {
+	/// The file returned an error.
+	Io(io::Error),
+	/// The arguments are not properly aligned or out of bounds.
+	Invalid(CheckError),
+}
+
+impl NorFlashError for FileError {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Io(error) => error.kind().into(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+impl From<io::Error> for FileError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+/// Flash stored in a host file, for [`serve`] to expose to virtual flashes on the target.
+///
+/// Writes only clear bits, like on a real NOR flash, and the contents persist between runs, so
+/// firmware tested through a [`ChannelFlash`](crate::channel::ChannelFlash) finds its data again
+/// after a reset.
+pub struct FileFlash<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> {
+	file: File,
+	capacity: usize,
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+	FileFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	/// Open the file at `path` holding a flash of `capacity` bytes.
+	///
+	/// A missing or empty file is created fully erased. Fails with
+	/// [`std::io::ErrorKind::InvalidInput`] if `capacity` is not a multiple of `ERASE_SIZE`, and
+	/// with [`std::io::ErrorKind::InvalidData`] if the file has another length.
+	pub fn open<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<Self> {
+		if capacity % ERASE_SIZE != 0 {
+			return Err(io::ErrorKind::InvalidInput.into());
+		}
+		let file = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(path)?;
+		let mut flash = Self { file, capacity };
+		match flash.file.metadata()?.len() as usize {
+			0 => flash.fill(0, capacity)?,
+			length if length == capacity => {}
+			_ => return Err(io::ErrorKind::InvalidData.into()),
+		}
+		Ok(flash)
+	}
+
+	/// Release the file.
+	pub fn into_inner(self) -> File {
+		self.file
+	}
+
+	/// Fill `length` bytes from `offset` with the erased value.
+	fn fill(&mut self, offset: usize, length: usize) -> io::Result<()> {
+		let mut sector = [0; ERASE_SIZE];
+		fill_erased::<Self>(0, &mut sector);
+		self.file.seek(SeekFrom::Start(offset as u64))?;
+		for _ in 0..length / ERASE_SIZE {
+			self.file.write_all(&sector)?;
+		}
+		self.file.flush()
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType
+	for FileFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	type Error = FileError;
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+	for FileFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const READ_SIZE: usize = READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len()).map_err(FileError::Invalid)?;
+		self.file.seek(SeekFrom::Start(offset as u64))?;
+		self.file.read_exact(bytes)?;
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+	for FileFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+	const WRITE_SIZE: usize = WRITE_SIZE;
+	const ERASE_SIZE: usize = ERASE_SIZE;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to).map_err(FileError::Invalid)?;
+		self.fill(from as usize, (to - from) as usize)?;
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len()).map_err(FileError::Invalid)?;
+		let mut data = vec![0; bytes.len()];
+		self.file.seek(SeekFrom::Start(offset as u64))?;
+		self.file.read_exact(&mut data)?;
+		for ((address, old), new) in (offset..).zip(data.iter_mut()).zip(bytes) {
+			*old = program_byte(erased_byte::<Self>(address), *old, *new);
+		}
+		self.file.seek(SeekFrom::Start(offset as u64))?;
+		self.file.write_all(&data)?;
+		self.file.flush()?;
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.file.sync_data()?;
+		Ok(())
+	}
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> MountableStorage
+	for FileFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+}
+
+impl<const READ_SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> MultiwriteNorFlash
+	for FileFlash<READ_SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlashBox;
+	use std::os::unix::net::UnixStream;
+	use std::thread;
+
+	type Flash = MockFlashBox<1, 4, 4096>;
+
+	#[test]
+	fn large_requests() {
+		let (mut server, client) = UnixStream::pair().unwrap();
+		let handle = thread::spawn(move || {
+			let mut flash = Flash::new(3 << 20);
+			serve(&mut flash, &mut server).unwrap();
+		});
+
+		let mut flash = RemoteFlash::<_, 1, 4, 4096>::connect(client).unwrap();
+		assert!(!flash.info().multiwrite);
+		let data: Vec<u8> = (0..3 << 19).map(|index: u32| (index % 251) as u8).collect();
+		flash.write(4096, &data).unwrap();
+		let mut read = vec![0; data.len()];
+		flash.read(4096, &mut read).unwrap();
+		assert!(read == data);
+
+		// An oversized frame is discarded and rejected, and the server keeps serving.
+		let mut stream = flash.into_inner();
+		let mut frame = vec![WRITE];
+		frame.extend_from_slice(&(MAX_PAYLOAD as u32 + 1).to_le_bytes());
+		frame.resize(5 + MAX_PAYLOAD + 1, 0);
+		stream.write_all(&frame).unwrap();
+		assert_eq!(receive(&mut stream).unwrap(), Some((UNKNOWN, Vec::new())));
+		let mut flash = RemoteFlash::<_, 1, 4, 4096>::connect(stream).unwrap();
+		let mut byte = [0; 1];
+		flash.read(4096 + 1000, &mut byte).unwrap();
+		assert_eq!(byte[0], data[1000]);
+
+		drop(flash);
+		handle.join().unwrap();
+	}
+
+	#[test]
+	fn file_flash_capacity() {
+		let path = std::env::temp_dir().join(std::format!("file-flash-{}", std::process::id()));
+		let error = FileFlash::<1, 4, 1024>::open(&path, 1000).err().unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+		assert!(!path.exists());
+	}
+}
@@ -0,0 +1,453 @@
+use core::ops::Range;
+
+use crate::addr::align_up;
+use crate::crc::Crc32;
+use crate::nor_flash::{
+	check_erase, check_write, erased_byte, fill_erased, write_chunked, CheckError,
+	MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffer used to read and write the cursor.
+const BUFFER: usize = 32;
+
+/// Size of the header of a cursor: magic, image identifier and CRC.
+const HEADER: usize = 12;
+
+/// Magic number of a cursor.
+const MAGIC: u32 = 0x5253_4344;
+
+fn u32_at(bytes: &[u8], index: usize) -> u32 {
+	let mut word = [0; 4];
+	word.copy_from_slice(&bytes[4 * index..4 * index + 4]);
+	u32::from_le_bytes(word)
+}
+
+/// Erase of a range performed one `ERASE_SIZE` page per [`step`](Self::step).
+///
+/// The state only holds addresses, and can be saved with [`to_bytes`](Self::to_bytes) to resume
+/// the erase after a reset.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ResumableErase {
+	next: u32,
+	to: u32,
+}
+
+impl ResumableErase {
+	/// Prepare the erase of `from..to`, checking the arguments against `flash`.
+	pub fn new<S: NorFlash>(flash: &S, from: u32, to: u32) -> Result<Self, CheckError> {
+		check_erase(flash, from, to)?;
+		Ok(Self { next: from, to })
+	}
+
+	/// Erase the next page, returns whether the erase is complete.
+	///
+	/// The progress is only updated once the page is erased, so the step is repeated after an
+	/// error or a reset.
+	pub fn step<S: NorFlash>(&mut self, flash: &mut S) -> Result<bool, S::Error> {
+		if self.is_done() {
+			return Ok(true);
+		}
+		let end = self.next + S::ERASE_SIZE as u32;
+		flash.erase(self.next, end)?;
+		self.next = end;
+		Ok(self.is_done())
+	}
+
+	/// Whether the whole range has been erased.
+	pub fn is_done(&self) -> bool {
+		self.next >= self.to
+	}
+
+	/// Start of the range left to erase.
+	pub fn position(&self) -> u32 {
+		self.next
+	}
+
+	/// Serialize the state, e.g. to store it in a journal.
+	pub fn to_bytes(&self) -> [u8; 8] {
+		let mut bytes = [0; 8];
+		bytes[..4].copy_from_slice(&self.next.to_le_bytes());
+		bytes[4..].copy_from_slice(&self.to.to_le_bytes());
+		bytes
+	}
+
+	/// Restore a state saved with [`to_bytes`](Self::to_bytes).
+	///
+	/// Returns `None` if the state is inconsistent.
+	pub fn from_bytes(bytes: [u8; 8]) -> Option<Self> {
+		let (next, to) = (u32_at(&bytes, 0), u32_at(&bytes, 1));
+		if next > to {
+			return None;
+		}
+		Some(Self { next, to })
+	}
+}
+
+/// Write of a buffer performed at most one `ERASE_SIZE` page per [`step`](Self::step).
+///
+/// The state does not borrow the data, which must be passed again to every step, so it can be
+/// saved with [`to_bytes`](Self::to_bytes) to resume the write after a reset.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ResumableWrite {
+	offset: u32,
+	length: u32,
+	done: u32,
+}
+
+impl ResumableWrite {
+	/// Prepare the write of `length` bytes at `offset`, checking the arguments against `flash`.
+	pub fn new<S: NorFlash>(flash: &S, offset: u32, length: usize) -> Result<Self, CheckError> {
+		check_write(flash, offset, length)?;
+		Ok(Self {
+			offset,
+			length: length as u32,
+			done: 0,
+		})
+	}
+
+	/// Write the part of `bytes` up to the next page boundary, returns whether the write is
+	/// complete.
+	///
+	/// The progress is only updated once the part is written, so the step is repeated after an
+	/// error or a reset.
+	///
+	/// **NOTE** This will panic if `bytes` is not as long as the write was created with
+	pub fn step<S: NorFlash>(&mut self, flash: &mut S, bytes: &[u8]) -> Result<bool, S::Error> {
+		assert_eq!(bytes.len(), self.length as usize, "Wrong data length");
+		if self.is_done() {
+			return Ok(true);
+		}
+		let address = self.offset + self.done;
+		let page = S::ERASE_SIZE as u32;
+		let part = (page - address % page).min(self.length - self.done);
+		let start = self.done as usize;
+		write_chunked(flash, address, &bytes[start..start + part as usize])?;
+		self.done += part;
+		Ok(self.is_done())
+	}
+
+	/// Whether all the data has been written.
+	pub fn is_done(&self) -> bool {
+		self.done >= self.length
+	}
+
+	/// Number of bytes written so far.
+	pub fn written(&self) -> usize {
+		self.done as usize
+	}
+
+	/// Serialize the state, e.g. to store it in a journal.
+	pub fn to_bytes(&self) -> [u8; 12] {
+		let mut bytes = [0; 12];
+		bytes[..4].copy_from_slice(&self.offset.to_le_bytes());
+		bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+		bytes[8..].copy_from_slice(&self.done.to_le_bytes());
+		bytes
+	}
+
+	/// Restore a state saved with [`to_bytes`](Self::to_bytes).
+	///
+	/// Returns `None` if the state is inconsistent.
+	pub fn from_bytes(bytes: [u8; 12]) -> Option<Self> {
+		let (offset, length, done) = (u32_at(&bytes, 0), u32_at(&bytes, 1), u32_at(&bytes, 2));
+		if done > length {
+			return None;
+		}
+		Some(Self {
+			offset,
+			length,
+			done,
+		})
+	}
+}
+
+/// Errors returned by [`DownloadCursor`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CursorError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The position is beyond what the cursor can record.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for CursorError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Progress of an image download, persisted in a region of a flash.
+///
+/// The region holds the identifier of the image being downloaded, e.g. its version or checksum,
+/// followed by a bitmap with one bit per `granule` bytes of the image. Once the data of a granule
+/// is durably written, its bit is flipped from the erased value, writing the same words again as
+/// allowed by [`MultiwriteNorFlash`], so the region is only erased when a new download starts.
+/// After a reboot, the download resumes from [`position`](Self::position) without reading or
+/// hashing the data already written.
+///
+/// A power loss while updating the bitmap leaves either position, as the data is written before.
+pub struct DownloadCursor<S> {
+	flash: S,
+	range: Range<u32>,
+	granule: u32,
+	id: Option<u32>,
+	/// Number of granules recorded.
+	done: u32,
+}
+
+impl<S: MultiwriteNorFlash> DownloadCursor<S> {
+	/// Open the cursor stored in `range` of `flash`, recording the progress by `granule` bytes.
+	///
+	/// **NOTE** This will panic if `range` is not aligned to `ERASE_SIZE`, if `granule` is zero, or
+	/// if `READ_SIZE` or `WRITE_SIZE` is over 32 bytes.
+	pub fn open(flash: S, range: Range<u32>, granule: u32) -> Result<Self, CursorError<S::Error>> {
+		let erase = S::ERASE_SIZE as u32;
+		assert!(
+			range.start % erase == 0 && range.end % erase == 0,
+			"Range not aligned to ERASE_SIZE"
+		);
+		assert!(granule > 0, "Granule is zero");
+		assert!(
+			S::READ_SIZE.max(S::WRITE_SIZE) <= BUFFER,
+			"READ_SIZE or WRITE_SIZE too large"
+		);
+		let mut cursor = Self {
+			flash,
+			range,
+			granule,
+			id: None,
+			done: 0,
+		};
+		let mut buffer = [0; BUFFER];
+		let header = &mut buffer[..align_up(HEADER, S::READ_SIZE)];
+		cursor
+			.flash
+			.read(cursor.range.start, header)
+			.map_err(CursorError::Flash)?;
+		if u32_at(header, 0) != MAGIC || Crc32::checksum(&header[..8]) != u32_at(header, 2) {
+			return Ok(cursor);
+		}
+		cursor.id = Some(u32_at(header, 1));
+
+		let mut address = cursor.bitmap();
+		while address < cursor.range.end {
+			let part = (BUFFER as u32).min(cursor.range.end - address) as usize;
+			cursor
+				.flash
+				.read(address, &mut buffer[..part])
+				.map_err(CursorError::Flash)?;
+			for (address, byte) in (address..).zip(&buffer[..part]) {
+				let programmed = byte ^ erased_byte::<S>(address);
+				cursor.done += programmed.trailing_ones();
+				if programmed != 0xff {
+					return Ok(cursor);
+				}
+			}
+			address += part as u32;
+		}
+		Ok(cursor)
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Identifier of the image being downloaded, if any.
+	pub fn id(&self) -> Option<u32> {
+		self.id
+	}
+
+	/// Number of bytes of the image durably written.
+	pub fn position(&self) -> u32 {
+		self.done * self.granule
+	}
+
+	/// The position to resume the download of the image `id` from, zero for another image.
+	pub fn resume(&self, id: u32) -> u32 {
+		if self.id == Some(id) {
+			self.position()
+		} else {
+			0
+		}
+	}
+
+	/// Largest position the cursor can record.
+	pub fn capacity(&self) -> u32 {
+		((self.range.end - self.bitmap()) * 8).saturating_mul(self.granule)
+	}
+
+	/// Start the download of the image `id` from the beginning.
+	pub fn start(&mut self, id: u32) -> Result<(), CursorError<S::Error>> {
+		self.clear()?;
+		let length = align_up(HEADER, S::WRITE_SIZE);
+		let mut buffer = [0; BUFFER];
+		fill_erased::<S>(self.range.start, &mut buffer[..length]);
+		buffer[..4].copy_from_slice(&MAGIC.to_le_bytes());
+		buffer[4..8].copy_from_slice(&id.to_le_bytes());
+		let crc = Crc32::checksum(&buffer[..8]);
+		buffer[8..HEADER].copy_from_slice(&crc.to_le_bytes());
+		write_chunked(&mut self.flash, self.range.start, &buffer[..length])
+			.map_err(CursorError::Flash)?;
+		self.id = Some(id);
+		Ok(())
+	}
+
+	/// Record that the first `position` bytes of the image are durably written.
+	///
+	/// The position is rounded down to a multiple of the granule, and going back is ignored, as is
+	/// advancing before a download is started.
+	pub fn advance(&mut self, position: u32) -> Result<(), CursorError<S::Error>> {
+		if position > self.capacity() {
+			return Err(CursorError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: position as usize,
+					capacity: self.capacity() as usize,
+				},
+			)));
+		}
+		let done = position / self.granule;
+		if done <= self.done || self.id.is_none() {
+			return Ok(());
+		}
+		// Rewrite the words holding the new bits, with the bits already flipped unchanged.
+		let word = S::WRITE_SIZE as u32;
+		let chunk = BUFFER as u32 - BUFFER as u32 % word;
+		let mut offset = self.done / 8 / word * word;
+		let end = align_up(((done + 7) / 8) as usize, S::WRITE_SIZE) as u32;
+		while offset < end {
+			let part = chunk.min(end - offset);
+			let mut buffer = [0; BUFFER];
+			let address = self.bitmap() + offset;
+			for (index, byte) in buffer[..part as usize].iter_mut().enumerate() {
+				let bits = done.saturating_sub((offset + index as u32) * 8).min(8);
+				*byte = erased_byte::<S>(address + index as u32) ^ (0xffu16 >> (8 - bits)) as u8;
+			}
+			write_chunked(&mut self.flash, address, &buffer[..part as usize])
+				.map_err(CursorError::Flash)?;
+			offset += part;
+		}
+		self.done = done;
+		Ok(())
+	}
+
+	/// Forget the download, e.g. once the image is complete.
+	pub fn clear(&mut self) -> Result<(), CursorError<S::Error>> {
+		self.flash
+			.erase(self.range.start, self.range.end)
+			.map_err(CursorError::Flash)?;
+		self.id = None;
+		self.done = 0;
+		Ok(())
+	}
+
+	/// Start of the bitmap.
+	fn bitmap(&self) -> u32 {
+		self.range.start + align_up(HEADER, S::READ_SIZE.max(S::WRITE_SIZE)) as u32
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{ErasedToZero, MemoryMockFlash, MockError, MockFlash, MultiwriteMockFlash};
+
+	type Flash = MockFlash<4096, 1, 4, 1024>;
+
+	#[test]
+	fn erase_resumed() {
+		let mut flash = Flash::from_bytes([0x55; 4096]);
+		assert!(matches!(
+			ResumableErase::new(&flash, 512, 2048),
+			Err(CheckError::NotAligned)
+		));
+		let mut erase = ResumableErase::new(&flash, 1024, 4096).unwrap();
+		assert_eq!(erase.step(&mut flash), Ok(false));
+		assert_eq!(erase.position(), 2048);
+
+		// The state is saved and restored, e.g. across a reset.
+		let mut erase = ResumableErase::from_bytes(erase.to_bytes()).unwrap();
+		assert_eq!(erase.step(&mut flash), Ok(false));
+		assert_eq!(erase.step(&mut flash), Ok(true));
+		assert!(erase.is_done());
+		assert_eq!(erase.step(&mut flash), Ok(true));
+		assert!(flash.as_bytes()[..1024].iter().all(|&byte| byte == 0x55));
+		assert!(flash.as_bytes()[1024..].iter().all(|&byte| byte == 0xff));
+
+		let mut bytes = erase.to_bytes();
+		bytes[..4].copy_from_slice(&8192u32.to_le_bytes());
+		assert_eq!(ResumableErase::from_bytes(bytes), None);
+	}
+
+	#[test]
+	fn write_resumed() {
+		let mut flash = Flash::new();
+		let mut data = [0; 2560];
+		for (index, byte) in data.iter_mut().enumerate() {
+			*byte = index as u8;
+		}
+		assert!(ResumableWrite::new(&flash, 2048, data.len()).is_err());
+		let mut write = ResumableWrite::new(&flash, 512, data.len()).unwrap();
+		assert_eq!(write.step(&mut flash, &data), Ok(false));
+		assert_eq!(write.written(), 512);
+
+		// Power is cut during a step, which is repeated once its page is erased again.
+		flash.cut_next_write(1);
+		let saved = write.to_bytes();
+		assert_eq!(write.step(&mut flash, &data), Err(MockError::PowerLoss));
+		assert_eq!(write.to_bytes(), saved);
+		flash.erase(1024, 2048).unwrap();
+		let mut write = ResumableWrite::from_bytes(saved).unwrap();
+		while !write.step(&mut flash, &data).unwrap() {}
+		assert_eq!(write.written(), data.len());
+		assert_eq!(&flash.as_bytes()[512..512 + data.len()], &data[..]);
+
+		let mut bytes = write.to_bytes();
+		bytes[8..].copy_from_slice(&4096u32.to_le_bytes());
+		assert_eq!(ResumableWrite::from_bytes(bytes), None);
+	}
+
+	fn cursor_roundtrip<S: MultiwriteNorFlash>(flash: S) {
+		let mut cursor = DownloadCursor::open(flash, 1024..2048, 256).unwrap();
+		assert_eq!(cursor.id(), None);
+		assert_eq!(cursor.capacity(), (1024 - 12) * 8 * 256);
+		cursor.advance(1024).unwrap();
+		assert_eq!(cursor.position(), 0);
+
+		cursor.start(0x0102_0304).unwrap();
+		cursor.advance(1000).unwrap();
+		assert_eq!(cursor.position(), 768);
+		cursor.advance(512).unwrap();
+		assert_eq!(cursor.position(), 768);
+		cursor.advance(40 * 256 + 10).unwrap();
+		assert!(matches!(
+			cursor.advance(cursor.capacity() + 1),
+			Err(CursorError::Invalid(CheckError::OutOfBounds(_)))
+		));
+
+		let mut cursor = DownloadCursor::open(cursor.into_inner(), 1024..2048, 256).unwrap();
+		assert_eq!(cursor.id(), Some(0x0102_0304));
+		assert_eq!(cursor.position(), 40 * 256);
+		assert_eq!(cursor.resume(0x0102_0304), 40 * 256);
+		assert_eq!(cursor.resume(0x0102_0305), 0);
+		cursor.advance(cursor.capacity()).unwrap();
+
+		let mut cursor = DownloadCursor::open(cursor.into_inner(), 1024..2048, 256).unwrap();
+		assert_eq!(cursor.position(), cursor.capacity());
+		cursor.clear().unwrap();
+		let cursor = DownloadCursor::open(cursor.into_inner(), 1024..2048, 256).unwrap();
+		assert_eq!((cursor.id(), cursor.position()), (None, 0));
+	}
+
+	#[test]
+	fn cursor() {
+		cursor_roundtrip(MultiwriteMockFlash::new(Flash::new()));
+		cursor_roundtrip(MultiwriteMockFlash::new(
+			MemoryMockFlash::<_, ErasedToZero>::new(Flash::new()),
+		));
+	}
+}
@@ -0,0 +1,140 @@
+use core::ops::Deref;
+
+use crate::nor_flash::{is_erased, ChipErase, ReadNorFlash};
+
+/// Size of the chunks in which secrets are read.
+const CHUNK: usize = 32;
+
+/// Compare the bytes at `offset` of `flash` with `expected`, in constant time.
+///
+/// The secret is read in fixed-size chunks and every byte is compared, so the time taken only
+/// depends on the length of `expected` and not on where the first difference is. This is meant
+/// for bootloaders checking keys or tokens stored in flash without leaking them through timing.
+///
+/// **NOTE** This will panic if `READ_SIZE` is over 32 bytes.
+pub fn verify_secret<S: ReadNorFlash>(
+	flash: &mut S,
+	offset: u32,
+	expected: &[u8],
+) -> Result<bool, S::Error> {
+	assert!(S::READ_SIZE <= CHUNK, "READ_SIZE too large");
+	let chunk = CHUNK - CHUNK % S::READ_SIZE;
+	let end = offset as usize + expected.len();
+	let end = (end + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+	let mut buffer = [0; CHUNK];
+	let mut difference = 0;
+	let mut address = offset - offset % S::READ_SIZE as u32;
+	let mut done = 0;
+	while done < expected.len() {
+		let read = chunk.min(end - address as usize);
+		flash.read(address, &mut buffer[..read])?;
+		let from = offset.saturating_sub(address) as usize;
+		let part = (read - from).min(expected.len() - done);
+		difference |= buffer[from..from + part]
+			.iter()
+			.zip(&expected[done..done + part])
+			.fold(0, |difference, (a, b)| difference | (a ^ b));
+		address += read as u32;
+		done += part;
+	}
+	wipe(&mut buffer);
+	Ok(difference == 0)
+}
+
+/// Erase the whole of `flash` and check that nothing is left of its previous contents.
+///
+/// Returns whether every byte reads back as erased, `false` e.g. when the flash is write
+/// protected and ignored the erase command.
+///
+/// **NOTE** This will panic if `READ_SIZE` is over 32 bytes.
+pub fn secure_erase<S: ChipErase>(flash: &mut S) -> Result<bool, S::Error> {
+	assert!(S::READ_SIZE <= CHUNK, "READ_SIZE too large");
+	flash.erase_chip()?;
+	let chunk = CHUNK - CHUNK % S::READ_SIZE;
+	let capacity = flash.capacity();
+	let mut buffer = [0; CHUNK];
+	let mut address = 0;
+	while address < capacity {
+		let read = chunk.min(capacity - address);
+		flash.read(address as u32, &mut buffer[..read])?;
+		if !is_erased::<S>(address as u32, &buffer[..read]) {
+			wipe(&mut buffer);
+			return Ok(false);
+		}
+		address += read;
+	}
+	Ok(true)
+}
+
+/// Overwrite `bytes` with zeros.
+///
+/// With the `zeroize` feature the writes are guaranteed not to be optimized away, which is also
+/// used to wipe the merge and read buffers of the adapters once they are done with them.
+/// Without it this is a plain fill, which the compiler may elide for buffers not read again.
+pub fn wipe(bytes: &mut [u8]) {
+	#[cfg(feature = "zeroize")]
+	zeroize::Zeroize::zeroize(bytes);
+	#[cfg(not(feature = "zeroize"))]
+	bytes.fill(0);
+}
+
+/// Secret read from a flash into a caller buffer, which is wiped when dropped.
+///
+/// The buffer is only accessible through this guard, so the secret does not outlive its use. The
+/// read does not need to be aligned, the unaligned head and tail words go through a scratch buffer
+/// which is wiped as well. Buffers of adapters in between, e.g. the merge buffer of
+/// [`RmwNorFlashStorage`](crate::nor_flash::RmwNorFlashStorage), are only wiped with the
+/// `zeroize` feature.
+pub struct SensitiveRead<'a> {
+	bytes: &'a mut [u8],
+}
+
+impl<'a> SensitiveRead<'a> {
+	/// Read `bytes.len()` bytes at `offset` of `flash` into `bytes`.
+	///
+	/// **NOTE** This will panic if `READ_SIZE` is over 32 bytes.
+	pub fn read<S: ReadNorFlash>(
+		flash: &mut S,
+		offset: u32,
+		bytes: &'a mut [u8],
+	) -> Result<Self, S::Error> {
+		assert!(S::READ_SIZE <= CHUNK, "READ_SIZE too large");
+		// Wrap the buffer right away, so that it is wiped on errors too.
+		let secret = Self { bytes };
+		let size = S::READ_SIZE;
+		let mut word = [0; CHUNK];
+		let mut done = 0;
+		let mut result = Ok(());
+		while done < secret.bytes.len() && result.is_ok() {
+			let address = offset as usize + done;
+			let skip = address % size;
+			let left = secret.bytes.len() - done;
+			if skip == 0 && left >= size {
+				let part = left - left % size;
+				result = flash.read(address as u32, &mut secret.bytes[done..done + part]);
+				done += part;
+			} else {
+				let part = (size - skip).min(left);
+				result = flash.read((address - skip) as u32, &mut word[..size]);
+				secret.bytes[done..done + part].copy_from_slice(&word[skip..skip + part]);
+				done += part;
+			}
+		}
+		wipe(&mut word);
+		result.map(|_| secret)
+	}
+}
+
+impl Deref for SensitiveRead<'_> {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		self.bytes
+	}
+}
+
+impl Drop for SensitiveRead<'_> {
+	fn drop(&mut self) {
+		wipe(self.bytes);
+	}
+}
@@ -0,0 +1,201 @@
+use crate::nor_flash::Geometry;
+
+/// `SFDP` signature at the start of the parameter space.
+const SIGNATURE: u32 = 0x5044_4653;
+
+/// Parameter ID of the JEDEC Basic Flash Parameter Table.
+const BASIC_TABLE_ID: u16 = 0xff00;
+
+/// Errors returned while parsing SFDP data.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SfdpError {
+	/// The data does not start with the `SFDP` signature.
+	BadSignature,
+	/// A header or table points past the end of the data.
+	Truncated,
+	/// There is no Basic Flash Parameter Table.
+	MissingBasicTable,
+	/// The Basic Flash Parameter Table is too short or describes an invalid device.
+	InvalidBasicTable,
+}
+
+impl core::fmt::Display for SfdpError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::BadSignature => write!(f, "Missing SFDP signature"),
+			Self::Truncated => write!(f, "SFDP data is truncated"),
+			Self::MissingBasicTable => write!(f, "No basic flash parameter table"),
+			Self::InvalidBasicTable => write!(f, "Invalid basic flash parameter table"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SfdpError {}
+
+/// Addressing modes supported by the device.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AddressMode {
+	/// Only 3-byte addresses.
+	ThreeByte,
+	/// 3-byte addresses by default, 4-byte addresses can be enabled.
+	ThreeOrFourByte,
+	/// Only 4-byte addresses.
+	FourByte,
+}
+
+/// An erase command supported by the device.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct EraseType {
+	/// Size in bytes of the erased region.
+	pub size: u32,
+	/// Instruction performing the erase.
+	pub opcode: u8,
+}
+
+/// Optional features supported by the device.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Features {
+	/// Double transfer rate clocking.
+	pub dtr: bool,
+	/// 1-1-2 fast read.
+	pub fast_read_1_1_2: bool,
+	/// 1-2-2 fast read.
+	pub fast_read_1_2_2: bool,
+	/// 1-1-4 fast read.
+	pub fast_read_1_1_4: bool,
+	/// 1-4-4 fast read.
+	pub fast_read_1_4_4: bool,
+}
+
+/// Device parameters parsed from the JEDEC Serial Flash Discoverable Parameters (JESD216).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Sfdp {
+	/// Major and minor revision of the SFDP standard.
+	pub revision: (u8, u8),
+	/// Capacity of the device in bytes.
+	pub capacity: u64,
+	/// Supported addressing modes.
+	pub address_mode: AddressMode,
+	/// Supported erase commands, by increasing size.
+	pub erase_types: [Option<EraseType>; 4],
+	/// Size of the program page in bytes, if described by the table.
+	pub page_size: Option<u32>,
+	/// Optional features.
+	pub features: Features,
+}
+
+fn dword(data: &[u8], offset: usize) -> Result<u32, SfdpError> {
+	let bytes = data.get(offset..offset + 4).ok_or(SfdpError::Truncated)?;
+	Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl Sfdp {
+	/// Parse the SFDP parameter space, as read by the driver starting from SFDP address 0.
+	///
+	/// `data` must contain the SFDP header, the parameter headers and the Basic Flash Parameter
+	/// Table they point to.
+	pub fn parse(data: &[u8]) -> Result<Self, SfdpError> {
+		if dword(data, 0)? != SIGNATURE {
+			return Err(SfdpError::BadSignature);
+		}
+		let header = dword(data, 4)?;
+		let revision = ((header >> 8) as u8, header as u8);
+		let headers = (header >> 16) as u8 as usize + 1;
+
+		let (mut table, mut length) = (None, 0);
+		for index in 0..headers {
+			let (first, second) = (dword(data, 8 + 8 * index)?, dword(data, 12 + 8 * index)?);
+			let id = (second >> 24) as u16 * 0x100 + first as u8 as u16;
+			if id == BASIC_TABLE_ID {
+				table = Some((second & 0x00ff_ffff) as usize);
+				length = (first >> 24) as usize;
+				break;
+			}
+		}
+		let table = table.ok_or(SfdpError::MissingBasicTable)?;
+		if length < 9 {
+			return Err(SfdpError::InvalidBasicTable);
+		}
+		let table = data
+			.get(table..table + 4 * length)
+			.ok_or(SfdpError::Truncated)?;
+		let dw = |n: usize| dword(table, 4 * (n - 1));
+
+		let first = dw(1)?;
+		let address_mode = match (first >> 17) & 0b11 {
+			0b00 => AddressMode::ThreeByte,
+			0b01 => AddressMode::ThreeOrFourByte,
+			0b10 => AddressMode::FourByte,
+			_ => return Err(SfdpError::InvalidBasicTable),
+		};
+		let features = Features {
+			dtr: first & (1 << 19) != 0,
+			fast_read_1_1_2: first & (1 << 16) != 0,
+			fast_read_1_2_2: first & (1 << 20) != 0,
+			fast_read_1_4_4: first & (1 << 21) != 0,
+			fast_read_1_1_4: first & (1 << 22) != 0,
+		};
+
+		let density = dw(2)?;
+		let bits = if density & 0x8000_0000 == 0 {
+			u64::from(density) + 1
+		} else {
+			let exponent = density & 0x7fff_ffff;
+			if exponent >= 64 {
+				return Err(SfdpError::InvalidBasicTable);
+			}
+			1u64 << exponent
+		};
+
+		let mut erase_types = [None; 4];
+		let mut count = 0;
+		for (dword, shift) in [(8, 0), (8, 16), (9, 0), (9, 16)].iter() {
+			let value = dw(*dword)? >> shift;
+			let (exponent, opcode) = (value as u8, (value >> 8) as u8);
+			if exponent != 0 && exponent < 32 {
+				erase_types[count] = Some(EraseType {
+					size: 1 << exponent,
+					opcode,
+				});
+				count += 1;
+			}
+		}
+		erase_types[..count].sort_unstable_by_key(|erase| erase.map(|e| e.size));
+
+		let page_size = if length >= 11 {
+			Some(1 << ((dw(11)? >> 4) & 0xf))
+		} else {
+			None
+		};
+
+		Ok(Self {
+			revision,
+			capacity: bits / 8,
+			address_mode,
+			erase_types,
+			page_size,
+			features,
+		})
+	}
+
+	/// The smallest supported erase command.
+	pub fn smallest_erase(&self) -> Option<EraseType> {
+		self.erase_types[0]
+	}
+
+	/// Runtime geometry of the device, erasing with the smallest supported erase command.
+	///
+	/// Returns `None` if no erase command is described, or the capacity does not fit in `usize`.
+	pub fn geometry(&self) -> Option<Geometry> {
+		if self.capacity > usize::MAX as u64 {
+			return None;
+		}
+		Some(Geometry {
+			capacity: self.capacity as usize,
+			read_size: 1,
+			write_size: 1,
+			erase_size: self.smallest_erase()?.size as usize,
+		})
+	}
+}
@@ -0,0 +1,48 @@
+use crate::nor_flash::{FlashStatus, NorFlash, PowerControl};
+use crate::resumable::{ResumableErase, ResumableWrite};
+
+/// A resumable operation to complete before shutting down.
+#[derive(Debug)]
+pub enum Pending<'a> {
+	/// An erase to complete.
+	Erase(&'a mut ResumableErase),
+	/// A write to complete, with the data it was created for.
+	Write(&'a mut ResumableWrite, &'a [u8]),
+}
+
+impl Pending<'_> {
+	/// Run the remaining steps of the operation.
+	fn complete<S: NorFlash>(&mut self, flash: &mut S) -> Result<(), S::Error> {
+		match self {
+			Self::Erase(erase) => while !erase.step(flash)? {},
+			Self::Write(write, bytes) => while !write.step(flash, bytes)? {},
+		}
+		Ok(())
+	}
+}
+
+/// Bring `flash` into a state safe for losing power, in one call for brown-out handlers.
+///
+/// This waits for the program or erase command the flash is running, completes the `pending`
+/// resumable operations, [flushes](NorFlash::flush) buffered writes, waits for the flash again
+/// and puts it into deep power-down with [`PowerControl::sleep`]. Resumable operations not worth
+/// the remaining energy are aborted by leaving them out of `pending`: their state is only updated
+/// by completed steps, so they resume after the reset from where they stopped.
+///
+/// The shutdown stops at the first error, the flash being possibly left awake.
+///
+/// **NOTE** This will panic if a pending write is given data of another length than it was
+/// created with.
+pub fn shutdown<S>(flash: &mut S, pending: &mut [Pending<'_>]) -> Result<(), S::Error>
+where
+	S: NorFlash + FlashStatus + PowerControl,
+{
+	span!("shutdown", pending = pending.len());
+	flash.wait_ready()?;
+	for operation in pending.iter_mut() {
+		operation.complete(flash)?;
+	}
+	flash.flush()?;
+	flash.wait_ready()?;
+	flash.sleep()
+}
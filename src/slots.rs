@@ -0,0 +1,353 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use crate::crc::{Checksum, Crc32};
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, write_chunked, CheckError, NorFlash, NorFlashError,
+	NorFlashErrorKind, OutOfBoundsInfo,
+};
+
+/// Size of the buffer used to read and write unaligned tails and trailers.
+const BUFFER: usize = 32;
+
+/// Size of the trailer: magic word and CRC.
+const TRAILER: usize = 8;
+
+const MAGIC: u32 = 0x544f_4c53;
+
+/// Errors returned by [`SlotStore`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SlotError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The slot index is out of bounds of the store.
+	Invalid(CheckError),
+}
+
+impl<E: NorFlashError> NorFlashError for SlotError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+		}
+	}
+}
+
+/// Store of fixed-size objects of `SLOT` bytes, addressed by index.
+///
+/// Every slot spans whole erase pages, so writing or removing a slot never touches the others,
+/// and is protected by a checksum computed by `C`, written after its data. The slots holding a valid object are found
+/// when mounting the store and kept in a bitmap, so lookups do not access the flash. This suits
+/// calibration tables or keys better than a key-value store, with a fixed layout and a bounded
+/// cost for every operation.
+pub struct SlotStore<S, const SLOT: usize, C = Crc32> {
+	flash: S,
+	range: Range<u32>,
+	valid: Vec<u32>,
+	reads: usize,
+	mount_reads: usize,
+	checksum: PhantomData<C>,
+}
+
+impl<S: NorFlash, const SLOT: usize> SlotStore<S, SLOT> {
+	/// Mount the store in `range` of `flash`, checking every slot.
+	///
+	/// # Errors
+	///
+	/// Returns [`SlotError::Invalid`] with [`CheckError::NotAligned`] if `range` is not aligned to
+	/// `ERASE_SIZE`, and with [`CheckError::OutOfBounds`] if it is out of bounds or if
+	/// `READ_SIZE` or `WRITE_SIZE` is over 32 bytes.
+	pub fn mount(flash: S, range: Range<u32>) -> Result<Self, SlotError<S::Error>> {
+		Self::mount_with_checksum(flash, range)
+	}
+}
+
+impl<S: NorFlash, const SLOT: usize, C: Checksum> SlotStore<S, SLOT, C> {
+	/// Mount the store with objects protected by the checksum `C` instead of [`Crc32`].
+	///
+	/// # Errors
+	///
+	/// Returns [`SlotError::Invalid`] in the cases of [`mount`](SlotStore::mount).
+	pub fn mount_with_checksum(flash: S, range: Range<u32>) -> Result<Self, SlotError<S::Error>> {
+		check_range(flash.capacity(), S::ERASE_SIZE, range.start, range.end)
+			.and_then(|()| check_slice(BUFFER, 1, 0, S::READ_SIZE.max(S::WRITE_SIZE)))
+			.map_err(SlotError::Invalid)?;
+		let slots = ((range.end - range.start) / Self::stride()) as usize;
+		let mut store = Self {
+			flash,
+			range,
+			valid: vec![0; (slots + 31) / 32],
+			reads: 0,
+			mount_reads: 0,
+			checksum: PhantomData,
+		};
+		for index in 0..slots {
+			if store.load(index).map_err(SlotError::Flash)?.is_some() {
+				store.valid[index / 32] |= 1 << (index % 32);
+			}
+		}
+		store.mount_reads = store.reads;
+		Ok(store)
+	}
+
+	/// Release the flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Number of reads of the flash performed by the mount.
+	///
+	/// This is bounded by the geometry of the store, up to three reads per slot, which gives the
+	/// worst case mount time.
+	pub fn mount_reads(&self) -> usize {
+		self.mount_reads
+	}
+
+	/// Number of slots of the store.
+	pub fn len(&self) -> usize {
+		((self.range.end - self.range.start) / Self::stride()) as usize
+	}
+
+	/// Whether the store has no slots.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Whether slot `index` holds a valid object.
+	pub fn is_valid(&self, index: usize) -> bool {
+		index < self.len() && self.valid[index / 32] & 1 << (index % 32) != 0
+	}
+
+	/// The indices of the slots holding a valid object.
+	pub fn valid(&self) -> impl Iterator<Item = usize> + '_ {
+		(0..self.len()).filter(move |index| self.is_valid(*index))
+	}
+
+	/// Read the object of slot `index`, `None` if the slot is empty or corrupted.
+	pub fn read(&mut self, index: usize) -> Result<Option<[u8; SLOT]>, SlotError<S::Error>> {
+		self.check(index)?;
+		if !self.is_valid(index) {
+			return Ok(None);
+		}
+		let object = self.load(index).map_err(SlotError::Flash)?;
+		if object.is_none() {
+			self.valid[index / 32] &= !(1 << (index % 32));
+		}
+		Ok(object)
+	}
+
+	/// Write `object` to slot `index`, replacing its previous object.
+	pub fn write(&mut self, index: usize, object: &[u8; SLOT]) -> Result<(), SlotError<S::Error>> {
+		self.remove(index)?;
+		let address = self.address(index);
+		let aligned = SLOT - SLOT % S::WRITE_SIZE;
+		write_chunked(&mut self.flash, address, &object[..aligned]).map_err(SlotError::Flash)?;
+		if aligned < SLOT {
+			let mut tail = [0; BUFFER];
+			fill_erased::<S>(address + aligned as u32, &mut tail[..S::WRITE_SIZE]);
+			tail[..SLOT - aligned].copy_from_slice(&object[aligned..]);
+			self.flash
+				.write(address + aligned as u32, &tail[..S::WRITE_SIZE])
+				.map_err(SlotError::Flash)?;
+		}
+		let mut trailer = [0; BUFFER];
+		fill_erased::<S>(
+			address + Self::data_size() as u32,
+			&mut trailer[..Self::trailer_size()],
+		);
+		trailer[..4].copy_from_slice(&MAGIC.to_le_bytes());
+		trailer[4..8].copy_from_slice(&Self::crc(index, object).to_le_bytes());
+		self.flash
+			.write(
+				address + Self::data_size() as u32,
+				&trailer[..Self::trailer_size()],
+			)
+			.map_err(SlotError::Flash)?;
+		self.valid[index / 32] |= 1 << (index % 32);
+		Ok(())
+	}
+
+	/// Erase slot `index`.
+	pub fn remove(&mut self, index: usize) -> Result<(), SlotError<S::Error>> {
+		self.check(index)?;
+		let address = self.address(index);
+		self.valid[index / 32] &= !(1 << (index % 32));
+		self.flash
+			.erase(address, address + Self::stride())
+			.map_err(SlotError::Flash)
+	}
+
+	fn align() -> usize {
+		S::READ_SIZE.max(S::WRITE_SIZE)
+	}
+
+	fn data_size() -> usize {
+		(SLOT + Self::align() - 1) / Self::align() * Self::align()
+	}
+
+	fn trailer_size() -> usize {
+		(TRAILER + Self::align() - 1) / Self::align() * Self::align()
+	}
+
+	/// Size of a slot, in whole erase pages.
+	fn stride() -> u32 {
+		let size = Self::data_size() + Self::trailer_size();
+		((size + S::ERASE_SIZE - 1) / S::ERASE_SIZE * S::ERASE_SIZE) as u32
+	}
+
+	fn address(&self, index: usize) -> u32 {
+		self.range.start + index as u32 * Self::stride()
+	}
+
+	fn check(&self, index: usize) -> Result<(), SlotError<S::Error>> {
+		if index >= self.len() {
+			return Err(SlotError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: index as u32 * Self::stride(),
+					length: Self::stride() as usize,
+					capacity: (self.range.end - self.range.start) as usize,
+				},
+			)));
+		}
+		Ok(())
+	}
+
+	/// The checksum of an object, also covering its index so that objects cannot be mixed up.
+	fn crc(index: usize, object: &[u8]) -> u32 {
+		let mut crc = C::default();
+		crc.update(&(index as u32).to_le_bytes());
+		crc.update(object);
+		crc.finish()
+	}
+
+	/// Read from the flash, counting the reads.
+	fn read_flash(&mut self, address: u32, bytes: &mut [u8]) -> Result<(), S::Error> {
+		self.reads += 1;
+		self.flash.read(address, bytes)
+	}
+
+	/// Read slot `index` from the flash, `None` if its trailer or CRC does not match.
+	fn load(&mut self, index: usize) -> Result<Option<[u8; SLOT]>, S::Error> {
+		let address = self.address(index);
+		let mut buffer = [0; BUFFER];
+		let trailer = &mut buffer[..Self::trailer_size()];
+		self.read_flash(address + Self::data_size() as u32, trailer)?;
+		let word = |index: usize| {
+			let mut word = [0; 4];
+			word.copy_from_slice(&trailer[4 * index..4 * index + 4]);
+			u32::from_le_bytes(word)
+		};
+		let (magic, crc) = (word(0), word(1));
+		if magic != MAGIC {
+			return Ok(None);
+		}
+
+		let mut object = [0; SLOT];
+		let aligned = SLOT - SLOT % S::READ_SIZE;
+		self.read_flash(address, &mut object[..aligned])?;
+		if aligned < SLOT {
+			let tail = &mut buffer[..S::READ_SIZE];
+			self.read_flash(address + aligned as u32, tail)?;
+			object[aligned..].copy_from_slice(&tail[..SLOT - aligned]);
+		}
+		Ok(Some(object).filter(|object| Self::crc(index, object) == crc))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::crc::Crc16;
+	use crate::mock::{MockError, MockFlash};
+
+	type Flash = MockFlash<4096, 1, 4, 1024>;
+
+	#[test]
+	fn mount_invalid() {
+		assert!(SlotStore::<_, 16>::mount(Flash::new(), 0..4096).is_ok());
+		assert!(matches!(
+			SlotStore::<_, 16>::mount(Flash::new(), 512..4096),
+			Err(SlotError::Invalid(CheckError::NotAligned))
+		));
+		assert!(matches!(
+			SlotStore::<_, 16>::mount(Flash::new(), 0..8192),
+			Err(SlotError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert!(matches!(
+			SlotStore::<_, 16>::mount(MockFlash::<4096, 64, 4, 1024>::new(), 0..4096),
+			Err(SlotError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn roundtrip() {
+		let mut store =
+			SlotStore::<_, 18>::mount(MockFlash::<4096, 2, 4, 256>::new(), 0..4096).unwrap();
+		assert_eq!(store.len(), 16);
+		assert_eq!(store.read(3), Ok(None));
+		store.write(3, b"calibration table ").unwrap();
+		store.write(7, &[0x11; 18]).unwrap();
+		store.write(7, &[0x22; 18]).unwrap();
+		store.write(15, &[0x33; 18]).unwrap();
+		store.remove(15).unwrap();
+		assert!(matches!(
+			store.write(16, &[0; 18]),
+			Err(SlotError::Invalid(CheckError::OutOfBounds(_)))
+		));
+
+		let mut store = SlotStore::<_, 18>::mount(store.into_inner(), 0..4096).unwrap();
+		assert!(store.valid().eq([3, 7].iter().copied()));
+		assert!(store.mount_reads() <= 3 * store.len());
+		assert_eq!(store.read(3), Ok(Some(*b"calibration table ")));
+		assert_eq!(store.read(7), Ok(Some([0x22; 18])));
+		assert_eq!(store.read(15), Ok(None));
+		assert!(matches!(
+			store.read(16),
+			Err(SlotError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn corrupted() {
+		let mut store = SlotStore::<_, 16>::mount(Flash::new(), 0..4096).unwrap();
+		store.write(0, &[0x44; 16]).unwrap();
+		store.write(1, &[0x55; 16]).unwrap();
+
+		// An object copied to another slot does not match the CRC covering its index.
+		let mut bytes = [0; 4096];
+		bytes.copy_from_slice(store.into_inner().as_bytes());
+		bytes.copy_within(0..1024, 2048);
+		bytes[1024] ^= 1;
+		let mut store = SlotStore::<_, 16>::mount(Flash::from_bytes(bytes), 0..4096).unwrap();
+		assert!(store.valid().eq([0].iter().copied()));
+		assert_eq!(store.read(2), Ok(None));
+
+		// Power is cut before the trailer is written.
+		let mut flash = store.into_inner();
+		flash.cut_next_write(1);
+		let mut store = SlotStore::<_, 16>::mount(&mut flash, 0..4096).unwrap();
+		assert_eq!(
+			store.write(0, &[0x66; 16]),
+			Err(SlotError::Flash(MockError::PowerLoss))
+		);
+		drop(store);
+		let mut store = SlotStore::<_, 16>::mount(&mut flash, 0..4096).unwrap();
+		assert!(!store.is_valid(0));
+		assert_eq!(store.read(0), Ok(None));
+	}
+
+	#[test]
+	fn custom_checksum() {
+		let mut store =
+			SlotStore::<_, 16, Crc16>::mount_with_checksum(Flash::new(), 0..4096).unwrap();
+		store.write(1, &[0x77; 16]).unwrap();
+		let mut store =
+			SlotStore::<_, 16, Crc16>::mount_with_checksum(store.into_inner(), 0..4096).unwrap();
+		assert_eq!(store.read(1), Ok(Some([0x77; 16])));
+
+		let store = SlotStore::<_, 16>::mount(store.into_inner(), 0..4096).unwrap();
+		assert!(!store.is_valid(1));
+	}
+}
@@ -0,0 +1,351 @@
+use embedded_hal::spi::{Operation, SpiDevice};
+
+use crate::nor_flash::{
+	check_erase, check_read, check_write, optimize_erase, CheckError, ChipErase, ConfigBits,
+	ErrorType, FlashInfo, FlashStatus, MultiwriteNorFlash, NorFlash, NorFlashError,
+	NorFlashErrorKind, PowerControl, ReadNorFlash,
+};
+use crate::sfdp::{Sfdp, SfdpError};
+
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS: u8 = 0x05;
+const READ_STATUS_2: u8 = 0x35;
+const READ_STATUS_3: u8 = 0x15;
+const WRITE_STATUS: u8 = 0x01;
+const WRITE_STATUS_2: u8 = 0x31;
+const WRITE_STATUS_3: u8 = 0x11;
+const READ_JEDEC_ID: u8 = 0x9f;
+const READ_SFDP: u8 = 0x5a;
+const POWER_DOWN: u8 = 0xb9;
+const RELEASE_POWER_DOWN: u8 = 0xab;
+
+const READ: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const SECTOR_ERASE: u8 = 0x20;
+const HALF_BLOCK_ERASE: u8 = 0x52;
+const BLOCK_ERASE: u8 = 0xd8;
+const CHIP_ERASE: u8 = 0xc7;
+
+const READ_4B: u8 = 0x13;
+const PAGE_PROGRAM_4B: u8 = 0x12;
+const SECTOR_ERASE_4B: u8 = 0x21;
+const HALF_BLOCK_ERASE_4B: u8 = 0x5c;
+const BLOCK_ERASE_4B: u8 = 0xdc;
+
+/// Write in progress bit of the status register.
+const STATUS_BUSY: u8 = 0x01;
+
+const SECTOR_SIZE: u32 = 4096;
+const HALF_BLOCK_SIZE: u32 = 32768;
+const BLOCK_SIZE: u32 = 65536;
+const PAGE_SIZE: u32 = 256;
+
+/// Largest device reachable with 3-byte addresses.
+const MAX_3B_CAPACITY: u32 = 1 << 24;
+
+/// Bytes of the SFDP space read while probing, which must contain the Basic Flash Parameter Table.
+const SFDP_LENGTH: usize = 256;
+
+/// Errors returned by [`SpiNor`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SpiNorError<E> {
+	/// The SPI device returned an error.
+	Spi(E),
+	/// The arguments are not properly aligned or out of bounds.
+	Invalid(CheckError),
+	/// The device could not be identified while probing.
+	Sfdp(SfdpError),
+	/// The device does not support 4K sector erase, or the configuration register does not exist.
+	Unsupported,
+}
+
+impl<E: core::fmt::Debug> NorFlashError for SpiNorError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Invalid(error) => error.kind(),
+			_ => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Driver for serial NOR flashes using the standard command set.
+///
+/// Reads use `03h`, programs use `02h` split on 256 byte pages, and erases are planned by
+/// [`optimize_erase`] among `20h` for 4K sectors, `52h` for 32K blocks and `D8h` for 64K blocks,
+/// depending on the erase sizes supported by the device. Devices larger than 16 MiB are addressed
+/// with the 4-byte variants of these commands.
+///
+/// Deep power-down is entered with `B9h` and left with `ABh`. The device needs a few microseconds
+/// after leaving it before accepting commands (`tRES1` in datasheets), the delay between
+/// transactions of the SPI device must be configured accordingly when using [`PowerControl`].
+pub struct SpiNor<SPI> {
+	spi: SPI,
+	capacity: u32,
+	erase_sizes: &'static [u32],
+	asleep: bool,
+}
+
+/// The supported subset of the 4K, 32K and 64K erase sizes.
+fn erase_sizes(half_block: bool, block: bool) -> &'static [u32] {
+	match (half_block, block) {
+		(false, false) => &[SECTOR_SIZE],
+		(true, false) => &[SECTOR_SIZE, HALF_BLOCK_SIZE],
+		(false, true) => &[SECTOR_SIZE, BLOCK_SIZE],
+		(true, true) => &[SECTOR_SIZE, HALF_BLOCK_SIZE, BLOCK_SIZE],
+	}
+}
+
+impl<SPI> SpiNor<SPI>
+where
+	SPI: SpiDevice,
+{
+	/// Create a driver for a device of `capacity` bytes, erasing with 4K sectors and 64K blocks.
+	pub fn new(spi: SPI, capacity: u32) -> Self {
+		Self {
+			spi,
+			capacity,
+			erase_sizes: erase_sizes(false, true),
+			asleep: false,
+		}
+	}
+
+	/// Erase with the standard commands among `sizes`, 4K sector erase is always used.
+	pub fn with_erase_sizes(mut self, sizes: &[u32]) -> Self {
+		self.erase_sizes = erase_sizes(
+			sizes.contains(&HALF_BLOCK_SIZE),
+			sizes.contains(&BLOCK_SIZE),
+		);
+		self
+	}
+
+	/// Create a driver configured from the SFDP tables of the device.
+	///
+	/// With the `chips` feature, devices without SFDP support are looked up by JEDEC ID in
+	/// [`chips`](crate::chips).
+	pub fn probe(mut spi: SPI) -> Result<Self, SpiNorError<SPI::Error>> {
+		let mut data = [0; SFDP_LENGTH];
+		spi.transaction(&mut [
+			Operation::Write(&[READ_SFDP, 0, 0, 0, 0]),
+			Operation::Read(&mut data),
+		])
+		.map_err(SpiNorError::Spi)?;
+
+		let (capacity, sizes) = match Sfdp::parse(&data) {
+			Ok(sfdp) => {
+				let supports = |size| {
+					sfdp.erase_types
+						.iter()
+						.flatten()
+						.any(|erase| erase.size == size)
+				};
+				if !supports(SECTOR_SIZE) {
+					return Err(SpiNorError::Unsupported);
+				}
+				(
+					sfdp.capacity,
+					erase_sizes(supports(HALF_BLOCK_SIZE), supports(BLOCK_SIZE)),
+				)
+			}
+			#[cfg(feature = "chips")]
+			Err(error) => {
+				let mut driver = Self::new(spi, 0);
+				let chip = crate::chips::lookup(driver.read_jedec_id()?)
+					.ok_or(SpiNorError::Sfdp(error))?;
+				if chip.erase_sizes[0] != SECTOR_SIZE {
+					return Err(SpiNorError::Unsupported);
+				}
+				driver.capacity = chip.capacity;
+				return Ok(driver.with_erase_sizes(chip.erase_sizes));
+			}
+			#[cfg(not(feature = "chips"))]
+			Err(error) => return Err(SpiNorError::Sfdp(error)),
+		};
+		if capacity > u64::from(u32::MAX) {
+			return Err(SpiNorError::Unsupported);
+		}
+		let mut driver = Self::new(spi, capacity as u32);
+		driver.erase_sizes = sizes;
+		Ok(driver)
+	}
+
+	/// Read the manufacturer, memory type and capacity bytes of the device.
+	pub fn read_jedec_id(&mut self) -> Result<[u8; 3], SpiNorError<SPI::Error>> {
+		let mut id = [0; 3];
+		self.spi
+			.transaction(&mut [Operation::Write(&[READ_JEDEC_ID]), Operation::Read(&mut id)])
+			.map_err(SpiNorError::Spi)?;
+		Ok(id)
+	}
+
+	/// Release the SPI device.
+	pub fn into_inner(self) -> SPI {
+		self.spi
+	}
+
+	/// Build the command and address header, returns its length.
+	fn header(&self, opcode: u8, opcode_4b: u8, address: u32, header: &mut [u8; 5]) -> usize {
+		let address = address.to_be_bytes();
+		if self.capacity > MAX_3B_CAPACITY {
+			header[0] = opcode_4b;
+			header[1..5].copy_from_slice(&address);
+			5
+		} else {
+			header[0] = opcode;
+			header[1..4].copy_from_slice(&address[1..]);
+			4
+		}
+	}
+
+	/// Run a program or erase command and wait for its completion.
+	fn modify(&mut self, header: &[u8], data: &[u8]) -> Result<(), SpiNorError<SPI::Error>> {
+		self.spi.write(&[WRITE_ENABLE]).map_err(SpiNorError::Spi)?;
+		self.spi
+			.transaction(&mut [Operation::Write(header), Operation::Write(data)])
+			.map_err(SpiNorError::Spi)?;
+		self.wait_ready()
+	}
+}
+
+impl<SPI: SpiDevice> ErrorType for SpiNor<SPI> {
+	type Error = SpiNorError<SPI::Error>;
+}
+
+impl<SPI> ReadNorFlash for SpiNor<SPI>
+where
+	SPI: SpiDevice,
+{
+	const READ_SIZE: usize = 1;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len()).map_err(SpiNorError::Invalid)?;
+		let mut header = [0; 5];
+		let length = self.header(READ, READ_4B, offset, &mut header);
+		self.spi
+			.transaction(&mut [Operation::Write(&header[..length]), Operation::Read(bytes)])
+			.map_err(SpiNorError::Spi)
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity as usize
+	}
+}
+
+impl<SPI> NorFlash for SpiNor<SPI>
+where
+	SPI: SpiDevice,
+{
+	const WRITE_SIZE: usize = 1;
+	const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+	const MAX_WRITE_CHUNK: usize = PAGE_SIZE as usize;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to).map_err(SpiNorError::Invalid)?;
+		let steps = optimize_erase(self.erase_sizes, from, to).map_err(SpiNorError::Invalid)?;
+		for (address, size) in steps {
+			let (opcode, opcode_4b) = match size {
+				BLOCK_SIZE => (BLOCK_ERASE, BLOCK_ERASE_4B),
+				HALF_BLOCK_SIZE => (HALF_BLOCK_ERASE, HALF_BLOCK_ERASE_4B),
+				_ => (SECTOR_ERASE, SECTOR_ERASE_4B),
+			};
+			let mut header = [0; 5];
+			let length = self.header(opcode, opcode_4b, address, &mut header);
+			self.modify(&header[..length], &[])?;
+		}
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len()).map_err(SpiNorError::Invalid)?;
+		let mut address = offset;
+		let mut bytes = bytes;
+		while !bytes.is_empty() {
+			let chunk = ((PAGE_SIZE - address % PAGE_SIZE) as usize).min(bytes.len());
+			let mut header = [0; 5];
+			let length = self.header(PAGE_PROGRAM, PAGE_PROGRAM_4B, address, &mut header);
+			self.modify(&header[..length], &bytes[..chunk])?;
+			address += chunk as u32;
+			bytes = &bytes[chunk..];
+		}
+		Ok(())
+	}
+
+	fn info(&self) -> FlashInfo {
+		FlashInfo {
+			multiwrite: true,
+			chip_erase: true,
+			power_down: true,
+			..FlashInfo::new(self)
+		}
+	}
+}
+
+impl<SPI: SpiDevice> MultiwriteNorFlash for SpiNor<SPI> {}
+
+impl<SPI: SpiDevice> FlashStatus for SpiNor<SPI> {
+	fn is_busy(&mut self) -> Result<bool, Self::Error> {
+		let mut status = [0];
+		self.spi
+			.transaction(&mut [
+				Operation::Write(&[READ_STATUS]),
+				Operation::Read(&mut status),
+			])
+			.map_err(SpiNorError::Spi)?;
+		Ok(status[0] & STATUS_BUSY != 0)
+	}
+}
+
+impl<SPI: SpiDevice> PowerControl for SpiNor<SPI> {
+	fn sleep(&mut self) -> Result<(), Self::Error> {
+		self.spi.write(&[POWER_DOWN]).map_err(SpiNorError::Spi)?;
+		self.asleep = true;
+		Ok(())
+	}
+
+	fn wake(&mut self) -> Result<(), Self::Error> {
+		self.spi
+			.write(&[RELEASE_POWER_DOWN])
+			.map_err(SpiNorError::Spi)?;
+		self.asleep = false;
+		Ok(())
+	}
+
+	fn is_asleep(&self) -> Option<bool> {
+		Some(self.asleep)
+	}
+}
+
+impl<SPI: SpiDevice> ChipErase for SpiNor<SPI> {
+	fn erase_chip(&mut self) -> Result<(), Self::Error> {
+		self.modify(&[CHIP_ERASE], &[])
+	}
+}
+
+/// Status registers 1 to 3, numbered 0 to 2, with the commands of most vendors.
+///
+/// The quad enable bit is bit 1 of register 1 on most devices. Registers are written volatile
+/// or not depending on the device, see its datasheet.
+impl<SPI: SpiDevice> ConfigBits for SpiNor<SPI> {
+	fn read_config(&mut self, register: u8) -> Result<u8, Self::Error> {
+		let opcode = match register {
+			0 => READ_STATUS,
+			1 => READ_STATUS_2,
+			2 => READ_STATUS_3,
+			_ => return Err(SpiNorError::Unsupported),
+		};
+		let mut value = [0];
+		self.spi
+			.transaction(&mut [Operation::Write(&[opcode]), Operation::Read(&mut value)])
+			.map_err(SpiNorError::Spi)?;
+		Ok(value[0])
+	}
+
+	fn write_config(&mut self, register: u8, value: u8) -> Result<(), Self::Error> {
+		let opcode = match register {
+			0 => WRITE_STATUS,
+			1 => WRITE_STATUS_2,
+			2 => WRITE_STATUS_3,
+			_ => return Err(SpiNorError::Unsupported),
+		};
+		self.modify(&[opcode], &[value])
+	}
+}
@@ -0,0 +1,250 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::nor_flash::{
+	check_erase, check_read, check_write, is_erased, ErrorType, NorFlash, NorFlashError,
+	NorFlashErrorKind, ProgramPolarity, ReadNorFlash,
+};
+
+/// A violation of the `NorFlash` contract detected by [`StrictNorFlash`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Violation {
+	/// A read was not aligned to `READ_SIZE` or went past the end of the flash.
+	Read {
+		/// Offset of the read.
+		offset: u32,
+		/// Length of the read.
+		length: usize,
+		/// Why the read was rejected.
+		kind: NorFlashErrorKind,
+	},
+	/// A write was not aligned to `WRITE_SIZE` or went past the end of the flash.
+	Write {
+		/// Offset of the write.
+		offset: u32,
+		/// Length of the write.
+		length: usize,
+		/// Why the write was rejected.
+		kind: NorFlashErrorKind,
+	},
+	/// An erase was not aligned to `ERASE_SIZE` or went past the end of the flash.
+	Erase {
+		/// Start of the erased range.
+		from: u32,
+		/// End of the erased range.
+		to: u32,
+		/// Why the erase was rejected.
+		kind: NorFlashErrorKind,
+	},
+	/// A write targeted a word which was not erased when the wrapper was created.
+	NotErased {
+		/// Address of the offending word.
+		address: u32,
+	},
+	/// A write targeted a word which has already been written since its last erase.
+	DoubleWrite {
+		/// Address of the offending word.
+		address: u32,
+	},
+}
+
+impl Violation {
+	/// The generic error kind matching this violation.
+	pub fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Read { kind, .. } | Self::Write { kind, .. } | Self::Erase { kind, .. } => *kind,
+			Self::NotErased { .. } | Self::DoubleWrite { .. } => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+impl core::fmt::Display for Violation {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::Read {
+				offset,
+				length,
+				kind,
+			} => write!(f, "read of {} bytes at {:#x}: {}", length, offset, kind),
+			Self::Write {
+				offset,
+				length,
+				kind,
+			} => write!(f, "write of {} bytes at {:#x}: {}", length, offset, kind),
+			Self::Erase { from, to, kind } => {
+				write!(f, "erase of {:#x}..{:#x}: {}", from, to, kind)
+			}
+			Self::NotErased { address } => {
+				write!(f, "write to word at {:#x} which is not erased", address)
+			}
+			Self::DoubleWrite { address } => {
+				write!(f, "word at {:#x} written twice without erase", address)
+			}
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Violation {}
+
+/// Errors returned by [`StrictNorFlash`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StrictError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The operation violates the `NorFlash` contract and was not forwarded.
+	Violation(Violation),
+}
+
+impl<E: NorFlashError> NorFlashError for StrictError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Violation(v) => v.kind(),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Word {
+	Erased,
+	Programmed,
+	Unknown,
+}
+
+/// Debug wrapper enforcing the `NorFlash` contract on its clients.
+///
+/// The state of every `WRITE_SIZE` word is tracked in RAM, so that writes to words which are not
+/// erased, or which were already written since the last erase, are rejected with a precise
+/// [`Violation`] instead of silently corrupting data on the device.
+pub struct StrictNorFlash<S> {
+	flash: S,
+	words: Vec<Word>,
+}
+
+impl<S> StrictNorFlash<S>
+where
+	S: NorFlash,
+{
+	/// Wrap `flash`, scanning its contents to find out which words are currently erased.
+	pub fn new(mut flash: S) -> Result<Self, S::Error> {
+		let count = flash.capacity() / S::WRITE_SIZE;
+		let mut words = vec![Word::Unknown; count];
+		let mut buf = vec![0; S::WRITE_SIZE.max(S::READ_SIZE)];
+		let step = buf.len();
+		let mut offset = 0;
+		while offset + step <= flash.capacity() {
+			flash.read(offset as u32, &mut buf)?;
+			for (index, word) in buf.chunks(S::WRITE_SIZE).enumerate() {
+				if is_erased::<S>((offset + index * S::WRITE_SIZE) as u32, word) {
+					words[offset / S::WRITE_SIZE + index] = Word::Erased;
+				}
+			}
+			offset += step;
+		}
+		Ok(Self { flash, words })
+	}
+
+	/// Wrap `flash`, assuming it has been fully erased beforehand.
+	pub fn new_erased(flash: S) -> Self {
+		let count = flash.capacity() / S::WRITE_SIZE;
+		Self {
+			flash,
+			words: vec![Word::Erased; count],
+		}
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+}
+
+impl<S: ErrorType> ErrorType for StrictNorFlash<S> {
+	type Error = StrictError<S::Error>;
+}
+
+impl<S> ReadNorFlash for StrictNorFlash<S>
+where
+	S: NorFlash,
+{
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len()).map_err(|error| {
+			StrictError::Violation(Violation::Read {
+				offset,
+				length: bytes.len(),
+				kind: error.kind(),
+			})
+		})?;
+		self.flash.read(offset, bytes).map_err(StrictError::Flash)
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S> NorFlash for StrictNorFlash<S>
+where
+	S: NorFlash,
+{
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to).map_err(|error| {
+			StrictError::Violation(Violation::Erase {
+				from,
+				to,
+				kind: error.kind(),
+			})
+		})?;
+		let words = &mut self.words[from as usize / S::WRITE_SIZE..to as usize / S::WRITE_SIZE];
+		// The erased range is undefined if the erase fails half way.
+		words.fill(Word::Unknown);
+		self.flash.erase(from, to).map_err(StrictError::Flash)?;
+		words.fill(Word::Erased);
+		Ok(())
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len()).map_err(|error| {
+			StrictError::Violation(Violation::Write {
+				offset,
+				length: bytes.len(),
+				kind: error.kind(),
+			})
+		})?;
+		let first = offset as usize / S::WRITE_SIZE;
+		let words = &mut self.words[first..first + bytes.len() / S::WRITE_SIZE];
+		for (index, word) in words.iter().enumerate() {
+			let address = offset + (index * S::WRITE_SIZE) as u32;
+			match word {
+				Word::Erased => {}
+				Word::Programmed => {
+					return Err(StrictError::Violation(Violation::DoubleWrite { address }))
+				}
+				Word::Unknown => {
+					return Err(StrictError::Violation(Violation::NotErased { address }))
+				}
+			}
+		}
+		// The written words are undefined if the write fails half way.
+		words.fill(Word::Unknown);
+		self.flash
+			.write(offset, bytes)
+			.map_err(StrictError::Flash)?;
+		words.fill(Word::Programmed);
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush().map_err(StrictError::Flash)
+	}
+}
@@ -0,0 +1,336 @@
+use std::format;
+use std::vec;
+use std::vec::Vec;
+
+use crate::nor_flash::{
+	erase_all, erased_byte, fill_erased, programmed_byte, ChipErase, MultiwriteNorFlash, NorFlash,
+	NorFlashError, NorFlashErrorKind,
+};
+
+/// Parameters of a conformance run.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Config {
+	/// Seed of the pseudo random operation generator, runs are reproducible for a given seed.
+	pub seed: u64,
+	/// Number of random operations to perform.
+	pub operations: usize,
+	/// Maximum length in bytes of a single random read or write.
+	pub max_length: usize,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			seed: 0x5eed,
+			operations: 1000,
+			max_length: 1024,
+		}
+	}
+}
+
+/// Small xorshift generator, good enough to produce operation sequences.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+	pub(crate) fn new(seed: u64) -> Self {
+		Self(seed.max(1))
+	}
+
+	pub(crate) fn next_u64(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	/// A value in `0..bound`, `bound` must not be zero.
+	pub(crate) fn below(&mut self, bound: usize) -> usize {
+		(self.next_u64() % bound as u64) as usize
+	}
+
+	pub(crate) fn fill(&mut self, bytes: &mut [u8]) {
+		bytes.iter_mut().for_each(|b| *b = self.next_u64() as u8);
+	}
+}
+
+/// Reference model of a NOR flash, tracking contents and erased words.
+struct Model {
+	data: Vec<u8>,
+	blank: Vec<u8>,
+	erased: Vec<bool>,
+	write_size: usize,
+}
+
+impl Model {
+	fn new<S: NorFlash>(capacity: usize) -> Self {
+		let mut blank = vec![0; capacity];
+		fill_erased::<S>(0, &mut blank);
+		Self {
+			data: blank.clone(),
+			blank,
+			erased: vec![true; capacity / S::WRITE_SIZE],
+			write_size: S::WRITE_SIZE,
+		}
+	}
+
+	fn erase(&mut self, from: usize, to: usize) {
+		self.data[from..to].copy_from_slice(&self.blank[from..to]);
+		self.erased[from / self.write_size..to / self.write_size].fill(true);
+	}
+
+	fn write(&mut self, offset: usize, bytes: &[u8]) {
+		self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+		let first = offset / self.write_size;
+		self.erased[first..first + bytes.len() / self.write_size].fill(false);
+	}
+
+	fn is_erased(&self, offset: usize, length: usize) -> bool {
+		let first = offset / self.write_size;
+		self.erased[first..first + length / self.write_size]
+			.iter()
+			.all(|erased| *erased)
+	}
+}
+
+fn expect_kind<E: NorFlashError>(op: &str, result: Result<(), E>, expected: NorFlashErrorKind) {
+	match result {
+		Err(e) if e.kind() == expected => {}
+		Err(e) => panic!("{}: expected {:?}, got {:?}", op, expected, e),
+		Ok(()) => panic!("{}: expected {:?}, got Ok", op, expected),
+	}
+}
+
+fn expect_ok<E: NorFlashError>(op: &str, result: Result<(), E>) {
+	if let Err(e) = result {
+		panic!("{}: unexpected error {:?}", op, e);
+	}
+}
+
+/// Check that out of bounds and misaligned arguments are rejected with the right error kind.
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_bounds<S: NorFlash>(flash: &mut S) {
+	let capacity = flash.capacity();
+	let mut buf = vec![0; S::READ_SIZE.max(S::WRITE_SIZE)];
+
+	let read = &mut buf[..S::READ_SIZE];
+	let result = flash.read(capacity as u32, read);
+	expect_kind("read past end", result, NorFlashErrorKind::OutOfBounds);
+
+	let write = &buf[..S::WRITE_SIZE];
+	let result = flash.write(capacity as u32, write);
+	expect_kind("write past end", result, NorFlashErrorKind::OutOfBounds);
+
+	let (from, to) = (capacity as u32, (capacity + S::ERASE_SIZE) as u32);
+	let result = flash.erase(from, to);
+	expect_kind("erase past end", result, NorFlashErrorKind::OutOfBounds);
+
+	let result = flash.erase(S::ERASE_SIZE as u32, 0);
+	expect_kind(
+		"erase with from > to",
+		result,
+		NorFlashErrorKind::OutOfBounds,
+	);
+
+	if S::READ_SIZE > 1 {
+		let result = flash.read(1, &mut buf[..S::READ_SIZE]);
+		expect_kind("misaligned read", result, NorFlashErrorKind::NotAligned);
+	}
+	if S::WRITE_SIZE > 1 {
+		let result = flash.write(1, &buf[..S::WRITE_SIZE]);
+		expect_kind("misaligned write", result, NorFlashErrorKind::NotAligned);
+	}
+	if S::ERASE_SIZE > 1 {
+		let result = flash.erase(1, S::ERASE_SIZE as u32 + 1);
+		expect_kind("misaligned erase", result, NorFlashErrorKind::NotAligned);
+	}
+}
+
+/// Check that erasing the whole flash leaves it filled with its `ERASE_PATTERN`.
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_erase_all<S: NorFlash>(flash: &mut S) {
+	expect_ok("erase all", erase_all(flash));
+	expect_erased(flash);
+}
+
+/// Check that erasing the chip leaves it filled with its `ERASE_PATTERN`, including previously
+/// written words.
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_erase_chip<S: ChipErase>(flash: &mut S) {
+	let capacity = flash.capacity();
+	let word = vec![0; S::WRITE_SIZE];
+	expect_ok("erase all", flash.erase(0, capacity as u32));
+	expect_ok("write", flash.write(0, &word));
+	if capacity > S::WRITE_SIZE {
+		let last = (capacity - S::WRITE_SIZE) as u32;
+		expect_ok("write", flash.write(last, &word));
+	}
+	expect_ok("erase chip", flash.erase_chip());
+	expect_erased(flash);
+}
+
+fn expect_erased<S: NorFlash>(flash: &mut S) {
+	let capacity = flash.capacity();
+	let mut buf = vec![0; S::ERASE_SIZE];
+	for from in (0..capacity).step_by(S::ERASE_SIZE) {
+		buf.fill(0);
+		expect_ok("read", flash.read(from as u32, &mut buf));
+		let erased = |(index, byte): (usize, &u8)| *byte == erased_byte::<S>((from + index) as u32);
+		if let Some(index) = buf.iter().enumerate().position(|byte| !erased(byte)) {
+			panic!(
+				"byte at {:#x} not erased: {:#04x}",
+				from + index,
+				buf[index]
+			);
+		}
+	}
+}
+
+/// Perform random operations on `flash`, comparing every read against a reference model.
+///
+/// Writes only target erased words, as required by the `NorFlash` contract. Misaligned operations
+/// are interleaved and must be rejected without modifying the contents.
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_random_operations<S: NorFlash>(flash: &mut S, config: &Config) {
+	let capacity = flash.capacity();
+	let mut rng = Rng::new(config.seed);
+	let mut model = Model::new::<S>(capacity);
+	let mut buf = vec![0; config.max_length.max(S::WRITE_SIZE).min(capacity)];
+	let mut expected = buf.clone();
+	expect_ok("erase all", flash.erase(0, capacity as u32));
+
+	let align = |value: usize, size: usize| value - value % size;
+	for _ in 0..config.operations {
+		match rng.below(4) {
+			0 => {
+				let pages = capacity / S::ERASE_SIZE;
+				let from = rng.below(pages);
+				let to = from + 1 + rng.below((pages - from).min(4));
+				let (from, to) = (from * S::ERASE_SIZE, to * S::ERASE_SIZE);
+				let op = format!("erase {:#x}..{:#x}", from, to);
+				expect_ok(&op, flash.erase(from as u32, to as u32));
+				model.erase(from, to);
+			}
+			1 => {
+				let offset = align(rng.below(capacity), S::WRITE_SIZE);
+				let length = align(rng.below(buf.len() + 1), S::WRITE_SIZE)
+					.min(align(capacity - offset, S::WRITE_SIZE));
+				if !model.is_erased(offset, length) {
+					continue;
+				}
+				rng.fill(&mut buf[..length]);
+				let op = format!("write {} bytes at {:#x}", length, offset);
+				expect_ok(&op, flash.write(offset as u32, &buf[..length]));
+				model.write(offset, &buf[..length]);
+			}
+			2 => {
+				let offset = align(rng.below(capacity), S::READ_SIZE);
+				let length = align(rng.below(buf.len() + 1), S::READ_SIZE)
+					.min(align(capacity - offset, S::READ_SIZE));
+				let op = format!("read {} bytes at {:#x}", length, offset);
+				expect_ok(&op, flash.read(offset as u32, &mut buf[..length]));
+				expected[..length].copy_from_slice(&model.data[offset..offset + length]);
+				if let Some(index) = (0..length).find(|i| buf[*i] != expected[*i]) {
+					panic!(
+						"{}: byte at {:#x} is {:#04x}, expected {:#04x}",
+						op,
+						offset + index,
+						buf[index],
+						expected[index]
+					);
+				}
+			}
+			_ => {
+				if S::WRITE_SIZE > 1 && capacity > S::WRITE_SIZE {
+					let offset = align(rng.below(capacity - S::WRITE_SIZE), S::WRITE_SIZE) + 1;
+					let op = format!("misaligned write at {:#x}", offset);
+					let result = flash.write(offset as u32, &buf[..S::WRITE_SIZE]);
+					expect_kind(&op, result, NorFlashErrorKind::NotAligned);
+				}
+			}
+		}
+	}
+}
+
+/// Check that `MultiwriteNorFlash` writes on top of written words only change bits from their
+/// erased value, e.g. AND the existing contents when erasing to `0xff`.
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_multiwrite<S: MultiwriteNorFlash>(flash: &mut S, config: &Config) {
+	let mut rng = Rng::new(config.seed);
+	let length = S::ERASE_SIZE;
+	let (mut first, mut second, mut read) = (vec![0; length], vec![0; length], vec![0; length]);
+	rng.fill(&mut first);
+	rng.fill(&mut second);
+
+	expect_ok("erase", flash.erase(0, length as u32));
+	expect_ok("first write", flash.write(0, &first));
+	expect_ok("second write", flash.write(0, &second));
+	expect_ok("read", flash.read(0, &mut read));
+	for (index, byte) in read.iter().enumerate() {
+		let expected = programmed_byte::<S>(index as u32, first[index], second[index]);
+		if *byte != expected {
+			panic!(
+				"byte at {:#x} is {:#04x} after multiwrite, expected {:#04x}",
+				index, byte, expected
+			);
+		}
+	}
+}
+
+/// Run all `NorFlash` conformance checks against `flash`.
+///
+/// This is destructive, the whole flash is erased and overwritten with random data.
+///
+/// # Panics
+///
+/// Panics with a description of the first misbehaviour found.
+pub fn check_nor_flash<S: NorFlash>(flash: &mut S, config: &Config) {
+	check_bounds(flash);
+	check_erase_all(flash);
+	check_random_operations(flash, config);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{MockFlash, MockFlashBox, MultiwriteMockFlash};
+
+	#[test]
+	fn mock_flash() {
+		let mut flash = MockFlash::<16384, 4, 4, 1024>::new();
+		check_nor_flash(&mut flash, &Config::default());
+		check_erase_chip(&mut flash);
+	}
+
+	#[test]
+	fn mock_flash_box() {
+		let mut flash = MockFlashBox::<1, 8, 4096>::new(65536);
+		check_nor_flash(&mut flash, &Config::default());
+		check_erase_chip(&mut flash);
+	}
+
+	#[test]
+	fn multiwrite_mock_flash() {
+		let mut flash = MultiwriteMockFlash::new(MockFlash::<16384, 1, 4, 1024>::new());
+		check_multiwrite(&mut flash, &Config::default());
+		check_nor_flash(&mut flash, &Config::default());
+
+		let mut flash = MultiwriteMockFlash::new(MockFlashBox::<1, 4, 1024>::new(16384));
+		check_multiwrite(&mut flash, &Config::default());
+		check_nor_flash(&mut flash, &Config::default());
+	}
+}
@@ -0,0 +1,471 @@
+use core::convert::Infallible;
+use core::ops::Range;
+
+use crate::addr::align_up;
+use crate::crc::Crc32;
+use crate::digest::hash_region;
+use crate::nor_flash::{
+	check_range, check_slice, fill_erased, CheckError, NorFlash, NorFlashError, NorFlashErrorKind,
+	OutOfBoundsInfo, ReadNorFlash,
+};
+
+/// Size of the buffer used to stage records and read unaligned values.
+const BUFFER: usize = 32;
+
+/// Size of the header of a record: type and length.
+pub const HEADER: usize = 4;
+
+/// Size of the CRC following the value of a record.
+pub const CRC: usize = 4;
+
+/// Type marking the end of the records, as read from erased flash.
+pub const END: u16 = 0xffff;
+
+/// Errors returned by the TLV functions.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TlvError<E> {
+	/// The flash returned an error.
+	Flash(E),
+	/// The record does not fit in the buffer or region.
+	Invalid(CheckError),
+	/// The length or CRC of a record is wrong.
+	Corrupt,
+}
+
+impl<E: NorFlashError> NorFlashError for TlvError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Corrupt => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// A record decoded from a buffer.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Record<'a> {
+	/// Type of the record.
+	pub tag: u16,
+	/// Value of the record.
+	pub value: &'a [u8],
+}
+
+/// Space taken by a record holding `length` bytes, padded to `align`.
+pub const fn record_len(length: usize, align: usize) -> usize {
+	align_up(HEADER + length + CRC, align)
+}
+
+/// Encode a record of type `tag` holding `value` at the start of `bytes`, padded to `align`.
+///
+/// Records are a little endian `u16` type and `u16` length, the value, and a CRC-32 of all these,
+/// padded with `FFh` so that the next record starts at a multiple of `align`. Returns the space
+/// taken by the record.
+///
+/// # Errors
+///
+/// Returns [`CheckError::Reserved`] if `tag` is [`END`], and [`CheckError::OutOfBounds`] if
+/// `value` is longer than 65535 bytes or the record does not fit in `bytes`.
+pub fn encode(tag: u16, value: &[u8], align: usize, bytes: &mut [u8]) -> Result<usize, CheckError> {
+	check_record(tag, value)?;
+	let length = record_len(value.len(), align);
+	check_slice(bytes.len(), 1, 0, length)?;
+	let crc = HEADER + value.len();
+	bytes[..2].copy_from_slice(&tag.to_le_bytes());
+	bytes[2..HEADER].copy_from_slice(&(value.len() as u16).to_le_bytes());
+	bytes[HEADER..crc].copy_from_slice(value);
+	let checksum = Crc32::checksum(&bytes[..crc]);
+	bytes[crc..crc + CRC].copy_from_slice(&checksum.to_le_bytes());
+	bytes[crc + CRC..length].fill(0xff);
+	Ok(length)
+}
+
+/// Iterate over the records encoded in `bytes` with [`encode`], padded to `align`.
+pub fn records(bytes: &[u8], align: usize) -> Records<'_> {
+	Records {
+		bytes,
+		align,
+		offset: 0,
+	}
+}
+
+/// Iterator over the records of a buffer, returned by [`records`].
+///
+/// This stops at the end of the buffer or at an [`END`] type, and after the first corrupted
+/// record.
+#[derive(Debug, Clone)]
+pub struct Records<'a> {
+	bytes: &'a [u8],
+	align: usize,
+	offset: usize,
+}
+
+impl<'a> Records<'a> {
+	/// Offset following the last record read, where the next one can be appended.
+	pub fn position(&self) -> usize {
+		self.offset
+	}
+}
+
+impl<'a> Iterator for Records<'a> {
+	type Item = Result<Record<'a>, TlvError<Infallible>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let bytes = &self.bytes[self.offset..];
+		if bytes.len() < HEADER || bytes[..2] == END.to_le_bytes() {
+			return None;
+		}
+		let tag = u16::from_le_bytes([bytes[0], bytes[1]]);
+		let length = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+		let crc = HEADER + length;
+		let valid = bytes.len() >= crc + CRC
+			&& Crc32::checksum(&bytes[..crc]).to_le_bytes() == bytes[crc..crc + CRC];
+		if !valid {
+			self.offset = self.bytes.len();
+			return Some(Err(TlvError::Corrupt));
+		}
+		self.offset = (self.offset + record_len(length, self.align)).min(self.bytes.len());
+		Some(Ok(Record {
+			tag,
+			value: &bytes[HEADER..crc],
+		}))
+	}
+}
+
+/// Write a record of type `tag` holding `value` at `offset` of `flash`, returning the offset of
+/// the next record.
+///
+/// Records are padded to the larger of `READ_SIZE` and `WRITE_SIZE` with the erased value, and
+/// laid out as described for [`encode`].
+///
+/// # Errors
+///
+/// Returns [`TlvError::Invalid`] in the cases of [`encode`], with [`CheckError::NotAligned`] if
+/// `offset` is not aligned to the larger of `READ_SIZE` and `WRITE_SIZE`, and with
+/// [`CheckError::OutOfBounds`] if the record does not fit in the flash or if `READ_SIZE` or
+/// `WRITE_SIZE` is over 32 bytes.
+pub fn write_record<S: NorFlash>(
+	flash: &mut S,
+	offset: u32,
+	tag: u16,
+	value: &[u8],
+) -> Result<u32, TlvError<S::Error>> {
+	check_record(tag, value).map_err(TlvError::Invalid)?;
+	let align = align::<S>().map_err(TlvError::Invalid)?;
+	let length = record_len(value.len(), align);
+	check_slice(flash.capacity(), align, offset, length).map_err(TlvError::Invalid)?;
+
+	let mut header = [0; HEADER];
+	header[..2].copy_from_slice(&tag.to_le_bytes());
+	header[2..].copy_from_slice(&(value.len() as u16).to_le_bytes());
+	let mut crc = Crc32::new();
+	crc.update(&header);
+	crc.update(value);
+	let crc = crc.finish().to_le_bytes();
+
+	let mut buffer = [0; BUFFER];
+	let mut fill = 0;
+	let mut address = offset;
+	for &byte in header.iter().chain(value).chain(crc.iter()) {
+		buffer[fill] = byte;
+		fill += 1;
+		if fill == BUFFER {
+			flash.write(address, &buffer).map_err(TlvError::Flash)?;
+			address += BUFFER as u32;
+			fill = 0;
+		}
+	}
+	if fill > 0 {
+		fill_erased::<S>(address + fill as u32, &mut buffer[fill..]);
+		let tail = align_up(fill, align);
+		flash
+			.write(address, &buffer[..tail])
+			.map_err(TlvError::Flash)?;
+	}
+	Ok(offset + length as u32)
+}
+
+/// Iterate over the records written with [`write_record`] in `range` of `flash`.
+///
+/// # Errors
+///
+/// Returns [`CheckError::NotAligned`] if `range` is not aligned to the larger of `READ_SIZE` and
+/// `WRITE_SIZE`, and [`CheckError::OutOfBounds`] if it is out of bounds or if these are over 32
+/// bytes.
+pub fn flash_records<S: NorFlash>(
+	flash: &mut S,
+	range: Range<u32>,
+) -> Result<FlashRecords<'_, S>, CheckError> {
+	check_range(flash.capacity(), align::<S>()?, range.start, range.end)?;
+	Ok(FlashRecords::new(flash, range))
+}
+
+/// A record found in a flash region.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FlashRecord {
+	/// Type of the record.
+	pub tag: u16,
+	/// Offset of the value of the record.
+	pub offset: u32,
+	/// Length of the value of the record.
+	pub length: usize,
+}
+
+/// Iterator over the records of a flash region, returned by [`flash_records`].
+///
+/// The CRC of every record is checked before it is returned. This stops at the end of the region
+/// or at an erased header, and after the first corrupted record.
+pub struct FlashRecords<'a, S> {
+	flash: &'a mut S,
+	offset: u32,
+	end: u32,
+}
+
+impl<'a, S: NorFlash> FlashRecords<'a, S> {
+	/// Iterate over `range`, which is already checked by the caller.
+	pub(crate) fn new(flash: &'a mut S, range: Range<u32>) -> Self {
+		Self {
+			flash,
+			offset: range.start,
+			end: range.end,
+		}
+	}
+
+	/// Offset following the last record read, where the next one can be written.
+	pub fn position(&self) -> u32 {
+		self.offset
+	}
+
+	/// Read `bytes.len()` bytes of the value of `record`, from its start.
+	pub fn read_value(
+		&mut self,
+		record: &FlashRecord,
+		bytes: &mut [u8],
+	) -> Result<(), TlvError<S::Error>> {
+		if bytes.len() > record.length {
+			return Err(TlvError::Invalid(CheckError::OutOfBounds(
+				OutOfBoundsInfo {
+					offset: 0,
+					length: bytes.len(),
+					capacity: record.length,
+				},
+			)));
+		}
+		read_at(self.flash, record.offset, bytes).map_err(TlvError::Flash)
+	}
+
+	fn read_record(&mut self) -> Result<Option<FlashRecord>, TlvError<S::Error>> {
+		let align = align::<S>().map_err(TlvError::Invalid)?;
+		let mut buffer = [0; BUFFER];
+		let header = &mut buffer[..align_up(HEADER, S::READ_SIZE)];
+		if ((self.end - self.offset) as usize) < header.len() {
+			return Ok(None);
+		}
+		self.flash
+			.read(self.offset, header)
+			.map_err(TlvError::Flash)?;
+		let tag = u16::from_le_bytes([header[0], header[1]]);
+		if tag == END {
+			return Ok(None);
+		}
+		let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+		let footprint = record_len(length, align);
+		if footprint > (self.end - self.offset) as usize {
+			return Err(TlvError::Corrupt);
+		}
+		let mut crc = Crc32::new();
+		let value = self.offset + HEADER as u32;
+		hash_region(
+			self.flash,
+			self.offset..value + length as u32,
+			&mut crc,
+			&mut buffer,
+		)
+		.map_err(TlvError::Flash)?;
+		let mut stored = [0; CRC];
+		read_at(self.flash, value + length as u32, &mut stored).map_err(TlvError::Flash)?;
+		if crc.finish() != u32::from_le_bytes(stored) {
+			return Err(TlvError::Corrupt);
+		}
+		self.offset += footprint as u32;
+		Ok(Some(FlashRecord {
+			tag,
+			offset: value,
+			length,
+		}))
+	}
+}
+
+impl<'a, S: NorFlash> Iterator for FlashRecords<'a, S> {
+	type Item = Result<FlashRecord, TlvError<S::Error>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let result = self.read_record();
+		if result.is_err() {
+			self.offset = self.end;
+		}
+		result.transpose()
+	}
+}
+
+/// Check that `tag` is not [`END`] and that the length of `value` fits in the header.
+fn check_record(tag: u16, value: &[u8]) -> Result<(), CheckError> {
+	if tag == END {
+		return Err(CheckError::Reserved);
+	}
+	check_slice(u16::MAX as usize, 1, 0, value.len())
+}
+
+fn align<S: NorFlash>() -> Result<usize, CheckError> {
+	let align = S::READ_SIZE.max(S::WRITE_SIZE);
+	check_slice(BUFFER, 1, 0, align)?;
+	Ok(align)
+}
+
+/// Read `bytes.len()` bytes at the unaligned `address`.
+fn read_at<S: ReadNorFlash>(
+	flash: &mut S,
+	mut address: u32,
+	bytes: &mut [u8],
+) -> Result<(), S::Error> {
+	let chunk = BUFFER - BUFFER % S::READ_SIZE;
+	let mut buffer = [0; BUFFER];
+	let mut done = 0;
+	while done < bytes.len() {
+		let skip = address as usize % S::READ_SIZE;
+		let length = (chunk - skip).min(bytes.len() - done);
+		let read = align_up(skip + length, S::READ_SIZE);
+		flash.read(address - skip as u32, &mut buffer[..read])?;
+		bytes[done..done + length].copy_from_slice(&buffer[skip..skip + length]);
+		done += length;
+		address += length as u32;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockFlash;
+
+	#[test]
+	fn reserved_and_long_records() {
+		let mut bytes = [0; 64];
+		assert_eq!(encode(1, b"value", 4, &mut bytes), Ok(16));
+		assert_eq!(
+			encode(END, b"value", 4, &mut bytes),
+			Err(CheckError::Reserved)
+		);
+		let value = [0; u16::MAX as usize + 1];
+		assert!(matches!(
+			encode(1, &value, 4, &mut bytes),
+			Err(CheckError::OutOfBounds(_))
+		));
+
+		let mut flash = MockFlash::<4096, 1, 4, 1024>::new();
+		assert_eq!(write_record(&mut flash, 0, 1, b"value"), Ok(16));
+		assert_eq!(
+			write_record(&mut flash, 16, END, b"value"),
+			Err(TlvError::Invalid(CheckError::Reserved))
+		);
+		assert!(matches!(
+			write_record(&mut flash, 16, 1, &value),
+			Err(TlvError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn flash_records_invalid() {
+		let mut flash = MockFlash::<4096, 1, 4, 1024>::new();
+		assert!(flash_records(&mut flash, 0..1024).is_ok());
+		assert!(matches!(
+			flash_records(&mut flash, 2..1024),
+			Err(CheckError::NotAligned)
+		));
+		assert!(matches!(
+			flash_records(&mut flash, 0..8192),
+			Err(CheckError::OutOfBounds(_))
+		));
+		let mut flash = MockFlash::<4096, 64, 4, 1024>::new();
+		assert!(matches!(
+			flash_records(&mut flash, 0..1024),
+			Err(CheckError::OutOfBounds(_))
+		));
+		assert!(matches!(
+			write_record(&mut flash, 0, 1, b"value"),
+			Err(TlvError::Invalid(CheckError::OutOfBounds(_)))
+		));
+	}
+
+	#[test]
+	fn buffer_roundtrip() {
+		let mut bytes = [0xff; 64];
+		let mut offset = 0;
+		for (tag, value) in [(1, &b"serial"[..]), (2, b""), (3, b"mac")].iter() {
+			offset += encode(*tag, value, 8, &mut bytes[offset..]).unwrap();
+		}
+		assert_eq!(offset, 16 + 8 + 16);
+
+		let mut iter = records(&bytes, 8);
+		assert_eq!(
+			iter.next(),
+			Some(Ok(Record {
+				tag: 1,
+				value: b"serial"
+			}))
+		);
+		assert_eq!(iter.next(), Some(Ok(Record { tag: 2, value: b"" })));
+		assert_eq!(
+			iter.next(),
+			Some(Ok(Record {
+				tag: 3,
+				value: b"mac"
+			}))
+		);
+		assert_eq!(iter.next(), None);
+		assert_eq!(iter.position(), offset);
+
+		bytes[19] ^= 1;
+		let mut iter = records(&bytes, 8);
+		assert!(iter.next().unwrap().is_ok());
+		assert_eq!(iter.next(), Some(Err(TlvError::Corrupt)));
+		assert_eq!(iter.next(), None);
+		assert!(records(&bytes[..20], 8).nth(1).unwrap().is_err());
+	}
+
+	#[test]
+	fn flash_roundtrip() {
+		let mut flash = MockFlash::<4096, 1, 4, 1024>::new();
+		let long = [0x5a; 100];
+		let mut offset = write_record(&mut flash, 0, 1, b"serial").unwrap();
+		offset = write_record(&mut flash, offset, 2, &long).unwrap();
+		offset = write_record(&mut flash, offset, 3, b"").unwrap();
+		assert_eq!(offset, 16 + 108 + 8);
+
+		let mut iter = flash_records(&mut flash, 0..1024).unwrap();
+		let first = iter.next().unwrap().unwrap();
+		assert_eq!((first.tag, first.offset, first.length), (1, 4, 6));
+		let second = iter.next().unwrap().unwrap();
+		assert_eq!((second.tag, second.length), (2, long.len()));
+		let mut value = [0; 100];
+		iter.read_value(&second, &mut value).unwrap();
+		assert_eq!(value, long);
+		iter.read_value(&first, &mut value[..6]).unwrap();
+		assert_eq!(&value[..6], b"serial");
+		assert!(matches!(
+			iter.read_value(&first, &mut value[..7]),
+			Err(TlvError::Invalid(CheckError::OutOfBounds(_)))
+		));
+		assert_eq!(iter.next().unwrap().unwrap().length, 0);
+		assert!(iter.next().is_none());
+		assert_eq!(iter.position(), offset);
+
+		// Power is cut while appending a record.
+		flash.cut_next_write(1);
+		assert!(write_record(&mut flash, offset, 4, b"cut").is_err());
+		let mut iter = flash_records(&mut flash, 0..1024).unwrap();
+		assert_eq!(iter.by_ref().take(3).filter(Result::is_ok).count(), 3);
+		assert_eq!(iter.next(), Some(Err(TlvError::Corrupt)));
+		assert!(iter.next().is_none());
+	}
+}
@@ -0,0 +1,38 @@
+/// Enter a span named `$name` with the given fields until the end of the scope, recording its
+/// duration in microseconds when it closes.
+///
+/// This expands to nothing without the `trace` feature.
+macro_rules! span {
+	($name:literal $(, $field:ident = $value:expr)* $(,)?) => {
+		#[cfg(feature = "trace")]
+		let _span = $crate::trace::Timed::enter(tracing::trace_span!(
+			$name,
+			$($field = $value,)*
+			duration_us = tracing::field::Empty
+		));
+	};
+}
+
+#[cfg(feature = "trace")]
+pub(crate) struct Timed {
+	span: tracing::span::EnteredSpan,
+	start: std::time::Instant,
+}
+
+#[cfg(feature = "trace")]
+impl Timed {
+	pub(crate) fn enter(span: tracing::Span) -> Self {
+		Self {
+			span: span.entered(),
+			start: std::time::Instant::now(),
+		}
+	}
+}
+
+#[cfg(feature = "trace")]
+impl Drop for Timed {
+	fn drop(&mut self) {
+		let duration = self.start.elapsed().as_micros() as u64;
+		self.span.record("duration_us", duration);
+	}
+}
@@ -0,0 +1,259 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::nor_flash::{
+	check_erase, check_read, check_write, erased_byte, programmed_byte, CheckError, ErrorType,
+	MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ProgramPolarity, ReadNorFlash,
+};
+
+/// A misbehavior of a driver detected by [`ValidatingNorFlash`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Misbehavior {
+	/// A byte was not `ERASE_BYTE` after a successful erase.
+	NotErased {
+		/// Address of the offending byte.
+		address: u32,
+		/// Value read back.
+		value: u8,
+	},
+	/// A byte did not hold the written data after a successful write.
+	NotWritten {
+		/// Address of the offending byte.
+		address: u32,
+		/// Value expected from the written data.
+		expected: u8,
+		/// Value read back.
+		value: u8,
+	},
+	/// A read returned data different from what was last erased or written.
+	Corrupted {
+		/// Address of the offending byte.
+		address: u32,
+		/// Value expected from the previous operations.
+		expected: u8,
+		/// Value returned by the read.
+		value: u8,
+	},
+}
+
+impl core::fmt::Display for Misbehavior {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::NotErased { address, value } => {
+				write!(f, "byte at {:#x} is {:#04x} after erase", address, value)
+			}
+			Self::NotWritten {
+				address,
+				expected,
+				value,
+			} => write!(
+				f,
+				"byte at {:#x} is {:#04x} after write of {:#04x}",
+				address, value, expected
+			),
+			Self::Corrupted {
+				address,
+				expected,
+				value,
+			} => write!(
+				f,
+				"byte at {:#x} read as {:#04x} instead of {:#04x}",
+				address, value, expected
+			),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Misbehavior {}
+
+/// Errors returned by [`ValidatingNorFlash`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ValidationError<E> {
+	/// The wrapped flash returned an error.
+	Flash(E),
+	/// The arguments are not aligned or out of bounds, the operation was not forwarded.
+	Invalid(CheckError),
+	/// The wrapped flash reported success but did not behave as a NOR flash.
+	Misbehavior(Misbehavior),
+}
+
+impl<E: NorFlashError> NorFlashError for ValidationError<E> {
+	fn kind(&self) -> NorFlashErrorKind {
+		match self {
+			Self::Flash(e) => e.kind(),
+			Self::Invalid(error) => error.kind(),
+			Self::Misbehavior(_) => NorFlashErrorKind::Other,
+		}
+	}
+}
+
+/// Debug wrapper checking that a driver behaves as a NOR flash.
+///
+/// Arguments are checked before being forwarded, and a shadow copy of the contents is kept in RAM.
+/// Every erase and write is read back and compared with the shadow copy, and every read is
+/// compared with it, so that drivers silently ignoring or corrupting operations are caught with a
+/// precise [`Misbehavior`]. This is the counterpart of
+/// [`StrictNorFlash`](crate::strict::StrictNorFlash), which checks the clients of a flash.
+pub struct ValidatingNorFlash<S> {
+	flash: S,
+	/// Expected contents, `None` where unknown after a failed operation.
+	shadow: Vec<Option<u8>>,
+}
+
+impl<S> ValidatingNorFlash<S>
+where
+	S: NorFlash,
+{
+	/// Wrap `flash`, reading its whole contents into the shadow copy.
+	pub fn new(flash: S) -> Result<Self, S::Error> {
+		let capacity = flash.capacity();
+		let mut validating = Self {
+			flash,
+			shadow: vec![None; capacity],
+		};
+		let mut buf = vec![0; capacity];
+		validating.flash.read(0, &mut buf)?;
+		for (expected, value) in validating.shadow.iter_mut().zip(buf) {
+			*expected = Some(value);
+		}
+		Ok(validating)
+	}
+
+	/// Release the wrapped flash.
+	pub fn into_inner(self) -> S {
+		self.flash
+	}
+
+	/// Extend `from..to` to `READ_SIZE` boundaries.
+	fn readable(&self, from: usize, to: usize) -> Range<usize> {
+		let start = from - from % S::READ_SIZE;
+		let end = (to + S::READ_SIZE - 1) / S::READ_SIZE * S::READ_SIZE;
+		start..end.min(self.flash.capacity())
+	}
+
+	/// Read back `range` and compare it with the shadow copy, which then takes the read values.
+	fn verify<F>(
+		&mut self,
+		range: Range<usize>,
+		misbehavior: F,
+	) -> Result<(), ValidationError<S::Error>>
+	where
+		F: Fn(u32, u8, u8) -> Misbehavior,
+	{
+		let range = self.readable(range.start, range.end);
+		let mut buf = vec![0; range.len()];
+		self.flash
+			.read(range.start as u32, &mut buf)
+			.map_err(ValidationError::Flash)?;
+		let shadow = &mut self.shadow[range.clone()];
+		let mut result = Ok(());
+		for (index, (expected, value)) in shadow.iter_mut().zip(buf).enumerate() {
+			match *expected {
+				Some(expected) if expected != value && result.is_ok() => {
+					let address = (range.start + index) as u32;
+					result = Err(ValidationError::Misbehavior(misbehavior(
+						address, expected, value,
+					)));
+				}
+				_ => {}
+			}
+			*expected = Some(value);
+		}
+		result
+	}
+}
+
+impl<S: ErrorType> ErrorType for ValidatingNorFlash<S> {
+	type Error = ValidationError<S::Error>;
+}
+
+impl<S> ReadNorFlash for ValidatingNorFlash<S>
+where
+	S: NorFlash,
+{
+	const READ_SIZE: usize = S::READ_SIZE;
+
+	fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+		check_read(self, offset, bytes.len()).map_err(ValidationError::Invalid)?;
+		self.flash
+			.read(offset, bytes)
+			.map_err(ValidationError::Flash)?;
+		let shadow = &self.shadow[offset as usize..offset as usize + bytes.len()];
+		for (index, (expected, value)) in shadow.iter().zip(bytes.iter()).enumerate() {
+			match *expected {
+				Some(expected) if expected != *value => {
+					return Err(ValidationError::Misbehavior(Misbehavior::Corrupted {
+						address: offset + index as u32,
+						expected,
+						value: *value,
+					}))
+				}
+				_ => {}
+			}
+		}
+		Ok(())
+	}
+
+	fn capacity(&self) -> usize {
+		self.flash.capacity()
+	}
+}
+
+impl<S> NorFlash for ValidatingNorFlash<S>
+where
+	S: NorFlash,
+{
+	const WRITE_SIZE: usize = S::WRITE_SIZE;
+	const ERASE_SIZE: usize = S::ERASE_SIZE;
+	const ERASE_BYTE: u8 = S::ERASE_BYTE;
+	const ERASE_PATTERN: &'static [u8] = S::ERASE_PATTERN;
+	const PROGRAM_POLARITY: ProgramPolarity = S::PROGRAM_POLARITY;
+	const MAX_WRITE_CHUNK: usize = S::MAX_WRITE_CHUNK;
+
+	fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+		check_erase(self, from, to).map_err(ValidationError::Invalid)?;
+		let range = from as usize..to as usize;
+		let result = self.flash.erase(from, to);
+		if let Err(error) = result {
+			self.shadow[range].fill(None);
+			return Err(ValidationError::Flash(error));
+		}
+		for (address, expected) in (from..).zip(&mut self.shadow[range.clone()]) {
+			*expected = Some(erased_byte::<S>(address));
+		}
+		self.verify(range, |address, _, value| Misbehavior::NotErased {
+			address,
+			value,
+		})
+	}
+
+	fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+		check_write(self, offset, bytes.len()).map_err(ValidationError::Invalid)?;
+		let range = offset as usize..offset as usize + bytes.len();
+		let result = self.flash.write(offset, bytes);
+		if let Err(error) = result {
+			self.shadow[range].fill(None);
+			return Err(ValidationError::Flash(error));
+		}
+		// Programming can only change bits as allowed by `PROGRAM_POLARITY`, which also covers
+		// multiple writes to the same word.
+		for ((address, expected), byte) in
+			(offset..).zip(&mut self.shadow[range.clone()]).zip(bytes)
+		{
+			*expected = expected.map(|value| programmed_byte::<S>(address, value, *byte));
+		}
+		self.verify(range, |address, expected, value| Misbehavior::NotWritten {
+			address,
+			expected,
+			value,
+		})
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.flash.flush().map_err(ValidationError::Flash)
+	}
+}
+
+impl<S: MultiwriteNorFlash> MultiwriteNorFlash for ValidatingNorFlash<S> {}